@@ -0,0 +1,265 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use hyper::{Body, Response, StatusCode};
+use rust_proxy_server::cache::{now_secs, ByteRanges, CacheEntry, CacheMeta, ProxyCache};
+use rust_proxy_server::config::CacheConfig;
+use rust_proxy_server::handler::partial_response;
+use tempfile::TempDir;
+use tokio::runtime::Runtime;
+
+// 典型负载：小图标/接口响应、图片、视频分片
+const SIZES: [(&str, usize); 3] = [("4KB", 4 << 10), ("256KB", 256 << 10), ("4MB", 4 << 20)];
+const KEYS: usize = 32;
+// 范围请求的对象与每次读取的大小（播放器的常见请求粒度）
+const RANGE_OBJECT: usize = 32 << 20;
+const RANGE_LEN: u64 = 256 << 10;
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+fn meta(len: usize) -> CacheMeta {
+    serde_json::from_value(serde_json::json!({
+        "content_type": "application/octet-stream",
+        "is_complete": true,
+        "total_size": len,
+        "etag": "\"bench\"",
+        "last_modified": "Mon, 01 Jan 2024 00:00:00 GMT",
+        "stored_at": now_secs(),
+        "freshness_secs": 3600,
+        "url": "http://origin.test/object",
+        "headers": [
+            ["cache-control", "public, max-age=3600"],
+            ["server", "origin"],
+            ["accept-ranges", "bytes"],
+        ],
+        "version": 1,
+    }))
+    .unwrap()
+}
+
+fn entry(len: usize) -> CacheEntry {
+    CacheEntry {
+        content: Bytes::from((0..len).map(|i| (i % 251) as u8).collect::<Vec<u8>>()),
+        meta: meta(len),
+    }
+}
+
+// 与命中缓存时返回给客户端的响应相同：状态、内容类型、长度以及重建的缓存头
+fn serve(entry: CacheEntry) -> Response<Body> {
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, entry.meta.content_type_header())
+        .header(hyper::header::CONTENT_LENGTH, entry.content.len())
+        .body(Body::from(entry.content.clone()))
+        .unwrap();
+    entry.meta.insert_cached_headers(response.headers_mut(), now_secs());
+    response
+}
+
+// 内存预算为 1 字节时任何条目都放不进内存缓存，每次读取都走磁盘
+async fn disk_only_cache(config: CacheConfig) -> (TempDir, ProxyCache) {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ProxyCache::builder()
+        .dir(dir.path())
+        .config(config)
+        .memory_bytes(1)
+        .build()
+        .await
+        .unwrap();
+    (dir, cache)
+}
+
+async fn fill(cache: &ProxyCache, len: usize) {
+    for i in 0..KEYS {
+        cache.set(format!("key-{}", i), entry(len)).await.unwrap();
+    }
+    cache.flush().await.unwrap();
+}
+
+fn memory_hits(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("memory_hit");
+    for (name, len) in SIZES {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = rt.block_on(async {
+            let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+            fill(&cache, len).await;
+            cache
+        });
+        let mut i = 0;
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                i = (i + 1) % KEYS;
+                let entry = rt.block_on(cache.get(&format!("key-{}", i))).unwrap();
+                criterion::black_box(serve(entry))
+            })
+        });
+    }
+    group.finish();
+}
+
+fn disk_hits(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("disk_hit");
+    group.sample_size(30);
+    let mut packed = CacheConfig::default();
+    packed.packing.enabled = true;
+    // 打包存储只接收小对象
+    let cases = SIZES
+        .iter()
+        .map(|&(name, len)| (name.to_string(), len, CacheConfig::default()))
+        .chain([("4KB-packed".to_string(), 4 << 10, packed)]);
+    for (name, len, config) in cases {
+        let (_dir, cache) = rt.block_on(async {
+            let (dir, cache) = disk_only_cache(config).await;
+            fill(&cache, len).await;
+            (dir, cache)
+        });
+        let mut i = 0;
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                i = (i + 1) % KEYS;
+                let entry = rt.block_on(cache.get(&format!("key-{}", i))).unwrap();
+                criterion::black_box(serve(entry))
+            })
+        });
+    }
+    group.finish();
+}
+
+fn range_slicing(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("range_slicing");
+    group.throughput(Throughput::Bytes(RANGE_LEN));
+    let offsets: Vec<u64> = (0..RANGE_OBJECT as u64 / RANGE_LEN).map(|i| i * RANGE_LEN).collect();
+
+    // 内存中的部分条目：前后两段已缓存，中间是空洞
+    let object = entry(RANGE_OBJECT);
+    let half = RANGE_OBJECT / 2;
+    let partial = CacheEntry {
+        content: Bytes::new(),
+        meta: CacheMeta {
+            is_complete: false,
+            ..object.meta.clone()
+        },
+    }
+    .merge(0, &object.content[..half - (4 << 20)], None)
+    .merge(half as u64, &object.content[half..], None);
+    let cached: Vec<u64> = offsets
+        .iter()
+        .copied()
+        .filter(|&start| partial.slice(start, start + RANGE_LEN - 1).is_some())
+        .collect();
+    let mut i = 0;
+    group.bench_function("memory_partial", |b| {
+        b.iter(|| {
+            i = (i + 1) % cached.len();
+            let start = cached[i];
+            let end = start + RANGE_LEN - 1;
+            let slice = partial.slice(start, end).unwrap();
+            criterion::black_box(partial_response(&partial.meta, start, end, slice, now_secs()).unwrap())
+        })
+    });
+
+    // 分块存储在磁盘上的大对象：只读取覆盖请求范围的块
+    let config = CacheConfig {
+        chunk_bytes: 1 << 20,
+        ..CacheConfig::default()
+    };
+    let (_dir, cache) = rt.block_on(async {
+        let (dir, cache) = disk_only_cache(config).await;
+        cache.set("video".to_string(), object.clone()).await.unwrap();
+        cache.flush().await.unwrap();
+        (dir, cache)
+    });
+    let mut i = 0;
+    group.sample_size(30);
+    group.bench_function("disk_chunks", |b| {
+        b.iter(|| {
+            i = (i + 1) % offsets.len();
+            let start = offsets[i];
+            let end = start + RANGE_LEN - 1;
+            let (meta, end, data) = rt.block_on(cache.get_range("video", start, end)).unwrap();
+            criterion::black_box(partial_response(&meta, start, end, data, now_secs()).unwrap())
+        })
+    });
+
+    // 续传补齐空洞：把一段新数据合并进已有的部分内容
+    let ranges = ByteRanges::single(0, half as u64);
+    let content = object.content.slice(..half);
+    group.bench_function("merge", |b| {
+        b.iter(|| {
+            let start = half as u64 + RANGE_LEN;
+            criterion::black_box(ranges.merge(&content, start, &object.content[half..half + RANGE_LEN as usize]))
+        })
+    });
+    group.finish();
+}
+
+// 多个连接并发访问：9 成读 1 成写，对象大小混合。
+// 内存预算放得下全部对象时只比较锁争用，预算不足时一部分读取落到磁盘
+fn concurrent_mixed(c: &mut Criterion) {
+    const TASKS: usize = 16;
+    const OPS_PER_TASK: usize = 64;
+    const MIXED_KEYS: usize = 96;
+    let rt = runtime();
+    let mut group = c.benchmark_group("concurrent_mixed");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements((TASKS * OPS_PER_TASK) as u64));
+    let entries: Arc<Vec<CacheEntry>> =
+        Arc::new((0..MIXED_KEYS).map(|i| entry(SIZES[i % SIZES.len()].1)).collect());
+    for (name, memory_bytes) in [("fits-in-memory", 1u64 << 30), ("16MB-memory", 16 << 20)] {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = rt.block_on(async {
+            let cache = ProxyCache::builder()
+                .dir(dir.path())
+                .memory_bytes(memory_bytes)
+                .build()
+                .await
+                .unwrap();
+            for (i, entry) in entries.iter().enumerate() {
+                cache.set(format!("key-{}", i), entry.clone()).await.unwrap();
+            }
+            cache.flush().await.unwrap();
+            Arc::new(cache)
+        });
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                rt.block_on(async {
+                    let tasks: Vec<_> = (0..TASKS)
+                        .map(|t| {
+                            let (cache, entries) = (cache.clone(), entries.clone());
+                            tokio::spawn(async move {
+                                for op in 0..OPS_PER_TASK {
+                                    let i = (t * 31 + op * 7) % MIXED_KEYS;
+                                    let key = format!("key-{}", i);
+                                    if op % 10 == 9 {
+                                        cache.set(key, entries[i].clone()).await.unwrap();
+                                    } else if let Some(entry) = cache.get(&key).await {
+                                        criterion::black_box(serve(entry));
+                                    }
+                                }
+                            })
+                        })
+                        .collect();
+                    for task in tasks {
+                        task.await.unwrap();
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, memory_hits, disk_hits, range_slicing, concurrent_mixed);
+criterion_main!(benches);
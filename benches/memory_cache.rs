@@ -0,0 +1,45 @@
+use std::sync::Arc;
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rust_proxy_server::cache::ShardedLru;
+
+const KEYS: usize = 64;
+const THREADS: usize = 8;
+const OPS_PER_THREAD: usize = 2_000;
+
+// 多线程并发命中：1 个分片等价于原先的单把全局锁
+fn concurrent_hits(c: &mut Criterion) {
+    let mut group = c.benchmark_group("memory_cache_concurrent_hits");
+    for shards in [1usize, 8, 32] {
+        let cache = Arc::new(ShardedLru::new(KEYS, shards));
+        for i in 0..KEYS {
+            cache.put(format!("key-{}", i), vec![0u8; 1024]);
+        }
+        let keys: Arc<Vec<String>> = Arc::new((0..KEYS).map(|i| format!("key-{}", i)).collect());
+
+        group.bench_with_input(BenchmarkId::from_parameter(shards), &shards, |b, _| {
+            b.iter(|| {
+                let handles: Vec<_> = (0..THREADS)
+                    .map(|t| {
+                        let cache = cache.clone();
+                        let keys = keys.clone();
+                        thread::spawn(move || {
+                            for i in 0..OPS_PER_THREAD {
+                                let key = &keys[(i + t) % keys.len()];
+                                criterion::black_box(cache.get(key));
+                            }
+                        })
+                    })
+                    .collect();
+                for handle in handles {
+                    handle.join().unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_hits);
+criterion_main!(benches);
@@ -0,0 +1,25 @@
+#![no_main]
+
+use hyper::header::{HeaderValue, CONTENT_RANGE};
+use hyper::HeaderMap;
+use libfuzzer_sys::fuzz_target;
+use rust_proxy_server::handler::content_range;
+use rust_proxy_server::utils::parse_range;
+
+// 客户端的 Range 与源站的 Content-Range 都是不可信输入：任意内容都不能引起 panic，
+// 解析出的区间必须有序
+fuzz_target!(|data: &[u8]| {
+    let Ok(value) = std::str::from_utf8(data) else {
+        return;
+    };
+    let _ = parse_range(value);
+
+    let Ok(value) = HeaderValue::from_str(value) else {
+        return;
+    };
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_RANGE, value);
+    if let Some((start, end, _)) = content_range(&headers) {
+        assert!(start <= end);
+    }
+});
@@ -0,0 +1,33 @@
+#![no_main]
+
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use rust_proxy_server::cache::ByteRanges;
+
+const MAX_OBJECT: u64 = 1 << 16;
+
+fn object(start: u64, end: u64) -> Vec<u8> {
+    (start..end).map(|i| (i % 251) as u8).collect()
+}
+
+// 每 4 个字节描述一次写入（起点与长度各 2 字节），依次合并进空条目。
+// 合并后的区间必须有序且互不相邻，紧凑内容与区间长度一致，每个区间取回的都是原对象的字节
+fuzz_target!(|data: &[u8]| {
+    let mut ranges = ByteRanges::default();
+    let mut content = Bytes::new();
+    for write in data.chunks_exact(4) {
+        let start = u16::from_le_bytes([write[0], write[1]]) as u64;
+        let len = (u16::from_le_bytes([write[2], write[3]]) as u64).min(MAX_OBJECT - start);
+        (ranges, content) = ranges.merge(&content, start, &object(start, start + len));
+    }
+
+    let slice = ranges.as_slice();
+    assert!(slice.iter().all(|&(s, e)| s < e));
+    assert!(slice.windows(2).all(|pair| pair[0].1 < pair[1].0));
+    assert_eq!(ranges.covered_bytes(), content.len() as u64);
+    for &(s, e) in slice {
+        assert_eq!(ranges.slice(&content, s, e).as_deref(), Some(&object(s, e)[..]));
+    }
+    let gap_bytes: u64 = ranges.gaps(MAX_OBJECT).iter().map(|(s, e)| e - s).sum();
+    assert_eq!(gap_bytes + ranges.covered_bytes(), MAX_OBJECT);
+});
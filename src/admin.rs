@@ -0,0 +1,394 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures::future::join_all;
+use hyper::{Body, Client, Method, Request, Response, StatusCode, Uri};
+use serde::Serialize;
+
+use crate::audit::{self, Caller};
+use crate::cache::ProxyCache;
+use crate::client_usage::CLIENT_USAGE;
+use crate::config::{AdminConfig, AdminRole, Config};
+use crate::constants::{PURGE_FORWARDED_HEADER, PURGE_PROPAGATION_TIMEOUT_SECONDS};
+use crate::drain::DRAIN;
+use crate::metrics::METRICS;
+use crate::recent_requests::RECENT_REQUESTS;
+use crate::rpc;
+use crate::upstream::HttpClient;
+
+// 管理接口，只在 admin.listen 上提供，经过认证后才会进入这里
+pub async fn handle_admin_request(
+    req: Request<Body>,
+    cache: Arc<ProxyCache>,
+    client: HttpClient,
+    config: Arc<Config>,
+) -> Result<Response<Body>> {
+    // 改变状态的操作都记入审计日志
+    let caller = Caller::of(&req, cache.clock().clone());
+    // 只读的调用者只能查看，/rpc 中的方法逐个检查
+    if caller.principal.role != AdminRole::Admin
+        && req.method() != Method::GET
+        && req.uri().path() != "/rpc"
+    {
+        return Ok(Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::empty())?);
+    }
+
+    // DELETE /downloads/<id>：取消下载，已读取的数据不写入缓存
+    if let Some(id) = req.uri().path().strip_prefix("/downloads/") {
+        if req.method() != Method::DELETE {
+            return Ok(Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Body::empty())?);
+        }
+        let cancelled = id.parse().map(|id| client.downloads().cancel(id)).unwrap_or(false);
+        let result = if cancelled { "ok" } else { "not found" };
+        audit::record(&caller, "downloads.cancel", &[id.to_string()], &[], result).await;
+        let status = if cancelled { StatusCode::NO_CONTENT } else { StatusCode::NOT_FOUND };
+        return Ok(Response::builder().status(status).body(Body::empty())?);
+    }
+
+    match (req.method(), req.uri().path()) {
+        // 当前生效的配置（密钥已隐去）
+        (&Method::GET, "/config") => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(hyper::header::CONTENT_TYPE, "application/toml")
+            .body(Body::from(toml::to_string_pretty(&config.redacted())?))?),
+        // 正在从源站下载的对象：已读取字节数、总大小、速度与预计剩余时间
+        (&Method::GET, "/downloads") => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(&client.downloads().list())?))?),
+        // 按源站 host（或路由）与内容类别统计的命中率，以及平均耗时
+        (&Method::GET, "/stats") => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(&METRICS.traffic_stats())?))?),
+        // 按客户端 IP 与用户名统计的最近一分钟、一小时、一天以及当天（UTC）的上下行字节数
+        (&Method::GET, "/stats/clients") => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(&CLIENT_USAGE.stats(cache.clock().now_secs()))?))?),
+        // 最近处理的请求，从新到旧；?limit=N 只返回最新的 N 个
+        (&Method::GET, "/debug/requests") => {
+            let limit = req
+                .uri()
+                .query()
+                .unwrap_or_default()
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("limit="))
+                .and_then(|limit| limit.parse().ok())
+                .unwrap_or(usize::MAX);
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_string(&RECENT_REQUESTS.list(limit))?))?)
+        }
+        // 请求体为 URL（/cache/purge）或标签（/cache/purge-tag），每行一个；?soft=1 时只标记为过期，不删除内容
+        (&Method::POST, path @ ("/cache/purge" | "/cache/purge-tag")) => {
+            let by = if path == "/cache/purge" { PurgeBy::Url } else { PurgeBy::Tag };
+            let soft = req
+                .uri()
+                .query()
+                .unwrap_or_default()
+                .split('&')
+                .any(|pair| pair == "soft=1" || pair == "soft=true");
+            let forwarded = req.headers().contains_key(PURGE_FORWARDED_HEADER);
+            let body = hyper::body::to_bytes(req.into_body()).await?;
+            let report = audited_purge(&caller, &cache, &config, by, body, soft, forwarded).await?;
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_string(&report)?))?)
+        }
+        // JSON-RPC 2.0 控制接口，供编排工具批量管理实例
+        (&Method::POST, "/rpc") => {
+            let body = hyper::body::to_bytes(req.into_body()).await?;
+            rpc::handle(&body, &caller, &cache, &client, &config).await
+        }
+        // 下线排空：POST 开始，DELETE 取消，GET 查看状态与进行中的请求数
+        (&Method::GET, "/drain") => drain_status(),
+        (&Method::POST, "/drain") => {
+            DRAIN.start(cache.clock().now_secs());
+            audit::record(&caller, "drain.start", &[], &[], "ok").await;
+            drain_status()
+        }
+        (&Method::DELETE, "/drain") => {
+            DRAIN.stop();
+            audit::record(&caller, "drain.stop", &[], &[], "ok").await;
+            drain_status()
+        }
+        // 等待写盘队列清空
+        (&Method::POST, "/cache/flush") => {
+            let result = cache.flush().await;
+            audit::record(&caller, "cache.flush", &[], &[], &audit::outcome(&result)).await;
+            result?;
+            Ok(Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Body::empty())?)
+        }
+        _ => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())?),
+    }
+}
+
+fn drain_status() -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&DRAIN.status())?))?)
+}
+
+// 按 URL 或按源站给条目打的标签（Surrogate-Key / Cache-Tag）删除
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PurgeBy {
+    Url,
+    Tag,
+}
+
+impl PurgeBy {
+    fn path(self) -> &'static str {
+        match self {
+            PurgeBy::Url => "/cache/purge",
+            PurgeBy::Tag => "/cache/purge-tag",
+        }
+    }
+
+    fn action(self, soft: bool) -> &'static str {
+        match (self, soft) {
+            (PurgeBy::Url, false) => "cache.purge",
+            (PurgeBy::Url, true) => "cache.soft_purge",
+            (PurgeBy::Tag, false) => "cache.purge_tag",
+            (PurgeBy::Tag, true) => "cache.soft_purge_tag",
+        }
+    }
+}
+
+#[derive(Default, Serialize)]
+pub struct PurgeReport {
+    purged: usize,
+    not_cached: usize,
+    invalid: Vec<String>,
+    // 按标签删除时实际删除（或标记过期）的条目数，一个标签通常对应多个条目
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entries: Option<usize>,
+    // 其他实例的地址 -> "ok" 或失败原因
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    peers: BTreeMap<String, String>,
+    // 本实例实际删除（或标记过期）的缓存键，只写入审计日志
+    #[serde(skip)]
+    keys: Vec<String>,
+}
+
+// 删除（或标记过期）每行一个的 URL 或标签，并转发给 admin.peers 中的其他实例；
+// 转发与本地删除同时进行，收到的是转发请求时不再继续转发
+pub async fn purge(
+    cache: &ProxyCache,
+    config: &Config,
+    by: PurgeBy,
+    body: Bytes,
+    soft: bool,
+    forwarded: bool,
+) -> Result<PurgeReport> {
+    let query = if soft { "?soft=1" } else { "" };
+    let propagation = async {
+        if forwarded {
+            return BTreeMap::new();
+        }
+        propagate_purge(&config.admin, &format!("{}{}", by.path(), query), body.clone()).await
+    };
+    let local = async {
+        match by {
+            PurgeBy::Url => purge_urls(cache, config, &body, soft).await,
+            PurgeBy::Tag => purge_tags(cache, &body, soft).await,
+        }
+    };
+    let (report, peers) = futures::join!(local, propagation);
+    let mut report = report?;
+    report.peers = peers;
+    Ok(report)
+}
+
+// purge 并记入审计日志：请求的 URL 或标签、实际受影响的缓存键与结果
+pub async fn audited_purge(
+    caller: &Caller,
+    cache: &ProxyCache,
+    config: &Config,
+    by: PurgeBy,
+    body: Bytes,
+    soft: bool,
+    forwarded: bool,
+) -> Result<PurgeReport> {
+    let targets = lines(&body);
+    let result = purge(cache, config, by, body, soft, forwarded).await;
+    let keys = result.as_ref().map(|report| report.keys.as_slice()).unwrap_or_default();
+    audit::record(caller, by.action(soft), &targets, keys, &audit::outcome(&result)).await;
+    result
+}
+
+fn lines(body: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(body)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// 请求体中每行一个 URL，缓存键按 URL 所属路由的规则计算，与请求时一致；
+// 按客户端类别、压缩编码分开缓存时删除所有版本的条目
+async fn purge_urls(
+    cache: &ProxyCache,
+    config: &Config,
+    body: &[u8],
+    soft: bool,
+) -> Result<PurgeReport> {
+    let mut report = PurgeReport::default();
+    for url in lines(body) {
+        let Ok(uri) = url.parse::<Uri>() else {
+            report.invalid.push(url);
+            continue;
+        };
+        let mut found = false;
+        for key in config.cache_key_variants(&uri) {
+            let removed = purge_key(cache, &key, soft).await?;
+            if removed {
+                report.keys.push(key);
+            }
+            found |= removed;
+        }
+        if found {
+            report.purged += 1;
+        } else {
+            report.not_cached += 1;
+        }
+    }
+    Ok(report)
+}
+
+// 请求体中每行一个标签，删除带有该标签的所有条目
+async fn purge_tags(cache: &ProxyCache, body: &[u8], soft: bool) -> Result<PurgeReport> {
+    let mut report = PurgeReport::default();
+    for tag in lines(body) {
+        let mut found = false;
+        for key in cache.tagged(&tag) {
+            if purge_key(cache, &key, soft).await? {
+                report.keys.push(key);
+                found = true;
+            }
+        }
+        if found {
+            report.purged += 1;
+        } else {
+            report.not_cached += 1;
+        }
+    }
+    report.entries = Some(report.keys.len());
+    Ok(report)
+}
+
+async fn purge_key(cache: &ProxyCache, key: &str, soft: bool) -> Result<bool> {
+    if soft {
+        cache.soft_purge(key).await
+    } else {
+        cache.purge(key).await
+    }
+}
+
+// 把 purge 请求原样转发给 admin.peers 中的所有实例
+async fn propagate_purge(admin: &AdminConfig, path: &str, body: Bytes) -> BTreeMap<String, String> {
+    let client = Client::new();
+    let timeout = Duration::from_secs(PURGE_PROPAGATION_TIMEOUT_SECONDS);
+    let authenticated = admin.token.is_some() || !admin.tokens.is_empty();
+    let requests = admin.peers.iter().map(|peer| {
+        let client = &client;
+        let body = body.clone();
+        async move {
+            let mut builder = Request::post(format!("http://{}{}", peer, path))
+                .header(PURGE_FORWARDED_HEADER, "1");
+            if let Some(token) = admin.peer_token() {
+                builder = builder.header(hyper::header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            let result = match builder.body(Body::from(body)) {
+                // 配置了 token 却没有可转发的 admin token，对方一定会拒绝
+                Ok(_) if authenticated && admin.peer_token().is_none() => "no admin token to forward".to_string(),
+                Ok(req) => match tokio::time::timeout(timeout, client.request(req)).await {
+                    Ok(Ok(resp)) if resp.status().is_success() => "ok".to_string(),
+                    Ok(Ok(resp)) => resp.status().to_string(),
+                    Ok(Err(e)) => e.to_string(),
+                    Err(_) => "timed out".to_string(),
+                },
+                Err(e) => e.to_string(),
+            };
+            if result != "ok" {
+                tracing::warn!("failed to propagate purge to {}: {}", peer, result);
+            }
+            (peer.clone(), result)
+        }
+    });
+    join_all(requests).await.into_iter().collect()
+}
+
+// 通过认证的管理接口调用者，作为请求扩展传给 handle_admin_request，名字记入审计日志
+#[derive(Clone, Debug)]
+pub struct Principal {
+    pub name: String,
+    pub role: AdminRole,
+}
+
+impl Principal {
+    // 未配置任何 token 时的调用者，可以执行所有操作
+    pub fn anonymous() -> Self {
+        Principal {
+            name: "anonymous".to_string(),
+            role: AdminRole::Admin,
+        }
+    }
+}
+
+// Authorization: Bearer <token>，与 admin.token 及 admin.tokens 逐个比较；未配置任何 token 时不检查
+pub fn authenticate(req: &Request<Body>, admin: &AdminConfig) -> Option<Principal> {
+    if admin.token.is_none() && admin.tokens.is_empty() {
+        return Some(Principal::anonymous());
+    }
+    let given = req
+        .headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))?;
+    let legacy = admin.token.iter().map(|token| ("token", token.as_str(), AdminRole::Admin));
+    let named = admin
+        .tokens
+        .iter()
+        .map(|token| (token.name.as_str(), token.token.as_str(), token.role));
+    // 比较所有 token 后再返回，耗时与匹配的是哪一个无关
+    legacy.chain(named).fold(None, |found, (name, token, role)| {
+        let matched = constant_time_eq(given.as_bytes(), token.as_bytes());
+        found.or(matched.then(|| Principal {
+            name: name.to_string(),
+            role,
+        }))
+    })
+}
+
+// metrics.require_token 时 /metrics 需要任一角色的 token
+pub fn metrics_authorized(req: &Request<Body>, config: &Config) -> bool {
+    !config.metrics.require_token || req.uri().path() != "/metrics" || authenticate(req, &config.admin).is_some()
+}
+
+pub fn unauthorized() -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header(hyper::header::WWW_AUTHENTICATE, "Bearer")
+        .body(Body::empty())?)
+}
+
+// 比较耗时与不匹配的位置无关，避免通过响应时间猜出 token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
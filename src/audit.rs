@@ -0,0 +1,113 @@
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+use hyper::{Body, Request};
+use serde::Serialize;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::admin::Principal;
+use crate::clock::SharedClock;
+use crate::config::AuditLogConfig;
+use crate::listener::ClientAddr;
+
+// 管理操作的审计日志：清除、取消下载、排空等改变状态的操作以 JSON 行追加到单独的文件。
+// 与决策日志不同，每条记录都写入并落盘后才返回，不会因为队列满而丢弃
+static AUDIT_LOG: OnceLock<Mutex<File>> = OnceLock::new();
+
+// 发起管理操作的一方：认证得到的身份与客户端地址；记录时间取自缓存使用的时钟
+#[derive(Clone)]
+pub struct Caller {
+    pub principal: Principal,
+    pub client: Option<IpAddr>,
+    pub clock: SharedClock,
+}
+
+impl Caller {
+    pub fn of(req: &Request<Body>, clock: SharedClock) -> Self {
+        Caller {
+            // 没有经过认证（库的使用者直接调用）时与未配置 token 相同
+            principal: req
+                .extensions()
+                .get::<Principal>()
+                .cloned()
+                .unwrap_or_else(Principal::anonymous),
+            client: req.extensions().get::<ClientAddr>().map(|ClientAddr(addr)| addr.ip()),
+            clock,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    time: u64,
+    principal: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client: Option<IpAddr>,
+    // cache.purge / cache.flush / downloads.cancel / drain.start / drain.stop
+    action: &'a str,
+    // 操作的对象：URL、下载 ID 等
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    targets: &'a [String],
+    // 实际受影响的缓存键
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    keys: &'a [String],
+    // ok 或失败原因
+    result: &'a str,
+}
+
+pub async fn init(config: &AuditLogConfig) -> Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+    let file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&config.path)
+        .await
+        .with_context(|| format!("failed to open audit log {}", config.path.display()))?;
+    let _ = AUDIT_LOG.set(Mutex::new(file));
+    Ok(())
+}
+
+// 记录一次管理操作；写入失败只记录运行日志，操作本身已经完成
+pub async fn record(caller: &Caller, action: &str, targets: &[String], keys: &[String], result: &str) {
+    let Some(log) = AUDIT_LOG.get() else {
+        return;
+    };
+    let record = AuditRecord {
+        time: caller.clock.now_secs(),
+        principal: &caller.principal.name,
+        client: caller.client,
+        action,
+        targets,
+        keys,
+        result,
+    };
+    let mut line = match serde_json::to_string(&record) {
+        Ok(line) => line,
+        Err(e) => {
+            tracing::error!("failed to encode audit record: {}", e);
+            return;
+        }
+    };
+    line.push('\n');
+    let mut file = log.lock().await;
+    let written = match file.write_all(line.as_bytes()).await {
+        Ok(()) => file.sync_data().await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = written {
+        tracing::error!("failed to write audit log: {} ({})", e, line.trim_end());
+    }
+}
+
+// 审计记录中的结果：ok 或错误信息
+pub fn outcome<T>(result: &Result<T>) -> String {
+    match result {
+        Ok(_) => "ok".to_string(),
+        Err(e) => format!("{:#}", e),
+    }
+}
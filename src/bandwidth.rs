@@ -0,0 +1,122 @@
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::pin::Pin;
+use std::sync::{LazyLock, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::Instant;
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use hyper::body::HttpBody;
+use hyper::{Body, Response};
+use lru::LruCache;
+
+use crate::config::ReadAheadConfig;
+use crate::constants::{
+    CLIENT_BANDWIDTH_CHUNK_BYTES, CLIENT_BANDWIDTH_EWMA_ALPHA, CLIENT_BANDWIDTH_MIN_SAMPLE_BYTES,
+    CLIENT_BANDWIDTH_TRACKED,
+};
+
+// 按客户端 IP 记录的下行带宽（字节/秒，指数加权移动平均）
+pub struct ClientBandwidth {
+    clients: Mutex<LruCache<IpAddr, f64>>,
+}
+
+pub static CLIENT_BANDWIDTH: LazyLock<ClientBandwidth> = LazyLock::new(|| ClientBandwidth {
+    clients: Mutex::new(LruCache::new(
+        NonZeroUsize::new(CLIENT_BANDWIDTH_TRACKED).unwrap(),
+    )),
+});
+
+impl ClientBandwidth {
+    pub fn observe(&self, ip: IpAddr, bytes: u64, secs: f64) {
+        if bytes < CLIENT_BANDWIDTH_MIN_SAMPLE_BYTES || secs <= 0.0 {
+            return;
+        }
+        let sample = bytes as f64 / secs;
+        let mut clients = self.clients.lock().unwrap();
+        let rate = match clients.get(&ip) {
+            Some(prev) => prev + CLIENT_BANDWIDTH_EWMA_ALPHA * (sample - prev),
+            None => sample,
+        };
+        clients.put(ip, rate);
+    }
+
+    pub fn estimate(&self, ip: IpAddr) -> Option<f64> {
+        self.clients.lock().unwrap().peek(&ip).copied()
+    }
+
+    // 预读窗口：客户端 window_secs 秒内能取走的数据量，未测量过的客户端使用最小窗口
+    pub fn read_ahead_bytes(&self, ip: IpAddr, config: &ReadAheadConfig) -> u64 {
+        if !config.enabled {
+            return 0;
+        }
+        let window = self
+            .estimate(ip)
+            .map(|rate| (rate * config.window_secs) as u64)
+            .unwrap_or(config.min_bytes);
+        window.clamp(config.min_bytes, config.max_bytes.max(config.min_bytes))
+    }
+}
+
+// 包装返回给客户端的响应体，从开始发送到发送完毕计算实际下行速率。
+// hyper 按套接字可写的速度读取响应体，慢速客户端会让读取变慢
+pub fn measure(response: Response<Body>, ip: IpAddr) -> Response<Body> {
+    // 204、304 与 HEAD 等没有响应体的响应原样返回，不能补上 Content-Length: 0
+    if response.body().is_end_stream() {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    // 包装成流后 hyper 不再知道长度，保留原来的 Content-Length
+    if let Some(len) = HttpBody::size_hint(&body).exact() {
+        parts
+            .headers
+            .entry(hyper::header::CONTENT_LENGTH)
+            .or_insert_with(|| len.into());
+    }
+    let body = Body::wrap_stream(MeteredBody {
+        inner: body,
+        ip,
+        pending: Bytes::new(),
+        started: None,
+        bytes: 0,
+    });
+    Response::from_parts(parts, body)
+}
+
+struct MeteredBody {
+    inner: Body,
+    ip: IpAddr,
+    // 缓存命中时整个对象是一个块，hyper 会一次取走并立即读到结尾，
+    // 切成小块后读取速度才跟随套接字的发送速度
+    pending: Bytes,
+    started: Option<Instant>,
+    bytes: u64,
+}
+
+impl Stream for MeteredBody {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.started.get_or_insert_with(Instant::now);
+        if self.pending.is_empty() {
+            match ready!(self.inner.poll_next_unpin(cx)) {
+                Some(Ok(chunk)) => self.pending = chunk,
+                other => return Poll::Ready(other),
+            }
+        }
+        let len = self.pending.len().min(CLIENT_BANDWIDTH_CHUNK_BYTES);
+        let chunk = self.pending.split_to(len);
+        self.bytes += chunk.len() as u64;
+        Poll::Ready(Some(Ok(chunk)))
+    }
+}
+
+// 发送完毕或客户端中途断开时 hyper 都会丢弃响应体，在这里记录样本
+impl Drop for MeteredBody {
+    fn drop(&mut self) {
+        if let Some(started) = self.started {
+            CLIENT_BANDWIDTH.observe(self.ip, self.bytes, started.elapsed().as_secs_f64());
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+use crate::config::MemoryAdmissionConfig;
+use crate::constants::{ADMISSION_SKETCH_DEPTH, ADMISSION_SKETCH_WIDTH};
+
+// 内存缓存的准入（TinyLFU）：用 count-min sketch 近似记录每个键的读取次数，
+// 累计读取次数达到计数器总数的 10 倍后全部减半，使频率反映近期的访问
+pub struct Admission {
+    max_object_bytes: u64,
+    min_hits: u8,
+    sketch: Mutex<Sketch>,
+}
+
+struct Sketch {
+    // 每行 ADMISSION_SKETCH_WIDTH 个 4 位计数器，存放在 u8 中
+    rows: Vec<Vec<u8>>,
+    additions: usize,
+}
+
+const MAX_COUNT: u8 = 15;
+
+impl Sketch {
+    fn new() -> Self {
+        Sketch {
+            rows: vec![vec![0; ADMISSION_SKETCH_WIDTH]; ADMISSION_SKETCH_DEPTH],
+            additions: 0,
+        }
+    }
+
+    // 增加一次计数，返回增加后的估计值（各行中的最小值）
+    fn increment(&mut self, key: &str) -> u8 {
+        let mut estimate = MAX_COUNT;
+        for (seed, row) in self.rows.iter_mut().enumerate() {
+            let counter = &mut row[index(seed, key)];
+            if *counter < MAX_COUNT {
+                *counter += 1;
+            }
+            estimate = estimate.min(*counter);
+        }
+        self.additions += 1;
+        if self.additions >= ADMISSION_SKETCH_WIDTH * 10 {
+            self.age();
+        }
+        estimate
+    }
+
+    fn age(&mut self) {
+        for counter in self.rows.iter_mut().flatten() {
+            *counter /= 2;
+        }
+        self.additions /= 2;
+    }
+}
+
+fn index(seed: usize, key: &str) -> usize {
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    key.hash(&mut hasher);
+    hasher.finish() as usize % ADMISSION_SKETCH_WIDTH
+}
+
+impl Admission {
+    pub fn new(config: &MemoryAdmissionConfig) -> Self {
+        Admission {
+            max_object_bytes: config.max_object_bytes,
+            min_hits: config.min_hits,
+            sketch: Mutex::new(Sketch::new()),
+        }
+    }
+
+    // 每次从磁盘（或打包存储）读取条目时调用：记录一次读取，返回是否放入内存缓存
+    pub fn admit(&self, key: &str, len: u64) -> bool {
+        let hits = self.sketch.lock().unwrap().increment(key);
+        len <= self.max_object_bytes || hits >= self.min_hits
+    }
+}
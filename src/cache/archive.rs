@@ -0,0 +1,123 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use super::generations::meta_path;
+use super::inspect::{self, EntryInfo};
+use super::packs::Packs;
+use super::{chunks, now_secs};
+use crate::cache_key::is_valid_key;
+use crate::config::PackingConfig;
+
+// 缓存归档（tar.gz）：每个条目包含内容文件与 .meta，
+// 用于把预热好的缓存带到离线或边缘站点导入
+
+pub fn export(
+    dir: &Path,
+    file: &Path,
+    filter: impl Fn(&EntryInfo) -> bool,
+) -> Result<usize> {
+    let out = File::create(file).with_context(|| format!("failed to create {}", file.display()))?;
+    let mut tar = tar::Builder::new(GzEncoder::new(out, Compression::default()));
+    let mut exported = 0;
+    for entry in inspect::list(dir)? {
+        // 没有元数据的条目无法在目标站点使用
+        if entry.meta.is_none() || !filter(&entry) {
+            continue;
+        }
+        // 打包条目导出为普通的内容文件与 .meta，导入后按文件存放
+        if entry.packed.is_some() {
+            let meta = serde_json::to_vec(entry.meta.as_ref().unwrap())?;
+            append_bytes(&mut tar, &entry.key, &entry.read_content(dir)?)?;
+            append_bytes(&mut tar, &format!("{}.meta", entry.key), &meta)?;
+            exported += 1;
+            continue;
+        }
+        let path = entry.path(dir);
+        if entry.chunks.is_some() {
+            // 分块条目按 <内容名>/<块序号> 逐块写入
+            for chunk in chunks::chunk_files(&path)? {
+                let name = chunk.file_name().unwrap().to_string_lossy().into_owned();
+                tar.append_path_with_name(&chunk, format!("{}/{}", entry.name, name))?;
+            }
+        } else {
+            tar.append_path_with_name(&path, &entry.name)?;
+        }
+        tar.append_path_with_name(meta_path(dir, &entry.key), format!("{}.meta", entry.key))?;
+        exported += 1;
+    }
+    tar.into_inner()?.finish()?;
+    Ok(exported)
+}
+
+fn append_bytes(tar: &mut tar::Builder<impl Write>, name: &str, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(now_secs());
+    header.set_cksum();
+    tar.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+// 导入归档；只接受缓存键形式的文件名，防止归档写到缓存目录之外
+pub fn import(dir: &Path, file: &Path, overwrite: bool) -> Result<usize> {
+    std::fs::create_dir_all(dir)?;
+    let input = File::open(file).with_context(|| format!("failed to open {}", file.display()))?;
+    let mut archive = tar::Archive::new(GzDecoder::new(input));
+    let packs = Packs::open(dir, &PackingConfig::default())?;
+    let mut imported = 0;
+    for item in archive.entries()? {
+        let mut item = item?;
+        let name = item.path()?.to_string_lossy().into_owned();
+        // 内容为 <key>[.<代号>]，分块条目为 <key>[.<代号>]/<块序号>，元数据为 <key>.meta；
+        // 按目录存放的键本身含有 /，只有上一级带代号后缀时最后一段才是块序号
+        let (content, chunk) = match name.rsplit_once('/') {
+            Some((content, chunk))
+                if content.rsplit('/').next().is_some_and(|last| last.contains('.')) =>
+            {
+                (content, Some(chunk))
+            }
+            _ => (name.as_str(), None),
+        };
+        let (key, suffix) = content.split_once('.').unwrap_or((content, ""));
+        let is_meta = suffix == "meta" && chunk.is_none();
+        let digits = |s: &str, len: Option<usize>| {
+            !s.is_empty()
+                && s.bytes().all(|b| b.is_ascii_digit())
+                && len.map(|len| s.len() == len).unwrap_or(true)
+        };
+        let valid = is_valid_key(key)
+            && (suffix.is_empty() || is_meta || digits(suffix, None))
+            && chunk.map(|c| digits(c, Some(6))).unwrap_or(true);
+        if !valid {
+            bail!("unexpected file {} in cache archive", name);
+        }
+        // 已有条目时跳过该条目的所有文件
+        let packed = packs.contains(key);
+        if !overwrite && (packed || meta_path(dir, key).exists()) {
+            continue;
+        }
+        // 覆盖打包存储的条目：作废打包记录，之后按文件存放
+        if packed {
+            packs.remove(key)?;
+        }
+        let target = dir.join(&name);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // 先写临时文件再 rename，与运行时写盘方式一致
+        let tmp = dir.join(format!("{}.tmp", name));
+        item.unpack(&tmp)?;
+        std::fs::rename(&tmp, &target)?;
+        if !is_meta && chunk.is_none_or(|c| c == "000001") {
+            imported += 1;
+        }
+    }
+    Ok(imported)
+}
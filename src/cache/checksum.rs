@@ -0,0 +1,49 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper::header::HeaderMap;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+pub fn sha256_hex(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+// 校验源站通过 Content-MD5 / Digest 头声明的摘要
+// 没有可识别的摘要头时返回 None
+pub fn verify_origin_digest(headers: &HeaderMap, content: &[u8]) -> Option<bool> {
+    let mut checked = None;
+
+    if let Some(expected) = headers
+        .get("content-md5")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| STANDARD.decode(v.trim()).ok())
+    {
+        if Md5::digest(content).as_slice() != expected.as_slice() {
+            return Some(false);
+        }
+        checked = Some(true);
+    }
+
+    // Digest: sha-256=<base64>, md5=<base64>（RFC 3230）
+    if let Some(digest) = headers.get("digest").and_then(|v| v.to_str().ok()) {
+        for item in digest.split(',') {
+            let Some((algorithm, value)) = item.trim().split_once('=') else {
+                continue;
+            };
+            let Ok(expected) = STANDARD.decode(value.trim()) else {
+                continue;
+            };
+            let actual = match algorithm.to_ascii_lowercase().as_str() {
+                "sha-256" => Sha256::digest(content).to_vec(),
+                "md5" => Md5::digest(content).to_vec(),
+                _ => continue,
+            };
+            if actual != expected {
+                return Some(false);
+            }
+            checked = Some(true);
+        }
+    }
+
+    checked
+}
@@ -0,0 +1,123 @@
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use bytes::{Bytes, BytesMut};
+
+// 大对象分块存储：<key>/000001、<key>/000002 …，除最后一块外每块大小相同。
+// 追加数据时只重写内容发生变化的块，范围读取只打开覆盖该范围的块
+
+fn chunk_name(index: usize) -> String {
+    format!("{:06}", index + 1)
+}
+
+fn is_chunk_name(name: &str) -> bool {
+    name.len() == 6 && name.bytes().all(|b| b.is_ascii_digit())
+}
+
+// 按序号排列的块文件
+pub(crate) fn chunk_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for item in fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))? {
+        let path = item?.path();
+        if path.file_name().and_then(|n| n.to_str()).map(is_chunk_name).unwrap_or(false) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+pub(crate) fn chunked_size(dir: &Path) -> Result<u64> {
+    let mut size = 0;
+    for path in chunk_files(dir)? {
+        size += fs::metadata(path)?.len();
+    }
+    Ok(size)
+}
+
+// previous 为上一代的块目录，内容未变化的块（例如续传只追加了尾部）直接硬链接，不重写
+pub(crate) fn write_chunks(
+    dir: &Path,
+    content: &[u8],
+    chunk_bytes: u64,
+    previous: Option<&Path>,
+) -> Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let chunks: Vec<&[u8]> = content.chunks(chunk_bytes as usize).collect();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let path = dir.join(chunk_name(index));
+        if let Some(old) = previous.map(|previous| previous.join(chunk_name(index))) {
+            let unchanged = fs::metadata(&old)
+                .map(|m| m.len() == chunk.len() as u64)
+                .unwrap_or(false)
+                && fs::read(&old).map(|old| old == *chunk).unwrap_or(false);
+            if unchanged {
+                let _ = fs::remove_file(&path);
+                if fs::hard_link(&old, &path).is_ok() {
+                    continue;
+                }
+            }
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, chunk)?;
+        fs::rename(&tmp, &path)?;
+    }
+
+    // 对象变小后多余的块
+    for path in chunk_files(dir)?.into_iter().skip(chunks.len()) {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn read_chunks(dir: &Path) -> Result<Bytes> {
+    let files = chunk_files(dir)?;
+    let mut content = BytesMut::new();
+    for path in files {
+        content.extend_from_slice(&fs::read(path)?);
+    }
+    Ok(content.freeze())
+}
+
+// 读取紧凑内容中 [offset, offset + len) 的字节
+pub(crate) fn read_range(dir: &Path, offset: u64, len: u64) -> Result<Bytes> {
+    let files = chunk_files(dir)?;
+    let Some(first) = files.first() else {
+        bail!("no chunks in {}", dir.display());
+    };
+    let chunk_bytes = fs::metadata(first)?.len();
+    if chunk_bytes == 0 {
+        bail!("empty chunk in {}", dir.display());
+    }
+
+    let mut data = Vec::with_capacity(len as usize);
+    let mut pos = offset;
+    let end = offset + len;
+    while pos < end {
+        let path = files
+            .get((pos / chunk_bytes) as usize)
+            .with_context(|| format!("missing chunk in {}", dir.display()))?;
+        let within = pos % chunk_bytes;
+        let take = (chunk_bytes - within).min(end - pos);
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(within))?;
+        if file.take(take).read_to_end(&mut data)? as u64 != take {
+            bail!("chunk {} is truncated", path.display());
+        }
+        pos += take;
+    }
+    Ok(Bytes::from(data))
+}
+
+// 只保留前 keep 个块，返回释放的字节数
+pub(crate) fn truncate_chunks(dir: &Path, keep: usize) -> Result<u64> {
+    let mut freed = 0;
+    for path in chunk_files(dir)?.into_iter().skip(keep) {
+        freed += fs::metadata(&path)?.len();
+        fs::remove_file(path)?;
+    }
+    Ok(freed)
+}
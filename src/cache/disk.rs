@@ -0,0 +1,47 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+
+// 写盘任务持久化内容文件与 .meta 所用的文件系统操作。
+// 测试中换成 FaultyDisk（testing 特性）即可模拟磁盘已满、IO 错误与写了一半的文件，
+// 检查缓存退化为只用内存、客户端照常拿到响应。分块存储与打包存储的写入不经过这里
+pub trait Disk: Send + Sync {
+    fn write(&self, path: PathBuf, data: Bytes) -> BoxFuture<'static, io::Result<()>>;
+
+    fn rename(&self, from: PathBuf, to: PathBuf) -> BoxFuture<'static, io::Result<()>>;
+
+    fn create_dir_all(&self, path: PathBuf) -> BoxFuture<'static, io::Result<()>>;
+}
+
+pub type SharedDisk = Arc<dyn Disk>;
+
+// 本地文件系统；启用 uring 特性时内容经 io_uring 写入
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LocalDisk;
+
+impl Disk for LocalDisk {
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    fn write(&self, path: PathBuf, data: Bytes) -> BoxFuture<'static, io::Result<()>> {
+        Box::pin(super::uring::write(path, data))
+    }
+
+    #[cfg(not(all(feature = "uring", target_os = "linux")))]
+    fn write(&self, path: PathBuf, data: Bytes) -> BoxFuture<'static, io::Result<()>> {
+        Box::pin(tokio::fs::write(path, data))
+    }
+
+    fn rename(&self, from: PathBuf, to: PathBuf) -> BoxFuture<'static, io::Result<()>> {
+        Box::pin(tokio::fs::rename(from, to))
+    }
+
+    fn create_dir_all(&self, path: PathBuf) -> BoxFuture<'static, io::Result<()>> {
+        Box::pin(tokio::fs::create_dir_all(path))
+    }
+}
+
+pub fn local() -> SharedDisk {
+    Arc::new(LocalDisk)
+}
@@ -0,0 +1,77 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+
+use super::disk::{Disk, LocalDisk};
+
+// 注入的故障，只在测试中使用（需要开启 testing 特性）
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    // 写入与建目录返回磁盘已满（ENOSPC）
+    NoSpace,
+    // 所有操作返回 IO 错误（EIO）
+    Io,
+    // 只写入前一半数据，然后返回 IO 错误
+    PartialWrite,
+}
+
+// 按注入的故障让操作失败的本地文件系统，未注入故障时与 LocalDisk 相同
+#[derive(Default)]
+pub struct FaultyDisk {
+    fault: Mutex<Option<Fault>>,
+}
+
+impl FaultyDisk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 之后的操作按 fault 失败，None 表示恢复正常
+    pub fn inject(&self, fault: Option<Fault>) {
+        *self.fault.lock().unwrap() = fault;
+    }
+
+    fn fault(&self) -> Option<Fault> {
+        *self.fault.lock().unwrap()
+    }
+}
+
+fn no_space() -> io::Error {
+    io::Error::from(io::ErrorKind::StorageFull)
+}
+
+fn io_error() -> io::Error {
+    io::Error::other("injected I/O error")
+}
+
+impl Disk for FaultyDisk {
+    fn write(&self, path: PathBuf, data: Bytes) -> BoxFuture<'static, io::Result<()>> {
+        match self.fault() {
+            None => LocalDisk.write(path, data),
+            Some(Fault::NoSpace) => Box::pin(async { Err(no_space()) }),
+            Some(Fault::Io) => Box::pin(async { Err(io_error()) }),
+            Some(Fault::PartialWrite) => Box::pin(async move {
+                tokio::fs::write(&path, data.slice(..data.len() / 2)).await?;
+                Err(io_error())
+            }),
+        }
+    }
+
+    fn rename(&self, from: PathBuf, to: PathBuf) -> BoxFuture<'static, io::Result<()>> {
+        match self.fault() {
+            Some(Fault::Io) => Box::pin(async { Err(io_error()) }),
+            _ => LocalDisk.rename(from, to),
+        }
+    }
+
+    fn create_dir_all(&self, path: PathBuf) -> BoxFuture<'static, io::Result<()>> {
+        match self.fault() {
+            Some(Fault::Io) => Box::pin(async { Err(io_error()) }),
+            Some(Fault::NoSpace) if !Path::new(&path).is_dir() => Box::pin(async { Err(no_space()) }),
+            _ => LocalDisk.create_dir_all(path),
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::header::{GetAll, HeaderValue, CACHE_CONTROL, DATE, EXPIRES, LAST_MODIFIED};
+use hyper::HeaderMap;
+
+use crate::config::CachePolicy;
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn header_time(headers: &HeaderMap, name: hyper::header::HeaderName) -> Option<u64> {
+    let value = headers.get(name)?.to_str().ok()?;
+    let time = httpdate::parse_http_date(value).ok()?;
+    time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+}
+
+// 源站专门给代理的缓存头，不转发给客户端
+const X_ACCEL_EXPIRES: &str = "x-accel-expires";
+const SURROGATE_CONTROL: &str = "surrogate-control";
+
+// 读取 Cache-Control 指令的值，例如 max-age=60
+fn cache_control_value(headers: &HeaderMap, directive: &str) -> Option<u64> {
+    directive_value(headers.get_all(CACHE_CONTROL), directive)
+}
+
+fn has_directive(headers: &HeaderMap, directive: &str) -> bool {
+    directive_present(headers.get_all(CACHE_CONTROL), directive)
+}
+
+// Cache-Control 与 Surrogate-Control 的格式相同：逗号分隔的指令，可以带 =值
+fn directive_value(values: GetAll<'_, HeaderValue>, directive: &str) -> Option<u64> {
+    values
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|d| d.trim().split_once('='))
+        .find(|(name, _)| name.eq_ignore_ascii_case(directive))
+        .and_then(|(_, value)| value.trim_matches('"').parse().ok())
+}
+
+fn directive_present(values: GetAll<'_, HeaderValue>, directive: &str) -> bool {
+    values
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|d| d.trim().eq_ignore_ascii_case(directive))
+}
+
+// 源站给代理的新鲜期：X-Accel-Expires（秒数，或 @ 加 Unix 时间）优先，其次是 Surrogate-Control 的 max-age
+pub fn surrogate_lifetime(headers: &HeaderMap, now: u64) -> Option<u64> {
+    let accel = headers.get(X_ACCEL_EXPIRES).and_then(|v| v.to_str().ok()).and_then(|value| {
+        match value.trim().strip_prefix('@') {
+            Some(time) => time.parse::<u64>().ok().map(|time| time.saturating_sub(now)),
+            None => value.trim().parse().ok(),
+        }
+    });
+    accel.or_else(|| directive_value(headers.get_all(SURROGATE_CONTROL), "max-age"))
+}
+
+// 源站要求代理不保存：X-Accel-Expires: 0（nginx 的约定）或 Surrogate-Control: no-store
+pub fn surrogate_no_store(headers: &HeaderMap) -> bool {
+    let accel_off = headers
+        .get(X_ACCEL_EXPIRES)
+        .is_some_and(|v| v.to_str().is_ok_and(|value| value.trim() == "0"));
+    accel_off || directive_present(headers.get_all(SURROGATE_CONTROL), "no-store")
+}
+
+// 发给客户端之前去掉，客户端与下游缓存只看到 Cache-Control
+pub fn strip_surrogate_headers(headers: &mut HeaderMap) {
+    headers.remove(X_ACCEL_EXPIRES);
+    headers.remove(SURROGATE_CONTROL);
+}
+
+// 源站明确允许共享缓存存储带凭据请求的响应
+pub fn shared_cacheable(headers: &HeaderMap) -> bool {
+    has_directive(headers, "public")
+        || has_directive(headers, "must-revalidate")
+        || cache_control_value(headers, "s-maxage").is_some()
+}
+
+// 响应的新鲜期（秒），路由强制了上下限时按其截断。
+// 返回 None 表示无法确定新鲜期，条目不会过期
+pub fn lifetime(headers: &HeaderMap, policy: &CachePolicy, now: u64) -> Option<u64> {
+    let secs = origin_lifetime(headers, policy, now);
+    let secs = match policy.min_ttl_secs {
+        Some(min) => secs.map(|secs| secs.max(min)),
+        None => secs,
+    };
+    match policy.max_ttl_secs {
+        Some(max) => Some(secs.map_or(max, |secs| secs.min(max))),
+        None => secs,
+    }
+}
+
+// 源站缓存头给出的新鲜期，按 RFC 7234 4.2.1 / 4.2.2：
+// s-maxage > max-age > Expires > 启发式（Last-Modified 距今时长的一定比例）；
+// 源站给代理的新鲜期优先于这些给客户端的头
+fn origin_lifetime(headers: &HeaderMap, policy: &CachePolicy, now: u64) -> Option<u64> {
+    if policy.surrogate_headers {
+        if let Some(secs) = surrogate_lifetime(headers, now) {
+            return Some(secs);
+        }
+    }
+    if has_directive(headers, "no-cache") {
+        return Some(0);
+    }
+    if let Some(secs) = cache_control_value(headers, "s-maxage") {
+        return Some(secs);
+    }
+    if let Some(secs) = cache_control_value(headers, "max-age") {
+        return Some(secs);
+    }
+    let date = header_time(headers, DATE).unwrap_or(now);
+    if headers.contains_key(EXPIRES) {
+        // 无法解析的 Expires 视为已过期
+        return Some(
+            header_time(headers, EXPIRES)
+                .map(|expires| expires.saturating_sub(date))
+                .unwrap_or(0),
+        );
+    }
+    let last_modified = header_time(headers, LAST_MODIFIED)?;
+    let heuristic = (date.saturating_sub(last_modified) as f64 * policy.heuristic_fraction) as u64;
+    Some(heuristic.min(policy.heuristic_max_secs))
+}
@@ -0,0 +1,100 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// 内容文件按代存放（<key>.<generation>），.meta 中的 generation 指向当前代。
+// 写入新代后原子替换 .meta，旧代等正在读取的请求结束后再删除，读者不会看到写了一半的文件
+
+// 条目的元数据 <key>.meta。键中可以有 /（按目录存放），不能有点
+pub(crate) fn meta_path(dir: &Path, key: &str) -> PathBuf {
+    dir.join(format!("{}.meta", key))
+}
+
+// 当前代的内容路径；旧版本条目没有代号，内容直接存放在 <key>
+pub(crate) fn content_path(dir: &Path, key: &str, generation: Option<u64>) -> PathBuf {
+    match generation {
+        Some(generation) => dir.join(format!("{}.{}", key, generation)),
+        None => dir.join(key),
+    }
+}
+
+#[derive(Default)]
+struct State {
+    // 路径 -> 正在读取的请求数
+    readers: HashMap<PathBuf, usize>,
+    // 已被新代替换、等待读者结束后删除的路径
+    retired: HashSet<PathBuf>,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Generations {
+    state: Arc<Mutex<State>>,
+}
+
+impl Generations {
+    // 读取期间持有，防止内容被删除
+    pub(crate) fn acquire(&self, path: &Path) -> ReaderGuard {
+        *self
+            .state
+            .lock()
+            .unwrap()
+            .readers
+            .entry(path.to_path_buf())
+            .or_default() += 1;
+        ReaderGuard {
+            generations: self.clone(),
+            path: path.to_path_buf(),
+        }
+    }
+
+    // 旧代没有读者时立即删除，否则由最后一个读者删除
+    pub(crate) fn retire(&self, path: PathBuf) {
+        let mut state = self.state.lock().unwrap();
+        if state.readers.contains_key(&path) {
+            state.retired.insert(path);
+        } else {
+            drop(state);
+            remove_content(&path);
+        }
+    }
+
+    fn release(&self, path: &Path) {
+        let mut state = self.state.lock().unwrap();
+        let Some(count) = state.readers.get_mut(path) else {
+            return;
+        };
+        *count -= 1;
+        if *count > 0 {
+            return;
+        }
+        state.readers.remove(path);
+        if state.retired.remove(path) {
+            drop(state);
+            remove_content(path);
+        }
+    }
+}
+
+pub(crate) struct ReaderGuard {
+    generations: Generations,
+    path: PathBuf,
+}
+
+impl Drop for ReaderGuard {
+    fn drop(&mut self) {
+        self.generations.release(&self.path);
+    }
+}
+
+fn remove_content(path: &Path) {
+    let result = if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    };
+    if let Err(e) = result {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            tracing::warn!("failed to remove old generation {}: {}", path.display(), e);
+        }
+    }
+}
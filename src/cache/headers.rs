@@ -0,0 +1,86 @@
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, AGE, CONNECTION};
+
+// 写入缓存的源站响应头，命中时原样带回给客户端
+
+// 逐跳头与命中时重新计算的头不保存；Set-Cookie 属于单个用户，不能在共享缓存中复用。
+// Age 保留源站的值，命中时再加上在本地缓存中停留的时间
+const SKIPPED: &[&str] = &[
+    "connection",
+    "content-length",
+    "content-range",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-connection",
+    "set-cookie",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+// Connection 头列出的字段同样是逐跳的（RFC 9110 7.6.1）
+fn connection_options(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|option| option.trim().to_ascii_lowercase())
+        .filter(|option| !option.is_empty())
+        .collect()
+}
+
+pub fn capture_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    let options = connection_options(headers);
+    headers
+        .iter()
+        .filter(|(name, _)| {
+            !SKIPPED.contains(&name.as_str()) && !options.iter().any(|o| o == name.as_str())
+        })
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect()
+}
+
+// 304 携带的头替换已保存的同名头（RFC 9111 4.3.4），替换后的值留在原来的位置。
+// Age 描述的是旧响应，304 没有带 Age 时一并去掉
+pub fn merge_headers(saved: &mut Vec<(String, String)>, headers: &HeaderMap) {
+    let updated = capture_headers(headers);
+    let mut merged = Vec::with_capacity(saved.len() + updated.len());
+    let mut placed: Vec<String> = Vec::new();
+    for (name, value) in saved.drain(..) {
+        if updated.iter().any(|(n, _)| *n == name) {
+            if !placed.contains(&name) {
+                merged.extend(updated.iter().filter(|(n, _)| *n == name).cloned());
+                placed.push(name);
+            }
+        } else if name != AGE.as_str() {
+            merged.push((name, value));
+        }
+    }
+    for (name, value) in &updated {
+        if !placed.contains(name) {
+            merged.push((name.clone(), value.clone()));
+        }
+    }
+    *saved = merged;
+}
+
+// 按源站的顺序写出保存的头，再补上响应中已有而源站没有的头（如 Content-Length）
+pub fn restore_headers(saved: &[(String, String)], headers: &mut HeaderMap) {
+    let mut restored = HeaderMap::with_capacity(saved.len() + headers.len());
+    for (name, value) in saved {
+        if let (Ok(name), Ok(value)) =
+            (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value))
+        {
+            restored.append(name, value);
+        }
+    }
+    for name in headers.keys() {
+        if !restored.contains_key(name) {
+            for value in headers.get_all(name) {
+                restored.append(name.clone(), value.clone());
+            }
+        }
+    }
+    *headers = restored;
+}
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::watch;
+
+// 同一个键上正在进行的操作：第一个到达的请求执行，同时到达的其他请求等待它公布的结果
+pub struct InFlight<T> {
+    pending: Arc<Mutex<HashMap<String, watch::Receiver<Option<T>>>>>,
+}
+
+pub enum Joined<T> {
+    Leader(Leader<T>),
+    Follower(Follower<T>),
+}
+
+impl<T: Clone> InFlight<T> {
+    pub fn new() -> Self {
+        InFlight {
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn join(&self, key: &str) -> Joined<T> {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(rx) = pending.get(key) {
+            return Joined::Follower(Follower(rx.clone()));
+        }
+        let (tx, rx) = watch::channel(None);
+        pending.insert(key.to_string(), rx);
+        Joined::Leader(Leader {
+            key: key.to_string(),
+            tx,
+            pending: self.pending.clone(),
+        })
+    }
+}
+
+impl<T: Clone> Default for InFlight<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 执行操作的请求。没有公布结果就被丢弃（客户端断开、出错）时，等待者各自执行
+pub struct Leader<T> {
+    key: String,
+    tx: watch::Sender<Option<T>>,
+    pending: Arc<Mutex<HashMap<String, watch::Receiver<Option<T>>>>>,
+}
+
+impl<T> Leader<T> {
+    pub fn finish(self, value: T) {
+        self.tx.send_replace(Some(value));
+    }
+}
+
+impl<T> Drop for Leader<T> {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(&self.key);
+    }
+}
+
+pub struct Follower<T>(watch::Receiver<Option<T>>);
+
+impl<T: Clone> Follower<T> {
+    // 执行者放弃时返回 None
+    pub async fn wait(mut self) -> Option<T> {
+        let value = self.0.wait_for(|value| value.is_some()).await.ok()?;
+        value.clone()
+    }
+}
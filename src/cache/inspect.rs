@@ -0,0 +1,345 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+
+use super::generations::{content_path, meta_path};
+use super::is_reserved_dir;
+use super::packs::{self, Packs, PACK_DIR};
+use super::{chunks, ByteRanges, CacheMeta, PackedLocation};
+use crate::clock::Clock;
+use crate::config::{Config, PackingConfig};
+use crate::constants::PACK_MIN_LIVE_RATIO;
+
+// 离线查看磁盘缓存（服务未运行时调试用）
+
+pub struct EntryInfo {
+    pub key: String,
+    // 内容文件（或分块目录）名，按代存放时为 <key>.<generation>
+    pub name: String,
+    pub size: u64,
+    pub modified: SystemTime,
+    // .meta 缺失、无法解析或指向其他代（被替换后残留的旧代）时为 None
+    pub meta: Option<CacheMeta>,
+    // 分块存储时的块数
+    pub chunks: Option<usize>,
+    // 打包存储的小对象所在的段文件位置，此时 name 为段文件名
+    pub packed: Option<PackedLocation>,
+}
+
+impl EntryInfo {
+    pub fn path(&self, dir: &Path) -> PathBuf {
+        dir.join(&self.name)
+    }
+
+    pub fn read_content(&self, dir: &Path) -> Result<Bytes> {
+        if let Some(location) = self.packed {
+            return packs::read_content(dir, location);
+        }
+        let path = self.path(dir);
+        if self.chunks.is_some() {
+            chunks::read_chunks(&path)
+        } else {
+            Ok(Bytes::from(fs::read(path)?))
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct GcReport {
+    pub removed: usize,
+    // 只淘汰了尾部块的分块条目数
+    pub truncated: usize,
+    pub freed_bytes: u64,
+}
+
+fn read_meta(dir: &Path, key: &str) -> Option<CacheMeta> {
+    fs::read_to_string(meta_path(dir, key))
+        .ok()
+        .and_then(|m| serde_json::from_str(&m).ok())
+}
+
+// 内容文件或分块目录：没有扩展名，或扩展名为代号
+fn is_content_path(path: &Path) -> bool {
+    let generation_ok = match path.extension().and_then(|e| e.to_str()) {
+        None => true,
+        Some(ext) => !ext.is_empty() && ext.bytes().all(|b| b.is_ascii_digit()),
+    };
+    (path.is_file() || path.is_dir()) && generation_ok
+}
+
+// 按目录存放的键（<主机>/<路径>）所在的子目录：没有扩展名，也不是旧版本没有代号的分块目录
+fn is_layout_dir(path: &Path) -> bool {
+    path.is_dir()
+        && path.extension().is_none()
+        && chunks::chunk_files(path).is_ok_and(|files| files.is_empty())
+}
+
+// 缓存目录下的一层，打包存储与隔离目录除外
+fn children(root: &Path, dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for item in fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))? {
+        let path = item?.path();
+        let reserved = path.file_name().and_then(|n| n.to_str()).is_some_and(is_reserved_dir);
+        if !(dir == root && reserved) {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+// 相对缓存目录的路径，总是以 / 分隔（与缓存键相同，Windows 上也是如此）；不是 UTF-8 时返回 None
+fn relative_name(root: &Path, path: &Path) -> Option<String> {
+    let components: Option<Vec<&str>> = path
+        .strip_prefix(root)
+        .ok()?
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect();
+    Some(components?.join("/"))
+}
+
+// 所有条目文件相对缓存目录的路径（含按目录存放的子目录中的文件）
+pub(crate) fn entry_names(root: &Path) -> Result<Vec<String>> {
+    fn walk(root: &Path, dir: &Path, names: &mut Vec<String>) -> Result<()> {
+        for path in children(root, dir)? {
+            if is_layout_dir(&path) {
+                walk(root, &path, names)?;
+            } else if let Some(name) = relative_name(root, &path) {
+                names.push(name);
+            }
+        }
+        Ok(())
+    }
+    let mut names = Vec::new();
+    walk(root, root, &mut names)?;
+    Ok(names)
+}
+
+pub fn list(dir: &Path) -> Result<Vec<EntryInfo>> {
+    let mut entries = Vec::new();
+    list_dir(dir, dir, &mut entries)?;
+    for (key, location, meta) in Packs::open(dir, &PackingConfig::default())?.entries() {
+        entries.push(EntryInfo {
+            key,
+            name: format!("{}/{:08}.pack", PACK_DIR, location.segment),
+            size: location.len,
+            modified: packs::segment_modified(dir, location.segment)?,
+            meta,
+            chunks: None,
+            packed: Some(location),
+        });
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+fn list_dir(root: &Path, dir: &Path, entries: &mut Vec<EntryInfo>) -> Result<()> {
+    for path in children(root, dir)? {
+        if is_layout_dir(&path) {
+            list_dir(root, &path, entries)?;
+            continue;
+        }
+        if !is_content_path(&path) {
+            continue;
+        }
+        let metadata = fs::metadata(&path)?;
+        let (size, chunks) = if metadata.is_dir() {
+            (chunks::chunked_size(&path)?, Some(chunks::chunk_files(&path)?.len()))
+        } else {
+            (metadata.len(), None)
+        };
+        // 相对缓存目录的路径，键中没有点，第一个点之后是代号
+        let Some(name) = relative_name(root, &path) else {
+            continue;
+        };
+        let key = name.split('.').next().unwrap_or_default().to_string();
+        let meta = read_meta(root, &key)
+            .filter(|meta| content_path(root, &key, meta.generation) == path);
+        entries.push(EntryInfo {
+            key,
+            name,
+            size,
+            modified: metadata.modified()?,
+            meta,
+            chunks,
+            packed: None,
+        });
+    }
+    Ok(())
+}
+
+// 按缓存键或 URL 查找条目，URL 按配置中的缓存键策略换算成键
+pub fn find(dir: &Path, target: &str, config: &Config) -> Result<EntryInfo> {
+    let key = if target.contains("://") {
+        config.cache_key(&target.parse().context("invalid URL")?)
+    } else {
+        target.to_string()
+    };
+    // 同一个键可能残留旧代，优先返回 .meta 指向的当前代
+    let mut candidates: Vec<EntryInfo> = list(dir)?
+        .into_iter()
+        .filter(|entry| entry.key == key)
+        .collect();
+    candidates.sort_by_key(|entry| entry.meta.is_none());
+    candidates
+        .into_iter()
+        .next()
+        .with_context(|| format!("no cache entry for {}", target))
+}
+
+pub fn remove(dir: &Path, entry: &EntryInfo) -> Result<u64> {
+    // 打包条目写入墓碑记录，空间在压缩段文件时回收
+    if entry.packed.is_some() {
+        packs::append_tombstone(dir, &entry.key)?;
+        return Ok(entry.size);
+    }
+    let path = entry.path(dir);
+    if entry.chunks.is_some() {
+        fs::remove_dir_all(&path)
+    } else {
+        fs::remove_file(&path)
+    }
+    .with_context(|| format!("failed to remove {}", path.display()))?;
+    // 旧代没有对应的 .meta，不能删除当前代的元数据
+    if entry.meta.is_some() {
+        let _ = fs::remove_file(meta_path(dir, &entry.key));
+    }
+    Ok(entry.size)
+}
+
+// 分块条目只淘汰尾部的块，保留的部分记为不完整条目，之后可续传补齐。
+// 返回释放的字节数，无法只保留一部分时返回 None
+fn truncate(dir: &Path, entry: &EntryInfo, excess: u64) -> Result<Option<u64>> {
+    let (Some(count), Some(meta)) = (entry.chunks, &entry.meta) else {
+        return Ok(None);
+    };
+    let path = entry.path(dir);
+    let files = chunks::chunk_files(&path)?;
+    let Some(first) = files.first() else {
+        return Ok(None);
+    };
+    let chunk_bytes = fs::metadata(first)?.len();
+    let keep = (entry.size.saturating_sub(excess) / chunk_bytes.max(1)) as usize;
+    if keep == 0 || keep >= count {
+        return Ok(None);
+    }
+
+    let ranges = meta
+        .ranges
+        .clone()
+        .unwrap_or_else(|| ByteRanges::prefix(entry.size));
+    let meta = CacheMeta {
+        is_complete: false,
+        sha256: None,
+        ranges: Some(ranges.truncate_packed(keep as u64 * chunk_bytes)),
+        ..meta.clone()
+    };
+    // 先更新元数据再删除块，中途失败时元数据记录的区间不会多于实际内容
+    fs::write(meta_path(dir, &entry.key), serde_json::to_string(&meta)?)?;
+    Ok(Some(chunks::truncate_chunks(&path, keep)?))
+}
+
+// 清理残留的临时文件与孤立的 .meta，可选删除已过期条目，
+// 再按修改时间从旧到新淘汰，直到总大小不超过 max_bytes；是否过期按 clock 的时间判断
+pub fn gc(dir: &Path, max_bytes: Option<u64>, expired: bool, clock: &dyn Clock) -> Result<GcReport> {
+    if !dir.is_dir() {
+        bail!("{} is not a directory", dir.display());
+    }
+    let mut report = GcReport::default();
+    clean_dir(dir, dir, &mut report)?;
+
+    let mut entries = list(dir)?;
+    let now = clock.now_secs();
+    entries.retain(|entry| {
+        let stale = match &entry.meta {
+            None => true,
+            Some(meta) => expired && !meta.is_fresh(now),
+        };
+        if stale {
+            if let Ok(freed) = remove(dir, entry) {
+                report.removed += 1;
+                report.freed_bytes += freed;
+            }
+        }
+        !stale
+    });
+
+    if let Some(max_bytes) = max_bytes {
+        entries.sort_by_key(|entry| entry.modified);
+        let mut total: u64 = entries.iter().map(|e| e.size).sum();
+        for entry in &entries {
+            if total <= max_bytes {
+                break;
+            }
+            if let Some(freed) = truncate(dir, entry, total - max_bytes)? {
+                total -= freed;
+                report.truncated += 1;
+                report.freed_bytes += freed;
+                continue;
+            }
+            let freed = remove(dir, entry)?;
+            total -= freed;
+            report.removed += 1;
+            report.freed_bytes += freed;
+        }
+    }
+
+    // 压缩失效记录过多的段文件
+    Packs::open(dir, &PackingConfig::default())?.compact(PACK_MIN_LIVE_RATIO)?;
+    prune_layout_dirs(dir, dir)?;
+    Ok(report)
+}
+
+// 删除残留的临时文件与孤立的 .meta，逐层进入按目录存放的子目录
+fn clean_dir(root: &Path, dir: &Path, report: &mut GcReport) -> Result<()> {
+    for path in children(root, dir)? {
+        if is_layout_dir(&path) {
+            clean_dir(root, &path, report)?;
+            continue;
+        }
+        let orphan = match path.extension().and_then(|e| e.to_str()) {
+            Some("tmp") => true,
+            Some("meta") => {
+                let name = relative_name(root, &path).unwrap_or_default();
+                let key = name.trim_end_matches(".meta");
+                read_meta(root, key)
+                    .map(|meta| !content_path(root, key, meta.generation).exists())
+                    .unwrap_or(true)
+            }
+            _ => false,
+        };
+        // 分块目录中残留的临时块
+        if path.is_dir() {
+            for chunk in fs::read_dir(&path)? {
+                let chunk = chunk?.path();
+                if chunk.extension().and_then(|e| e.to_str()) == Some("tmp") {
+                    report.freed_bytes += fs::metadata(&chunk).map(|m| m.len()).unwrap_or(0);
+                    fs::remove_file(&chunk)?;
+                    report.removed += 1;
+                }
+            }
+        }
+        if orphan {
+            report.freed_bytes += fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+            fs::remove_file(&path)?;
+            report.removed += 1;
+        }
+    }
+    Ok(())
+}
+
+// 条目删除后留下的空目录（缓存目录本身除外）
+fn prune_layout_dirs(root: &Path, dir: &Path) -> Result<()> {
+    for path in children(root, dir)? {
+        if is_layout_dir(&path) {
+            prune_layout_dirs(root, &path)?;
+            if fs::read_dir(&path)?.next().is_none() {
+                fs::remove_dir(&path)?;
+            }
+        }
+    }
+    Ok(())
+}
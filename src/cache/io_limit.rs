@@ -0,0 +1,59 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Instant;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::metrics::METRICS;
+
+// 限制同时进行的磁盘读写数，突发的大量缓存读写不会把磁盘（尤其是机械硬盘）压满而拖慢所有请求。
+// 超出上限的操作排队等待，并记录排队深度与等待时间
+#[derive(Clone)]
+pub struct DiskIoLimiter {
+    permits: Arc<Semaphore>,
+}
+
+impl DiskIoLimiter {
+    pub fn new(max_concurrent: usize) -> Self {
+        DiskIoLimiter {
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+        }
+    }
+
+    // 持有返回的许可期间执行磁盘操作
+    pub async fn acquire(&self) -> OwnedSemaphorePermit {
+        if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+            return permit;
+        }
+        let queued = Queued::enter();
+        let started = Instant::now();
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("disk IO semaphore is never closed");
+        drop(queued);
+        METRICS.cache_disk_io_waits.fetch_add(1, Ordering::Relaxed);
+        METRICS
+            .cache_disk_io_wait_micros
+            .fetch_add(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+        permit
+    }
+}
+
+// 排队深度计数：等待中的 acquire 被取消（请求中断）时同样减一
+struct Queued;
+
+impl Queued {
+    fn enter() -> Self {
+        METRICS.cache_disk_io_queued.fetch_add(1, Ordering::Relaxed);
+        Queued
+    }
+}
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        METRICS.cache_disk_io_queued.fetch_sub(1, Ordering::Relaxed);
+    }
+}
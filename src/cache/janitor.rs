@@ -0,0 +1,216 @@
+use std::collections::BTreeSet;
+use std::hash::{BuildHasher, RandomState};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use tokio::sync::{mpsc, oneshot};
+
+use super::writer::DiskJob;
+use super::{inspect, sha256_hex};
+use crate::clock::SharedClock;
+use crate::config::{CacheConfig, JanitorConfig};
+use crate::constants::JANITOR_PAUSE_RETRY_SECONDS;
+use crate::metrics::METRICS;
+
+// 负载信号：busy 返回 true 时后台整理推迟，把磁盘 IO 让给客户端请求
+pub trait Load: Send + Sync {
+    fn busy(&self) -> bool;
+}
+
+pub type SharedLoad = Arc<dyn Load>;
+
+// 默认的负载信号：有磁盘 IO 在排队，或正在处理的客户端请求超过上限
+pub struct RequestLoad {
+    pub max_requests: Option<u64>,
+}
+
+impl Load for RequestLoad {
+    fn busy(&self) -> bool {
+        METRICS.cache_disk_io_queued.load(Ordering::Relaxed) > 0
+            || self.max_requests.is_some_and(|max| {
+                METRICS.downstream_requests_active.load(Ordering::Relaxed) > max as i64
+            })
+    }
+}
+
+pub fn request_load(config: &JanitorConfig) -> SharedLoad {
+    Arc::new(RequestLoad {
+        max_requests: config.pause_above_requests,
+    })
+}
+
+// 交给写盘任务执行的整理任务：与写入不会交错，同样占用一个磁盘 IO 许可
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Task {
+    // 删除在该时间（unix 秒）之前已经过期的条目
+    Sweep { expired_before: u64 },
+    // 重新统计磁盘用量并按配额淘汰
+    Quota,
+    // 接着上次的位置校验一批条目的 SHA-256
+    Verify { batch: usize },
+    // 压缩打包存储
+    Compact,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Sweep,
+    Quota,
+    Verify,
+    Compact,
+}
+
+struct Scheduled {
+    kind: Kind,
+    interval: Duration,
+    next: SystemTime,
+    // 因负载推迟时最初应执行的时间
+    deferred_since: Option<SystemTime>,
+}
+
+// 按配置启用的任务与各自的间隔
+fn scheduled(config: &CacheConfig) -> Vec<(Kind, u64)> {
+    let janitor = &config.janitor;
+    let mut tasks = Vec::new();
+    if let Some(secs) = janitor.sweep_interval_secs {
+        tasks.push((Kind::Sweep, secs));
+    }
+    if config.max_disk_bytes.is_some() {
+        tasks.push((Kind::Quota, janitor.quota_interval_secs));
+    }
+    if let Some(secs) = janitor.verify_interval_secs.filter(|_| config.verify_checksums) {
+        tasks.push((Kind::Verify, secs));
+    }
+    if config.packing.enabled {
+        tasks.push((Kind::Compact, config.packing.compact_interval_secs));
+    }
+    tasks
+}
+
+// 间隔乘以 1 ± fraction 之间的随机系数，多个任务、多个实例不会在同一时刻扫描磁盘
+struct Jitter {
+    fraction: f64,
+    hasher: RandomState,
+    counter: u64,
+}
+
+impl Jitter {
+    fn apply(&mut self, interval: Duration) -> Duration {
+        self.counter += 1;
+        let unit = self.hasher.hash_one(self.counter) as f64 / u64::MAX as f64;
+        interval.mul_f64(1.0 + self.fraction * (2.0 * unit - 1.0))
+    }
+}
+
+// 后台整理调度：所有整理任务由这一个任务按时间先后依次交给写盘任务，
+// 上一项完成后才安排下一项；负载高时推迟，稍后再检查。推迟最多一个间隔，
+// 持续高负载时两次执行相隔不超过两个间隔；打包压缩不推迟，失效的段不会一直堆积
+pub(crate) fn spawn(disk_tx: &mpsc::Sender<DiskJob>, config: &CacheConfig, clock: SharedClock, load: SharedLoad) {
+    let janitor = config.janitor.clone();
+    let mut jitter = Jitter {
+        fraction: janitor.jitter,
+        hasher: RandomState::new(),
+        counter: 0,
+    };
+    let start = clock.now();
+    let mut tasks: Vec<Scheduled> = scheduled(config)
+        .into_iter()
+        .map(|(kind, secs)| {
+            let interval = Duration::from_secs(secs);
+            Scheduled {
+                kind,
+                interval,
+                next: start + jitter.apply(interval),
+                deferred_since: None,
+            }
+        })
+        .collect();
+    if tasks.is_empty() {
+        return;
+    }
+    // 不持有写盘队列，缓存被丢弃后写盘任务与调度一起结束
+    let disk_tx = disk_tx.downgrade();
+    tokio::spawn(async move {
+        while let Some(due) = tasks.iter_mut().min_by_key(|task| task.next) {
+            clock.sleep(due.next.duration_since(clock.now()).unwrap_or_default()).await;
+            let now = clock.now();
+            let since = *due.deferred_since.get_or_insert(due.next);
+            let overdue = now.duration_since(since).unwrap_or_default() >= due.interval;
+            if !matches!(due.kind, Kind::Compact) && !overdue && load.busy() {
+                METRICS.cache_janitor_deferred.fetch_add(1, Ordering::Relaxed);
+                due.next = now + Duration::from_secs(JANITOR_PAUSE_RETRY_SECONDS).min(due.interval);
+                continue;
+            }
+            due.deferred_since = None;
+            due.next = now + jitter.apply(due.interval);
+            let task = match due.kind {
+                Kind::Sweep => {
+                    let now_secs = now.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+                    Task::Sweep {
+                        expired_before: now_secs.saturating_sub(janitor.expired_grace_secs),
+                    }
+                }
+                Kind::Quota => Task::Quota,
+                Kind::Verify => Task::Verify {
+                    batch: janitor.verify_batch,
+                },
+                Kind::Compact => Task::Compact,
+            };
+            let Some(disk_tx) = disk_tx.upgrade() else {
+                break;
+            };
+            let (done_tx, done_rx) = oneshot::channel();
+            if disk_tx.send(DiskJob::Maintain(task, done_tx)).await.is_err() {
+                break;
+            }
+            drop(disk_tx);
+            if done_rx.await.is_err() {
+                break;
+            }
+        }
+    });
+}
+
+// 当前代的条目中，过期时间早于 expired_before 的键；没有时间信息的条目不过期
+pub(crate) fn expired(dir: &Path, expired_before: u64) -> Result<Vec<String>> {
+    let keys: BTreeSet<String> = inspect::list(dir)?
+        .into_iter()
+        .filter(|entry| {
+            entry.meta.as_ref().is_some_and(|meta| match (meta.stored_at, meta.freshness_secs) {
+                (Some(stored_at), Some(freshness)) => stored_at.saturating_add(freshness) <= expired_before,
+                _ => false,
+            })
+        })
+        .map(|entry| entry.key)
+        .collect();
+    Ok(keys.into_iter().collect())
+}
+
+// 按键的顺序从 after 之后校验最多 batch 个记录了 SHA-256 的完整条目。
+// 返回内容不一致的键，以及本批最后一个键（已到末尾时为 None，下一次从头开始）
+pub(crate) fn verify(dir: &Path, after: Option<&str>, batch: usize) -> Result<(Vec<String>, Option<String>)> {
+    let mut entries: Vec<_> = inspect::list(dir)?
+        .into_iter()
+        .filter(|entry| {
+            entry.meta.as_ref().is_some_and(|meta| meta.is_complete && meta.sha256.is_some())
+                && after.is_none_or(|after| entry.key.as_str() > after)
+        })
+        .collect();
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+    let last = (entries.len() > batch).then(|| entries[batch - 1].key.clone());
+    let mut corrupt = Vec::new();
+    for entry in entries.into_iter().take(batch) {
+        let expected = entry.meta.as_ref().and_then(|meta| meta.sha256.as_deref());
+        let intact = entry
+            .read_content(dir)
+            .is_ok_and(|content| Some(sha256_hex(&content).as_str()) == expected);
+        if !intact {
+            tracing::warn!("checksum mismatch for cache entry {}, discarding it", entry.key);
+            corrupt.push(entry.key);
+        }
+    }
+    Ok((corrupt, last))
+}
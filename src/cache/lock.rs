@@ -0,0 +1,54 @@
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+
+pub(crate) const LOCK_FILE: &str = ".lock";
+
+// 缓存目录的独占锁（flock）：同一时刻只有一个进程写缓存目录，各进程在内存中维护的
+// 打包段写入位置、代号等状态才不会互相覆盖。进程退出（包括崩溃）时由内核释放；
+// 升级时旧进程先停止写盘再释放，新进程拿到锁之后才打开缓存
+pub struct CacheLock {
+    _file: File,
+}
+
+impl CacheLock {
+    // 已被其他进程（或本进程中另一个缓存实例）持有时返回 None
+    pub fn try_acquire(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(LOCK_FILE);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::fd::AsRawFd;
+            // SAFETY: 只对本函数打开的文件描述符加锁
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == -1 {
+                let e = std::io::Error::last_os_error();
+                if e.kind() == std::io::ErrorKind::WouldBlock {
+                    return Ok(None);
+                }
+                return Err(e).with_context(|| format!("failed to lock {}", path.display()));
+            }
+        }
+        Ok(Some(CacheLock { _file: file }))
+    }
+
+    // 等待持有者释放，超时返回错误
+    pub async fn acquire(dir: &Path, timeout: Duration) -> Result<Self> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(lock) = Self::try_acquire(dir)? {
+                return Ok(lock);
+            }
+            if Instant::now() >= deadline {
+                bail!("cache dir {} is still locked after {}s", dir.display(), timeout.as_secs());
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
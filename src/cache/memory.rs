@@ -0,0 +1,90 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use lru::LruCache;
+
+// 分片 LRU：按 key 哈希到不同分片，每个分片独立加锁，
+// 避免大量并发命中时所有连接争抢同一把锁
+pub struct ShardedLru<V> {
+    shards: Vec<Mutex<Shard<V>>>,
+    // 按字节限制时每个分片的预算；None 表示只按条目数限制
+    max_weight: Option<usize>,
+    weigh: fn(&V) -> usize,
+}
+
+struct Shard<V> {
+    lru: LruCache<String, V>,
+    weight: usize,
+}
+
+impl<V: Clone> ShardedLru<V> {
+    // capacity 为总容量，平均分配到各分片（向上取整）
+    pub fn new(capacity: usize, shards: usize) -> Self {
+        let shards = shards.max(1);
+        let per_shard = NonZeroUsize::new(capacity.div_ceil(shards).max(1)).unwrap();
+        ShardedLru {
+            shards: (0..shards)
+                .map(|_| Mutex::new(Shard { lru: LruCache::new(per_shard), weight: 0 }))
+                .collect(),
+            max_weight: None,
+            weigh: |_| 0,
+        }
+    }
+
+    // 按总字节数限制，预算平均分配到各分片；超过单个分片预算的值不缓存
+    pub fn with_max_bytes(max_bytes: usize, shards: usize, weigh: fn(&V) -> usize) -> Self {
+        let shards = shards.max(1);
+        ShardedLru {
+            shards: (0..shards)
+                .map(|_| Mutex::new(Shard { lru: LruCache::unbounded(), weight: 0 }))
+                .collect(),
+            max_weight: Some(max_bytes.div_ceil(shards)),
+            weigh,
+        }
+    }
+
+    fn shard(&self, key: &str) -> &Mutex<Shard<V>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    pub fn get(&self, key: &str) -> Option<V> {
+        self.shard(key).lock().unwrap().lru.get(key).cloned()
+    }
+
+    pub fn put(&self, key: String, value: V) {
+        let weight = (self.weigh)(&value);
+        let mut shard = self.shard(&key).lock().unwrap();
+        if self.max_weight.is_some_and(|max| weight > max) {
+            // 旧值也不再保留，避免命中过期的内容
+            if let Some(old) = shard.lru.pop(&key) {
+                shard.weight -= (self.weigh)(&old);
+            }
+            return;
+        }
+        shard.weight += weight;
+        // 返回同一个 key 的旧值，或按条目数淘汰的最久未用的值
+        if let Some((_, old)) = shard.lru.push(key, value) {
+            shard.weight -= (self.weigh)(&old);
+        }
+        let Some(max) = self.max_weight else {
+            return;
+        };
+        while shard.weight > max {
+            match shard.lru.pop_lru() {
+                Some((_, old)) => shard.weight -= (self.weigh)(&old),
+                None => break,
+            }
+        }
+    }
+
+    pub fn remove(&self, key: &str) -> Option<V> {
+        let mut shard = self.shard(key).lock().unwrap();
+        let old = shard.lru.pop(key)?;
+        shard.weight -= (self.weigh)(&old);
+        Some(old)
+    }
+}
@@ -0,0 +1,160 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+
+use super::inspect::entry_names;
+use super::writer::tmp_path;
+use super::CacheMeta;
+use crate::cache_key::is_valid_key;
+use crate::constants::META_VERSION;
+
+// 隔离目录：版本未知或无法升级的条目连同内容移到这里，不再被读取或清理，留给人工检查
+pub(crate) const QUARANTINE_DIR: &str = "quarantine";
+
+// .meta 格式迁移：MIGRATIONS[i] 把版本 i 的元数据升级到版本 i + 1。
+// 修改 CacheMeta 的格式时增加 META_VERSION 并在这里追加一步，已有的缓存在启动时逐步升级
+type Migration = fn(&mut Map<String, Value>) -> Result<()>;
+
+const MIGRATIONS: &[Migration] = &[v0_to_v1];
+
+const _: () = assert!(MIGRATIONS.len() == META_VERSION as usize);
+
+// 版本 0 是加入版本号之前的格式，之后增加的字段都有默认值，可以直接按版本 1 读取
+fn v0_to_v1(_meta: &mut Map<String, Value>) -> Result<()> {
+    Ok(())
+}
+
+// 把元数据升级到当前版本，返回是否有改动；由更新的程序写入的版本无法理解，返回错误
+pub(crate) fn upgrade(value: &mut Value) -> Result<bool> {
+    let Some(meta) = value.as_object_mut() else {
+        bail!("metadata is not a JSON object");
+    };
+    let version = match meta.get("version") {
+        Some(version) => version.as_u64().context("invalid metadata version")?,
+        None => 0,
+    };
+    if version > META_VERSION as u64 {
+        bail!("unknown metadata version {} (this build understands {})", version, META_VERSION);
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(meta)?;
+    }
+    meta.insert("version".to_string(), Value::from(META_VERSION));
+    Ok(version < META_VERSION as u64)
+}
+
+// 解析元数据，旧版本只在内存中升级（打包存储的记录不重写）
+pub(crate) fn decode(bytes: &[u8]) -> Result<CacheMeta> {
+    let mut value: Value = serde_json::from_slice(bytes)?;
+    upgrade(&mut value)?;
+    Ok(serde_json::from_value(value)?)
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct MigrationReport {
+    pub upgraded: usize,
+    pub quarantined: usize,
+    pub rekeyed: usize,
+}
+
+// 启动时检查缓存目录中的所有 .meta：旧版本升级后原子地写回；
+// 未知版本或升级后仍无法读取的条目隔离起来，不会被当作孤立文件删除，也不会被误读；
+// 旧版本在 Windows 以外保留了键中的大写字母，这些条目改名为现在的键
+pub(crate) fn run(dir: &Path) -> Result<MigrationReport> {
+    let mut report = MigrationReport::default();
+    let names = entry_names(dir)?;
+    for name in &names {
+        let Some(key) = name.strip_suffix(".meta") else {
+            continue;
+        };
+        let path = dir.join(name);
+        let upgraded = fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| {
+                let mut value: Value = serde_json::from_slice(&bytes)?;
+                let changed = upgrade(&mut value)?;
+                serde_json::from_value::<CacheMeta>(value.clone())?;
+                Ok(changed.then_some(value))
+            });
+        match upgraded {
+            Ok(None) => {}
+            Ok(Some(value)) => {
+                let tmp = tmp_path(&path);
+                fs::write(&tmp, serde_json::to_vec(&value)?)?;
+                fs::rename(&tmp, &path)?;
+                report.upgraded += 1;
+            }
+            Err(e) => {
+                tracing::warn!("quarantining cache entry {}: {:#}", key, e);
+                quarantine(dir, key, &names)?;
+                report.quarantined += 1;
+                continue;
+            }
+        }
+        if let Some(new_key) = encode_uppercase(key) {
+            rekey(dir, key, &new_key, &names)?;
+            report.rekeyed += 1;
+        }
+    }
+    Ok(report)
+}
+
+// 条目的 .meta、内容文件、分块目录与临时文件
+fn entry_files<'a>(key: &'a str, names: &'a [String]) -> impl Iterator<Item = &'a String> + 'a {
+    names.iter().filter(move |name| {
+        name.strip_prefix(key)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    })
+}
+
+// 键中的大写字母按百分号编码（与 cache_key 的文件名转义相同，已有的百分号编码不变）；
+// 没有需要编码的大写字母时返回 None
+fn encode_uppercase(key: &str) -> Option<String> {
+    let mut encoded = String::with_capacity(key.len() + 8);
+    let mut escaped = 0u8;
+    for c in key.chars() {
+        if c.is_ascii_uppercase() && escaped == 0 {
+            encoded.push_str(&format!("%{:02X}", c as u8));
+        } else {
+            encoded.push(c);
+        }
+        escaped = if c == '%' { 2 } else { escaped.saturating_sub(1) };
+    }
+    (encoded != key).then_some(encoded)
+}
+
+// 把条目的所有文件改到新键下；新键超长（现在会被截断或哈希）或已被占用时直接删除旧条目
+fn rekey(dir: &Path, key: &str, new_key: &str, names: &[String]) -> Result<()> {
+    let keep = is_valid_key(new_key) && !dir.join(format!("{}.meta", new_key)).exists();
+    for name in entry_files(key, names) {
+        let path = dir.join(name);
+        if keep {
+            let target = dir.join(format!("{}{}", new_key, &name[key.len()..]));
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(&path, &target).with_context(|| format!("failed to rename {}", name))?;
+        } else if path.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+// 移走 <key>.meta 以及该条目的所有内容文件、分块目录与临时文件
+fn quarantine(dir: &Path, key: &str, names: &[String]) -> Result<()> {
+    let target = dir.join(QUARANTINE_DIR);
+    fs::create_dir_all(&target)?;
+    for name in entry_files(key, names) {
+        if let Some(parent) = target.join(name).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(dir.join(name), target.join(name))
+            .with_context(|| format!("failed to quarantine {}", name))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,808 @@
+mod admission;
+pub mod archive;
+mod checksum;
+mod chunks;
+mod disk;
+#[cfg(any(test, feature = "testing"))]
+mod faulty_disk;
+mod freshness;
+mod generations;
+mod headers;
+mod inflight;
+mod io_limit;
+mod janitor;
+mod lock;
+pub mod inspect;
+mod memory;
+mod migrate;
+mod packs;
+mod popularity;
+mod pressure;
+mod ranges;
+mod tags;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+mod uring;
+mod validators;
+mod writer;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use hyper::header::{
+    HeaderMap, HeaderValue, AGE, DATE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::clock::{self, SharedClock};
+use crate::config::CacheConfig;
+use crate::metrics::METRICS;
+use crate::constants::{
+    CACHE_DIR, MAX_CACHE_SIZE, MAX_FILE_SIZE, MEMORY_CACHE_SHARDS,
+    MMAP_THRESHOLD,
+};
+
+pub use checksum::{sha256_hex, verify_origin_digest};
+pub use freshness::{
+    lifetime, now_secs, shared_cacheable, strip_surrogate_headers, surrogate_lifetime, surrogate_no_store,
+};
+pub use headers::capture_headers;
+pub use inflight::{Follower, InFlight, Joined, Leader};
+pub use janitor::{Load, RequestLoad, SharedLoad};
+pub use io_limit::DiskIoLimiter;
+pub use lock::CacheLock;
+pub use memory::ShardedLru;
+pub use disk::{Disk, LocalDisk, SharedDisk};
+#[cfg(any(test, feature = "testing"))]
+pub use faulty_disk::{Fault, FaultyDisk};
+pub use packs::PackedLocation;
+pub use popularity::Popularity;
+pub use ranges::ByteRanges;
+pub use tags::parse_tags;
+pub use validators::{is_weak, strong_match, weak_match};
+use admission::Admission;
+use generations::{content_path, meta_path, Generations, ReaderGuard};
+use packs::Packs;
+use pressure::DiskPressure;
+use tags::TagIndex;
+use writer::{DiskJob, PendingWrites, Writer};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CacheMeta {
+    pub content_type: String,
+    pub is_complete: bool,
+    pub total_size: Option<u64>,
+    // 源站校验器，续传前用于 If-Range 确认对象未发生变化
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub last_modified: Option<String>,
+    // 完整内容的 SHA-256，启用校验时由后台写盘任务计算
+    #[serde(default)]
+    pub sha256: Option<String>,
+    // 从源站获取或最近一次验证的时间（unix 秒）
+    #[serde(default)]
+    pub stored_at: Option<u64>,
+    // 新鲜期（秒），None 表示不过期
+    #[serde(default)]
+    pub freshness_secs: Option<u64>,
+    // 对象的 URL，用于离线查看缓存
+    #[serde(default)]
+    pub url: Option<String>,
+    // 不完整条目已缓存的字节区间；旧版本条目没有该字段，视为从 0 开始的前缀
+    #[serde(default)]
+    pub ranges: Option<ByteRanges>,
+    // 磁盘上当前内容的代号，由写盘任务分配
+    #[serde(default)]
+    pub generation: Option<u64>,
+    // 被软删除：视为过期，重新验证成功后清除
+    #[serde(default)]
+    pub invalidated: bool,
+    // 源站响应头（不含逐跳头），命中时用于重建响应
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    // 元数据格式版本，加入版本号之前写入的 .meta 没有该字段，按版本 0 处理
+    #[serde(default)]
+    pub version: u32,
+}
+
+impl CacheMeta {
+    // If-Range 只能使用强 ETag，否则退回到 Last-Modified
+    pub fn if_range_validator(&self) -> Option<&str> {
+        match &self.etag {
+            Some(etag) if !validators::is_weak(etag) => Some(etag),
+            _ => self.last_modified.as_deref(),
+        }
+    }
+
+    // 保存的 Content-Type 不是合法的头部值时（旧版本或导入的条目）按二进制内容返回
+    pub fn content_type_header(&self) -> HeaderValue {
+        HeaderValue::from_str(&self.content_type).unwrap_or_else(|_| {
+            tracing::warn!(
+                "invalid cached content type {:?}, using application/octet-stream",
+                self.content_type
+            );
+            HeaderValue::from_static("application/octet-stream")
+        })
+    }
+
+    // 重新验证得到的 304 更新已保存的响应头
+    pub fn merge_headers(&mut self, headers: &HeaderMap) {
+        headers::merge_headers(&mut self.headers, headers);
+    }
+
+    // 命中缓存时重建响应头：源站的完整响应头（逐跳头除外）、校验器，
+    // 以及 Date 与加上本地停留时间的 Age。旧版本条目没有保存响应头，此时补一个 now 对应的 Date
+    pub fn insert_cached_headers(&self, headers: &mut HeaderMap, now: u64) {
+        headers::restore_headers(&self.headers, headers);
+        // 把源站的校验器带回给客户端，客户端之后可以发起条件请求
+        let values = [(ETAG, &self.etag), (LAST_MODIFIED, &self.last_modified)];
+        for (name, value) in values {
+            if let Some(value) = value.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+                headers.insert(name, value);
+            }
+        }
+        if !headers.contains_key(DATE) {
+            let date = httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(now));
+            headers.insert(DATE, HeaderValue::from_str(&date).unwrap());
+        }
+        if let Some(stored_at) = self.stored_at {
+            let origin_age = headers
+                .get(AGE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .unwrap_or(0);
+            let age = origin_age.saturating_add(now.saturating_sub(stored_at));
+            headers.insert(AGE, HeaderValue::from(age));
+        }
+    }
+
+    // 客户端的 If-Range 是否仍指向缓存的对象：实体标签用强比较，日期须与 Last-Modified 完全一致
+    pub fn if_range_matches(&self, value: &str) -> bool {
+        if validators::is_entity_tag(value) {
+            self.etag.as_deref().is_some_and(|etag| strong_match(etag, value))
+        } else {
+            self.last_modified.as_deref() == Some(value.trim())
+        }
+    }
+
+    // 源站的 206/304 是否仍是缓存的同一个对象：响应带 ETag 时与缓存的 ETag 比较，
+    // 缓存的是强标签时要求强匹配
+    pub fn same_representation(&self, headers: &HeaderMap) -> bool {
+        let returned = headers.get(ETAG).and_then(|v| v.to_str().ok());
+        match (self.etag.as_deref(), returned) {
+            (Some(stored), Some(returned)) if validators::is_weak(stored) => {
+                weak_match(stored, returned)
+            }
+            (Some(stored), Some(returned)) => strong_match(stored, returned),
+            _ => true,
+        }
+    }
+
+    // 客户端的条件请求是否可以直接用 304 应答。
+    // If-None-Match 使用弱比较，存在时忽略 If-Modified-Since
+    pub fn not_modified(&self, headers: &HeaderMap) -> bool {
+        if let Some(value) = headers.get(IF_NONE_MATCH) {
+            let Ok(value) = value.to_str() else {
+                return false;
+            };
+            return self.etag.as_deref().is_some_and(|etag| validators::none_match(value, etag));
+        }
+        let since = headers
+            .get(IF_MODIFIED_SINCE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| httpdate::parse_http_date(v).ok());
+        let modified = self
+            .last_modified
+            .as_deref()
+            .and_then(|v| httpdate::parse_http_date(v).ok());
+        matches!((since, modified), (Some(since), Some(modified)) if modified <= since)
+    }
+
+    // 旧版本写入的条目没有时间信息，视为新鲜
+    pub fn is_fresh(&self, now: u64) -> bool {
+        if self.invalidated {
+            return false;
+        }
+        match (self.stored_at, self.freshness_secs) {
+            (Some(stored_at), Some(freshness)) => now < stored_at.saturating_add(freshness),
+            _ => true,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CacheEntry {
+    pub content: Bytes,
+    pub meta: CacheMeta,
+}
+
+impl CacheEntry {
+    pub fn ranges(&self) -> ByteRanges {
+        self.meta
+            .ranges
+            .clone()
+            .unwrap_or_else(|| ByteRanges::prefix(self.content.len() as u64))
+    }
+
+    // 对象的 [start, end] 字节（闭区间），未完全缓存时返回 None
+    pub fn slice(&self, start: u64, end: u64) -> Option<Bytes> {
+        self.ranges().slice(&self.content, start, end + 1)
+    }
+
+    // 合并从 start 开始的数据，覆盖整个对象后标记为完整
+    pub fn merge(&self, start: u64, data: &[u8], total_size: Option<u64>) -> CacheEntry {
+        let (ranges, content) = self.ranges().merge(&self.content, start, data);
+        let total_size = total_size.or(self.meta.total_size);
+        let is_complete = total_size.map(|total| ranges.covers(total)).unwrap_or(false);
+        CacheEntry {
+            content,
+            meta: CacheMeta {
+                is_complete,
+                total_size,
+                ranges: (!is_complete).then_some(ranges),
+                sha256: None,
+                ..self.meta.clone()
+            },
+        }
+    }
+}
+
+pub struct ProxyCache {
+    memory_cache: Arc<ShardedLru<CacheEntry>>,
+    // cache.memory_content 关闭时不使用内存缓存，磁盘内容不论大小都 mmap 读取
+    memory_content: bool,
+    // 磁盘命中后是否放入内存缓存
+    admission: Admission,
+    cache_dir: PathBuf,
+    // 写盘队列（write-behind），落盘前的条目保存在 pending 中
+    disk_tx: mpsc::Sender<DiskJob>,
+    pending: PendingWrites,
+    write_seq: AtomicU64,
+    verify_checksums: bool,
+    verify_on_read: bool,
+    popularity: Popularity,
+    generations: Generations,
+    pressure: DiskPressure,
+    disk_io: DiskIoLimiter,
+    // 打包存储的小对象，未启用时只读取已有的段文件
+    packs: Packs,
+    // Surrogate-Key / Cache-Tag 标签索引，供按标签删除
+    tags: TagIndex,
+    // 正在向源站重新验证的过期条目（方法 + 缓存键）；结果为 None 表示源站不可用，继续使用旧内容
+    revalidations: InFlight<Option<CacheEntry>>,
+    clock: SharedClock,
+    // 缓存目录的独占锁，升级交接时释放
+    lock: Mutex<Option<CacheLock>>,
+}
+
+// ProxyCache 的构造参数：默认使用 CACHE_DIR 与配置中的限制，
+// 测试或嵌入到其他程序时可以换成独立的目录与更小的限制
+pub struct ProxyCacheBuilder {
+    dir: PathBuf,
+    config: CacheConfig,
+    clock: SharedClock,
+    disk: SharedDisk,
+    load: Option<SharedLoad>,
+    lock: Option<CacheLock>,
+}
+
+impl Default for ProxyCacheBuilder {
+    fn default() -> Self {
+        ProxyCacheBuilder {
+            dir: PathBuf::from(CACHE_DIR),
+            config: CacheConfig::default(),
+            clock: clock::system(),
+            disk: disk::local(),
+            load: None,
+            lock: None,
+        }
+    }
+}
+
+impl ProxyCacheBuilder {
+    // 缓存策略；之后设置的 memory_bytes / disk_bytes 覆盖其中的限制
+    pub fn config(mut self, config: CacheConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = dir.into();
+        self
+    }
+
+    pub fn memory_bytes(mut self, bytes: u64) -> Self {
+        self.config.max_memory_bytes = Some(bytes);
+        self
+    }
+
+    pub fn disk_bytes(mut self, bytes: u64) -> Self {
+        self.config.max_disk_bytes = Some(bytes);
+        self
+    }
+
+    // 新鲜期、Age 与后台整理使用的时间来源
+    pub fn clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // 写盘使用的文件系统操作，测试中可以注入故障
+    pub fn disk(mut self, disk: SharedDisk) -> Self {
+        self.disk = disk;
+        self
+    }
+
+    // 后台整理判断是否应推迟的负载信号；未设置时按 cache.janitor.pause_above_requests
+    pub fn load(mut self, load: SharedLoad) -> Self {
+        self.load = Some(load);
+        self
+    }
+
+    // 已经取得的目录锁（升级时新进程等上一个进程释放后取得）；未设置时打开缓存时加锁，
+    // 目录已被其他进程锁住则失败
+    pub fn lock(mut self, lock: CacheLock) -> Self {
+        self.lock = Some(lock);
+        self
+    }
+
+    pub async fn build(self) -> Result<ProxyCache> {
+        let load = self.load.unwrap_or_else(|| janitor::request_load(&self.config.janitor));
+        ProxyCache::open(self.dir, &self.config, self.clock, self.disk, load, self.lock).await
+    }
+}
+
+impl ProxyCache {
+    pub fn builder() -> ProxyCacheBuilder {
+        ProxyCacheBuilder::default()
+    }
+
+    pub async fn new(config: &CacheConfig) -> Result<Self> {
+        Self::builder().config(config.clone()).build().await
+    }
+
+    async fn open(
+        cache_dir: PathBuf,
+        config: &CacheConfig,
+        clock: SharedClock,
+        disk: SharedDisk,
+        load: SharedLoad,
+        lock: Option<CacheLock>,
+    ) -> Result<Self> {
+        if !cache_dir.exists() {
+            fs::create_dir_all(&cache_dir).await?;
+        }
+        // 迁移、隔离与写盘都在持有目录锁之后进行
+        let lock = match lock {
+            Some(lock) => lock,
+            None => CacheLock::try_acquire(&cache_dir)?
+                .with_context(|| format!("cache dir {} is in use by another process", cache_dir.display()))?,
+        };
+        // 先把旧格式的元数据升级到当前版本，版本未知的条目隔离起来，旧的大写键改名
+        let report = {
+            let dir = cache_dir.clone();
+            tokio::task::spawn_blocking(move || migrate::run(&dir)).await??
+        };
+        if report.upgraded > 0 || report.quarantined > 0 || report.rekeyed > 0 {
+            tracing::info!(
+                "cache metadata migration: {} upgraded, {} quarantined, {} rekeyed",
+                report.upgraded,
+                report.quarantined,
+                report.rekeyed
+            );
+        }
+        let (disk_tx, disk_rx) = mpsc::channel(config.write_queue_size);
+        let pending: PendingWrites = Arc::new(Mutex::new(HashMap::new()));
+        let generations = Generations::default();
+        let pressure = DiskPressure::default();
+        let disk_io = DiskIoLimiter::new(config.disk_io_concurrency);
+        let packs = {
+            let (dir, packing) = (cache_dir.clone(), config.packing.clone());
+            tokio::task::spawn_blocking(move || Packs::open(&dir, &packing)).await??
+        };
+        janitor::spawn(&disk_tx, config, clock.clone(), load);
+        let memory_cache = match config.max_memory_bytes {
+            Some(bytes) => {
+                let weigh = |entry: &CacheEntry| entry.content.len();
+                ShardedLru::with_max_bytes(bytes as usize, MEMORY_CACHE_SHARDS, weigh)
+            }
+            None => ShardedLru::new(MAX_CACHE_SIZE, MEMORY_CACHE_SHARDS),
+        };
+        let memory_cache = Arc::new(memory_cache);
+        let tags = TagIndex::default();
+        {
+            let (tags, dir, packs) = (tags.clone(), cache_dir.clone(), packs.clone());
+            tokio::task::spawn_blocking(move || tags.rebuild(&dir, &packs));
+        }
+        tokio::spawn(writer::run_writer(
+            Writer {
+                cache_dir: cache_dir.clone(),
+                pending: pending.clone(),
+                generations: generations.clone(),
+                pressure: pressure.clone(),
+                disk_io: disk_io.clone(),
+                packs: packs.clone(),
+                memory_cache: memory_cache.clone(),
+                tags: tags.clone(),
+                disk,
+                config: config.clone(),
+            },
+            disk_rx,
+        ));
+
+        Ok(ProxyCache {
+            memory_cache,
+            memory_content: config.memory_content,
+            admission: Admission::new(&config.memory_admission),
+            cache_dir,
+            disk_tx,
+            pending,
+            write_seq: AtomicU64::new(0),
+            verify_checksums: config.verify_checksums,
+            verify_on_read: config.verify_checksums && config.verify_on_read,
+            popularity: Popularity::new(config.refresh.tracked_entries),
+            generations,
+            pressure,
+            disk_io,
+            packs,
+            tags,
+            revalidations: InFlight::new(),
+            clock,
+            lock: Mutex::new(Some(lock)),
+        })
+    }
+
+    // 缓存目录
+    pub fn dir(&self) -> &Path {
+        &self.cache_dir
+    }
+
+    pub fn clock(&self) -> &SharedClock {
+        &self.clock
+    }
+
+    pub fn verify_checksums(&self) -> bool {
+        self.verify_checksums
+    }
+
+    pub fn revalidations(&self) -> &InFlight<Option<CacheEntry>> {
+        &self.revalidations
+    }
+
+    // 命中统计，用于提前刷新热门条目
+    pub fn popularity(&self) -> &Popularity {
+        &self.popularity
+    }
+
+    fn remember(&self, key: &str, entry: &CacheEntry) {
+        if self.memory_content && entry.content.len() <= MAX_FILE_SIZE {
+            self.memory_cache.put(key.to_string(), entry.clone());
+        }
+    }
+
+    // 从磁盘读到的条目：通过准入的才放入内存缓存
+    fn promote(&self, key: &str, entry: &CacheEntry) {
+        if self.memory_content && self.admission.admit(key, entry.content.len() as u64) {
+            self.remember(key, entry);
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> Option<CacheEntry> {
+        // Try memory cache first
+        if let Some(entry) = self.memory_cache.get(key) {
+            return Some(entry);
+        }
+
+        // 尚未写入磁盘的条目
+        if let Some((_, entry)) = self.pending.lock().unwrap().get(key) {
+            return Some(entry.clone());
+        }
+
+        // 打包存储的小对象
+        if self.packs.contains(key) {
+            let _permit = self.disk_io.acquire().await;
+            let (packs, packed_key) = (self.packs.clone(), key.to_string());
+            let entry = tokio::task::spawn_blocking(move || packs.read(&packed_key))
+                .await
+                .ok()??;
+            self.promote(key, &entry);
+            return Some(entry);
+        }
+
+        // Try disk cache
+        // 先读 .meta 找到当前代；读取前内容恰好被新代替换并删除时重新读取一次
+        let meta_path = meta_path(&self.cache_dir, key);
+        let _permit = self.disk_io.acquire().await;
+        for _ in 0..2 {
+            let meta_str = fs::read_to_string(&meta_path).await.ok()?;
+            let meta = serde_json::from_str::<CacheMeta>(&meta_str).ok()?;
+            let file_path = content_path(&self.cache_dir, key, meta.generation);
+            let guard = self.generations.acquire(&file_path);
+            let mmap_threshold = if self.memory_content { MMAP_THRESHOLD } else { 0 };
+            let Ok(content) = read_content(&file_path, guard, mmap_threshold).await else {
+                continue;
+            };
+            if self.verify_on_read && !self.verify_content(key, &file_path, &content, &meta).await {
+                return None;
+            }
+            let entry = CacheEntry { content, meta };
+            // 加载到内存缓存
+            self.promote(key, &entry);
+            return Some(entry);
+        }
+        None
+    }
+
+    pub async fn set(&self, key: String, entry: CacheEntry) -> Result<()> {
+        // Update memory cache
+        self.remember(&key, &entry);
+        self.tags.update(&key, &entry.meta);
+
+        // 磁盘已满时只保留内存缓存，不排队写盘
+        if self.pressure.is_full() {
+            METRICS.cache_writes_skipped.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+
+        // 磁盘写入交给后台任务，队列满时等待（背压）
+        let seq = self.write_seq.fetch_add(1, Ordering::Relaxed);
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(key.clone(), (seq, entry.clone()));
+        self.disk_tx
+            .send(DiskJob::Write { key, seq, entry })
+            .await
+            .map_err(|_| anyhow::anyhow!("cache writer has stopped"))?;
+        Ok(())
+    }
+
+    // 只更新元数据（例如 304 重新验证后刷新时间），不重写内容文件
+    pub async fn update_meta(&self, key: String, entry: CacheEntry) -> Result<()> {
+        self.remember(&key, &entry);
+        self.store_meta(key, entry.meta).await
+    }
+
+    // 把新的元数据写入标签索引、待写队列与磁盘（.meta 文件或打包记录）
+    async fn store_meta(&self, key: String, meta: CacheMeta) -> Result<()> {
+        self.tags.update(&key, &meta);
+        if let Some((_, pending)) = self.pending.lock().unwrap().get_mut(&key) {
+            pending.meta = meta.clone();
+        }
+        self.disk_tx
+            .send(DiskJob::Meta { key, meta })
+            .await
+            .map_err(|_| anyhow::anyhow!("cache writer has stopped"))?;
+        Ok(())
+    }
+
+    // 删除条目（内存、待写队列与磁盘），返回条目是否存在
+    pub async fn purge(&self, key: &str) -> Result<bool> {
+        self.tags.remove(key);
+        let in_memory = self.memory_cache.remove(key).is_some();
+        let pending = self.pending.lock().unwrap().remove(key).is_some();
+        let on_disk = self.packs.contains(key)
+            || fs::try_exists(meta_path(&self.cache_dir, key))
+                .await
+                .unwrap_or(false);
+        // 排在之前的写入之后执行，队列中尚未落盘的内容同样会被删除；
+        // 等磁盘上的条目删除后再返回，之后的读取不会再命中旧内容
+        let (done_tx, done_rx) = oneshot::channel();
+        self.disk_tx
+            .send(DiskJob::Remove {
+                key: key.to_string(),
+                done: done_tx,
+            })
+            .await
+            .map_err(|_| anyhow::anyhow!("cache writer has stopped"))?;
+        done_rx.await?;
+        Ok(in_memory || pending || on_disk)
+    }
+
+    // 带有该标签（Surrogate-Key 或 Cache-Tag）的缓存键
+    pub fn tagged(&self, tag: &str) -> Vec<String> {
+        self.tags.keys(tag)
+    }
+
+    // 软删除：保留内容只将条目标记为过期，下次请求先向源站重新验证，
+    // 源站出错时仍可返回旧内容。返回条目是否存在
+    pub async fn soft_purge(&self, key: &str) -> Result<bool> {
+        // 只改写元数据，不从磁盘加载内容
+        let Some(mut meta) = self.get_meta(key).await else {
+            return Ok(false);
+        };
+        meta.invalidated = true;
+        if let Some(mut entry) = self.memory_cache.get(key) {
+            entry.meta = meta.clone();
+            self.memory_cache.put(key.to_string(), entry);
+        }
+        self.store_meta(key.to_string(), meta).await?;
+        Ok(true)
+    }
+
+    // 校验磁盘内容与元数据中的 SHA-256，不一致时删除损坏的条目
+    async fn verify_content(&self, key: &str, file_path: &Path, content: &Bytes, meta: &CacheMeta) -> bool {
+        let Some(expected) = meta.sha256.clone() else {
+            return true;
+        };
+        let content = content.clone();
+        let actual = tokio::task::spawn_blocking(move || sha256_hex(&content))
+            .await
+            .unwrap_or_default();
+        if actual == expected {
+            return true;
+        }
+        tracing::warn!("checksum mismatch for {}, discarding entry", file_path.display());
+        self.tags.remove(key);
+        let _ = fs::remove_file(meta_path(&self.cache_dir, key)).await;
+        self.generations.retire(file_path.to_path_buf());
+        false
+    }
+
+    // 只读取元数据，不加载内容（HEAD 命中时使用）；内容文件已不存在的条目视为未缓存
+    pub async fn get_meta(&self, key: &str) -> Option<CacheMeta> {
+        if let Some(entry) = self.memory_cache.get(key) {
+            return Some(entry.meta);
+        }
+        if let Some((_, entry)) = self.pending.lock().unwrap().get(key) {
+            return Some(entry.meta.clone());
+        }
+        // 打包存储的小对象元数据与内容在同一条记录中，一起读取
+        if self.packs.contains(key) {
+            return self.get(key).await.map(|entry| entry.meta);
+        }
+        let _permit = self.disk_io.acquire().await;
+        let meta_str = fs::read_to_string(meta_path(&self.cache_dir, key)).await.ok()?;
+        let meta = serde_json::from_str::<CacheMeta>(&meta_str).ok()?;
+        let exists = fs::try_exists(content_path(&self.cache_dir, key, meta.generation)).await;
+        exists.unwrap_or(false).then_some(meta)
+    }
+
+    // 范围读取：分块存储的条目只读取覆盖该范围的块，不加载整个对象。
+    // 返回元数据、截断到对象末尾后的结束位置与数据；其他情况返回 None，由调用方走常规路径
+    pub async fn get_range(&self, key: &str, start: u64, end: u64) -> Option<(CacheMeta, u64, Bytes)> {
+        if self.memory_cache.get(key).is_some()
+            || self.pending.lock().unwrap().contains_key(key)
+            || self.packs.contains(key)
+        {
+            return None;
+        }
+        let meta_path = meta_path(&self.cache_dir, key);
+        let _permit = self.disk_io.acquire().await;
+        let meta_str = fs::read_to_string(meta_path).await.ok()?;
+        let meta = serde_json::from_str::<CacheMeta>(&meta_str).ok()?;
+        // SHA-256 覆盖整个对象，单独的块无法校验：开启读取校验时交给 get 读取并校验整个对象
+        if self.verify_on_read && meta.sha256.is_some() {
+            return None;
+        }
+        let dir = content_path(&self.cache_dir, key, meta.generation);
+        if !dir.is_dir() {
+            return None;
+        }
+        let guard = self.generations.acquire(&dir);
+
+        let end = match meta.total_size {
+            Some(total) if total > 0 => end.min(total - 1),
+            _ => end,
+        };
+        let ranges = match meta.ranges.clone() {
+            Some(ranges) => ranges,
+            None => ByteRanges::prefix(chunks::chunked_size(&dir).ok()?),
+        };
+        if start > end || !ranges.contains(start, end + 1) {
+            return None;
+        }
+        let offset = ranges.packed_offset(start)? as u64;
+        let data = tokio::task::spawn_blocking(move || {
+            let _guard = guard;
+            chunks::read_range(&dir, offset, end - start + 1)
+        })
+        .await
+        .ok()?
+        .ok()?;
+        Some((meta, end, data))
+    }
+
+    // 升级交接：等队列中已提交的写入落盘后停止写盘并释放目录锁，之后只使用内存缓存，
+    // 磁盘上的内容仍可读取
+    pub async fn release_dir(&self) -> Result<()> {
+        let (done_tx, done_rx) = oneshot::channel();
+        self.disk_tx
+            .send(DiskJob::Suspend(done_tx))
+            .await
+            .map_err(|_| anyhow::anyhow!("cache writer has stopped"))?;
+        done_rx.await?;
+        self.lock.lock().unwrap().take();
+        Ok(())
+    }
+
+    // 交接失败：重新取得目录锁，按磁盘上的内容（新进程可能已写入）重建打包索引后恢复写盘
+    pub async fn reclaim_dir(&self, timeout: Duration) -> Result<()> {
+        let lock = CacheLock::acquire(&self.cache_dir, timeout).await?;
+        *self.lock.lock().unwrap() = Some(lock);
+        let (done_tx, done_rx) = oneshot::channel();
+        self.disk_tx
+            .send(DiskJob::Resume(done_tx))
+            .await
+            .map_err(|_| anyhow::anyhow!("cache writer has stopped"))?;
+        done_rx.await?
+    }
+
+    // 等待队列中所有已提交的写入完成，用于关闭前落盘
+    pub async fn flush(&self) -> Result<()> {
+        let (done_tx, done_rx) = oneshot::channel();
+        self.disk_tx
+            .send(DiskJob::Flush(done_tx))
+            .await
+            .map_err(|_| anyhow::anyhow!("cache writer has stopped"))?;
+        done_rx.await?;
+        Ok(())
+    }
+}
+
+// 缓存目录下由缓存自身使用的子目录与锁文件，按目录存放的缓存键不能以它们开头
+pub(crate) fn is_reserved_dir(name: &str) -> bool {
+    name == packs::PACK_DIR || name == migrate::QUARANTINE_DIR || name == lock::LOCK_FILE
+}
+
+// 确认缓存目录存在且可写，用于启动前的配置检查
+pub fn check_cache_dir(dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create cache dir {}", dir.display()))?;
+    let probe = dir.join(".write-check");
+    std::fs::write(&probe, b"")
+        .with_context(|| format!("cache dir {} is not writable", dir.display()))?;
+    let _ = std::fs::remove_file(probe);
+    Ok(())
+}
+
+// mmap 映射的内容，映射存在期间旧代不会被删除
+struct MappedContent {
+    mmap: memmap2::Mmap,
+    _guard: ReaderGuard,
+}
+
+impl AsRef<[u8]> for MappedContent {
+    fn as_ref(&self) -> &[u8] {
+        &self.mmap
+    }
+}
+
+// 读取磁盘缓存内容：不小于 mmap_threshold 的文件使用 mmap 映射，避免整个文件复制到堆内存
+async fn read_content(file_path: &Path, guard: ReaderGuard, mmap_threshold: usize) -> Result<Bytes> {
+    if file_path.is_dir() {
+        let dir = file_path.to_path_buf();
+        return tokio::task::spawn_blocking(move || {
+            let _guard = guard;
+            chunks::read_chunks(&dir)
+        })
+        .await?;
+    }
+    let len = fs::metadata(file_path).await?.len();
+    // 空文件不能映射
+    if len > 0 && len as usize >= mmap_threshold {
+        let file = std::fs::File::open(file_path)?;
+        // SAFETY: 每次写入都生成新一代文件，旧代在映射释放前不会被删除，
+        // 也不会被原地截断或修改
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(Bytes::from_owner(MappedContent { mmap, _guard: guard }))
+    } else {
+        read_small(file_path).await
+    }
+}
+
+#[cfg(all(feature = "uring", target_os = "linux"))]
+async fn read_small(file_path: &Path) -> Result<Bytes> {
+    Ok(uring::read(file_path.to_path_buf()).await?)
+}
+
+#[cfg(not(all(feature = "uring", target_os = "linux")))]
+async fn read_small(file_path: &Path) -> Result<Bytes> {
+    Ok(Bytes::from(fs::read(file_path).await?))
+}
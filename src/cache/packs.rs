@@ -0,0 +1,431 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+
+use super::{migrate, CacheEntry, CacheMeta};
+use crate::config::PackingConfig;
+use crate::metrics::METRICS;
+
+// 小对象打包存储：对象追加写入 packs/<序号>.pack 段文件，省去每个对象一个内容文件加一个 .meta。
+// 记录格式（整数为小端序）：
+// 类型(1) | key 长度(u16) | key | meta 长度(u32) | meta JSON | 内容长度(u32) | 内容。
+// 同一个 key 以最后一条记录为准，删除时写入墓碑记录。索引只在内存中，启动时扫描段文件重建
+
+pub(crate) const PACK_DIR: &str = "packs";
+const PUT: u8 = 1;
+const DELETE: u8 = 0;
+
+// 记录在段文件中的位置
+#[derive(Clone, Copy, Debug)]
+pub struct PackedLocation {
+    pub segment: u64,
+    pub offset: u64,
+    pub len: u64,
+}
+
+#[derive(Default)]
+struct Segment {
+    bytes: u64,
+    // 仍被索引引用的记录字节数
+    live: u64,
+}
+
+#[derive(Default)]
+struct State {
+    index: HashMap<String, PackedLocation>,
+    segments: BTreeMap<u64, Segment>,
+    // 正在追加写入的段
+    current: Option<(u64, File)>,
+}
+
+impl State {
+    // 索引指向新位置，旧记录计为失效
+    fn relocate(&mut self, key: &str, location: Option<PackedLocation>) {
+        let previous = match location {
+            Some(location) => {
+                if let Some(segment) = self.segments.get_mut(&location.segment) {
+                    segment.live += location.len;
+                }
+                self.index.insert(key.to_string(), location)
+            }
+            None => self.index.remove(key),
+        };
+        if let Some(previous) = previous {
+            if let Some(segment) = self.segments.get_mut(&previous.segment) {
+                segment.live = segment.live.saturating_sub(previous.len);
+            }
+        }
+        METRICS.cache_packed_objects.store(self.index.len() as i64, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Packs {
+    dir: PathBuf,
+    config: PackingConfig,
+    state: Arc<Mutex<State>>,
+}
+
+struct Record {
+    kind: u8,
+    key: String,
+    meta: Vec<u8>,
+    content: Vec<u8>,
+}
+
+fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("{:08}.pack", segment))
+}
+
+// 按序号排列的段文件
+fn segment_ids(dir: &Path) -> Result<Vec<u64>> {
+    let mut ids = Vec::new();
+    if !dir.is_dir() {
+        return Ok(ids);
+    }
+    for item in fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))? {
+        let path = item?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("pack") {
+            continue;
+        }
+        if let Some(id) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse().ok()) {
+            ids.push(id);
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+fn encode(kind: u8, key: &str, meta: &[u8], content: &[u8]) -> Result<Vec<u8>> {
+    if key.len() > u16::MAX as usize || meta.len() > u32::MAX as usize {
+        bail!("cache entry {} is too large to pack", key);
+    }
+    let content_len = u32::try_from(content.len()).context("object too large to pack")?;
+    let mut record = Vec::with_capacity(11 + key.len() + meta.len() + content.len());
+    record.push(kind);
+    record.extend_from_slice(&(key.len() as u16).to_le_bytes());
+    record.extend_from_slice(key.as_bytes());
+    record.extend_from_slice(&(meta.len() as u32).to_le_bytes());
+    record.extend_from_slice(meta);
+    record.extend_from_slice(&content_len.to_le_bytes());
+    record.extend_from_slice(content);
+    Ok(record)
+}
+
+// 读取下一条记录，返回记录与其长度；正好在文件末尾时返回 None，记录被截断时返回 UnexpectedEof。
+// remaining 为段中剩余的字节数，损坏的长度字段超出它时返回 InvalidData，不按其分配内存
+fn read_record(reader: &mut impl Read, remaining: u64) -> io::Result<Option<(Record, u64)>> {
+    let mut kind = [0u8; 1];
+    if reader.read(&mut kind)? == 0 {
+        return Ok(None);
+    }
+    let mut left = remaining.saturating_sub(1);
+    let mut field = |reader: &mut dyn Read, len: usize| -> io::Result<Vec<u8>> {
+        if len as u64 > left {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("record field of {} bytes exceeds the {} bytes left in the segment", len, left),
+            ));
+        }
+        left -= len as u64;
+        let mut buf = vec![0u8; len];
+        reader.read_exact(&mut buf)?;
+        Ok(buf)
+    };
+    let len16 = field(reader, 2)?;
+    let key = field(reader, u16::from_le_bytes([len16[0], len16[1]]) as usize)?;
+    let len32 = field(reader, 4)?;
+    let meta = field(reader, u32::from_le_bytes(len32[..].try_into().unwrap()) as usize)?;
+    let len32 = field(reader, 4)?;
+    let content = field(reader, u32::from_le_bytes(len32[..].try_into().unwrap()) as usize)?;
+    let len = 11 + key.len() + meta.len() + content.len();
+    let key = String::from_utf8(key).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(Some((Record { kind: kind[0], key, meta, content }, len as u64)))
+}
+
+// 段中各条记录的类型与键，按写入顺序
+fn record_keys(dir: &Path, segment: u64) -> Result<Vec<(u8, String)>> {
+    let file = File::open(segment_path(dir, segment))?;
+    let mut remaining = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut keys = Vec::new();
+    while let Some((record, len)) = read_record(&mut reader, remaining)? {
+        remaining -= len;
+        keys.push((record.kind, record.key));
+    }
+    Ok(keys)
+}
+
+// 读取指定位置的一条记录
+fn read_at(dir: &Path, location: PackedLocation) -> Result<(String, CacheMeta, Bytes)> {
+    let mut file = File::open(segment_path(dir, location.segment))?;
+    file.seek(SeekFrom::Start(location.offset))?;
+    let mut buf = vec![0u8; location.len as usize];
+    file.read_exact(&mut buf)?;
+    match read_record(&mut buf.as_slice(), location.len)? {
+        Some((record, _)) if record.kind == PUT => {
+            let meta = migrate::decode(&record.meta)?;
+            Ok((record.key, meta, Bytes::from(record.content)))
+        }
+        _ => bail!("no packed record at {}:{}", location.segment, location.offset),
+    }
+}
+
+// 扫描段文件重建索引；进程中途退出留下的半条记录被截掉
+fn scan(dir: &Path) -> Result<State> {
+    let mut state = State::default();
+    for id in segment_ids(dir)? {
+        let path = segment_path(dir, id);
+        let file = File::open(&path)?;
+        let size = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        state.segments.insert(id, Segment::default());
+        let mut offset = 0;
+        loop {
+            let (record, len) = match read_record(&mut reader, size - offset) {
+                Ok(Some(next)) => next,
+                Ok(None) => break,
+                Err(e) => {
+                    let path = path.display();
+                    tracing::warn!("truncating damaged pack {} at {}: {}", path, offset, e);
+                    let file = OpenOptions::new().write(true).open(segment_path(dir, id))?;
+                    file.set_len(offset)?;
+                    break;
+                }
+            };
+            let location = PackedLocation { segment: id, offset, len };
+            state.relocate(&record.key, (record.kind == PUT).then_some(location));
+            offset += len;
+        }
+        state.segments.get_mut(&id).unwrap().bytes = offset;
+    }
+    Ok(state)
+}
+
+impl Packs {
+    pub(crate) fn open(cache_dir: &Path, config: &PackingConfig) -> Result<Self> {
+        let dir = cache_dir.join(PACK_DIR);
+        if config.enabled {
+            fs::create_dir_all(&dir)?;
+        }
+        let state = scan(&dir)?;
+        Ok(Packs {
+            dir,
+            config: config.clone(),
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    // 重新取得目录锁后按磁盘上的段文件重建索引，期间其他进程可能追加过记录
+    pub(crate) fn reload(&self) -> Result<()> {
+        let state = scan(&self.dir)?;
+        *self.state.lock().unwrap() = state;
+        Ok(())
+    }
+
+    pub(crate) fn accepts(&self, entry: &CacheEntry) -> bool {
+        self.config.enabled && entry.content.len() as u64 <= self.config.max_object_bytes
+    }
+
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        self.state.lock().unwrap().index.contains_key(key)
+    }
+
+    // 追加到当前段，写满后换新段
+    fn append(&self, state: &mut State, record: &[u8]) -> Result<PackedLocation> {
+        let len = record.len() as u64;
+        let full = |state: &State, id: u64| {
+            let bytes = state.segments.get(&id).map(|s| s.bytes).unwrap_or(0);
+            bytes > 0 && bytes + len > self.config.segment_bytes
+        };
+        let reuse = match &state.current {
+            Some((id, _)) => !full(state, *id),
+            None => false,
+        };
+        if !reuse {
+            // 启动后第一次写入沿用最后一个未写满的段
+            let last = state.segments.keys().next_back().copied();
+            let id = match (state.current.as_ref(), last) {
+                (None, Some(last)) if !full(state, last) => last,
+                (_, last) => last.map(|id| id + 1).unwrap_or(1),
+            };
+            fs::create_dir_all(&self.dir)?;
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(segment_path(&self.dir, id))?;
+            state.segments.entry(id).or_default();
+            state.current = Some((id, file));
+        }
+        let (id, file) = state.current.as_mut().unwrap();
+        let id = *id;
+        file.write_all(record)?;
+        let segment = state.segments.get_mut(&id).unwrap();
+        let offset = segment.bytes;
+        segment.bytes += len;
+        Ok(PackedLocation { segment: id, offset, len })
+    }
+
+    pub(crate) fn put(&self, key: &str, entry: &CacheEntry) -> Result<()> {
+        let meta = CacheMeta {
+            generation: None,
+            ..entry.meta.clone()
+        };
+        let record = encode(PUT, key, &serde_json::to_vec(&meta)?, &entry.content)?;
+        let mut state = self.state.lock().unwrap();
+        let location = self.append(&mut state, &record)?;
+        state.relocate(key, Some(location));
+        Ok(())
+    }
+
+    // 写入墓碑记录，返回条目是否存在
+    pub(crate) fn remove(&self, key: &str) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        if !state.index.contains_key(key) {
+            return Ok(false);
+        }
+        let record = encode(DELETE, key, &[], &[])?;
+        self.append(&mut state, &record)?;
+        state.relocate(key, None);
+        Ok(true)
+    }
+
+    // 读取期间段文件可能恰好被压缩删除，此时按新的索引重读一次
+    pub(crate) fn read(&self, key: &str) -> Option<CacheEntry> {
+        for _ in 0..2 {
+            let location = *self.state.lock().unwrap().index.get(key)?;
+            match read_at(&self.dir, location) {
+                Ok((_, meta, content)) => return Some(CacheEntry { content, meta }),
+                Err(e) => tracing::debug!("failed to read packed entry {}: {}", key, e),
+            }
+        }
+        None
+    }
+
+    // 只更新元数据：小对象直接连同内容重写一条记录
+    pub(crate) fn update_meta(&self, key: &str, meta: CacheMeta) -> Result<()> {
+        let Some(entry) = self.read(key) else {
+            return Ok(());
+        };
+        self.put(key, &CacheEntry { content: entry.content, meta })
+    }
+
+    // 把有效数据占比过低的段中仍有效的记录搬到当前段，再删除旧段，返回回收的字节数。
+    // 只在写盘任务中执行，期间不会有其他写入
+    pub(crate) fn compact(&self, min_live_ratio: f64) -> Result<u64> {
+        let candidates: Vec<u64> = {
+            // 最后一个段是（或将是）追加写入的段，记录会被搬到这里，不参与压缩
+            let state = self.state.lock().unwrap();
+            let last = state.segments.keys().next_back().copied();
+            state
+                .segments
+                .iter()
+                .filter(|(id, segment)| {
+                    Some(**id) != last
+                        && (segment.live as f64) < segment.bytes as f64 * min_live_ratio
+                })
+                .map(|(id, _)| *id)
+                .collect()
+        };
+        let mut reclaimed = 0;
+        for id in candidates {
+            let keys: Vec<(String, PackedLocation)> = {
+                let state = self.state.lock().unwrap();
+                state
+                    .index
+                    .iter()
+                    .filter(|(_, location)| location.segment == id)
+                    .map(|(key, location)| (key.clone(), *location))
+                    .collect()
+            };
+            for (key, location) in keys {
+                let (_, meta, content) = read_at(&self.dir, location)?;
+                self.put(&key, &CacheEntry { content, meta })?;
+            }
+            // 更早的段里仍有该键的记录时墓碑也要搬走，否则重启重建索引时旧记录复活
+            for key in self.buried(id)? {
+                let record = encode(DELETE, &key, &[], &[])?;
+                let mut state = self.state.lock().unwrap();
+                self.append(&mut state, &record)?;
+            }
+            let mut state = self.state.lock().unwrap();
+            if let Some(segment) = state.segments.remove(&id) {
+                reclaimed += segment.bytes;
+            }
+            drop(state);
+            fs::remove_file(segment_path(&self.dir, id))?;
+            METRICS.cache_pack_compactions.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(reclaimed)
+    }
+
+    // 段 id 中的墓碑里，键当前已删除、且更早的段中还有该键记录的那些
+    fn buried(&self, id: u64) -> Result<Vec<String>> {
+        let mut tombstones: HashSet<String> = HashSet::new();
+        for (kind, key) in record_keys(&self.dir, id)? {
+            match kind {
+                DELETE => tombstones.insert(key),
+                _ => tombstones.remove(&key),
+            };
+        }
+        let older: Vec<u64> = {
+            let state = self.state.lock().unwrap();
+            tombstones.retain(|key| !state.index.contains_key(key));
+            state.segments.range(..id).map(|(id, _)| *id).collect()
+        };
+        let mut buried = Vec::new();
+        for older in older {
+            if tombstones.is_empty() {
+                break;
+            }
+            for (_, key) in record_keys(&self.dir, older)? {
+                if let Some(key) = tombstones.take(&key) {
+                    buried.push(key);
+                }
+            }
+        }
+        Ok(buried)
+    }
+
+    // 所有有效条目：(key, 位置, 元数据)，供离线工具使用
+    pub(crate) fn entries(&self) -> Vec<(String, PackedLocation, Option<CacheMeta>)> {
+        let index: Vec<(String, PackedLocation)> = {
+            let state = self.state.lock().unwrap();
+            state.index.iter().map(|(k, l)| (k.clone(), *l)).collect()
+        };
+        index
+            .into_iter()
+            .map(|(key, location)| {
+                let meta = read_at(&self.dir, location).ok().map(|(_, meta, _)| meta);
+                (key, location, meta)
+            })
+            .collect()
+    }
+}
+
+// 离线读取打包条目的内容
+pub(crate) fn read_content(cache_dir: &Path, location: PackedLocation) -> Result<Bytes> {
+    Ok(read_at(&cache_dir.join(PACK_DIR), location)?.2)
+}
+
+pub(crate) fn segment_modified(cache_dir: &Path, segment: u64) -> Result<SystemTime> {
+    Ok(fs::metadata(segment_path(&cache_dir.join(PACK_DIR), segment))?.modified()?)
+}
+
+// 离线删除：直接在最后一个段末尾追加墓碑记录，不需要重建索引
+pub(crate) fn append_tombstone(cache_dir: &Path, key: &str) -> Result<()> {
+    let dir = cache_dir.join(PACK_DIR);
+    let Some(last) = segment_ids(&dir)?.pop() else {
+        return Ok(());
+    };
+    let mut file = OpenOptions::new().append(true).open(segment_path(&dir, last))?;
+    file.write_all(&encode(DELETE, key, &[], &[])?)?;
+    Ok(())
+}
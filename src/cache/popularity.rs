@@ -0,0 +1,66 @@
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use hyper::Uri;
+use lru::LruCache;
+
+struct HotEntry {
+    uri: Uri,
+    hits: u64,
+}
+
+// 记录最近被命中的条目及命中次数，供提前刷新挑选热门对象
+pub struct Popularity {
+    entries: Mutex<LruCache<String, HotEntry>>,
+}
+
+impl Popularity {
+    pub fn new(capacity: usize) -> Self {
+        Popularity {
+            entries: Mutex::new(LruCache::new(NonZeroUsize::new(capacity.max(1)).unwrap())),
+        }
+    }
+
+    pub fn record_hit(&self, key: &str, uri: &Uri) {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get_mut(key) {
+            Some(entry) => entry.hits += 1,
+            None => {
+                entries.put(
+                    key.to_string(),
+                    HotEntry {
+                        uri: uri.clone(),
+                        hits: 1,
+                    },
+                );
+            }
+        }
+    }
+
+    // 命中次数不低于 min_hits 的条目，按命中次数从高到低排列
+    pub fn hot_entries(&self, min_hits: u64) -> Vec<(String, Uri, u64)> {
+        let entries = self.entries.lock().unwrap();
+        let mut hot: Vec<_> = entries
+            .iter()
+            .filter(|(_, entry)| entry.hits >= min_hits)
+            .map(|(key, entry)| (key.clone(), entry.uri.clone(), entry.hits))
+            .collect();
+        hot.sort_unstable_by_key(|(_, _, hits)| std::cmp::Reverse(*hits));
+        hot
+    }
+
+    // 命中次数减半，使热度反映最近的访问频率；归零的条目不再跟踪
+    pub fn decay(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        let cold: Vec<String> = entries
+            .iter_mut()
+            .filter_map(|(key, entry)| {
+                entry.hits /= 2;
+                (entry.hits == 0).then(|| key.clone())
+            })
+            .collect();
+        for key in cold {
+            entries.pop(&key);
+        }
+    }
+}
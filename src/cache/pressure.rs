@@ -0,0 +1,96 @@
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use super::inspect::{self, EntryInfo};
+use crate::constants::{DISK_FULL_EVICT_FRACTION, DISK_FULL_RETRY_SECONDS};
+use crate::metrics::METRICS;
+
+// 磁盘空间不足时的降级：暂停写盘（只保留内存缓存），紧急淘汰最旧的条目，
+// 一段时间后再尝试写入，成功即恢复
+#[derive(Clone, Default)]
+pub(crate) struct DiskPressure {
+    full_since: Arc<Mutex<Option<Instant>>>,
+}
+
+impl DiskPressure {
+    // 是否应跳过写盘；超过重试间隔后放行一次写入用于探测空间是否恢复
+    pub(crate) fn is_full(&self) -> bool {
+        match *self.full_since.lock().unwrap() {
+            Some(since) => since.elapsed() < Duration::from_secs(DISK_FULL_RETRY_SECONDS),
+            None => false,
+        }
+    }
+
+    pub(crate) fn mark_full(&self) {
+        let mut full_since = self.full_since.lock().unwrap();
+        if full_since.is_none() {
+            tracing::error!("cache disk is full, caching to disk is suspended");
+        }
+        *full_since = Some(Instant::now());
+        METRICS.cache_disk_full.store(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn recovered(&self) {
+        if self.full_since.lock().unwrap().take().is_some() {
+            tracing::info!("cache disk has free space again, caching to disk resumed");
+            METRICS.cache_disk_full.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+// ENOSPC / EDQUOT（Windows 上为 ERROR_DISK_FULL 等）
+pub(crate) fn is_disk_full(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause.downcast_ref::<std::io::Error>().is_some_and(|e| {
+            matches!(e.kind(), std::io::ErrorKind::StorageFull | std::io::ErrorKind::QuotaExceeded)
+        })
+    })
+}
+
+// 磁盘缓存超过配额时按写入时间从旧到新选出要淘汰的条目，直到总量不超过配额；
+// 返回淘汰后的总量与这些条目，删除由写盘任务完成
+pub(crate) fn quota_victims(dir: &Path, max_bytes: u64) -> Result<(u64, Vec<EntryInfo>)> {
+    let mut entries = inspect::list(dir)?;
+    let mut total: u64 = entries.iter().map(|entry| entry.size).sum();
+    if total <= max_bytes {
+        return Ok((total, Vec::new()));
+    }
+    // 删除打包条目只追加墓碑记录，腾不出空间
+    entries.retain(|entry| entry.packed.is_none());
+    entries.sort_by_key(|entry| entry.modified);
+    let mut victims = Vec::new();
+    for entry in entries {
+        if total <= max_bytes {
+            break;
+        }
+        total = total.saturating_sub(entry.size);
+        victims.push(entry);
+    }
+    Ok((total, victims))
+}
+
+// 按写入时间从旧到新选出要紧急淘汰的条目，直到达到磁盘缓存总量的一定比例；
+// 删除由写盘任务完成
+pub(crate) fn emergency_victims(dir: &Path) -> Result<Vec<EntryInfo>> {
+    let mut entries = inspect::list(dir)?;
+    // 删除打包条目只追加墓碑记录，腾不出空间
+    entries.retain(|entry| entry.packed.is_none());
+    let total: u64 = entries.iter().map(|entry| entry.size).sum();
+    let target = ((total as f64 * DISK_FULL_EVICT_FRACTION) as u64).max(1);
+    entries.sort_by_key(|entry| entry.modified);
+
+    let mut freed = 0;
+    let mut victims = Vec::new();
+    for entry in entries {
+        if freed >= target {
+            break;
+        }
+        freed += entry.size;
+        victims.push(entry);
+    }
+    Ok(victims)
+}
@@ -0,0 +1,146 @@
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+// 条目中已缓存的字节区间（左闭右开，按起点排序且互不相邻）。
+// 内容文件按区间顺序紧凑存放这些字节，区间之间的空洞不占空间
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ByteRanges(Vec<(u64, u64)>);
+
+impl ByteRanges {
+    // 单个区间 [start, end)
+    pub fn single(start: u64, end: u64) -> Self {
+        if start >= end {
+            ByteRanges(Vec::new())
+        } else {
+            ByteRanges(vec![(start, end)])
+        }
+    }
+
+    // 从 0 开始的连续前缀，对应旧版本的条目格式
+    pub fn prefix(len: u64) -> Self {
+        Self::single(0, len)
+    }
+
+    pub fn as_slice(&self) -> &[(u64, u64)] {
+        &self.0
+    }
+
+    pub fn covered_bytes(&self) -> u64 {
+        self.0.iter().map(|(s, e)| e - s).sum()
+    }
+
+    pub fn contains(&self, start: u64, end: u64) -> bool {
+        self.0.iter().any(|&(s, e)| s <= start && end <= e)
+    }
+
+    pub fn covers(&self, total: u64) -> bool {
+        total == 0 || self.contains(0, total)
+    }
+
+    // [0, total) 中尚未缓存的区间
+    pub fn gaps(&self, total: u64) -> Vec<(u64, u64)> {
+        let mut gaps = Vec::new();
+        let mut pos = 0;
+        for &(s, e) in &self.0 {
+            if s >= total {
+                break;
+            }
+            if s > pos {
+                gaps.push((pos, s));
+            }
+            pos = pos.max(e);
+        }
+        if pos < total {
+            gaps.push((pos, total));
+        }
+        gaps
+    }
+
+    // 包含 pos 的已缓存区间的结束位置（不含），pos 未缓存时返回 None
+    pub fn run_end(&self, pos: u64) -> Option<u64> {
+        self.0.iter().find(|&&(s, e)| s <= pos && pos < e).map(|&(_, e)| e)
+    }
+
+    // 对象偏移量在紧凑内容中的位置
+    pub fn packed_offset(&self, pos: u64) -> Option<usize> {
+        let mut packed = 0;
+        for &(s, e) in &self.0 {
+            if s <= pos && pos < e {
+                return Some((packed + pos - s) as usize);
+            }
+            packed += e - s;
+        }
+        None
+    }
+
+    // 只保留紧凑内容前 len 字节对应的区间（淘汰尾部数据时使用）
+    pub fn truncate_packed(&self, len: u64) -> ByteRanges {
+        let mut ranges = Vec::new();
+        let mut remaining = len;
+        for &(s, e) in &self.0 {
+            if remaining == 0 {
+                break;
+            }
+            let take = (e - s).min(remaining);
+            ranges.push((s, s + take));
+            remaining -= take;
+        }
+        ByteRanges(ranges)
+    }
+
+    // 取出对象的 [start, end) 字节
+    pub fn slice(&self, content: &Bytes, start: u64, end: u64) -> Option<Bytes> {
+        if start >= end || !self.contains(start, end) {
+            return None;
+        }
+        let offset = self.packed_offset(start)?;
+        content.get(offset..offset + (end - start) as usize)?;
+        Some(content.slice(offset..offset + (end - start) as usize))
+    }
+
+    // 将 start 处的 data 合并进紧凑内容，返回新的区间与内容
+    pub fn merge(&self, content: &Bytes, start: u64, data: &[u8]) -> (ByteRanges, Bytes) {
+        let mut segments: Vec<(u64, &[u8])> = Vec::with_capacity(self.0.len() + 1);
+        let mut packed = 0usize;
+        for &(s, e) in &self.0 {
+            let len = (e - s) as usize;
+            segments.push((s, &content[packed..packed + len]));
+            packed += len;
+        }
+        if !data.is_empty() {
+            segments.push((start, data));
+        }
+        segments.sort_by_key(|(s, _)| *s);
+
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
+        let mut merged = Vec::with_capacity(packed + data.len());
+        for (s, bytes) in segments {
+            let e = s + bytes.len() as u64;
+            match ranges.last_mut() {
+                // 与上一个区间重叠或相邻：只追加超出的部分（同一对象的重叠字节相同）
+                Some(last) if s <= last.1 => {
+                    if e > last.1 {
+                        merged.extend_from_slice(&bytes[(last.1 - s) as usize..]);
+                        last.1 = e;
+                    }
+                }
+                _ => {
+                    merged.extend_from_slice(bytes);
+                    ranges.push((s, e));
+                }
+            }
+        }
+        (ByteRanges(ranges), Bytes::from(merged))
+    }
+}
+
+impl std::fmt::Display for ByteRanges {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "none");
+        }
+        let parts: Vec<String> = self.0.iter().map(|(s, e)| format!("{}-{}", s, e - 1)).collect();
+        write!(f, "bytes {}", parts.join(","))
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use super::inspect::entry_names;
+use super::packs::Packs;
+use super::CacheMeta;
+
+// 源站给条目打的标签：Surrogate-Key 以空格分隔（Fastly），Cache-Tag 以逗号分隔（Cloudflare）
+const SURROGATE_KEY: &str = "surrogate-key";
+const CACHE_TAG: &str = "cache-tag";
+
+pub fn parse_tags(headers: &[(String, String)]) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for (name, value) in headers {
+        let parts: Vec<&str> = match name.as_str() {
+            SURROGATE_KEY => value.split_ascii_whitespace().collect(),
+            CACHE_TAG => value.split(',').map(str::trim).collect(),
+            _ => continue,
+        };
+        for tag in parts {
+            if !tag.is_empty() && !tags.iter().any(|t| t == tag) {
+                tags.push(tag.to_string());
+            }
+        }
+    }
+    tags
+}
+
+#[derive(Default)]
+struct TagMap {
+    keys: HashMap<String, HashSet<String>>,
+    tags: HashMap<String, Vec<String>>,
+}
+
+impl TagMap {
+    fn remove(&mut self, key: &str) {
+        for tag in self.tags.remove(key).unwrap_or_default() {
+            if let Some(keys) = self.keys.get_mut(&tag) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.keys.remove(&tag);
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, key: &str, tags: Vec<String>) {
+        self.remove(key);
+        if tags.is_empty() {
+            return;
+        }
+        for tag in &tags {
+            self.keys.entry(tag.clone()).or_default().insert(key.to_string());
+        }
+        self.tags.insert(key.to_string(), tags);
+    }
+}
+
+// 标签 -> 缓存键的索引，只保存在内存中，启动时扫描元数据重建。
+// 被淘汰的条目不会从索引中移除，按标签删除时发现不存在再清理
+#[derive(Clone, Default)]
+pub struct TagIndex {
+    map: Arc<Mutex<TagMap>>,
+}
+
+impl TagIndex {
+    // 条目写入或元数据更新后调用，替换该键原有的标签
+    pub fn update(&self, key: &str, meta: &CacheMeta) {
+        self.map.lock().unwrap().insert(key, parse_tags(&meta.headers));
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.map.lock().unwrap().remove(key);
+    }
+
+    pub fn keys(&self, tag: &str) -> Vec<String> {
+        let map = self.map.lock().unwrap();
+        map.keys.get(tag).map(|keys| keys.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    // 扫描结果只补充启动后还没有写入过的键，不覆盖更新的标签
+    fn load(&self, found: Vec<(String, Vec<String>)>) {
+        let mut map = self.map.lock().unwrap();
+        for (key, tags) in found {
+            if !map.tags.contains_key(&key) {
+                map.insert(&key, tags);
+            }
+        }
+    }
+
+    // 在阻塞线程中调用：读取磁盘与打包存储中所有条目的元数据
+    pub(crate) fn rebuild(&self, dir: &Path, packs: &Packs) {
+        let names = match entry_names(dir) {
+            Ok(names) => names,
+            Err(e) => {
+                tracing::warn!("failed to index cache tags: {:#}", e);
+                return;
+            }
+        };
+        let files = names.iter().filter_map(|name| {
+            let key = name.strip_suffix(".meta")?;
+            let meta: CacheMeta = serde_json::from_slice(&std::fs::read(dir.join(name)).ok()?).ok()?;
+            Some((key.to_string(), parse_tags(&meta.headers)))
+        });
+        let packed = packs
+            .entries()
+            .into_iter()
+            .filter_map(|(key, _, meta)| Some((key, parse_tags(&meta?.headers))));
+        let found: Vec<_> = files.chain(packed).filter(|(_, tags)| !tags.is_empty()).collect();
+        self.load(found);
+    }
+}
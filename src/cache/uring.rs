@@ -0,0 +1,98 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use bytes::Bytes;
+use tokio::sync::{mpsc, oneshot};
+
+// io_uring 需要独立的运行时，所有读写请求转发到专用线程执行
+enum UringJob {
+    Read {
+        path: PathBuf,
+        reply: oneshot::Sender<io::Result<Bytes>>,
+    },
+    Write {
+        path: PathBuf,
+        data: Bytes,
+        reply: oneshot::Sender<io::Result<()>>,
+    },
+}
+
+static URING: OnceLock<mpsc::UnboundedSender<UringJob>> = OnceLock::new();
+
+fn sender() -> &'static mpsc::UnboundedSender<UringJob> {
+    URING.get_or_init(|| {
+        let (tx, mut rx) = mpsc::unbounded_channel::<UringJob>();
+        std::thread::Builder::new()
+            .name("cache-uring".to_string())
+            .spawn(move || {
+                tokio_uring::start(async move {
+                    while let Some(job) = rx.recv().await {
+                        tokio_uring::spawn(run_job(job));
+                    }
+                });
+            })
+            .expect("failed to spawn io_uring thread");
+        tx
+    })
+}
+
+async fn run_job(job: UringJob) {
+    match job {
+        UringJob::Read { path, reply } => {
+            let _ = reply.send(read_file(path).await);
+        }
+        UringJob::Write { path, data, reply } => {
+            let _ = reply.send(write_file(path, data).await);
+        }
+    }
+}
+
+async fn read_file(path: PathBuf) -> io::Result<Bytes> {
+    let len = std::fs::metadata(&path)?.len() as usize;
+    let file = tokio_uring::fs::File::open(&path).await?;
+    let mut content = Vec::with_capacity(len);
+    while content.len() < len {
+        let buf = Vec::with_capacity(len - content.len());
+        let (res, buf) = file.read_at(buf, content.len() as u64).await;
+        if res? == 0 {
+            break;
+        }
+        content.extend_from_slice(&buf);
+    }
+    file.close().await?;
+    Ok(Bytes::from(content))
+}
+
+async fn write_file(path: PathBuf, mut data: Bytes) -> io::Result<()> {
+    let file = tokio_uring::fs::File::create(&path).await?;
+    let mut pos = 0u64;
+    while !data.is_empty() {
+        let (res, buf) = file.write_at(data, pos).await;
+        let written = res?;
+        if written == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        pos += written as u64;
+        data = buf.slice(written..);
+    }
+    file.close().await
+}
+
+pub(crate) async fn read(path: PathBuf) -> io::Result<Bytes> {
+    let (reply, rx) = oneshot::channel();
+    sender()
+        .send(UringJob::Read { path, reply })
+        .map_err(|_| io::Error::other("io_uring thread has stopped"))?;
+    rx.await
+        .map_err(|_| io::Error::other("io_uring thread has stopped"))?
+}
+
+pub(crate) async fn write(path: PathBuf, data: Bytes) -> io::Result<()> {
+    let (reply, rx) = oneshot::channel();
+    sender()
+        .send(UringJob::Write { path, data, reply })
+        .map_err(|_| io::Error::other("io_uring thread has stopped"))?;
+    rx.await
+        .map_err(|_| io::Error::other("io_uring thread has stopped"))?
+}
@@ -0,0 +1,36 @@
+// 实体标签比较（RFC 9110 8.8.3.2）：强比较要求两者都不是弱标签且值相同，
+// 弱比较忽略 W/ 前缀只比较值
+
+// 拆分为 (是否弱标签, 带引号的值)
+fn parse(tag: &str) -> (bool, &str) {
+    let tag = tag.trim();
+    match tag.strip_prefix("W/") {
+        Some(value) => (true, value),
+        None => (false, tag),
+    }
+}
+
+pub fn is_weak(tag: &str) -> bool {
+    parse(tag).0
+}
+
+pub fn strong_match(a: &str, b: &str) -> bool {
+    let (a_weak, a) = parse(a);
+    let (b_weak, b) = parse(b);
+    !a_weak && !b_weak && a == b
+}
+
+pub fn weak_match(a: &str, b: &str) -> bool {
+    parse(a).1 == parse(b).1
+}
+
+// If-None-Match 的值（* 或逗号分隔的标签列表）中是否有与 etag 弱匹配的标签
+pub fn none_match(header: &str, etag: &str) -> bool {
+    header.trim() == "*" || header.split(',').any(|tag| weak_match(tag, etag))
+}
+
+// If-Range 的值以引号或 W/ 开头时是实体标签，否则是日期
+pub fn is_entity_tag(value: &str) -> bool {
+    let value = value.trim();
+    value.starts_with('"') || value.starts_with("W/")
+}
@@ -0,0 +1,495 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use tokio::fs;
+use tokio::sync::{mpsc, oneshot};
+
+use super::disk::{Disk, SharedDisk};
+use super::generations::{content_path, meta_path, Generations};
+use super::io_limit::DiskIoLimiter;
+use super::janitor::{self, Task};
+use super::packs::Packs;
+use super::inspect::EntryInfo;
+use super::pressure::{emergency_victims, is_disk_full, quota_victims, DiskPressure};
+use crate::config::CacheConfig;
+use crate::metrics::METRICS;
+use super::memory::ShardedLru;
+use super::tags::TagIndex;
+use super::{chunks, sha256_hex, CacheEntry, CacheMeta};
+
+// 尚未落盘的条目：key -> (写入序号, 条目)
+pub(crate) type PendingWrites = Arc<Mutex<HashMap<String, (u64, CacheEntry)>>>;
+
+pub(crate) enum DiskJob {
+    Write {
+        key: String,
+        seq: u64,
+        entry: CacheEntry,
+    },
+    // 只重写 .meta 文件
+    Meta {
+        key: String,
+        meta: CacheMeta,
+    },
+    // 删除 .meta，内容等读者结束后删除，完成后通知
+    Remove {
+        key: String,
+        done: oneshot::Sender<()>,
+    },
+    // 后台整理任务，完成后通知调度
+    Maintain(Task, oneshot::Sender<()>),
+    // 队列按顺序处理，收到 Flush 时之前的写入都已完成
+    Flush(oneshot::Sender<()>),
+    // 升级交接：之前的写入完成后停止写盘，直到 Resume
+    Suspend(oneshot::Sender<()>),
+    // 重新取得目录锁后恢复写盘
+    Resume(oneshot::Sender<Result<()>>),
+}
+
+// 后台写盘任务与读取路径共享的状态
+pub(crate) struct Writer {
+    pub(crate) cache_dir: PathBuf,
+    pub(crate) pending: PendingWrites,
+    pub(crate) generations: Generations,
+    pub(crate) pressure: DiskPressure,
+    pub(crate) disk_io: DiskIoLimiter,
+    pub(crate) packs: Packs,
+    pub(crate) memory_cache: Arc<ShardedLru<CacheEntry>>,
+    pub(crate) tags: TagIndex,
+    pub(crate) disk: SharedDisk,
+    pub(crate) config: CacheConfig,
+}
+
+// 后台写盘任务：从有界队列中取出写入请求依次持久化
+pub(crate) async fn run_writer(writer: Writer, mut rx: mpsc::Receiver<DiskJob>) {
+    let Writer {
+        cache_dir,
+        pending,
+        generations,
+        pressure,
+        disk_io,
+        packs,
+        memory_cache,
+        tags,
+        disk,
+        config,
+    } = writer;
+    let compute_checksums = config.verify_checksums;
+    let chunk_bytes = config.chunk_bytes;
+    // 磁盘缓存总量的估计：写入时累加，超过配额时重新统计并淘汰；None 表示尚未统计
+    let mut disk_usage: Option<u64> = None;
+    // 后台完整性校验的进度：上一批最后一个键
+    let mut verify_cursor: Option<String> = None;
+    // 目录锁已交给其他进程：不再写盘，只保留内存缓存
+    let mut suspended = false;
+    while let Some(job) = rx.recv().await {
+        let _permit = match job {
+            DiskJob::Flush(_) | DiskJob::Suspend(_) | DiskJob::Resume(_) => None,
+            _ if suspended => None,
+            _ => Some(disk_io.acquire().await),
+        };
+        match job {
+            DiskJob::Write { key, seq, .. } if suspended => {
+                METRICS.cache_writes_skipped.fetch_add(1, Ordering::Relaxed);
+                remove_pending(&pending, &key, seq);
+            }
+            DiskJob::Meta { .. } if suspended => {}
+            DiskJob::Remove { done, .. } if suspended => {
+                let _ = done.send(());
+            }
+            DiskJob::Maintain(_, done) if suspended => {
+                let _ = done.send(());
+            }
+            DiskJob::Write { key, seq, mut entry } => {
+                // 磁盘已满：只保留内存缓存，代理流量照常转发
+                if pressure.is_full() {
+                    METRICS.cache_writes_skipped.fetch_add(1, Ordering::Relaxed);
+                    remove_pending(&pending, &key, seq);
+                    continue;
+                }
+                if compute_checksums && entry.meta.is_complete && entry.meta.sha256.is_none() {
+                    let content = entry.content.clone();
+                    entry.meta.sha256 = tokio::task::spawn_blocking(move || sha256_hex(&content))
+                        .await
+                        .ok();
+                }
+                let size = entry.content.len() as u64;
+                let written = if packs.accepts(&entry) {
+                    pack_entry(&cache_dir, &key, entry, &packs, &generations).await
+                } else {
+                    write_entry(&*disk, &cache_dir, &key, &entry, chunk_bytes, &generations, &packs).await
+                };
+                match written {
+                    Ok(()) => {
+                        pressure.recovered();
+                        if let Some(max_bytes) = config.max_disk_bytes {
+                            disk_usage = disk_usage.map(|usage| usage + size);
+                            if disk_usage.is_none_or(|usage| usage > max_bytes) {
+                                disk_usage = enforce_quota(
+                                    &cache_dir,
+                                    max_bytes,
+                                    &pending,
+                                    &memory_cache,
+                                    &tags,
+                                    &packs,
+                                    &generations,
+                                )
+                                .await;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        METRICS.cache_write_errors.fetch_add(1, Ordering::Relaxed);
+                        tracing::warn!("failed to persist cache entry {}: {}", key, e);
+                        if is_disk_full(&e) {
+                            pressure.mark_full();
+                            emergency_evict(&cache_dir, &pending, &memory_cache, &tags, &packs, &generations).await;
+                        }
+                    }
+                }
+                remove_pending(&pending, &key, seq);
+            }
+            DiskJob::Meta { key, meta } => {
+                let updated = if packs.contains(&key) {
+                    let (packs, key) = (packs.clone(), key.clone());
+                    blocking(move || packs.update_meta(&key, meta)).await
+                } else {
+                    update_meta(&*disk, &cache_dir, &key, meta).await
+                };
+                if let Err(e) = updated {
+                    tracing::warn!("failed to update cache meta {}: {}", key, e);
+                }
+            }
+            DiskJob::Remove { key, done } => {
+                remove_entry(&cache_dir, &key, &packs, &generations).await;
+                // 删除之前并发的读取可能把磁盘上的旧内容又加载回内存
+                memory_cache.remove(&key);
+                let _ = done.send(());
+            }
+            DiskJob::Maintain(task, done) => {
+                match task {
+                    Task::Compact => {
+                        let packs = packs.clone();
+                        let min_live_ratio = config.packing.min_live_ratio;
+                        match blocking(move || packs.compact(min_live_ratio)).await {
+                            Ok(0) => {}
+                            Ok(reclaimed) => tracing::info!("pack compaction reclaimed {} bytes", reclaimed),
+                            Err(e) => tracing::warn!("pack compaction failed: {}", e),
+                        }
+                    }
+                    Task::Quota => {
+                        if let Some(max_bytes) = config.max_disk_bytes {
+                            disk_usage = enforce_quota(
+                                &cache_dir,
+                                max_bytes,
+                                &pending,
+                                &memory_cache,
+                                &tags,
+                                &packs,
+                                &generations,
+                            )
+                            .await;
+                        }
+                    }
+                    Task::Sweep { expired_before } => {
+                        let dir = cache_dir.clone();
+                        match blocking(move || janitor::expired(&dir, expired_before)).await {
+                            Ok(keys) => {
+                                for key in &keys {
+                                    discard(key, &pending, &memory_cache, &tags);
+                                    remove_entry(&cache_dir, key, &packs, &generations).await;
+                                }
+                                if !keys.is_empty() {
+                                    METRICS.cache_expired_swept.fetch_add(keys.len() as u64, Ordering::Relaxed);
+                                    tracing::info!("removed {} expired cache entries", keys.len());
+                                    // 下一次写入时重新统计磁盘用量
+                                    disk_usage = None;
+                                }
+                            }
+                            Err(e) => tracing::warn!("expired entry sweep failed: {}", e),
+                        }
+                    }
+                    Task::Verify { batch } => {
+                        let (dir, after) = (cache_dir.clone(), verify_cursor.take());
+                        match blocking(move || janitor::verify(&dir, after.as_deref(), batch)).await {
+                            Ok((corrupt, last)) => {
+                                for key in &corrupt {
+                                    discard(key, &pending, &memory_cache, &tags);
+                                    remove_entry(&cache_dir, key, &packs, &generations).await;
+                                }
+                                METRICS.cache_corrupt_entries.fetch_add(corrupt.len() as u64, Ordering::Relaxed);
+                                verify_cursor = last;
+                            }
+                            Err(e) => tracing::warn!("cache integrity scan failed: {}", e),
+                        }
+                    }
+                }
+                let _ = done.send(());
+            }
+            DiskJob::Flush(done) => {
+                let _ = done.send(());
+            }
+            DiskJob::Suspend(done) => {
+                suspended = true;
+                let _ = done.send(());
+            }
+            DiskJob::Resume(done) => {
+                let packs = packs.clone();
+                let reloaded = blocking(move || packs.reload()).await;
+                if reloaded.is_ok() {
+                    suspended = false;
+                    // 其他进程可能写入过，下一次写入时重新统计磁盘用量
+                    disk_usage = None;
+                    verify_cursor = None;
+                }
+                let _ = done.send(reloaded);
+            }
+        }
+    }
+}
+
+// 删除 .meta 与打包记录，内容文件等读者结束后删除
+async fn remove_entry(cache_dir: &Path, key: &str, packs: &Packs, generations: &Generations) {
+    let removed = {
+        let (packs, key) = (packs.clone(), key.to_string());
+        blocking(move || packs.remove(&key)).await
+    };
+    if let Err(e) = removed {
+        tracing::warn!("failed to remove packed cache entry {}: {}", key, e);
+    }
+    if let Some(meta) = read_meta(cache_dir, key).await {
+        if let Err(e) = fs::remove_file(meta_path(cache_dir, key)).await {
+            tracing::warn!("failed to remove cache entry {}: {}", key, e);
+        }
+        generations.retire(content_path(cache_dir, key, meta.generation));
+    }
+}
+
+// 超过配额时淘汰最旧的条目，返回淘汰后的磁盘用量；统计失败时返回 None，下一次写入时重试
+async fn enforce_quota(
+    cache_dir: &Path,
+    max_bytes: u64,
+    pending: &PendingWrites,
+    memory_cache: &ShardedLru<CacheEntry>,
+    tags: &TagIndex,
+    packs: &Packs,
+    generations: &Generations,
+) -> Option<u64> {
+    let dir = cache_dir.to_path_buf();
+    let (usage, victims) = match blocking(move || quota_victims(&dir, max_bytes)).await {
+        Ok(found) => found,
+        Err(e) => {
+            tracing::warn!("failed to list {} for the disk quota: {}", cache_dir.display(), e);
+            return None;
+        }
+    };
+    for victim in &victims {
+        evict(cache_dir, victim, pending, memory_cache, tags, packs, generations).await;
+    }
+    METRICS.cache_quota_evictions.fetch_add(victims.len() as u64, Ordering::Relaxed);
+    Some(usage)
+}
+
+// 磁盘已满时紧急淘汰最旧的条目，与配额淘汰一样经过 Generations 删除并移出内存缓存
+async fn emergency_evict(
+    cache_dir: &Path,
+    pending: &PendingWrites,
+    memory_cache: &ShardedLru<CacheEntry>,
+    tags: &TagIndex,
+    packs: &Packs,
+    generations: &Generations,
+) {
+    let dir = cache_dir.to_path_buf();
+    let victims = match blocking(move || emergency_victims(&dir)).await {
+        Ok(victims) => victims,
+        Err(e) => {
+            tracing::warn!("emergency eviction failed to list {}: {}", cache_dir.display(), e);
+            return;
+        }
+    };
+    for victim in &victims {
+        evict(cache_dir, victim, pending, memory_cache, tags, packs, generations).await;
+    }
+    METRICS.cache_emergency_evictions.fetch_add(victims.len() as u64, Ordering::Relaxed);
+    let freed: u64 = victims.iter().map(|victim| victim.size).sum();
+    tracing::warn!("emergency eviction freed {} bytes from {}", freed, cache_dir.display());
+}
+
+// 淘汰扫描到的条目：内容交给 Generations，等正在读取的请求结束后再删除。
+// 待写队列中有同一个键更新的写入时，内存与标签索引中已是新内容，保留；
+// 指向其他代的残留旧代不影响当前条目，只删除内容
+async fn evict(
+    cache_dir: &Path,
+    victim: &EntryInfo,
+    pending: &PendingWrites,
+    memory_cache: &ShardedLru<CacheEntry>,
+    tags: &TagIndex,
+    packs: &Packs,
+    generations: &Generations,
+) {
+    if victim.meta.is_none() {
+        generations.retire(victim.path(cache_dir));
+        return;
+    }
+    if !pending.lock().unwrap().contains_key(&victim.key) {
+        tags.remove(&victim.key);
+        memory_cache.remove(&victim.key);
+    }
+    remove_entry(cache_dir, &victim.key, packs, generations).await;
+}
+
+// 后台整理删除的条目与 ProxyCache::purge 一样从标签索引、内存缓存与待写队列中移除，
+// 之后不会再从内存返回过期或损坏的内容
+fn discard(key: &str, pending: &PendingWrites, memory_cache: &ShardedLru<CacheEntry>, tags: &TagIndex) {
+    tags.remove(key);
+    memory_cache.remove(key);
+    pending.lock().unwrap().remove(key);
+}
+
+// 只移除本次写入对应的记录，避免覆盖更新的写入
+fn remove_pending(pending: &PendingWrites, key: &str, seq: u64) {
+    let mut pending = pending.lock().unwrap();
+    if pending.get(key).map(|(s, _)| *s == seq).unwrap_or(false) {
+        pending.remove(key);
+    }
+}
+
+// 在后台线程执行打包存储的同步读写
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(f).await?
+}
+
+// 小对象写入打包存储，同一个 key 之前按文件存放的内容随后删除
+async fn pack_entry(
+    cache_dir: &Path,
+    key: &str,
+    entry: CacheEntry,
+    packs: &Packs,
+    generations: &Generations,
+) -> Result<()> {
+    let (packed, packed_key) = (packs.clone(), key.to_string());
+    blocking(move || packed.put(&packed_key, &entry)).await?;
+    if let Some(meta) = read_meta(cache_dir, key).await {
+        fs::remove_file(meta_path(cache_dir, key)).await?;
+        generations.retire(content_path(cache_dir, key, meta.generation));
+    }
+    Ok(())
+}
+
+async fn write_entry(
+    disk: &dyn Disk,
+    cache_dir: &Path,
+    key: &str,
+    entry: &CacheEntry,
+    chunk_bytes: u64,
+    generations: &Generations,
+    packs: &Packs,
+) -> Result<()> {
+    // 新内容写入下一代，切换 .meta 之前读者看到的始终是旧代
+    let previous = read_meta(cache_dir, key).await;
+    let old_path = previous
+        .as_ref()
+        .map(|meta| content_path(cache_dir, key, meta.generation))
+        .filter(|path| path.exists());
+    let mut generation = previous
+        .and_then(|meta| meta.generation)
+        .map(|generation| generation + 1)
+        .unwrap_or(1);
+    // 条目被删除后重新写入时，旧内容可能仍在等待读者结束
+    while content_path(cache_dir, key, Some(generation)).exists() {
+        generation += 1;
+    }
+    let file_path = content_path(cache_dir, key, Some(generation));
+    create_parent(disk, &file_path).await?;
+    let meta = CacheMeta {
+        generation: Some(generation),
+        ..entry.meta.clone()
+    };
+    let written = match write_generation(disk, &file_path, entry, chunk_bytes, old_path.clone()).await {
+        Ok(()) => write_meta(disk, cache_dir, key, &meta).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = written {
+        // 写到一半失败（例如磁盘已满）时删除新一代的残留，.meta 仍指向旧代
+        let _ = fs::remove_dir_all(&file_path).await;
+        let _ = fs::remove_file(&file_path).await;
+        let _ = fs::remove_file(tmp_path(&file_path)).await;
+        return Err(e);
+    }
+    if let Some(old_path) = old_path {
+        generations.retire(old_path);
+    }
+    // 对象变大后不再打包存储，作废之前打包的记录
+    if packs.contains(key) {
+        let (packs, key) = (packs.clone(), key.to_string());
+        blocking(move || packs.remove(&key)).await?;
+    }
+    Ok(())
+}
+
+async fn write_generation(
+    disk: &dyn Disk,
+    file_path: &Path,
+    entry: &CacheEntry,
+    chunk_bytes: u64,
+    old_path: Option<PathBuf>,
+) -> Result<()> {
+    if entry.content.len() as u64 > chunk_bytes {
+        // 超过一个块的对象按块存放，未变化的块从旧代硬链接过来
+        let (dir, content) = (file_path.to_path_buf(), entry.content.clone());
+        let previous_dir = old_path.filter(|path| path.is_dir());
+        tokio::task::spawn_blocking(move || {
+            chunks::write_chunks(&dir, &content, chunk_bytes, previous_dir.as_deref())
+        })
+        .await??;
+    } else {
+        let tmp_path = tmp_path(file_path);
+        disk.write(tmp_path.clone(), entry.content.clone()).await?;
+        disk.rename(tmp_path, file_path.to_path_buf()).await?;
+    }
+    Ok(())
+}
+
+async fn read_meta(cache_dir: &Path, key: &str) -> Option<CacheMeta> {
+    let meta_str = fs::read_to_string(meta_path(cache_dir, key))
+        .await
+        .ok()?;
+    serde_json::from_str(&meta_str).ok()
+}
+
+// 只更新元数据时保留磁盘上当前的代号
+async fn update_meta(disk: &dyn Disk, cache_dir: &Path, key: &str, mut meta: CacheMeta) -> Result<()> {
+    meta.generation = read_meta(cache_dir, key).await.and_then(|m| m.generation);
+    write_meta(disk, cache_dir, key, &meta).await
+}
+
+// 先写临时文件再 rename，读者不会读到写了一半的 .meta
+async fn write_meta(disk: &dyn Disk, cache_dir: &Path, key: &str, meta: &CacheMeta) -> Result<()> {
+    let file_path = meta_path(cache_dir, key);
+    create_parent(disk, &file_path).await?;
+    let tmp_path = tmp_path(&file_path);
+    let written = disk.write(tmp_path.clone(), serde_json::to_vec(meta)?.into()).await;
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp_path).await;
+        return Err(e.into());
+    }
+    disk.rename(tmp_path, file_path).await?;
+    Ok(())
+}
+
+// 按目录存放的键（<主机>/<路径>）写入前先建好所在的目录
+async fn create_parent(disk: &dyn Disk, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        disk.create_dir_all(parent.to_path_buf()).await?;
+    }
+    Ok(())
+}
+
+pub(super) fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".tmp");
+    PathBuf::from(name)
+}
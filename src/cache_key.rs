@@ -0,0 +1,273 @@
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use hyper::header::{HeaderValue, ACCEPT_ENCODING, USER_AGENT};
+use hyper::{HeaderMap, Method, Uri};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::{CacheKeyConfig, KeyLayout};
+use crate::cache::is_reserved_dir;
+use crate::constants::{CACHE_KEY_MAX_DEPTH, CACHE_KEY_MAX_LEN, CACHE_KEY_MAX_PATH_LEN, DEFAULT_CLIENT_CLASS};
+use crate::utils::generate_cache_key;
+
+// 计算缓存键时可用的请求信息。uri 已经规范化，并去掉了签名参数和路由忽略的查询参数；
+// 没有原始请求时（管理接口清除、重启续传）method 为 GET，headers 为空
+pub struct KeyRequest<'a> {
+    pub method: &'a Method,
+    pub uri: &'a Uri,
+    pub headers: &'a HeaderMap,
+    // 按 User-Agent 归入的客户端类别，没有配置 cache.key.client_classes 时为 None
+    pub client_class: Option<&'a str>,
+}
+
+// 客户端类别：源站按 User-Agent 返回不同内容（移动版与桌面版页面、不同编码的视频）时，
+// 不同类别的客户端分别缓存，不会拿到其他类别的版本
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientClass {
+    pub name: String,
+    // 匹配 User-Agent 的正则，不区分大小写
+    pub user_agent: String,
+    #[serde(skip)]
+    compiled: Option<Regex>,
+}
+
+impl ClientClass {
+    pub fn new(name: &str, user_agent: &str) -> Result<Self> {
+        let mut class = ClientClass {
+            name: name.to_string(),
+            user_agent: user_agent.to_string(),
+            compiled: None,
+        };
+        class.compile()?;
+        Ok(class)
+    }
+
+    pub fn compile(&mut self) -> Result<()> {
+        let regex = RegexBuilder::new(&self.user_agent)
+            .case_insensitive(true)
+            .build()
+            .with_context(|| format!("invalid user_agent pattern for client class {}", self.name))?;
+        self.compiled = Some(regex);
+        Ok(())
+    }
+
+    fn matches(&self, user_agent: &str) -> bool {
+        self.compiled.as_ref().is_some_and(|regex| regex.is_match(user_agent))
+    }
+}
+
+// 按顺序匹配，第一个命中的类别生效；都不命中（包括没有 User-Agent）时为 default
+pub fn client_class<'a>(classes: &'a [ClientClass], headers: &HeaderMap) -> Option<&'a str> {
+    if classes.is_empty() {
+        return None;
+    }
+    let user_agent = headers
+        .get(USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let class = classes.iter().find(|class| class.matches(user_agent));
+    Some(class.map_or(DEFAULT_CLIENT_CLASS, |class| class.name.as_str()))
+}
+
+// 客户端接受 gzip（或以 * 接受任意编码且没有排除 gzip）时改为 gzip，否则改为 identity。
+// q=0 表示不接受；没有 Accept-Encoding 的客户端按 identity 处理，不会收到压缩的内容
+pub fn normalize_accept_encoding(headers: &mut HeaderMap) {
+    let mut gzip = None;
+    let mut any = None;
+    for item in headers.get_all(ACCEPT_ENCODING).iter().filter_map(|v| v.to_str().ok()).flat_map(|v| v.split(',')) {
+        let mut params = item.split(';');
+        let coding = params.next().unwrap_or_default().trim();
+        let accepted = params
+            .filter_map(|param| param.split_once('='))
+            .filter(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .all(|(_, q)| q.trim().parse::<f32>().map_or(true, |q| q > 0.0));
+        if coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip") {
+            gzip = Some(accepted);
+        } else if coding == "*" {
+            any = Some(accepted);
+        }
+    }
+    let value = if gzip.or(any).unwrap_or(false) { "gzip" } else { "identity" };
+    headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(value));
+}
+
+// 缓存键策略。返回的键同时用作缓存目录下的文件名（可以用 / 分层），
+// 超长、过深或含有文件名中不安全的字符时会被替换为它的 SHA-256
+pub trait CacheKeyStrategy: Send + Sync {
+    fn key(&self, req: &KeyRequest) -> String;
+}
+
+// 由库的使用者提供、覆盖配置文件中 cache.key 的策略
+#[derive(Clone)]
+pub struct CustomKeyStrategy(pub Arc<dyn CacheKeyStrategy>);
+
+impl fmt::Debug for CustomKeyStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CustomKeyStrategy")
+    }
+}
+
+// 配置文件中的策略：默认只对 URL 取 SHA-256，与旧版本的键相同；
+// 可以加入请求方法和指定请求头的值（例如 Accept-Encoding、租户 ID），
+// 或者改用可读的 URL 作为文件名
+impl CacheKeyStrategy for CacheKeyConfig {
+    fn key(&self, req: &KeyRequest) -> String {
+        let method = self.include_method.then(|| req.method.to_string());
+        let headers: Vec<String> = self
+            .headers
+            .iter()
+            .map(|name| {
+                let value = req.headers.get(name.as_str()).and_then(|v| v.to_str().ok());
+                format!("{}={}", name, value.unwrap_or(""))
+            })
+            .chain(req.client_class.map(|class| format!("class={}", class)))
+            .collect();
+        let parts = |target: String| {
+            let mut parts: Vec<String> = method.iter().cloned().collect();
+            parts.push(target);
+            parts.extend(headers.iter().cloned());
+            parts
+        };
+        let hashed = || {
+            // 没有额外的组成部分时保持旧的键，已有缓存仍然命中
+            if method.is_none() && headers.is_empty() {
+                generate_cache_key(req.uri)
+            } else {
+                sha256_hex(&parts(req.uri.to_string()).join("\n"))
+            }
+        };
+        match self.layout {
+            KeyLayout::Hashed => hashed(),
+            // 协议不影响对象内容的可读性，省略
+            KeyLayout::Path => {
+                let path = req.uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+                let value = parts(format!("{}{}", authority(req.uri), path)).join(" ");
+                let encoded: Vec<String> = value.split('/').map(encode).collect();
+                let key = shorten(encoded.join("_"), &value);
+                // 与 Windows 的设备名（nul、con 等）相同时无法作为文件名
+                if is_valid_key(&key) {
+                    key
+                } else {
+                    hashed()
+                }
+            }
+            KeyLayout::Tree => {
+                let mut tail: Vec<String> = req.uri.query().map(|q| format!("?{}", q)).into_iter().collect();
+                tail.extend(method.iter().chain(&headers).map(|part| format!(" {}", part)));
+                let key = tree(req.uri, &tail.concat());
+                // 主机名与缓存目录中的保留目录同名、路径过深或过长时无法按目录存放
+                if is_valid_key(&key) {
+                    key
+                } else {
+                    hashed()
+                }
+            }
+        }
+    }
+}
+
+fn sha256_hex(value: &str) -> String {
+    hex::encode(Sha256::digest(value.as_bytes()))
+}
+
+fn authority(uri: &Uri) -> &str {
+    uri.authority().map(|a| a.as_str()).unwrap_or("")
+}
+
+// 文件名中的转义：. 写成 ~（键中不能有点，.meta 与代号后缀以点分隔），
+// 字母数字与 - 以外的字符按百分号编码。Windows 与 macOS 的文件名通常不区分大小写，
+// 大写字母在所有系统上都要编码，否则只有大小写不同的 URL 会写到同一个文件
+fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'.' => encoded.push('~'),
+            b if b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' => encoded.push(b as char),
+            b => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+// 超过文件名长度上限时截断，并附上原始内容的哈希区分截断后相同的名字
+fn shorten(encoded: String, value: &str) -> String {
+    if encoded.len() <= CACHE_KEY_MAX_LEN {
+        return encoded;
+    }
+    let mut end = CACHE_KEY_MAX_LEN - 33;
+    // 不在百分号编码的中间截断
+    while encoded.as_bytes()[end - 1] == b'%' || encoded.as_bytes()[end - 2] == b'%' {
+        end -= 1;
+    }
+    format!("{}-{}", &encoded[..end], &sha256_hex(value)[..32])
+}
+
+// 目录结构 <主机>/<路径的各段>：空的路径段（根路径、结尾的 /）写成 _，
+// 查询参数与额外的组成部分附在最后一段
+fn tree(uri: &Uri, tail: &str) -> String {
+    let path = uri.path().strip_prefix('/').unwrap_or(uri.path());
+    let mut segments: Vec<String> = path.split('/').map(str::to_string).collect();
+    if let Some(last) = segments.last_mut() {
+        last.push_str(tail);
+    }
+    let host = authority(uri);
+    let segments = std::iter::once(host.to_string()).chain(segments).map(|segment| {
+        if segment.is_empty() {
+            "_".to_string()
+        } else {
+            shorten(encode(&segment), &segment)
+        }
+    });
+    segments.collect::<Vec<_>>().join("/")
+}
+
+// 缓存键能否直接作为（缓存目录下的相对）文件路径：每一段非空、不超长，
+// 只包含小写字母、数字与 - _ ~ %，也不是 Windows 的设备名，
+// 缓存目录可以在不同系统之间导出导入；分层的键不能过深，也不能放进缓存自己使用的目录
+pub fn is_valid_key(key: &str) -> bool {
+    let segments: Vec<&str> = key.split('/').collect();
+    let valid_segment = |segment: &&str| {
+        !segment.is_empty()
+            && segment.len() <= CACHE_KEY_MAX_LEN
+            && !is_device_name(segment)
+            && valid_chars(segment)
+    };
+    key.len() <= CACHE_KEY_MAX_PATH_LEN
+        && segments.len() <= CACHE_KEY_MAX_DEPTH
+        && !(segments.len() > 1 && is_reserved_dir(segments[0]))
+        && segments.iter().all(valid_segment)
+}
+
+// 大写字母只能出现在百分号编码的两位十六进制中
+fn valid_chars(segment: &str) -> bool {
+    let mut escaped = 0u8;
+    segment.bytes().all(|b| {
+        let upper = escaped > 0 && b.is_ascii_uppercase();
+        escaped = if b == b'%' { 2 } else { escaped.saturating_sub(1) };
+        upper || b.is_ascii_lowercase() || b.is_ascii_digit() || matches!(b, b'-' | b'_' | b'~' | b'%')
+    })
+}
+
+// Windows 保留的设备名，不区分大小写，加上扩展名（如 nul.meta）也不能使用
+fn is_device_name(segment: &str) -> bool {
+    let upper = segment.to_ascii_uppercase();
+    matches!(upper.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || ["COM", "LPT"].iter().any(|prefix| {
+            upper
+                .strip_prefix(prefix)
+                .is_some_and(|n| n.len() == 1 && matches!(n.as_bytes()[0], b'1'..=b'9'))
+        })
+}
+
+// 自定义策略返回的键不能直接作为文件名时改用它的哈希
+pub fn sanitize(key: String) -> String {
+    if is_valid_key(&key) {
+        key
+    } else {
+        sha256_hex(&key)
+    }
+}
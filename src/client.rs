@@ -0,0 +1,30 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use hyper::client::HttpConnector;
+
+use crate::config::{Config, UpstreamHttpVersion};
+use crate::connector::TrackedConnector;
+use crate::upstream::{HttpClient, OriginTlsConnector};
+
+// 按 upstream 配置构建访问源站的客户端：TCP 选项、TLS 与 HTTP 版本、连接池
+pub fn build(config: &Config) -> Result<HttpClient> {
+    let upstream = &config.upstream;
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    http.set_nodelay(upstream.tcp_nodelay);
+    http.set_keepalive(upstream.tcp_keepalive_secs.map(Duration::from_secs));
+    http.set_connect_timeout(upstream.connect_timeout_secs.map(Duration::from_secs));
+    // 握手时是否提供 h2 由 TLS 连接器按源站决定，协商出 h2 的连接自动使用 HTTP/2
+    let https = OriginTlsConnector::new(http, config)?;
+
+    let mut builder = hyper::Client::builder();
+    builder.pool_idle_timeout(Duration::from_secs(upstream.pool_idle_timeout_secs));
+    if let Some(max_idle) = upstream.pool_max_idle_per_host {
+        builder.pool_max_idle_per_host(max_idle);
+    }
+    if upstream.http_version == UpstreamHttpVersion::Http2 {
+        builder.http2_only(true);
+    }
+    Ok(HttpClient::new(builder.build(TrackedConnector::new(https)), config))
+}
@@ -0,0 +1,283 @@
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::pin::Pin;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::task::{ready, Context, Poll};
+
+use base64::Engine;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use hyper::body::HttpBody;
+use hyper::{Body, HeaderMap, Request, Response, StatusCode};
+use lru::LruCache;
+use serde::Serialize;
+
+use crate::clock::SharedClock;
+use crate::config::ClientUser;
+use crate::constants::{CLIENT_USAGE_TRACKED, SECONDS_PER_DAY};
+
+// 流量归属的对象：客户端 IP，或者 Proxy-Authorization 中通过校验的用户名。
+// 没有配置或校验失败的用户名不作为统计对象，否则换个用户名就能绕过配额
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientId {
+    Ip(IpAddr),
+    Identity(String),
+}
+
+// 请求方向（客户端上传的请求体）与响应方向（发给客户端的响应体）的字节数
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Transfer {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+impl Transfer {
+    pub fn total(&self) -> u64 {
+        self.bytes_in + self.bytes_out
+    }
+
+    fn add(&mut self, other: Transfer) {
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+    }
+}
+
+// 滑动窗口：N 个宽 width 秒的环形时间桶，每个桶记下所属的时间段编号
+struct Window<const N: usize> {
+    width: u64,
+    slots: [(u64, Transfer); N],
+}
+
+impl<const N: usize> Window<N> {
+    fn new(width: u64) -> Self {
+        Window {
+            width,
+            slots: [(0, Transfer::default()); N],
+        }
+    }
+
+    fn add(&mut self, now: u64, transfer: Transfer) {
+        let stamp = now / self.width;
+        let slot = &mut self.slots[(stamp % N as u64) as usize];
+        if slot.0 != stamp {
+            *slot = (stamp, Transfer::default());
+        }
+        slot.1.add(transfer);
+    }
+
+    // 包括当前桶在内最近 N 个桶的合计
+    fn sum(&self, now: u64) -> Transfer {
+        let stamp = now / self.width;
+        let mut total = Transfer::default();
+        for (slot_stamp, transfer) in &self.slots {
+            if *slot_stamp <= stamp && slot_stamp + N as u64 > stamp {
+                total.add(*transfer);
+            }
+        }
+        total
+    }
+}
+
+struct Usage {
+    seconds: Window<60>,
+    minutes: Window<60>,
+    hours: Window<24>,
+    // 配额按 UTC 自然日计算，零点清零
+    day: u64,
+    today: Transfer,
+}
+
+impl Usage {
+    fn new() -> Self {
+        Usage {
+            seconds: Window::new(1),
+            minutes: Window::new(60),
+            hours: Window::new(3600),
+            day: 0,
+            today: Transfer::default(),
+        }
+    }
+
+    fn today(&self, now: u64) -> Transfer {
+        if self.day == now / SECONDS_PER_DAY {
+            self.today
+        } else {
+            Transfer::default()
+        }
+    }
+}
+
+// 管理接口 /stats/clients 中每个客户端的一项
+#[derive(Debug, Serialize)]
+pub struct ClientStats {
+    #[serde(flatten)]
+    pub client: ClientId,
+    pub last_minute: Transfer,
+    pub last_hour: Transfer,
+    pub last_day: Transfer,
+    pub today: Transfer,
+}
+
+// 按客户端记录的流量。只保留最近活跃的客户端，被挤出的客户端重新从零计算
+pub struct ClientUsage {
+    clients: Mutex<LruCache<ClientId, Usage>>,
+}
+
+pub static CLIENT_USAGE: LazyLock<ClientUsage> = LazyLock::new(|| ClientUsage::new(CLIENT_USAGE_TRACKED));
+
+impl ClientUsage {
+    pub fn new(tracked: usize) -> Self {
+        ClientUsage {
+            clients: Mutex::new(LruCache::new(NonZeroUsize::new(tracked.max(1)).unwrap())),
+        }
+    }
+
+    pub fn record(&self, ids: &[ClientId], now: u64, transfer: Transfer) {
+        let mut clients = self.clients.lock().unwrap();
+        for id in ids {
+            let usage = clients.get_or_insert_mut(id.clone(), Usage::new);
+            usage.seconds.add(now, transfer);
+            usage.minutes.add(now, transfer);
+            usage.hours.add(now, transfer);
+            if usage.day != now / SECONDS_PER_DAY {
+                usage.day = now / SECONDS_PER_DAY;
+                usage.today = Transfer::default();
+            }
+            usage.today.add(transfer);
+        }
+    }
+
+    // 当天（UTC）已用的字节数，上下行合计
+    pub fn used_today(&self, id: &ClientId, now: u64) -> u64 {
+        let clients = self.clients.lock().unwrap();
+        clients.peek(id).map(|usage| usage.today(now).total()).unwrap_or(0)
+    }
+
+    // 任何一个统计对象用完当天的配额即视为超额
+    pub fn over_quota(&self, ids: &[ClientId], quota: u64, now: u64) -> bool {
+        ids.iter().any(|id| self.used_today(id, now) >= quota)
+    }
+
+    pub fn stats(&self, now: u64) -> Vec<ClientStats> {
+        let clients = self.clients.lock().unwrap();
+        let mut stats: Vec<ClientStats> = clients
+            .iter()
+            .map(|(id, usage)| ClientStats {
+                client: id.clone(),
+                last_minute: usage.seconds.sum(now),
+                last_hour: usage.minutes.sum(now),
+                last_day: usage.hours.sum(now),
+                today: usage.today(now),
+            })
+            .collect();
+        stats.sort_by(|a, b| a.client.cmp(&b.client));
+        stats
+    }
+}
+
+// 请求归属的统计对象：通过校验的用户名，否则是客户端 IP
+pub fn client_ids(ip: Option<IpAddr>, headers: &HeaderMap, users: &[ClientUser]) -> Vec<ClientId> {
+    match identity(headers, users) {
+        Some(user) => vec![ClientId::Identity(user)],
+        None => ip.map(ClientId::Ip).into_iter().collect(),
+    }
+}
+
+// Proxy-Authorization: Basic base64(user:password)，用户名与密码都与 users 中的一项相符时返回用户名
+fn identity(headers: &HeaderMap, users: &[ClientUser]) -> Option<String> {
+    let value = headers.get(hyper::header::PROXY_AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, credentials) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(credentials.trim())
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    // 比较所有用户后再返回，耗时与匹配的是哪一个无关
+    users.iter().fold(None, |found, candidate| {
+        let matched = candidate.name == user && constant_time_eq(password.as_bytes(), candidate.password.as_bytes());
+        found.or(matched.then(|| candidate.name.clone()))
+    })
+}
+
+// 比较耗时与不匹配的位置无关，避免通过响应时间猜出密码
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// 当天配额用完：429，Retry-After 为到 UTC 零点的秒数
+pub fn quota_exceeded(now: u64) -> Response<Body> {
+    let reset = SECONDS_PER_DAY - now % SECONDS_PER_DAY;
+    let mut response = Response::new(Body::from("daily transfer quota exceeded"));
+    *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    response.headers_mut().insert(hyper::header::RETRY_AFTER, reset.into());
+    response
+}
+
+// 统计客户端上传的请求体。没有请求体的请求（GET 等）保持原样，
+// 否则转发时会变成分块编码
+pub fn count_request(req: Request<Body>, ids: Arc<[ClientId]>, clock: SharedClock) -> Request<Body> {
+    if req.body().is_end_stream() {
+        return req;
+    }
+    let (parts, body) = req.into_parts();
+    Request::from_parts(parts, counted(body, ids, clock, Direction::In))
+}
+
+// 统计发给客户端的响应体，边发送边记录，大文件下载过程中就计入配额
+pub fn count_response(response: Response<Body>, ids: Arc<[ClientId]>, clock: SharedClock) -> Response<Body> {
+    if response.body().is_end_stream() {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    // 包装成流后 hyper 不再知道长度，保留原来的 Content-Length
+    if let Some(len) = HttpBody::size_hint(&body).exact() {
+        parts
+            .headers
+            .entry(hyper::header::CONTENT_LENGTH)
+            .or_insert_with(|| len.into());
+    }
+    Response::from_parts(parts, counted(body, ids, clock, Direction::Out))
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    In,
+    Out,
+}
+
+fn counted(inner: Body, ids: Arc<[ClientId]>, clock: SharedClock, direction: Direction) -> Body {
+    Body::wrap_stream(CountedBody {
+        inner,
+        ids,
+        clock,
+        direction,
+    })
+}
+
+struct CountedBody {
+    inner: Body,
+    ids: Arc<[ClientId]>,
+    clock: SharedClock,
+    direction: Direction,
+}
+
+impl Stream for CountedBody {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = ready!(self.inner.poll_next_unpin(cx));
+        if let Some(Ok(chunk)) = &item {
+            let len = chunk.len() as u64;
+            let transfer = match self.direction {
+                Direction::In => Transfer { bytes_in: len, bytes_out: 0 },
+                Direction::Out => Transfer { bytes_in: 0, bytes_out: len },
+            };
+            CLIENT_USAGE.record(&self.ids, self.clock.now_secs(), transfer);
+        }
+        Poll::Ready(item)
+    }
+}
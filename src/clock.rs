@@ -0,0 +1,85 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::future::BoxFuture;
+use tokio::sync::watch;
+
+// 时间来源：新鲜期、Age、后台整理与重试退避都通过它读取时间和等待，
+// 测试中换成 MockClock 即可手动推进时间，不必真的等待
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+
+    // 当前的 Unix 时间（秒），缓存元数据中的时间都以它为单位
+    fn now_secs(&self) -> u64 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+// 系统时钟
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+pub fn system() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+// 手动推进的时钟：时间只在调用 advance / set 时变化，
+// sleep 在时间被推进到截止时间之后才完成
+pub struct MockClock {
+    now: watch::Sender<SystemTime>,
+}
+
+impl MockClock {
+    pub fn new(start: SystemTime) -> Self {
+        MockClock {
+            now: watch::Sender::new(start),
+        }
+    }
+
+    // 从某个 Unix 时间（秒）开始
+    pub fn at_secs(secs: u64) -> Self {
+        Self::new(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.send_modify(|now| *now += duration);
+    }
+
+    pub fn set(&self, time: SystemTime) {
+        self.now.send_replace(time);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.now.borrow()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        let mut rx = self.now.subscribe();
+        let deadline = *rx.borrow() + duration;
+        Box::pin(async move {
+            // 时钟被丢弃后不会再推进，此时的等待永远不会完成
+            if rx.wait_for(|now| *now >= deadline).await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        })
+    }
+}
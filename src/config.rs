@@ -0,0 +1,1577 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use hyper::header::{HeaderValue, ACCEPT_ENCODING};
+use hyper::{HeaderMap, Method, Uri};
+use serde::{Deserialize, Serialize};
+
+use crate::cache::{shared_cacheable, surrogate_lifetime, surrogate_no_store};
+use crate::cache_key::{client_class, sanitize, CacheKeyStrategy, ClientClass, CustomKeyStrategy, KeyRequest};
+use crate::constants::{
+    AUDIT_LOG_PATH, CACHE_CHUNK_SIZE, CLIENT_WRITE_TIMEOUT_SECONDS, DECISION_LOG_PATH, DECISION_LOG_SAMPLE_RATE,
+    DEFAULT_CLIENT_CLASS, DISK_IO_CONCURRENCY, DISK_WRITE_QUEUE_SIZE, HEADER_READ_TIMEOUT_SECONDS, HEAD_CACHE_TTL_SECONDS, HEURISTIC_FRACTION,
+    HEURISTIC_MAX_SECONDS, IDEMPOTENCY_KEY_HEADER, JANITOR_EXPIRED_GRACE_SECONDS, JANITOR_JITTER,
+    JANITOR_PAUSE_ABOVE_REQUESTS, JANITOR_QUOTA_INTERVAL_SECONDS, JANITOR_VERIFY_BATCH, MAX_RETRIES, COMPRESSIBLE_TYPES, COMPRESS_MAX_BYTES,
+    COMPRESS_MIN_BYTES, KEEP_ALIVE_TIMEOUT_SECONDS, LISTEN_ADDR, LISTEN_BACKLOG,
+    MAX_FILE_SIZE, MAX_HEADER_BYTES, MAX_REQUESTS_PER_CONNECTION, MEMORY_ADMISSION_MAX_BYTES,
+    MEMORY_ADMISSION_MIN_HITS, METRICS_MAX_LABEL_VALUES,
+    MAX_REQUEST_BODY_SIZE, ORIGIN_PROBE_INTERVAL_SECONDS, PACK_COMPACT_INTERVAL_SECONDS,
+    PACK_MAX_OBJECT_BYTES, PACK_MIN_LIVE_RATIO, PACK_SEGMENT_BYTES, PEER_LOOKUP_TIMEOUT_MS,
+    POOL_IDLE_TIMEOUT_SECONDS, READ_AHEAD_MAX_BYTES, READ_AHEAD_MIN_BYTES,
+    READ_AHEAD_WINDOW_SECONDS, RECENT_REQUESTS_KEPT, REFRESH_AHEAD_FRACTION, REFRESH_IDLE_MAX_RPS,
+    REFRESH_INTERVAL_SECONDS, REFRESH_MAX_PER_TICK, REFRESH_MIN_HITS, REFRESH_TRACKED_ENTRIES,
+    RESUME_SHUTDOWN_GRACE_SECONDS, RETRY_METHODS, RUNTIME_THREAD_NAME, UPSTREAM_BODY_STALL_SECONDS, UPSTREAM_MAX_HEADERS, UPSTREAM_MAX_HEADER_BYTES,
+    UPSTREAM_MAX_REDIRECTS, TIMEOUT_SECONDS,
+};
+use crate::rewrite::{RewriteRule, UrlRule};
+use crate::origin_auth::OriginAuthConfig;
+use crate::signed_url::SignedUrlConfig;
+use crate::upstream::Priority;
+use crate::utils::retain_query_params;
+
+// 配置文件（TOML），所有字段都有默认值，未配置时与旧版本行为一致
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // 一组默认值，配置文件中明确写出的项仍然优先
+    pub profile: Profile,
+    pub listen: SocketAddr,
+    // 代理的工作方式，决定接受哪种形式的请求以及 Via、访问控制的默认值
+    pub mode: ProxyMode,
+    // 在回源请求与响应中加上 Via，未设置时按 mode 决定（只有正向代理默认加上）
+    pub via: Option<bool>,
+    // 拒绝客户端指定的回环、私有网络与链路本地地址（以及 localhost），有 host 的路由命中的目标除外；
+    // 未设置时按 mode 决定（正向代理与透明代理默认拒绝）
+    pub deny_private_targets: Option<bool>,
+    pub downstream: DownstreamConfig,
+    pub upstream: UpstreamConfig,
+    pub cache: CacheConfig,
+    pub peers: PeersConfig,
+    pub admin: AdminConfig,
+    pub metrics: MetricsConfig,
+    pub decision_log: DecisionLogConfig,
+    pub client_usage: ClientUsageConfig,
+    pub runtime: RuntimeConfig,
+    pub process: ProcessConfig,
+    pub sandbox: SandboxConfig,
+    // 按顺序匹配，第一个命中的路由生效
+    pub routes: Vec<RouteConfig>,
+    // 库的使用者替换的缓存键策略，优先于 cache.key
+    #[serde(skip)]
+    pub key_strategy: Option<CustomKeyStrategy>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            profile: Profile::default(),
+            listen: LISTEN_ADDR.parse().unwrap(),
+            mode: ProxyMode::default(),
+            via: None,
+            deny_private_targets: None,
+            downstream: DownstreamConfig::default(),
+            upstream: UpstreamConfig::default(),
+            cache: CacheConfig::default(),
+            peers: PeersConfig::default(),
+            admin: AdminConfig::default(),
+            metrics: MetricsConfig::default(),
+            decision_log: DecisionLogConfig::default(),
+            client_usage: ClientUsageConfig::default(),
+            runtime: RuntimeConfig::default(),
+            process: ProcessConfig::default(),
+            sandbox: SandboxConfig::default(),
+            routes: Vec::new(),
+            key_strategy: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Profile {
+    #[default]
+    Standard,
+    // 路由器、树莓派等内存很小的设备：内存中不缓存对象内容，磁盘内容全部 mmap 读取（由内核的页缓存承担），
+    // 缩小单个对象上限、写盘队列与各类缓冲，更大的对象直接透传
+    LowMemory,
+}
+
+impl Profile {
+    // 该档位相对于默认值修改的配置项，合并在配置文件之下
+    fn defaults(self) -> toml::Table {
+        match self {
+            Profile::Standard => toml::Table::new(),
+            Profile::LowMemory => toml::toml! {
+                [cache]
+                memory_content = false
+                max_object_bytes = 16777216
+                chunk_bytes = 16777216
+                disk_io_concurrency = 4
+                write_queue_size = 8
+                [downstream]
+                max_request_body_bytes = 1048576
+                [admin]
+                recent_requests = 32
+                [runtime]
+                max_blocking_threads = 16
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyMode {
+    // 同时接受绝对形式的请求（正向代理）与 Host 命中了路由的源形式请求（反向代理），与旧版本相同
+    #[default]
+    Auto,
+    // 只接受绝对形式的请求，源形式的请求都是发给代理自身的（指标等）
+    Forward,
+    // 只接受 Host 命中了路由的源形式请求，拒绝绝对形式的请求，避免被当作开放代理
+    Reverse,
+    // 被防火墙重定向过来的流量：源形式的请求按 Host 转发到任何站点，协议默认 http
+    Transparent,
+}
+
+// 管理接口，单独监听；未配置 listen 时不启动
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    pub listen: Option<SocketAddr>,
+    // 请求需携带 Authorization: Bearer <token>，相当于 tokens 中一个名为 token 的 admin
+    pub token: Option<String>,
+    // 按角色区分的 token，名字记入审计日志；配置了 token 或 tokens 后请求必须携带其中之一
+    pub tokens: Vec<AdminToken>,
+    // 其他实例的管理接口地址（如 "10.0.0.2:3001"），purge 会同时转发给它们，
+    // 使用 token 或 tokens 中第一个 admin 角色的 token（见 peer_token）
+    pub peers: Vec<String>,
+    pub audit_log: AuditLogConfig,
+    // /debug/requests 保留最近多少个请求（方法、URL、状态、各阶段耗时、缓存结果），0 表示不记录
+    pub recent_requests: usize,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        AdminConfig {
+            listen: None,
+            token: None,
+            tokens: Vec::new(),
+            peers: Vec::new(),
+            audit_log: AuditLogConfig::default(),
+            recent_requests: RECENT_REQUESTS_KEPT,
+        }
+    }
+}
+
+impl AdminConfig {
+    // 转发 purge 给其他实例时携带的 token：admin.token，否则 tokens 中第一个 admin 角色的 token。
+    // 配置了 token 却没有 admin 角色的 token 时无法转发（validate 会拒绝这样的配置）
+    pub fn peer_token(&self) -> Option<&str> {
+        self.token.as_deref().or_else(|| {
+            self.tokens
+                .iter()
+                .find(|token| token.role == AdminRole::Admin)
+                .map(|token| token.token.as_str())
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AdminToken {
+    pub name: String,
+    pub token: String,
+    #[serde(default)]
+    pub role: AdminRole,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminRole {
+    // 只能查看：GET 接口、/metrics 与只读的 RPC 方法，供监控面板使用
+    #[default]
+    ReadOnly,
+    // 可以执行清除缓存、排空等所有操作
+    Admin,
+}
+
+// 管理操作（清除缓存、取消下载、排空等）的审计日志，每行一个 JSON，只追加
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditLogConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+}
+
+impl Default for AuditLogConfig {
+    fn default() -> Self {
+        AuditLogConfig {
+            enabled: false,
+            path: PathBuf::from(AUDIT_LOG_PATH),
+        }
+    }
+}
+
+// 指标与健康检查，单独监听且不需要认证；未配置时由代理端口上的 /metrics 提供
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub listen: Option<SocketAddr>,
+    // 命中率、流量与延迟按什么区分
+    pub label: MetricsLabel,
+    // 标签最多取多少个不同的值，之后新出现的归入 other，Prometheus 的序列数不会无限增长
+    pub max_label_values: usize,
+    // /metrics 需要携带 admin.token 或 admin.tokens 中的任一 token；/health 始终不需要，供负载均衡器检查
+    pub require_token: bool,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            listen: None,
+            label: MetricsLabel::default(),
+            max_label_values: METRICS_MAX_LABEL_VALUES,
+            require_token: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsLabel {
+    // 源站主机名，取值随访问的站点增长（之前版本的行为）
+    #[default]
+    Host,
+    // 配置中的路由名，未命名的路由为 #序号，未匹配任何路由的请求为 default
+    Route,
+}
+
+// 缓存决策日志：按比例抽样记录请求的完整决策过程（缓存键、查找结果、新鲜度、范围处理、是否写入缓存），
+// 每行一个 JSON，用于离线分析缓存调优
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DecisionLogConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+    // 抽样比例（0 到 1）
+    pub sample_rate: f64,
+}
+
+impl Default for DecisionLogConfig {
+    fn default() -> Self {
+        DecisionLogConfig {
+            enabled: false,
+            path: PathBuf::from(DECISION_LOG_PATH),
+            sample_rate: DECISION_LOG_SAMPLE_RATE,
+        }
+    }
+}
+
+// tokio 运行时，只在启动时读取；未设置的项使用 tokio 的默认值（工作线程数等于 CPU 核数，阻塞线程最多 512 个）。
+// 小型设备可以减少线程数与栈大小，磁盘较慢的大机器可以增加阻塞线程
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RuntimeConfig {
+    // 命令行 --worker-threads 优先
+    pub worker_threads: Option<usize>,
+    // 执行文件读写等阻塞操作的线程上限，命令行 --max-blocking-threads 优先
+    pub max_blocking_threads: Option<usize>,
+    // 线程名，便于在 top -H、perf 中区分
+    pub thread_name: String,
+    pub thread_stack_bytes: Option<usize>,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        RuntimeConfig {
+            worker_threads: None,
+            max_blocking_threads: None,
+            thread_name: RUNTIME_THREAD_NAME.to_string(),
+            thread_stack_bytes: None,
+        }
+    }
+}
+
+// 进程管理（仅 Unix）：以 root 启动绑定 80/443 等低端口后切换到普通用户运行，
+// 缓存目录与日志文件都在切换之后创建；命令行 --daemon、--pid-file、--user、--group 优先
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProcessConfig {
+    // 脱离终端在后台运行，启动完成（端口已绑定、缓存已加载）后启动命令才返回
+    pub daemon: bool,
+    // 写入进程号的文件，退出时删除
+    pub pid_file: Option<PathBuf>,
+    // 绑定端口后切换到的用户，未设置 group 时使用该用户的主组
+    pub user: Option<String>,
+    pub group: Option<String>,
+    // 后台运行时运行日志追加写入的文件，未设置时丢弃
+    pub log_file: Option<PathBuf>,
+}
+
+// 限制进程能访问的文件与系统调用（仅 Linux），即使请求解析中存在可利用的漏洞也无法读取主机上的其他文件：
+// landlock 只允许读写缓存目录与配置中的日志文件、读取 DNS 与证书等系统配置，
+// seccomp 在启动完成后禁止执行程序、调试其他进程、挂载、加载内核模块与切换身份。
+// 开启后 SIGUSR2 升级无法启动新进程，旧进程继续服务
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    pub enabled: bool,
+    // 两项可以分别关闭，便于排查问题
+    pub landlock: bool,
+    pub seccomp: bool,
+    // 额外允许读取的文件或目录
+    pub read_paths: Vec<PathBuf>,
+    // 额外允许读写的文件或目录
+    pub write_paths: Vec<PathBuf>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        SandboxConfig {
+            enabled: false,
+            landlock: true,
+            seccomp: true,
+            read_paths: Vec::new(),
+            write_paths: Vec::new(),
+        }
+    }
+}
+
+// 按客户端统计上下行字节数，结果在管理接口 /stats/clients 中。Proxy-Authorization 中的
+// 用户名与密码与 users 中的一项相符时按用户名统计，否则按客户端 IP 统计
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientUsageConfig {
+    pub enabled: bool,
+    // 每个 IP、每个用户名每天（UTC）最多传输的字节数，用完后返回 429；未设置时不限制
+    pub daily_quota_bytes: Option<u64>,
+    pub users: Vec<ClientUser>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClientUser {
+    pub name: String,
+    pub password: String,
+}
+
+// 客户端连接设置，用于防御 slowloris 一类的慢速客户端
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DownstreamConfig {
+    // 读取完整请求头的超时时间（秒）
+    pub header_read_timeout_secs: u64,
+    // 请求行与请求头的最大字节数（不小于 8192）
+    pub max_header_bytes: usize,
+    // 向客户端写数据无进展的超时时间（秒），未设置时不限制
+    pub write_timeout_secs: Option<u64>,
+    // 转发给源站的请求体最大字节数，超过时返回 413；未设置时不限制
+    pub max_request_body_bytes: Option<u64>,
+    // 单个请求的整体处理时限（秒），超时返回 504；未设置时不限制
+    pub request_deadline_secs: Option<u64>,
+    // 客户端断开后是否继续完成上游下载并写入缓存
+    pub complete_in_background: bool,
+    // 长连接两次请求之间的最长空闲时间（秒），0 表示每个请求后关闭连接
+    pub keep_alive_timeout_secs: u64,
+    // 每个连接最多处理的请求数，达到后响应带 Connection: close；未设置时不限制
+    pub max_requests_per_connection: Option<u64>,
+    // 监听 socket 的连接队列长度；从旧进程接管的 socket 沿用原来的设置
+    pub listen_backlog: u32,
+    // SO_RCVBUF / SO_SNDBUF（字节），在监听 socket 上设置，接受的连接继承；未设置时使用系统默认值
+    pub recv_buffer_bytes: Option<usize>,
+    pub send_buffer_bytes: Option<usize>,
+    // 客户端连接空闲多久（秒）后开始发送 TCP keepalive 探测，及时发现已消失的客户端；未设置时不开启
+    pub tcp_keepalive_secs: Option<u64>,
+}
+
+impl Default for DownstreamConfig {
+    fn default() -> Self {
+        DownstreamConfig {
+            header_read_timeout_secs: HEADER_READ_TIMEOUT_SECONDS,
+            max_header_bytes: MAX_HEADER_BYTES,
+            write_timeout_secs: Some(CLIENT_WRITE_TIMEOUT_SECONDS),
+            max_request_body_bytes: Some(MAX_REQUEST_BODY_SIZE),
+            request_deadline_secs: None,
+            complete_in_background: false,
+            keep_alive_timeout_secs: KEEP_ALIVE_TIMEOUT_SECONDS,
+            max_requests_per_connection: Some(MAX_REQUESTS_PER_CONNECTION),
+            listen_backlog: LISTEN_BACKLOG,
+            recv_buffer_bytes: None,
+            send_buffer_bytes: None,
+            tcp_keepalive_secs: None,
+        }
+    }
+}
+
+// 上游连接池与 TCP 连接设置
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpstreamConfig {
+    // 每个源站最多保留的空闲连接数，未设置时不限制
+    pub pool_max_idle_per_host: Option<usize>,
+    // 空闲连接保留时间（秒）
+    pub pool_idle_timeout_secs: u64,
+    // 连接超时（秒），未设置时不限制
+    pub connect_timeout_secs: Option<u64>,
+    // 关闭 Nagle 算法，小请求立即发出
+    pub tcp_nodelay: bool,
+    // TCP keepalive 探测间隔（秒），未设置时关闭
+    pub tcp_keepalive_secs: Option<u64>,
+    // 与源站使用的 HTTP 版本
+    pub http_version: UpstreamHttpVersion,
+    // 每个源站同时进行的请求数上限，未设置时不限制
+    pub max_concurrent_per_host: Option<usize>,
+    // 超过上限时的最长排队时间（秒），超时返回 503；未设置时一直排队
+    pub queue_timeout_secs: Option<u64>,
+    // HEAD 探测结果（大小、ETag 等）的缓存时间（秒），0 表示不缓存
+    pub head_cache_ttl_secs: u64,
+    // 多源站路由的后台延迟探测间隔（秒）
+    pub origin_probe_interval_secs: u64,
+    // 每次回源等待响应头的超时时间（秒）
+    pub timeout_secs: u64,
+    // 回源失败（连接错误、超时）后的最多重试次数
+    pub max_retries: u32,
+    // 失败后自动重试的请求方法，其余方法只发送一次
+    pub retry_methods: Vec<String>,
+    // 携带该请求头的请求由客户端保证幂等，任何方法都可以重试；未设置时不启用
+    pub idempotency_key_header: Option<String>,
+    // 源站响应头部的字段数与总字节数上限，超出时按无效响应处理（502），防止异常源站占用内存
+    pub max_response_headers: usize,
+    pub max_response_header_bytes: usize,
+    // 读取源站响应体时连续多少秒收不到数据视为卡住，中止读取并尝试续传
+    pub body_stall_timeout_secs: u64,
+    pub redirects: RedirectConfig,
+    pub tls: TlsConfig,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        UpstreamConfig {
+            pool_max_idle_per_host: None,
+            pool_idle_timeout_secs: POOL_IDLE_TIMEOUT_SECONDS,
+            connect_timeout_secs: None,
+            tcp_nodelay: false,
+            tcp_keepalive_secs: None,
+            http_version: UpstreamHttpVersion::default(),
+            max_concurrent_per_host: None,
+            queue_timeout_secs: None,
+            head_cache_ttl_secs: HEAD_CACHE_TTL_SECONDS,
+            origin_probe_interval_secs: ORIGIN_PROBE_INTERVAL_SECONDS,
+            timeout_secs: TIMEOUT_SECONDS,
+            max_retries: MAX_RETRIES,
+            retry_methods: RETRY_METHODS.iter().map(|m| m.to_string()).collect(),
+            idempotency_key_header: Some(IDEMPOTENCY_KEY_HEADER.to_string()),
+            max_response_headers: UPSTREAM_MAX_HEADERS,
+            max_response_header_bytes: UPSTREAM_MAX_HEADER_BYTES,
+            body_stall_timeout_secs: UPSTREAM_BODY_STALL_SECONDS,
+            redirects: RedirectConfig::default(),
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamHttpVersion {
+    // 只使用 HTTP/1.1
+    #[default]
+    Http1,
+    // HTTPS 源站在握手时同时提供 h2 与 http/1.1，由源站选择；明文源站使用 HTTP/1.1
+    Alpn,
+    // 所有源站（包括明文）直接使用 HTTP/2，源站必须支持
+    Http2,
+}
+
+// 缓存路径上（GET/HEAD）源站返回 301/302/303/307/308 时由代理跟随，最终内容缓存在原始 URL 的键下，
+// 播放器不必自己处理跨域重定向。未开启时重定向原样返回给客户端，不缓存
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RedirectConfig {
+    pub follow: bool,
+    // 超过跳数时把最后一个重定向返回给客户端
+    pub max_hops: u32,
+    // 只跟随指向同一主机名的重定向（端口与协议可以不同）
+    pub same_host_only: bool,
+    // 最终内容同时缓存在最终 URL 的键下，直接请求最终 URL 的客户端也能命中
+    pub cache_final_url: bool,
+}
+
+impl Default for RedirectConfig {
+    fn default() -> Self {
+        RedirectConfig {
+            follow: false,
+            max_hops: UPSTREAM_MAX_REDIRECTS,
+            same_host_only: true,
+            cache_final_url: false,
+        }
+    }
+}
+
+// 访问 HTTPS 源站的 TLS 设置
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    // 在系统根证书之外额外信任的 CA（PEM，可包含多个证书）
+    pub ca_files: Vec<PathBuf>,
+    pub min_version: Option<TlsVersion>,
+    // 按源站覆盖，host 可以写成 host 或 host:port
+    pub origins: Vec<OriginTlsConfig>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.0")]
+    Tls10,
+    #[serde(rename = "1.1")]
+    Tls11,
+    #[serde(rename = "1.2")]
+    Tls12,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OriginTlsConfig {
+    pub host: String,
+    // 与全局 ca_files 一起生效
+    pub ca_files: Vec<PathBuf>,
+    // 向该源站出示的客户端证书与私钥（PEM，私钥为 PKCS#8）
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    // 未设置时使用全局 min_version
+    pub min_version: Option<TlsVersion>,
+    // 跳过证书与主机名校验，只用于实验环境
+    pub insecure_skip_verify: bool,
+    // HTTP/2 实现有问题的源站：http_version 为 alpn 时握手只提供 http/1.1
+    pub http1_only: bool,
+}
+
+// 缓存策略
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    // 单个对象可缓存的最大字节数，超过时直接透传不缓存
+    pub max_object_bytes: u64,
+    // 校验源站 Content-MD5 / Digest 摘要，并为完整条目记录 SHA-256
+    pub verify_checksums: bool,
+    // 从磁盘读取时重新校验 SHA-256（需要同时开启 verify_checksums）
+    pub verify_on_read: bool,
+    // 源站既没有 Cache-Control 也没有 Expires 时，新鲜期取 Last-Modified 距今时长的比例
+    pub heuristic_fraction: f64,
+    // 启发式新鲜期的上限（秒）
+    pub heuristic_max_secs: u64,
+    // 分块存储的块大小（字节），超过一个块的对象拆成多个块文件
+    pub chunk_bytes: u64,
+    // 同时进行的缓存磁盘读写数上限，超出的操作排队等待
+    pub disk_io_concurrency: usize,
+    // 等待写盘的条目数上限，条目落盘前内容保存在内存中；队列满时写入缓存的请求等待
+    pub write_queue_size: usize,
+    // 在内存中缓存对象内容；关闭后每次命中都从磁盘读取（mmap），内存中只有写盘队列中的条目
+    pub memory_content: bool,
+    // 内存缓存的总字节数上限；未设置时按条目数限制
+    pub max_memory_bytes: Option<u64>,
+    // 磁盘缓存的总字节数上限，超过时从最久未写入的条目开始淘汰；未设置时不限制
+    pub max_disk_bytes: Option<u64>,
+    // 源站没有 Content-Type 或为 application/octet-stream 时，按响应体开头的特征字节识别类型，
+    // 识别出的类型写入缓存并返回给客户端
+    pub sniff_content_type: bool,
+    // 按源站专门给代理的 X-Accel-Expires 与 Surrogate-Control 决定新鲜期与是否保存（nginx、Fastly 的约定），
+    // 优先于给客户端的 Cache-Control；这两个头不转发给客户端。默认关闭，关闭时忽略并原样转发
+    pub surrogate_headers: bool,
+    pub refresh: RefreshConfig,
+    pub read_ahead: ReadAheadConfig,
+    pub packing: PackingConfig,
+    pub janitor: JanitorConfig,
+    pub memory_admission: MemoryAdmissionConfig,
+    pub compression: CompressionConfig,
+    pub resume: ResumeConfig,
+    pub key: CacheKeyConfig,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            max_object_bytes: MAX_FILE_SIZE as u64,
+            verify_checksums: false,
+            verify_on_read: false,
+            heuristic_fraction: HEURISTIC_FRACTION,
+            heuristic_max_secs: HEURISTIC_MAX_SECONDS,
+            chunk_bytes: CACHE_CHUNK_SIZE,
+            disk_io_concurrency: DISK_IO_CONCURRENCY,
+            write_queue_size: DISK_WRITE_QUEUE_SIZE,
+            memory_content: true,
+            max_memory_bytes: None,
+            max_disk_bytes: None,
+            sniff_content_type: false,
+            surrogate_headers: false,
+            refresh: RefreshConfig::default(),
+            read_ahead: ReadAheadConfig::default(),
+            packing: PackingConfig::default(),
+            janitor: JanitorConfig::default(),
+            memory_admission: MemoryAdmissionConfig::default(),
+            compression: CompressionConfig::default(),
+            resume: ResumeConfig::default(),
+            key: CacheKeyConfig::default(),
+        }
+    }
+}
+
+// 缓存键的组成：默认只由规范化的 URL 决定。
+// 修改后已有条目的键随之改变，相当于清空缓存；按 URL 清除（管理接口、cache rm）
+// 只能算出 GET 且不带这些请求头时的键
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheKeyConfig {
+    pub layout: KeyLayout,
+    // GET 与 HEAD 分别缓存
+    pub include_method: bool,
+    // 这些请求头的值参与缓存键，例如 Accept-Encoding（相当于 Vary）或租户 ID
+    pub headers: Vec<String>,
+    // 按 User-Agent 归类的客户端类别参与缓存键，类别比完整的 User-Agent 少得多，命中率不会被稀释。
+    // 开启后所有条目的键都会改变（未匹配任何类别的客户端也带上 default 类别），已有的缓存不会迁移，
+    // 相当于清空缓存；按 URL 清除与重启续传会检查每个类别的键
+    pub client_classes: Vec<ClientClass>,
+    // 把客户端的 Accept-Encoding 改写为 gzip 或 identity 再计算缓存键、发往源站；
+    // 与 headers = ["accept-encoding"] 一起使用时每个 URL 最多缓存两个版本，而不是每种浏览器的写法各一个
+    pub normalize_accept_encoding: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyLayout {
+    // 文件名为 SHA-256
+    #[default]
+    Hashed,
+    // 文件名为转义后的主机与路径，便于直接查看缓存目录
+    Path,
+    // 按 <主机>/<路径> 分目录存放，可以直接用 ls、find 查找；
+    // 无法按目录存放的 URL（过深、过长或与保留目录冲突）退回 SHA-256 文件名
+    Tree,
+}
+
+// 范围请求回源时多取客户端请求之后的数据写入缓存，窗口大小按客户端实测带宽调整：
+// 慢速客户端只预读少量数据，局域网客户端预读更多
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReadAheadConfig {
+    pub enabled: bool,
+    // 预读相当于客户端多少秒内能取走的数据
+    pub window_secs: f64,
+    pub min_bytes: u64,
+    pub max_bytes: u64,
+}
+
+impl Default for ReadAheadConfig {
+    fn default() -> Self {
+        ReadAheadConfig {
+            enabled: false,
+            window_secs: READ_AHEAD_WINDOW_SECONDS,
+            min_bytes: READ_AHEAD_MIN_BYTES,
+            max_bytes: READ_AHEAD_MAX_BYTES,
+        }
+    }
+}
+
+// 小对象打包存储：不超过 max_object_bytes 的对象追加写入段文件，不再各占一个内容文件和 .meta，
+// 后台任务定期压缩失效记录过多的段
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PackingConfig {
+    pub enabled: bool,
+    pub max_object_bytes: u64,
+    // 段文件写到该大小后换新段
+    pub segment_bytes: u64,
+    // 压缩检查间隔（秒）
+    pub compact_interval_secs: u64,
+    // 有效数据占比低于该值的段会被压缩
+    pub min_live_ratio: f64,
+}
+
+impl Default for PackingConfig {
+    fn default() -> Self {
+        PackingConfig {
+            enabled: false,
+            max_object_bytes: PACK_MAX_OBJECT_BYTES,
+            segment_bytes: PACK_SEGMENT_BYTES,
+            compact_interval_secs: PACK_COMPACT_INTERVAL_SECONDS,
+            min_live_ratio: PACK_MIN_LIVE_RATIO,
+        }
+    }
+}
+
+// 后台整理：过期清理、配额统计、完整性校验与打包压缩由同一个调度任务依次执行，
+// 每项任务的间隔带随机抖动，负载高时推迟，避免与客户端请求争抢磁盘 IO
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JanitorConfig {
+    // 间隔的随机抖动比例（0 到 1 之间）
+    pub jitter: f64,
+    // 删除过期超过 expired_grace_secs 的条目的间隔（秒）；默认不清理，过期条目一直保留到
+    // 被覆盖或按配额淘汰。宽限期内的过期条目仍可重新验证或在源站出错时返回
+    pub sweep_interval_secs: Option<u64>,
+    pub expired_grace_secs: u64,
+    // 重新统计磁盘用量并按 max_disk_bytes 淘汰的间隔（秒），只在设置了 max_disk_bytes 时执行
+    pub quota_interval_secs: u64,
+    // 后台校验 SHA-256 的间隔（秒），每次接着上次的位置最多校验 verify_batch 个条目；未设置时不校验
+    pub verify_interval_secs: Option<u64>,
+    pub verify_batch: usize,
+    // 正在处理的客户端请求超过该数量时推迟；有磁盘 IO 在排队时总是推迟
+    pub pause_above_requests: Option<u64>,
+}
+
+impl Default for JanitorConfig {
+    fn default() -> Self {
+        JanitorConfig {
+            jitter: JANITOR_JITTER,
+            sweep_interval_secs: None,
+            expired_grace_secs: JANITOR_EXPIRED_GRACE_SECONDS,
+            quota_interval_secs: JANITOR_QUOTA_INTERVAL_SECONDS,
+            verify_interval_secs: None,
+            verify_batch: JANITOR_VERIFY_BATCH,
+            pause_above_requests: Some(JANITOR_PAUSE_ABOVE_REQUESTS),
+        }
+    }
+}
+
+// 磁盘命中后是否把对象放入内存缓存：小对象直接放入，大对象近期被读取足够多次才放入，
+// 偶尔访问一次的大文件不会挤掉内存中的热门小对象。新写入的对象不受影响
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemoryAdmissionConfig {
+    pub max_object_bytes: u64,
+    // 访问频率按近似计数估计并定期减半，最大为 15
+    pub min_hits: u8,
+}
+
+impl Default for MemoryAdmissionConfig {
+    fn default() -> Self {
+        MemoryAdmissionConfig {
+            max_object_bytes: MEMORY_ADMISSION_MAX_BYTES,
+            min_hits: MEMORY_ADMISSION_MIN_HITS,
+        }
+    }
+}
+
+// 源站没有压缩的小文本响应由代理 gzip，identity 与 gzip 两个版本按 Accept-Encoding 分别缓存，
+// 之后的命中直接返回对应的版本，不再重复压缩。需要开启 key.normalize_accept_encoding
+// 并把 accept-encoding 加入 key.headers，两个版本才有各自的缓存键
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    // 只压缩大小在该范围内的完整响应
+    pub min_bytes: u64,
+    pub max_bytes: u64,
+    // 压缩的 Content-Type，以 / 结尾时按前缀匹配（如 text/）
+    pub content_types: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            enabled: false,
+            min_bytes: COMPRESS_MIN_BYTES,
+            max_bytes: COMPRESS_MAX_BYTES,
+            content_types: COMPRESSIBLE_TYPES.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+}
+
+impl CompressionConfig {
+    pub fn compresses(&self, content_type: &str) -> bool {
+        let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        self.content_types.iter().any(|t| {
+            let t = t.to_ascii_lowercase();
+            if t.ends_with('/') {
+                mime.starts_with(&t)
+            } else {
+                mime == t
+            }
+        })
+    }
+}
+
+// 提前刷新：热门条目在过期前于空闲时段重新验证，客户端不必等待回源
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RefreshConfig {
+    pub enabled: bool,
+    // 调度间隔（秒）
+    pub interval_secs: u64,
+    // 剩余新鲜期低于新鲜期的该比例时刷新
+    pub ahead_fraction: f64,
+    // 命中次数（按间隔衰减）达到该值的条目才刷新
+    pub min_hits: u64,
+    // 每次调度最多刷新的条目数
+    pub max_per_tick: usize,
+    // 上一个间隔内回源请求速率（每秒）不超过该值时视为空闲
+    pub idle_max_rps: f64,
+    // 跟踪命中次数的条目数上限
+    pub tracked_entries: usize,
+}
+
+impl Default for RefreshConfig {
+    fn default() -> Self {
+        RefreshConfig {
+            enabled: false,
+            interval_secs: REFRESH_INTERVAL_SECONDS,
+            ahead_fraction: REFRESH_AHEAD_FRACTION,
+            min_hits: REFRESH_MIN_HITS,
+            max_per_tick: REFRESH_MAX_PER_TICK,
+            idle_max_rps: REFRESH_IDLE_MAX_RPS,
+            tracked_entries: REFRESH_TRACKED_ENTRIES,
+        }
+    }
+}
+
+// 重启后续传：退出时仍在进行的下载先把已收到的数据作为部分内容写入缓存并记录 URL，
+// 启动时在后台以低优先级补齐这些对象缺失的区间
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResumeConfig {
+    pub enabled: bool,
+    // 退出时最多等待被中断的下载保存数据的时间（秒）
+    pub shutdown_grace_secs: u64,
+}
+
+impl Default for ResumeConfig {
+    fn default() -> Self {
+        ResumeConfig {
+            enabled: false,
+            shutdown_grace_secs: RESUME_SHUTDOWN_GRACE_SECONDS,
+        }
+    }
+}
+
+// 某个请求实际生效的缓存策略（全局设置叠加路由覆盖）
+#[derive(Clone, Copy, Debug)]
+pub struct CachePolicy {
+    pub max_object_bytes: u64,
+    pub heuristic_fraction: f64,
+    pub heuristic_max_secs: u64,
+    // 范围请求回源时在请求末尾之后多取的字节数，0 表示不预读
+    pub read_ahead_bytes: u64,
+    // 请求携带 Authorization 且路由未豁免：只有源站明确允许共享的响应才写入缓存
+    pub authenticated: bool,
+    // 路由强制的新鲜期上下限，覆盖源站缓存头计算出的结果
+    pub min_ttl_secs: Option<u64>,
+    pub max_ttl_secs: Option<u64>,
+    pub sniff_content_type: bool,
+    pub surrogate_headers: bool,
+    pub fetch: FetchPolicy,
+}
+
+// 回源的超时与重试次数（upstream 设置叠加路由覆盖）
+#[derive(Clone, Copy, Debug)]
+pub struct FetchPolicy {
+    pub timeout: Duration,
+    pub max_retries: u32,
+}
+
+impl CachePolicy {
+    // RFC 7234 3.2：带凭据请求的响应须有 public、s-maxage 或 must-revalidate 才能存入共享缓存；
+    // 源站给代理的正的新鲜期同样视为明确允许（已经过期的绝对时间不算）
+    pub fn may_store(&self, headers: &HeaderMap, now: u64) -> bool {
+        if self.surrogate_headers && surrogate_no_store(headers) {
+            return false;
+        }
+        !self.authenticated
+            || shared_cacheable(headers)
+            || (self.surrogate_headers && surrogate_lifetime(headers, now).is_some_and(|secs| secs > 0))
+    }
+}
+
+// 兄弟代理：本地未命中时先向其他代理实例查询缓存
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PeersConfig {
+    // 兄弟代理的监听地址（如 "10.0.0.2:3000"）
+    pub addrs: Vec<String>,
+    // 等待兄弟代理应答的最长时间（毫秒），超时后回源
+    pub lookup_timeout_ms: u64,
+    // 分片模式：按 URL 一致性哈希，每个对象只由一个实例缓存，其他实例转发给它
+    pub shard: bool,
+    // 本实例在集群中的地址，须与其他实例 addrs 中的写法一致
+    pub self_addr: Option<String>,
+}
+
+impl Default for PeersConfig {
+    fn default() -> Self {
+        PeersConfig {
+            addrs: Vec::new(),
+            lookup_timeout_ms: PEER_LOOKUP_TIMEOUT_MS,
+            shard: false,
+            self_addr: None,
+        }
+    }
+}
+
+// 路由：按 host / 路径前缀匹配请求，覆盖全局设置
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RouteConfig {
+    pub name: String,
+    // 为空时匹配所有 host
+    pub host: Option<String>,
+    // 为空时匹配所有路径
+    pub path_prefix: Option<String>,
+    pub max_object_bytes: Option<u64>,
+    // 覆盖 upstream.timeout_secs / upstream.max_retries / downstream.request_deadline_secs，
+    // 例如清单文件 5 秒超时，大归档放宽到 10 分钟
+    pub timeout_secs: Option<u64>,
+    pub max_retries: Option<u32>,
+    pub request_deadline_secs: Option<u64>,
+    pub heuristic_fraction: Option<f64>,
+    pub heuristic_max_secs: Option<u64>,
+    // 上游并发已满时的排队优先级
+    pub priority: Option<Priority>,
+    // 可互换的源站列表（如 "https://eu.cdn.example.com"），按观测到的延迟选择最快的一个
+    pub origins: Vec<String>,
+    // 签名 URL 校验，未配置时不校验
+    pub signed_url: Option<SignedUrlConfig>,
+    // 回源请求签名（SigV4 / HMAC），未配置时原样转发
+    pub origin_auth: Option<OriginAuthConfig>,
+    // 播放列表 / HTML 响应体的替换规则，按顺序执行
+    pub rewrites: Vec<RewriteRule>,
+    // 请求 URL 的重定向与改写规则，按顺序匹配，在计算缓存键之前执行
+    pub url_rewrites: Vec<UrlRule>,
+    // 对 HTML 响应解析 Edge Side Includes
+    pub esi: bool,
+    // 直接连接的源站地址（ip:port），不在本机解析请求中的域名
+    pub connect_to: Option<SocketAddr>,
+    // 配合 connect_to 发给源站的 Host 与 TLS SNI，未设置时使用路由的 host
+    pub sni_host: Option<String>,
+    // 可信的内部 API：响应不随凭据变化，携带 Authorization 的请求也照常缓存
+    pub cache_authenticated: bool,
+    // 不参与缓存键的查询参数（如 CDN 按客户端下发的 token），以 * 结尾时按前缀匹配；
+    // 内容相同而 token 不同的分片 URL 共用一个缓存条目
+    pub cache_key_ignore_params: Vec<String>,
+    // 缓存键只使用路径，忽略全部查询参数
+    pub cache_key_ignore_query: bool,
+    // 不论源站的缓存头如何（包括 no-cache、max-age=0），新鲜期都不短于 / 不长于该值（秒）。
+    // 会让客户端拿到源站不希望被缓存的内容，须同时设置 override_origin_cache_headers = true
+    pub min_ttl_secs: Option<u64>,
+    pub max_ttl_secs: Option<u64>,
+    pub override_origin_cache_headers: bool,
+    // 反向代理收到源形式请求（/path 加 Host）时回源使用的协议，默认 http
+    pub scheme: Option<String>,
+}
+
+impl RouteConfig {
+    pub fn matches(&self, uri: &Uri) -> bool {
+        let host_ok = match &self.host {
+            Some(host) => uri
+                .host()
+                .map(|h| h.eq_ignore_ascii_case(host))
+                .unwrap_or(false),
+            None => true,
+        };
+        let path_ok = match &self.path_prefix {
+            Some(prefix) => uri.path().starts_with(prefix.as_str()),
+            None => true,
+        };
+        host_ok && path_ok
+    }
+
+    fn key_ignores(&self, param: &str) -> bool {
+        self.cache_key_ignore_query
+            || self.cache_key_ignore_params.iter().any(|ignored| match ignored.strip_suffix('*') {
+                Some(prefix) => param.starts_with(prefix),
+                None => param == ignored,
+            })
+    }
+
+    // 经 connect_to 回源时使用的域名
+    pub fn origin_host(&self) -> Option<&str> {
+        self.sni_host.as_deref().or(self.host.as_deref())
+    }
+}
+
+impl Config {
+    pub fn route(&self, uri: &Uri) -> Option<&RouteConfig> {
+        self.routes.iter().find(|route| route.matches(uri))
+    }
+
+    pub fn via_enabled(&self) -> bool {
+        self.via.unwrap_or(self.mode == ProxyMode::Forward)
+    }
+
+    pub fn private_targets_denied(&self) -> bool {
+        self.deny_private_targets
+            .unwrap_or(matches!(self.mode, ProxyMode::Forward | ProxyMode::Transparent))
+    }
+
+    // 请求在指标中的标签值
+    pub fn metrics_label(&self, uri: &Uri) -> String {
+        match self.metrics.label {
+            MetricsLabel::Host => uri.host().unwrap_or_default().to_ascii_lowercase(),
+            MetricsLabel::Route => match self.routes.iter().position(|route| route.matches(uri)) {
+                Some(i) if self.routes[i].name.is_empty() => format!("#{}", i),
+                Some(i) => self.routes[i].name.clone(),
+                None => "default".to_string(),
+            },
+        }
+    }
+
+    // 替换缓存键策略，例如按租户隔离或使用自定义的文件布局
+    pub fn with_cache_key_strategy(mut self, strategy: Arc<dyn CacheKeyStrategy>) -> Self {
+        self.key_strategy = Some(CustomKeyStrategy(strategy));
+        self
+    }
+
+    // URL 对应的缓存键，用于没有原始请求的场合（管理接口清除、重启续传）
+    pub fn cache_key(&self, uri: &Uri) -> String {
+        self.request_cache_key(&Method::GET, uri, &HeaderMap::new())
+    }
+
+    // URL 的所有缓存键（按 URL 清除、续传时全部检查）：每个客户端类别一个，
+    // 开启代理压缩时每个类别再分 identity 与 gzip 两个版本；都没有配置时只有一个
+    pub fn cache_key_variants(&self, uri: &Uri) -> Vec<String> {
+        let classes: Vec<Option<&str>> = match self.cache.key.client_classes.as_slice() {
+            [] => vec![None],
+            classes => classes
+                .iter()
+                .map(|class| Some(class.name.as_str()))
+                .chain([Some(DEFAULT_CLIENT_CLASS)])
+                .collect(),
+        };
+        let encodings: &[Option<&'static str>] = if self.cache.compression.enabled {
+            &[Some("identity"), Some("gzip")]
+        } else {
+            &[None]
+        };
+        let mut keys: Vec<String> = Vec::new();
+        for class in classes {
+            for encoding in encodings {
+                let mut headers = HeaderMap::new();
+                if let Some(encoding) = encoding {
+                    headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(encoding));
+                }
+                let key = self.class_cache_key(&Method::GET, uri, &headers, class);
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+        }
+        keys
+    }
+
+    // 请求对应的缓存键：去掉签名参数以及路由配置为不参与缓存键的查询参数，再交给缓存键策略
+    pub fn request_cache_key(&self, method: &Method, uri: &Uri, headers: &HeaderMap) -> String {
+        let class = client_class(&self.cache.key.client_classes, headers);
+        self.class_cache_key(method, uri, headers, class)
+    }
+
+    // 写入日志与调试接口的 URL：去掉路由的签名参数，日志中不留下可以直接使用的签名
+    pub fn loggable_uri(&self, uri: &Uri) -> String {
+        match self.route(uri).and_then(|route| route.signed_url.as_ref()) {
+            Some(signed_url) => signed_url.strip_signature(uri).to_string(),
+            None => uri.to_string(),
+        }
+    }
+
+    fn class_cache_key(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        client_class: Option<&str>,
+    ) -> String {
+        let uri = match self.route(uri) {
+            Some(route) => {
+                let uri = match &route.signed_url {
+                    Some(signed_url) => signed_url.strip_signature(uri),
+                    None => uri.clone(),
+                };
+                retain_query_params(&uri, |param| !route.key_ignores(param))
+            }
+            None => uri.clone(),
+        };
+        let req = KeyRequest {
+            method,
+            uri: &uri,
+            headers,
+            client_class,
+        };
+        match &self.key_strategy {
+            Some(CustomKeyStrategy(strategy)) => sanitize(strategy.key(&req)),
+            None => self.cache.key.key(&req),
+        }
+    }
+
+    pub fn cache_policy(&self, uri: &Uri) -> CachePolicy {
+        let route = self.route(uri);
+        CachePolicy {
+            max_object_bytes: route
+                .and_then(|route| route.max_object_bytes)
+                .unwrap_or(self.cache.max_object_bytes),
+            heuristic_fraction: route
+                .and_then(|route| route.heuristic_fraction)
+                .unwrap_or(self.cache.heuristic_fraction),
+            heuristic_max_secs: route
+                .and_then(|route| route.heuristic_max_secs)
+                .unwrap_or(self.cache.heuristic_max_secs),
+            read_ahead_bytes: 0,
+            authenticated: false,
+            min_ttl_secs: route
+                .filter(|route| route.override_origin_cache_headers)
+                .and_then(|route| route.min_ttl_secs),
+            max_ttl_secs: route
+                .filter(|route| route.override_origin_cache_headers)
+                .and_then(|route| route.max_ttl_secs),
+            sniff_content_type: self.cache.sniff_content_type,
+            surrogate_headers: self.cache.surrogate_headers,
+            fetch: self.fetch_policy(route),
+        }
+    }
+
+    pub fn fetch_policy(&self, route: Option<&RouteConfig>) -> FetchPolicy {
+        FetchPolicy {
+            timeout: Duration::from_secs(
+                route
+                    .and_then(|route| route.timeout_secs)
+                    .unwrap_or(self.upstream.timeout_secs),
+            ),
+            max_retries: route
+                .and_then(|route| route.max_retries)
+                .unwrap_or(self.upstream.max_retries),
+        }
+    }
+
+    // 单个请求的整体处理时限，路由的设置优先
+    pub fn request_deadline(&self, uri: &Uri) -> Option<Duration> {
+        self.route(uri)
+            .and_then(|route| route.request_deadline_secs)
+            .or(self.downstream.request_deadline_secs)
+            .map(Duration::from_secs)
+    }
+
+    // 检查解析阶段发现不了的配置错误，返回不影响启动的警告
+    pub fn validate(&self) -> Result<Vec<String>> {
+        let mut warnings = Vec::new();
+        if !(0.0..=1.0).contains(&self.cache.heuristic_fraction) {
+            bail!("cache.heuristic_fraction must be between 0 and 1");
+        }
+        let read_ahead = &self.cache.read_ahead;
+        if read_ahead.enabled && (read_ahead.window_secs <= 0.0 || read_ahead.max_bytes == 0) {
+            bail!("cache.read_ahead: window_secs and max_bytes must be greater than 0");
+        }
+        let runtime = &self.runtime;
+        if runtime.worker_threads == Some(0) || runtime.max_blocking_threads == Some(0) {
+            bail!("runtime.worker_threads and runtime.max_blocking_threads must be greater than 0");
+        }
+        let process = &self.process;
+        if !cfg!(unix) && (process.daemon || process.pid_file.is_some() || process.user.is_some() || process.group.is_some())
+        {
+            bail!("process: daemon, pid_file, user and group are only supported on Unix");
+        }
+        if process.log_file.is_some() && !process.daemon {
+            warnings.push("process.log_file has no effect without daemon".to_string());
+        }
+        if process.daemon && cfg!(feature = "console") {
+            warnings.push("process.daemon: tokio-console is not available in the background process".to_string());
+        }
+        if self.sandbox.enabled && !cfg!(target_os = "linux") {
+            bail!("sandbox is only supported on Linux");
+        }
+        let downstream = &self.downstream;
+        if downstream.listen_backlog == 0
+            || downstream.recv_buffer_bytes == Some(0)
+            || downstream.send_buffer_bytes == Some(0)
+            || downstream.tcp_keepalive_secs == Some(0)
+        {
+            bail!("downstream: listen_backlog, recv_buffer_bytes, send_buffer_bytes and tcp_keepalive_secs must be greater than 0");
+        }
+        if self.cache.chunk_bytes == 0 {
+            bail!("cache.chunk_bytes must be greater than 0");
+        }
+        if self.cache.disk_io_concurrency == 0 {
+            bail!("cache.disk_io_concurrency must be greater than 0");
+        }
+        if self.cache.write_queue_size == 0 {
+            bail!("cache.write_queue_size must be greater than 0");
+        }
+        if self.cache.max_memory_bytes == Some(0) || self.cache.max_disk_bytes == Some(0) {
+            bail!("cache.max_memory_bytes and cache.max_disk_bytes must be greater than 0");
+        }
+        for header in &self.cache.key.headers {
+            if hyper::header::HeaderName::from_bytes(header.as_bytes()).is_err() {
+                bail!("cache.key.headers: invalid header name {}", header);
+            }
+        }
+        let classes = &self.cache.key.client_classes;
+        for (i, class) in classes.iter().enumerate() {
+            if class.name.is_empty() || class.name == DEFAULT_CLIENT_CLASS {
+                bail!("cache.key.client_classes: name must be set and not {}", DEFAULT_CLIENT_CLASS);
+            }
+            if classes[..i].iter().any(|other| other.name == class.name) {
+                bail!("cache.key.client_classes: duplicate name {}", class.name);
+            }
+        }
+        let tokens = &self.admin.tokens;
+        for (i, token) in tokens.iter().enumerate() {
+            if token.name.is_empty() || token.token.is_empty() {
+                bail!("admin.tokens: name and token must be set");
+            }
+            if tokens[..i].iter().any(|other| other.name == token.name) {
+                bail!("admin.tokens: duplicate name {}", token.name);
+            }
+        }
+        if self.metrics.require_token && self.admin.token.is_none() && tokens.is_empty() {
+            bail!("metrics.require_token requires admin.token or admin.tokens");
+        }
+        if !self.client_usage.users.is_empty() && !self.client_usage.enabled {
+            bail!("client_usage.users requires client_usage.enabled");
+        }
+        if let Some(quota) = self.client_usage.daily_quota_bytes {
+            if !self.client_usage.enabled {
+                bail!("client_usage.daily_quota_bytes requires client_usage.enabled");
+            }
+            if quota == 0 {
+                bail!("client_usage.daily_quota_bytes must be greater than 0");
+            }
+        }
+        if !(0.0..=1.0).contains(&self.decision_log.sample_rate) {
+            bail!("decision_log.sample_rate must be between 0 and 1");
+        }
+        let packing = &self.cache.packing;
+        if packing.enabled {
+            if packing.max_object_bytes == 0 {
+                bail!("cache.packing.max_object_bytes must be greater than 0");
+            }
+            if packing.segment_bytes < packing.max_object_bytes {
+                bail!("cache.packing.segment_bytes must be at least max_object_bytes");
+            }
+            if packing.compact_interval_secs == 0 {
+                bail!("cache.packing.compact_interval_secs must be greater than 0");
+            }
+            if !(packing.min_live_ratio > 0.0 && packing.min_live_ratio <= 1.0) {
+                bail!("cache.packing.min_live_ratio must be greater than 0 and at most 1");
+            }
+        }
+        let janitor = &self.cache.janitor;
+        if !(0.0..1.0).contains(&janitor.jitter) {
+            bail!("cache.janitor.jitter must be at least 0 and less than 1");
+        }
+        if janitor.sweep_interval_secs == Some(0)
+            || janitor.quota_interval_secs == 0
+            || janitor.verify_interval_secs == Some(0)
+            || janitor.verify_batch == 0
+        {
+            bail!("cache.janitor: intervals and verify_batch must be greater than 0");
+        }
+        if janitor.verify_interval_secs.is_some() && !self.cache.verify_checksums {
+            warnings.push("cache.janitor.verify_interval_secs has no effect without cache.verify_checksums".to_string());
+        }
+        if !(1..=15).contains(&self.cache.memory_admission.min_hits) {
+            bail!("cache.memory_admission.min_hits must be between 1 and 15");
+        }
+        let compression = &self.cache.compression;
+        if compression.enabled {
+            let keyed = self.cache.key.headers.iter().any(|h| h.eq_ignore_ascii_case("accept-encoding"));
+            if !self.cache.key.normalize_accept_encoding || !keyed {
+                bail!("cache.compression requires cache.key.normalize_accept_encoding and accept-encoding in cache.key.headers");
+            }
+            if compression.min_bytes > compression.max_bytes {
+                bail!("cache.compression.min_bytes must not exceed max_bytes");
+            }
+        }
+        self.validate_tls(&mut warnings)?;
+        for method in &self.upstream.retry_methods {
+            if hyper::Method::from_bytes(method.as_bytes()).is_err() {
+                bail!("upstream.retry_methods: invalid method {}", method);
+            }
+        }
+        if let Some(header) = &self.upstream.idempotency_key_header {
+            if hyper::header::HeaderName::from_bytes(header.as_bytes()).is_err() {
+                bail!("upstream.idempotency_key_header: invalid header name {}", header);
+            }
+        }
+        if self.upstream.max_response_headers == 0 || self.upstream.max_response_header_bytes == 0 {
+            bail!("upstream.max_response_headers and max_response_header_bytes must be greater than 0");
+        }
+        if self.upstream.redirects.follow && self.upstream.redirects.max_hops == 0 {
+            bail!("upstream.redirects.max_hops must be greater than 0");
+        }
+        if self.upstream.redirects.cache_final_url && !self.upstream.redirects.follow {
+            warnings.push("upstream.redirects.cache_final_url has no effect without follow".to_string());
+        }
+        if self.upstream.body_stall_timeout_secs == 0 {
+            bail!("upstream.body_stall_timeout_secs must be greater than 0");
+        }
+        if self.upstream.timeout_secs == 0 {
+            bail!("upstream.timeout_secs must be greater than 0");
+        }
+        let listeners = [Some(self.listen), self.admin.listen, self.metrics.listen];
+        let bound: Vec<SocketAddr> = listeners.into_iter().flatten().collect();
+        if (1..bound.len()).any(|i| bound[..i].contains(&bound[i])) {
+            bail!("listen, admin.listen and metrics.listen must be different addresses");
+        }
+        for peer in &self.admin.peers {
+            if format!("http://{}/", peer).parse::<Uri>().is_err() {
+                bail!("admin.peers: invalid address {}", peer);
+            }
+        }
+        // 其他实例要求认证时 purge 只能以 admin 角色转发
+        if !self.admin.peers.is_empty() && !self.admin.tokens.is_empty() && self.admin.peer_token().is_none() {
+            bail!("admin.peers requires admin.token or an admin.tokens entry with the admin role");
+        }
+        if !self.admin.peers.is_empty() && self.admin.listen.is_none() {
+            warnings.push("admin.peers has no effect without admin.listen".to_string());
+        }
+        if let Some(addr) = self.admin.listen {
+            if self.admin.token.is_none() && !addr.ip().is_loopback() {
+                warnings.push(format!(
+                    "admin API on {} is reachable from the network without admin.token",
+                    addr
+                ));
+            }
+        }
+        self.validate_mode(&mut warnings)?;
+        if self.metrics.max_label_values == 0 {
+            bail!("metrics.max_label_values must be greater than 0");
+        }
+        if self.peers.shard && self.peers.self_addr.is_none() {
+            bail!("peers.shard requires peers.self_addr");
+        }
+        if self.peers.shard && self.peers.addrs.is_empty() {
+            warnings.push("peers.shard is enabled but peers.addrs is empty".to_string());
+        }
+        for (i, route) in self.routes.iter().enumerate() {
+            let name = if route.name.is_empty() {
+                format!("#{}", i)
+            } else {
+                route.name.clone()
+            };
+            if route.heuristic_fraction.is_some_and(|f| !(0.0..=1.0).contains(&f)) {
+                bail!("route {}: heuristic_fraction must be between 0 and 1", name);
+            }
+            if route.timeout_secs == Some(0) || route.request_deadline_secs == Some(0) {
+                bail!("route {}: timeout_secs and request_deadline_secs must be greater than 0", name);
+            }
+            for origin in &route.origins {
+                let uri: Uri = origin
+                    .parse()
+                    .with_context(|| format!("route {}: invalid origin {}", name, origin))?;
+                if uri.scheme().is_none() || uri.authority().is_none() {
+                    bail!("route {}: origin {} must look like scheme://host[:port]", name, origin);
+                }
+            }
+            if route.origins.len() == 1 {
+                warnings.push(format!(
+                    "route {}: a single origin is never latency-selected",
+                    name
+                ));
+            }
+            if let Some(addr) = route.connect_to {
+                if !route.origins.is_empty() {
+                    bail!("route {}: connect_to and origins cannot be used together", name);
+                }
+                let Some(host) = route.origin_host() else {
+                    bail!("route {}: connect_to requires host or sni_host", name);
+                };
+                // 同一地址的连接在连接池中共用，只能有一个握手域名
+                if let Some(other) = self.routes[..i]
+                    .iter()
+                    .find(|r| r.connect_to == Some(addr) && r.origin_host() != Some(host))
+                {
+                    bail!(
+                        "route {}: connect_to {} is already used by route {} with a different host",
+                        name, addr, other.name
+                    );
+                }
+            } else if route.sni_host.is_some() {
+                bail!("route {}: sni_host requires connect_to", name);
+            }
+            if route.scheme.as_deref().is_some_and(|s| s != "http" && s != "https") {
+                bail!("route {}: scheme must be http or https", name);
+            }
+            if route.scheme.is_some() && route.host.is_none() {
+                bail!("route {}: scheme requires host", name);
+            }
+            let forces_ttl = route.min_ttl_secs.is_some() || route.max_ttl_secs.is_some();
+            if forces_ttl && !route.override_origin_cache_headers {
+                bail!(
+                    "route {}: min_ttl_secs / max_ttl_secs ignore the origin's caching headers; \
+                     set override_origin_cache_headers = true to confirm",
+                    name
+                );
+            }
+            if let (Some(min), Some(max)) = (route.min_ttl_secs, route.max_ttl_secs) {
+                if min > max {
+                    bail!("route {}: min_ttl_secs must not exceed max_ttl_secs", name);
+                }
+            }
+            if route.override_origin_cache_headers && !forces_ttl {
+                warnings.push(format!(
+                    "route {}: override_origin_cache_headers has no effect without min_ttl_secs or max_ttl_secs",
+                    name
+                ));
+            }
+            if let Some(signed_url) = &route.signed_url {
+                signed_url
+                    .validate()
+                    .with_context(|| format!("route {}", name))?;
+            }
+            if let Some(origin_auth) = &route.origin_auth {
+                origin_auth
+                    .validate()
+                    .with_context(|| format!("route {}", name))?;
+            }
+            // 前面已有匹配所有请求的路由时，后面的路由永远不会生效
+            if let Some(catch_all) = self.routes[..i]
+                .iter()
+                .find(|r| r.host.is_none() && r.path_prefix.is_none())
+            {
+                warnings.push(format!(
+                    "route {} is unreachable: route {} matches every request",
+                    name, catch_all.name
+                ));
+            }
+        }
+        Ok(warnings)
+    }
+
+    // 只在其他模式下有意义的设置
+    fn validate_mode(&self, warnings: &mut Vec<String>) -> Result<()> {
+        match self.mode {
+            ProxyMode::Auto => {}
+            ProxyMode::Forward => {
+                if let Some(route) = self.routes.iter().find(|route| route.scheme.is_some()) {
+                    bail!(
+                        "route {}: scheme only applies to origin-form requests, which forward mode does not proxy",
+                        route.name
+                    );
+                }
+            }
+            ProxyMode::Reverse => {
+                if !self.routes.iter().any(|route| route.host.is_some()) {
+                    bail!("reverse mode requires at least one route with host");
+                }
+            }
+            ProxyMode::Transparent => {
+                if self.metrics.listen.is_none() {
+                    warnings.push(
+                        "in transparent mode every request with a Host is proxied; \
+                         set metrics.listen to reach /metrics and /health"
+                            .to_string(),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_tls(&self, warnings: &mut Vec<String>) -> Result<()> {
+        for origin in &self.upstream.tls.origins {
+            if origin.host.is_empty() {
+                bail!("upstream.tls.origins: host must not be empty");
+            }
+            if origin.client_cert.is_some() != origin.client_key.is_some() {
+                bail!(
+                    "upstream.tls.origins {}: client_cert and client_key must be set together",
+                    origin.host
+                );
+            }
+            if origin.insecure_skip_verify {
+                warnings.push(format!(
+                    "TLS certificate verification is disabled for {}",
+                    origin.host
+                ));
+            }
+            if origin.http1_only && self.upstream.http_version != UpstreamHttpVersion::Alpn {
+                warnings.push(format!(
+                    "upstream.tls.origins {}: http1_only only applies when upstream.http_version is alpn",
+                    origin.host
+                ));
+            }
+        }
+        for file in self.tls_files() {
+            if !file.is_file() {
+                bail!("upstream.tls: {} does not exist", file.display());
+            }
+        }
+        Ok(())
+    }
+
+    // 回源 TLS 使用的 CA 证书与客户端证书文件
+    pub fn tls_files(&self) -> Vec<&PathBuf> {
+        let tls = &self.upstream.tls;
+        let mut files: Vec<&PathBuf> = tls.ca_files.iter().collect();
+        for origin in &tls.origins {
+            files.extend(&origin.ca_files);
+            files.extend(origin.client_cert.iter().chain(&origin.client_key));
+        }
+        files
+    }
+
+    // 用于打印的副本，隐藏签名密钥
+    pub fn redacted(&self) -> Config {
+        let mut config = self.clone();
+        if config.admin.token.is_some() {
+            config.admin.token = Some("<redacted>".to_string());
+        }
+        for token in &mut config.admin.tokens {
+            token.token = "<redacted>".to_string();
+        }
+        for route in &mut config.routes {
+            if let Some(signed_url) = &mut route.signed_url {
+                signed_url.redact();
+            }
+            if let Some(origin_auth) = &mut route.origin_auth {
+                origin_auth.redact();
+            }
+        }
+        config
+    }
+
+    // 解析配置文件内容：先取出 profile，文件中没有写出的项使用该档位的默认值
+    pub fn parse(content: &str) -> Result<Config> {
+        let table: toml::Table = toml::from_str(content)?;
+        let profile = match table.get("profile") {
+            Some(profile) => Profile::deserialize(profile.clone())?,
+            None => Profile::default(),
+        };
+        let mut merged = profile.defaults();
+        merge_tables(&mut merged, table);
+        Ok(merged.try_into()?)
+    }
+
+    pub fn load(path: &Path) -> Result<Config> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        let mut config = Config::parse(&content)
+            .with_context(|| format!("failed to parse config file {}", path.display()))?;
+        for class in &mut config.cache.key.client_classes {
+            class.compile()?;
+        }
+        for route in &mut config.routes {
+            if let Some(signed_url) = &mut route.signed_url {
+                signed_url
+                    .load_keys()
+                    .with_context(|| format!("route {}", route.name))?;
+            }
+            for rule in &mut route.rewrites {
+                rule.compile()
+                    .with_context(|| format!("route {}", route.name))?;
+            }
+            for rule in &mut route.url_rewrites {
+                rule.compile()
+                    .with_context(|| format!("route {}", route.name))?;
+            }
+        }
+        Ok(config)
+    }
+}
+
+// 把 overlay 中的项合并到 base，两边都是表时逐项合并，否则 overlay 覆盖
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge_tables(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
@@ -1,13 +1,252 @@
 // 定义最大缓存个数为多少个
 pub const MAX_CACHE_SIZE: usize = 20;
+// 定义内存缓存分片数为 8 个
+pub const MEMORY_CACHE_SHARDS: usize = 8;
 // 定义最大文件大小为 100MB
 pub const MAX_FILE_SIZE: usize = 100 * 1024 * 1024; 
+// 定义续传时最多补齐的缺失区间数，超过则重新获取完整对象
+pub const MAX_RESUME_GAPS: usize = 8;
 // 定义超时时间为 30 秒
 pub const TIMEOUT_SECONDS: u64 = 30; 
 // 定义缓存目录为 cache
 pub const CACHE_DIR: &str = "cache"; 
+// 定义缓存元数据（.meta）的当前格式版本
+pub const META_VERSION: u32 = 1;
+// 定义缓存键（同时是文件名）每一段的最大长度为 160 个字符
+pub const CACHE_KEY_MAX_LEN: usize = 160;
+// 定义按目录存放的缓存键最多 16 层
+pub const CACHE_KEY_MAX_DEPTH: usize = 16;
+// 定义按目录存放的缓存键总长度上限为 1024 个字符
+pub const CACHE_KEY_MAX_PATH_LEN: usize = 1024;
 // 定义最大重试次数为 3 次
 pub const MAX_RETRIES: u32 = 3; 
+// 定义默认允许自动重试的幂等请求方法
+pub const RETRY_METHODS: [&str; 3] = ["GET", "HEAD", "OPTIONS"];
+// 定义客户端声明请求可安全重试的请求头
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
 // 定义重试延迟为 1000 毫秒
 pub const RETRY_DELAY_MS: u64 = 1000; 
+// 定义磁盘写入队列长度为 256 个
+pub const DISK_WRITE_QUEUE_SIZE: usize = 256;
+// 定义同时进行的缓存磁盘读写数上限为 16 个
+pub const DISK_IO_CONCURRENCY: usize = 16;
+// 定义打包存储的对象大小上限为 16KB
+pub const PACK_MAX_OBJECT_BYTES: u64 = 16 * 1024;
+// 定义打包存储的段文件大小为 64MB
+pub const PACK_SEGMENT_BYTES: u64 = 64 * 1024 * 1024;
+// 定义打包存储的压缩检查间隔为 300 秒
+pub const PACK_COMPACT_INTERVAL_SECONDS: u64 = 300;
+// 定义段文件有效数据占比低于 50% 时压缩
+pub const PACK_MIN_LIVE_RATIO: f64 = 0.5;
+// 定义后台整理任务间隔的随机抖动为 ±10%
+pub const JANITOR_JITTER: f64 = 0.1;
+// 定义清理过期条目时只删除过期超过 1 天的条目
+pub const JANITOR_EXPIRED_GRACE_SECONDS: u64 = 24 * 3600;
+// 定义重新统计磁盘用量的间隔为 600 秒
+pub const JANITOR_QUOTA_INTERVAL_SECONDS: u64 = 600;
+// 定义后台完整性校验每次最多检查 100 个条目
+pub const JANITOR_VERIFY_BATCH: usize = 100;
+// 定义正在处理的客户端请求超过 1000 个时推迟后台整理
+pub const JANITOR_PAUSE_ABOVE_REQUESTS: u64 = 1000;
+// 定义后台整理被推迟后 30 秒再检查负载
+pub const JANITOR_PAUSE_RETRY_SECONDS: u64 = 30;
+// 定义磁盘缓存超过 1MB 时使用 mmap 读取
+pub const MMAP_THRESHOLD: usize = 1024 * 1024;
+// 定义从磁盘读取时不超过 1MB 的对象直接放入内存缓存
+pub const MEMORY_ADMISSION_MAX_BYTES: u64 = 1024 * 1024;
+// 定义更大的对象近期被读取 4 次后才放入内存缓存
+pub const MEMORY_ADMISSION_MIN_HITS: u8 = 4;
+// 定义访问频率估计每行 65536 个计数器，共 4 行
+pub const ADMISSION_SKETCH_WIDTH: usize = 1 << 16;
+pub const ADMISSION_SKETCH_DEPTH: usize = 4;
+// 定义磁盘写满后暂停写盘的时长为 30 秒，之后再尝试写入
+pub const DISK_FULL_RETRY_SECONDS: u64 = 30;
+// 定义磁盘写满时紧急淘汰的比例为磁盘缓存总量的 10%
+pub const DISK_FULL_EVICT_FRACTION: f64 = 0.1;
+// 定义大对象分块存储的块大小为 8MB，超过一个块的对象按块文件存放
+pub const CACHE_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+// 定义默认监听地址为 127.0.0.1:3000
+pub const LISTEN_ADDR: &str = "127.0.0.1:3000";
+// 定义上游空闲连接保留时间为 90 秒
+pub const POOL_IDLE_TIMEOUT_SECONDS: u64 = 90;
+// 定义读取客户端请求头的超时时间为 30 秒
+pub const HEADER_READ_TIMEOUT_SECONDS: u64 = 30;
+// 定义客户端请求行与请求头的最大长度为 64KB
+pub const MAX_HEADER_BYTES: usize = 64 * 1024;
+// 定义向客户端写入数据无进展的超时时间为 60 秒
+pub const CLIENT_WRITE_TIMEOUT_SECONDS: u64 = 60;
+// 定义客户端长连接两次请求之间的空闲超时为 60 秒
+pub const KEEP_ALIVE_TIMEOUT_SECONDS: u64 = 60;
+// 定义单个客户端连接最多处理 1000 个请求
+pub const MAX_REQUESTS_PER_CONNECTION: u64 = 1000;
+// 定义监听 socket 的连接队列长度为 1024
+pub const LISTEN_BACKLOG: u32 = 1024;
+// 定义转发给源站的请求体最大为 16MB
+pub const MAX_REQUEST_BODY_SIZE: u64 = 16 * 1024 * 1024;
+// 定义 HEAD 探测结果缓存 60 秒
+pub const HEAD_CACHE_TTL_SECONDS: u64 = 60;
+// 定义 HEAD 探测结果最多缓存 1024 个 URL
+pub const ORIGIN_META_CACHE_SIZE: usize = 1024;
+// 定义客户端指定请求优先级的请求头
+pub const PRIORITY_HEADER: &str = "x-proxy-priority";
+// 定义默认按高优先级处理的播放列表扩展名
+pub const PLAYLIST_EXTENSIONS: [&str; 2] = [".m3u8", ".mpd"];
+// 定义开启调试响应头的请求头
+pub const DEBUG_HEADER: &str = "x-proxy-debug";
+// 定义多源站延迟探测间隔为 30 秒
+pub const ORIGIN_PROBE_INTERVAL_SECONDS: u64 = 30;
+// 定义源站延迟 EWMA 的平滑系数
+pub const ORIGIN_LATENCY_EWMA_ALPHA: f64 = 0.3;
+// 定义等待兄弟代理应答的超时时间为 500 毫秒
+pub const PEER_LOOKUP_TIMEOUT_MS: u64 = 500;
+// 定义兄弟代理之间查询时携带的请求头
+pub const PEER_HEADER: &str = "x-proxy-peer";
+// 定义转发给分片所属实例时携带的请求头
+pub const SHARD_HEADER: &str = "x-proxy-shard";
+// 定义一致性哈希环上每个实例的虚拟节点数
+pub const SHARD_VIRTUAL_NODES: usize = 100;
+// 定义响应体改写的最大字节数为 4MB
+pub const REWRITE_MAX_BODY_SIZE: u64 = 4 * 1024 * 1024;
+// 定义可以改写响应体的内容类型
+pub const REWRITE_CONTENT_TYPES: [&str; 5] = [
+    "application/vnd.apple.mpegurl",
+    "application/x-mpegurl",
+    "audio/mpegurl",
+    "application/dash+xml",
+    "text/html",
+];
+// 定义 ESI 模板的最大字节数为 1MB
+pub const ESI_MAX_TEMPLATE_SIZE: u64 = 1024 * 1024;
+// 定义单个 ESI 模板最多包含的片段数
+pub const ESI_MAX_INCLUDES: usize = 32;
+// 定义启发式新鲜期为 Last-Modified 距今时长的 10%
+pub const HEURISTIC_FRACTION: f64 = 0.1;
+// 定义启发式新鲜期的上限为 1 天
+pub const HEURISTIC_MAX_SECONDS: u64 = 24 * 60 * 60;
+// 定义提前刷新的调度间隔为 10 秒
+pub const REFRESH_INTERVAL_SECONDS: u64 = 10;
+// 定义剩余新鲜期低于 20% 时提前刷新
+pub const REFRESH_AHEAD_FRACTION: f64 = 0.2;
+// 定义提前刷新所需的最少命中次数
+pub const REFRESH_MIN_HITS: u64 = 2;
+// 定义每次调度最多刷新的条目数
+pub const REFRESH_MAX_PER_TICK: usize = 16;
+// 定义视为空闲的回源请求速率（每秒）
+pub const REFRESH_IDLE_MAX_RPS: f64 = 5.0;
+// 定义跟踪命中次数的条目数上限
+pub const REFRESH_TRACKED_ENTRIES: usize = 4096;
+// 定义记录待续传下载的文件名（位于缓存目录）
+pub const PENDING_DOWNLOADS_FILE: &str = "pending_downloads.json";
+// 定义退出时等待被中断的下载保存已收到数据的时间为 10 秒
+pub const RESUME_SHUTDOWN_GRACE_SECONDS: u64 = 10;
+// 定义预读窗口相当于客户端 10 秒内取走的数据量
+pub const READ_AHEAD_WINDOW_SECONDS: f64 = 10.0;
+// 定义预读窗口的下限为 256KB，也用于尚未测量带宽的客户端
+pub const READ_AHEAD_MIN_BYTES: u64 = 256 * 1024;
+// 定义预读窗口的上限为 16MB
+pub const READ_AHEAD_MAX_BYTES: u64 = 16 * 1024 * 1024;
+// 定义客户端带宽 EWMA 的平滑系数
+pub const CLIENT_BANDWIDTH_EWMA_ALPHA: f64 = 0.3;
+// 定义计入带宽测量的最小响应体为 256KB，更小的响应几乎都留在套接字缓冲区里
+pub const CLIENT_BANDWIDTH_MIN_SAMPLE_BYTES: u64 = 256 * 1024;
+// 定义测量带宽时响应体的切块大小为 64KB
+pub const CLIENT_BANDWIDTH_CHUNK_BYTES: usize = 64 * 1024;
+// 定义最多跟踪带宽的客户端数
+pub const CLIENT_BANDWIDTH_TRACKED: usize = 4096;
+// 定义最多跟踪流量的客户端数（IP 与用户名合计）
+pub const CLIENT_USAGE_TRACKED: usize = 4096;
+// 定义一天的秒数，按天计算的配额在 UTC 零点清零
+pub const SECONDS_PER_DAY: u64 = 24 * 3600;
+// 定义错误响应中标明上游失败分类的响应头
+pub const UPSTREAM_ERROR_HEADER: &str = "x-proxy-upstream-error";
+// 定义命中率与延迟指标的标签（路由名或 host）默认最多取 256 个不同的值，超出的归入 other
+pub const METRICS_MAX_LABEL_VALUES: usize = 256;
+// 定义请求延迟直方图各个桶的上界（秒）
+pub const METRICS_LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+// 定义转发给其他实例的 purge 请求携带的请求头，收到后不再继续转发
+pub const PURGE_FORWARDED_HEADER: &str = "x-proxy-purge-forwarded";
+// 定义转发 purge 给其他实例的超时时间为 5 秒
+pub const PURGE_PROPAGATION_TIMEOUT_SECONDS: u64 = 5;
+// 定义源站响应最多允许的头部字段数为 100 个
+pub const UPSTREAM_MAX_HEADERS: usize = 100;
+// 定义源站响应头部的总大小上限为 32KB
+pub const UPSTREAM_MAX_HEADER_BYTES: usize = 32 * 1024;
+// 定义读取源站响应体时 30 秒收不到数据视为卡住
+pub const UPSTREAM_BODY_STALL_SECONDS: u64 = 30;
+// 定义跟随源站重定向的最大跳数为 5
+pub const UPSTREAM_MAX_REDIRECTS: u32 = 5;
+// 定义响应体卡住后最多续传 3 次
+pub const UPSTREAM_BODY_RESUMES: u32 = 3;
+// 定义缓存决策日志的默认文件为 decisions.jsonl
+pub const DECISION_LOG_PATH: &str = "decisions.jsonl";
+// 定义缓存决策日志的默认抽样比例为 1%
+pub const DECISION_LOG_SAMPLE_RATE: f64 = 0.01;
+// 定义缓存决策日志写入队列长度为 1024 条，写不过来时丢弃
+pub const DECISION_LOG_QUEUE_SIZE: usize = 1024;
+// 定义管理操作审计日志的默认文件为 audit.jsonl
+pub const AUDIT_LOG_PATH: &str = "audit.jsonl";
+// 定义升级时把监听 socket 交给新进程的环境变量，值为逗号分隔的 地址=文件描述符
+pub const UPGRADE_LISTEN_FDS_ENV: &str = "PROXY_LISTEN_FDS";
+// 定义新进程报告已就绪所用的文件描述符的环境变量
+pub const UPGRADE_READY_FD_ENV: &str = "PROXY_UPGRADE_READY_FD";
+// 定义升级时等待新进程就绪（加载缓存索引、开始监听）最多 60 秒
+pub const UPGRADE_READY_TIMEOUT_SECONDS: u64 = 60;
+// 定义代理在 Via 头中使用的名字
+pub const VIA_PSEUDONYM: &str = "rust-proxy-server";
+// 定义不匹配任何客户端类别的请求所属的类别
+pub const DEFAULT_CLIENT_CLASS: &str = "default";
+// 定义管理接口 /debug/requests 默认保留最近 256 个请求
+pub const RECENT_REQUESTS_KEPT: usize = 256;
+// 定义运行时工作线程与阻塞线程的默认线程名
+pub const RUNTIME_THREAD_NAME: &str = "proxy-worker";
+// 定义注册 Windows 服务与事件日志来源所用的名字
+pub const SERVICE_NAME: &str = "rust-proxy-server";
+// 定义 Windows 服务在服务管理器中显示的名字
+pub const SERVICE_DISPLAY_NAME: &str = "Rust Proxy Server";
+// 定义卸载 Windows 服务时等待其停止最多 30 秒
+pub const SERVICE_STOP_WAIT_SECONDS: u64 = 30;
+// 定义开启沙箱后仍允许读取的系统文件：DNS 解析与 NSS 模块、CA 证书、CPU 与 cgroup 信息
+pub const SANDBOX_READ_PATHS: &[&str] = &[
+    "/etc/resolv.conf",
+    "/etc/hosts",
+    "/etc/host.conf",
+    "/etc/nsswitch.conf",
+    "/etc/gai.conf",
+    "/etc/ssl",
+    "/etc/pki",
+    "/etc/ca-certificates",
+    "/usr/share/ca-certificates",
+    "/usr/lib/ssl",
+    "/lib",
+    "/lib64",
+    "/usr/lib",
+    "/usr/lib64",
+    "/proc/self",
+    "/sys/fs/cgroup",
+    "/sys/devices/system/cpu",
+];
 
+// 定义 OAuth2 访问令牌在到期前多少秒刷新
+pub const OAUTH_REFRESH_BEFORE_SECS: u64 = 60;
+
+// 定义向令牌端点获取访问令牌的超时时间（秒）
+pub const OAUTH_TOKEN_TIMEOUT_SECS: u64 = 10;
+
+// 定义获取访问令牌失败后 5 秒内不再重试，期间的请求直接失败
+pub const OAUTH_FAILURE_BACKOFF_SECS: u64 = 5;
+
+// 定义代理压缩的响应大小下限为 1KB，更小的响应压缩后节省不了多少
+pub const COMPRESS_MIN_BYTES: u64 = 1024;
+
+// 定义代理压缩的响应大小上限为 1MB
+pub const COMPRESS_MAX_BYTES: u64 = 1024 * 1024;
+
+// 定义默认由代理压缩的 Content-Type
+pub const COMPRESSIBLE_TYPES: &[&str] = &[
+    "text/",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/vnd.apple.mpegurl",
+    "image/svg+xml",
+];
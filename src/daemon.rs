@@ -0,0 +1,192 @@
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+
+use crate::config::ProcessConfig;
+use crate::upgrade;
+
+// 后台运行：两次 fork 脱离终端与会话，启动命令等到后台进程报告就绪才退出，
+// 启动失败时返回非零值，错误仍然输出在终端上
+
+// 通知启动命令的管道写端，就绪前保持打开；后台进程在就绪前退出时管道关闭，启动命令随之失败
+static READY: Mutex<Option<OwnedFd>> = Mutex::new(None);
+
+// 必须在创建运行时之前调用：fork 只保留调用它的线程
+pub fn daemonize() -> Result<()> {
+    let (mut reader, writer) = io::pipe()?;
+    // SAFETY: 此时进程只有一个线程
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()).context("fork failed"),
+        0 => {}
+        _ => {
+            drop(writer);
+            let mut byte = [0u8; 1];
+            if matches!(reader.read(&mut byte), Ok(1)) {
+                std::process::exit(0);
+            }
+            eprintln!("the proxy exited before it was ready");
+            std::process::exit(1);
+        }
+    }
+    drop(reader);
+    // SAFETY: 同上
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error()).context("setsid failed");
+    }
+    // 再 fork 一次，会话首进程退出后不会再获得控制终端
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()).context("fork failed"),
+        0 => {}
+        // SAFETY: 中间进程直接退出，不执行析构
+        _ => unsafe { libc::_exit(0) },
+    }
+    *READY.lock().unwrap() = Some(writer.into());
+    Ok(())
+}
+
+// 所有端口绑定、缓存加载完成后调用：标准输入输出改为 /dev/null 或日志文件，并通知启动命令退出
+pub fn finish_startup(config: &ProcessConfig) -> Result<()> {
+    let Some(ready) = READY.lock().unwrap().take() else {
+        return Ok(());
+    };
+    let input = File::open("/dev/null")?;
+    let output = match &config.log_file {
+        Some(path) => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open log file {}", path.display()))?,
+        None => OpenOptions::new().write(true).open("/dev/null")?,
+    };
+    for (file, target) in [(&input, 0), (&output, 1), (&output, 2)] {
+        // SAFETY: 只替换标准输入输出的文件描述符
+        if unsafe { libc::dup2(file.as_raw_fd(), target) } == -1 {
+            return Err(io::Error::last_os_error()).context("failed to redirect standard streams");
+        }
+    }
+    File::from(ready).write_all(b"1")?;
+    Ok(())
+}
+
+// 端口绑定之后、创建缓存目录与日志文件之前调用，切换到 identity 解析出的用户与组。
+// 已经是目标身份时（例如升级启动的新进程）不做任何事
+pub fn drop_privileges((uid, gid): Identity) -> Result<()> {
+    // SAFETY: 以下调用只读取或修改本进程的身份
+    let current = unsafe { (libc::geteuid(), libc::getegid()) };
+    if uid.is_none_or(|uid| uid == current.0) && gid.is_none_or(|gid| gid == current.1) {
+        return Ok(());
+    }
+    if let Some(gid) = gid {
+        // 同时去掉 root 的附加组
+        if unsafe { libc::setgroups(1, &gid) } == -1 || unsafe { libc::setgid(gid) } == -1 {
+            return Err(io::Error::last_os_error()).with_context(|| format!("failed to switch to group {}", gid));
+        }
+    }
+    if let Some(uid) = uid {
+        if unsafe { libc::setuid(uid) } == -1 {
+            return Err(io::Error::last_os_error()).with_context(|| format!("failed to switch to user {}", uid));
+        }
+    }
+    tracing::info!("running as uid {} gid {}", unsafe { libc::getuid() }, unsafe { libc::getgid() });
+    Ok(())
+}
+
+// 切换后的 uid 与 gid，未配置用户或组时为 None
+pub type Identity = (Option<libc::uid_t>, Option<libc::gid_t>);
+
+// 查询用户与组数据库（/etc/passwd、/etc/group 或 NSS 模块），须在开启沙箱之前调用
+pub fn identity(config: &ProcessConfig) -> Result<Identity> {
+    let user = config.user.as_deref().map(lookup_user).transpose()?;
+    let gid = match &config.group {
+        Some(group) => Some(lookup_group(group)?),
+        None => user.map(|(_, gid)| gid),
+    };
+    Ok((user.map(|(uid, _)| uid), gid))
+}
+
+// 用户名或数字 uid，返回 uid 与主组
+fn lookup_user(name: &str) -> Result<(libc::uid_t, libc::gid_t)> {
+    let c_name = CString::new(name)?;
+    let mut buffer = vec![0; 16384];
+    // SAFETY: passwd 只包含整数与指针，全零是有效值；字符串字段指向 buffer，使用期间有效
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let rc = unsafe {
+        libc::getpwnam_r(c_name.as_ptr(), &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result)
+    };
+    if rc != 0 {
+        return Err(io::Error::from_raw_os_error(rc)).with_context(|| format!("failed to look up user {}", name));
+    }
+    if !result.is_null() {
+        return Ok((passwd.pw_uid, passwd.pw_gid));
+    }
+    match name.parse::<libc::uid_t>() {
+        // 没有对应用户名的数字 uid 以同样的数字作为组
+        Ok(uid) => Ok((uid, uid)),
+        Err(_) => bail!("unknown user {}", name),
+    }
+}
+
+// 组名或数字 gid
+fn lookup_group(name: &str) -> Result<libc::gid_t> {
+    let c_name = CString::new(name)?;
+    let mut buffer = vec![0; 16384];
+    // SAFETY: 同 lookup_user
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let rc = unsafe {
+        libc::getgrnam_r(c_name.as_ptr(), &mut group, buffer.as_mut_ptr(), buffer.len(), &mut result)
+    };
+    if rc != 0 {
+        return Err(io::Error::from_raw_os_error(rc)).with_context(|| format!("failed to look up group {}", name));
+    }
+    if !result.is_null() {
+        return Ok(group.gr_gid);
+    }
+    name.parse().map_err(|_| anyhow::anyhow!("unknown group {}", name))
+}
+
+// 进程号文件：已有文件中的进程仍在运行时拒绝启动（升级启动的新进程除外），
+// 退出时只有文件中仍是本进程的进程号才删除，升级后由新进程接管
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(path: &Path) -> Result<Self> {
+        if !upgrade::is_successor() {
+            if let Some(pid) = read_pid(path).filter(|pid| *pid != std::process::id() as libc::pid_t && is_running(*pid)) {
+                bail!("another instance is running (pid {} in {})", pid, path.display());
+            }
+        }
+        std::fs::write(path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("failed to write pid file {}", path.display()))?;
+        Ok(PidFile {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if read_pid(&self.path) == Some(std::process::id() as libc::pid_t) {
+            // 切换用户后可能没有权限删除，留下的文件在下次启动时被覆盖
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn read_pid(path: &Path) -> Option<libc::pid_t> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn is_running(pid: libc::pid_t) -> bool {
+    // SAFETY: 信号 0 只检查进程是否存在；属于其他用户的进程返回 EPERM
+    pid > 0
+        && (unsafe { libc::kill(pid, 0) } == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM))
+}
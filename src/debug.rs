@@ -0,0 +1,106 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use hyper::header::HeaderValue;
+use hyper::{Body, Response};
+use serde::Serialize;
+
+use crate::cache::ByteRanges;
+
+// 请求头 X-Proxy-Debug: 1 时收集的缓存决策信息，以响应头返回；抽样的请求同时写入决策日志
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DebugInfo {
+    pub cache_key: Option<String>,
+    // hit / partial / miss / bypass
+    pub lookup: Option<&'static str>,
+    pub complete: Option<bool>,
+    pub cached_ranges: Option<ByteRanges>,
+    pub total_size: Option<u64>,
+    pub freshness: Option<&'static str>,
+    pub upstream_requests: u32,
+    pub retries: u32,
+    // 等待源站并发名额与等待源站响应头的累计耗时（毫秒），包括重试
+    pub queue_ms: u64,
+    pub upstream_ms: u64,
+    // 跟随的源站重定向次数
+    pub redirects: u32,
+    // 上游请求失败时是否允许自动重试
+    pub retryable: Option<bool>,
+    // 范围请求回源时预读的字节数
+    pub read_ahead: Option<u64>,
+    // 范围请求的处理方式：cached / cached-chunks / fetched / misaligned / changed / ignored-by-origin /
+    // if-range-mismatch / passthrough / streamed
+    pub range: Option<&'static str>,
+    // 回源响应是否写入缓存：stored / meta-updated / too-large / not-shareable / not-cacheable
+    pub store: Option<&'static str>,
+    // 按响应体识别出的类型（源站没有声明或声明为通用二进制时）
+    pub content_type: Option<&'static str>,
+}
+
+pub type DebugHandle = Arc<Mutex<DebugInfo>>;
+
+tokio::task_local! {
+    static DEBUG: DebugHandle;
+}
+
+pub async fn with_debug<F: Future>(handle: DebugHandle, fut: F) -> F::Output {
+    DEBUG.scope(handle, fut).await
+}
+
+// 当前请求的调试信息，交给在后台继续处理该请求的任务
+pub fn current() -> Option<DebugHandle> {
+    DEBUG.try_with(Clone::clone).ok()
+}
+
+// 不在请求处理中（如后台刷新）时不做任何事
+pub fn record(f: impl FnOnce(&mut DebugInfo)) {
+    let _ = DEBUG.try_with(|handle| f(&mut handle.lock().unwrap()));
+}
+
+impl DebugInfo {
+    pub fn apply(&self, response: &mut Response<Body>) {
+        let headers = response.headers_mut();
+        let mut set = |name: &'static str, value: String| {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(name, value);
+            }
+        };
+        if let Some(key) = &self.cache_key {
+            set("x-proxy-cache-key", key.clone());
+        }
+        set("x-proxy-cache", self.lookup.unwrap_or("none").to_string());
+        if let Some(complete) = self.complete {
+            set("x-proxy-cache-complete", complete.to_string());
+        }
+        if let Some(ranges) = &self.cached_ranges {
+            let total = self
+                .total_size
+                .map(|t| t.to_string())
+                .unwrap_or_else(|| "*".to_string());
+            set("x-proxy-cache-ranges", format!("{}/{}", ranges, total));
+        }
+        if let Some(freshness) = self.freshness {
+            set("x-proxy-freshness", freshness.to_string());
+        }
+        set("x-proxy-upstream-requests", self.upstream_requests.to_string());
+        set("x-proxy-retries", self.retries.to_string());
+        if self.redirects > 0 {
+            set("x-proxy-redirects", self.redirects.to_string());
+        }
+        if let Some(retryable) = self.retryable {
+            set("x-proxy-retryable", retryable.to_string());
+        }
+        if let Some(read_ahead) = self.read_ahead {
+            set("x-proxy-read-ahead", read_ahead.to_string());
+        }
+        if let Some(range) = self.range {
+            set("x-proxy-range", range.to_string());
+        }
+        if let Some(store) = self.store {
+            set("x-proxy-store", store.to_string());
+        }
+        if let Some(content_type) = self.content_type {
+            set("x-proxy-sniffed-type", content_type.to_string());
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::hash::{BuildHasher, RandomState};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::config::DecisionLogConfig;
+use crate::constants::DECISION_LOG_QUEUE_SIZE;
+use crate::debug::DebugInfo;
+
+// 缓存决策日志：抽样请求的决策过程以 JSON 行写入单独的文件，不混入运行日志。
+// 写入在后台任务中进行，队列满时丢弃记录，不拖慢请求
+struct DecisionLog {
+    sender: mpsc::Sender<String>,
+    sample_rate: f64,
+    hasher: RandomState,
+    counter: AtomicU64,
+}
+
+static DECISION_LOG: OnceLock<DecisionLog> = OnceLock::new();
+
+// 一条决策记录，调试信息的字段展开到同一层
+#[derive(Serialize)]
+pub struct Decision<'a> {
+    pub time: u64,
+    pub method: &'a str,
+    pub uri: &'a str,
+    pub status: u16,
+    pub duration_ms: u64,
+    #[serde(flatten)]
+    pub info: &'a DebugInfo,
+}
+
+pub async fn init(config: &DecisionLogConfig) -> Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&config.path)
+        .await
+        .with_context(|| format!("failed to open decision log {}", config.path.display()))?;
+    let (sender, mut receiver) = mpsc::channel::<String>(DECISION_LOG_QUEUE_SIZE);
+    tokio::spawn(async move {
+        while let Some(line) = receiver.recv().await {
+            let written = match file.write_all(line.as_bytes()).await {
+                Ok(()) => file.flush().await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = written {
+                tracing::warn!("failed to write decision log: {}", e);
+            }
+        }
+    });
+    let _ = DECISION_LOG.set(DecisionLog {
+        sender,
+        sample_rate: config.sample_rate,
+        hasher: RandomState::new(),
+        counter: AtomicU64::new(0),
+    });
+    Ok(())
+}
+
+// 请求开始时决定是否记录；用随机种子对序号取哈希，得到均匀的抽样
+pub fn sampled() -> bool {
+    let Some(log) = DECISION_LOG.get() else {
+        return false;
+    };
+    if log.sample_rate >= 1.0 {
+        return true;
+    }
+    let n = log.counter.fetch_add(1, Ordering::Relaxed);
+    (log.hasher.hash_one(n) as f64) < log.sample_rate * u64::MAX as f64
+}
+
+pub fn write(decision: &Decision) {
+    let Some(log) = DECISION_LOG.get() else {
+        return;
+    };
+    match serde_json::to_string(decision) {
+        Ok(mut line) => {
+            line.push('\n');
+            let _ = log.sender.try_send(line);
+        }
+        Err(e) => tracing::warn!("failed to encode decision log record: {}", e),
+    }
+}
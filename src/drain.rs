@@ -0,0 +1,53 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+use crate::metrics::METRICS;
+
+// 下线排空：负载均衡器的健康检查返回 503，每个响应都带 Connection: close，
+// 客户端发完当前请求后换到其他实例；正在进行和新到达的请求照常处理（包括缓存命中），
+// 等进行中的请求数降到 0 后即可停止进程
+pub struct Drain {
+    // 开始排空的 Unix 时间（秒），0 表示未排空
+    since: AtomicU64,
+}
+
+pub static DRAIN: Drain = Drain {
+    since: AtomicU64::new(0),
+};
+
+// 管理接口 /drain 的状态
+#[derive(Debug, Serialize)]
+pub struct DrainStatus {
+    pub draining: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<u64>,
+    // 所有监听端口上正在处理的请求数，包括这次查询本身
+    pub active_requests: i64,
+}
+
+impl Drain {
+    // 已经在排空时保持原来的开始时间
+    pub fn start(&self, now: u64) {
+        let _ = self
+            .since
+            .compare_exchange(0, now.max(1), Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    pub fn stop(&self) {
+        self.since.store(0, Ordering::Relaxed);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.since.load(Ordering::Relaxed) != 0
+    }
+
+    pub fn status(&self) -> DrainStatus {
+        let since = self.since.load(Ordering::Relaxed);
+        DrainStatus {
+            draining: since != 0,
+            since: (since != 0).then_some(since),
+            active_requests: METRICS.downstream_requests_active.load(Ordering::Relaxed),
+        }
+    }
+}
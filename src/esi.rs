@@ -0,0 +1,169 @@
+use std::future::Future;
+use std::sync::LazyLock;
+
+use anyhow::{bail, Result};
+use bytes::{Bytes, BytesMut};
+use futures::future::join_all;
+use futures::StreamExt;
+use hyper::header::{HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::{Body, Response, Uri};
+use regex::{Captures, Regex};
+
+use crate::constants::{ESI_MAX_INCLUDES, ESI_MAX_TEMPLATE_SIZE};
+
+static INCLUDE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<esi:include\s([^>]*?)/?>(?:\s*</esi:include>)?").unwrap());
+static REMOVE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<esi:remove>.*?</esi:remove>").unwrap());
+static COMMENT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?s)<!--esi(.*?)-->").unwrap());
+static ATTR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"([a-z]+)\s*=\s*"([^"]*)""#).unwrap());
+
+struct Include {
+    src: String,
+    alt: Option<String>,
+    continue_on_error: bool,
+}
+
+// Edge Side Includes：模板按自身的缓存策略缓存，<esi:include> 片段在返回时
+// 通过 fetch 单独获取（同样经过缓存），拼装后返回给客户端
+pub async fn assemble<F, Fut>(response: Response<Body>, base: &Uri, fetch: F) -> Result<Response<Body>>
+where
+    F: Fn(Uri) -> Fut,
+    Fut: Future<Output = Result<Bytes>>,
+{
+    if !is_html(&response) {
+        return Ok(response);
+    }
+    let (mut parts, mut stream) = response.into_parts();
+    let mut buf = BytesMut::new();
+    while let Some(chunk) = stream.next().await {
+        buf.extend_from_slice(&chunk?);
+        // 超出上限的模板不解析，已读取的部分与剩余数据一起透传
+        if buf.len() as u64 > ESI_MAX_TEMPLATE_SIZE {
+            let prefix = futures::stream::once(async move { Ok::<_, hyper::Error>(buf.freeze()) });
+            return Ok(Response::from_parts(parts, Body::wrap_stream(prefix.chain(stream))));
+        }
+    }
+    let body = buf.freeze();
+    let Ok(template) = std::str::from_utf8(&body) else {
+        return Ok(Response::from_parts(parts, Body::from(body)));
+    };
+    if !template.contains("esi:") && !template.contains("<!--esi") {
+        return Ok(Response::from_parts(parts, Body::from(body)));
+    }
+
+    let template = COMMENT.replace_all(template, "$1");
+    let template = REMOVE.replace_all(&template, "");
+
+    let includes: Vec<Include> = INCLUDE
+        .captures_iter(&template)
+        .map(|caps| parse_include(&caps[1]))
+        .collect();
+    if includes.len() > ESI_MAX_INCLUDES {
+        bail!("too many esi:include tags ({})", includes.len());
+    }
+
+    // 片段并发获取
+    let fragments = join_all(includes.iter().map(|include| fetch_include(include, base, &fetch))).await;
+    let mut fragments = fragments.into_iter();
+    let mut failed = None;
+    let assembled = INCLUDE.replace_all(&template, |_: &Captures| match fragments.next() {
+        Some(Ok(fragment)) => String::from_utf8_lossy(&fragment).into_owned(),
+        Some(Err(e)) => {
+            failed.get_or_insert(e);
+            String::new()
+        }
+        None => String::new(),
+    });
+    if let Some(e) = failed {
+        return Err(e);
+    }
+
+    let body = Bytes::from(assembled.into_owned());
+    parts
+        .headers
+        .insert(CONTENT_LENGTH, HeaderValue::from(body.len() as u64));
+    // 拼装结果每次都可能不同，源站的校验值不再适用
+    parts.headers.remove(hyper::header::ETAG);
+    parts.headers.remove(hyper::header::LAST_MODIFIED);
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+fn is_html(response: &Response<Body>) -> bool {
+    let declared_len = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    response.status().is_success()
+        && !response.headers().contains_key(CONTENT_ENCODING)
+        && declared_len.map(|len| len <= ESI_MAX_TEMPLATE_SIZE).unwrap_or(true)
+        && response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_ascii_lowercase().starts_with("text/html"))
+            .unwrap_or(false)
+}
+
+fn parse_include(attrs: &str) -> Include {
+    let mut include = Include {
+        src: String::new(),
+        alt: None,
+        continue_on_error: false,
+    };
+    for caps in ATTR.captures_iter(attrs) {
+        match &caps[1] {
+            "src" => include.src = caps[2].to_string(),
+            "alt" => include.alt = Some(caps[2].to_string()),
+            "onerror" => include.continue_on_error = &caps[2] == "continue",
+            _ => {}
+        }
+    }
+    include
+}
+
+// src 失败时尝试 alt，仍失败且 onerror="continue" 时输出空内容
+async fn fetch_include<F, Fut>(include: &Include, base: &Uri, fetch: &F) -> Result<Bytes>
+where
+    F: Fn(Uri) -> Fut,
+    Fut: Future<Output = Result<Bytes>>,
+{
+    let mut result = fetch_src(&include.src, base, fetch).await;
+    if let (Err(_), Some(alt)) = (&result, &include.alt) {
+        result = fetch_src(alt, base, fetch).await;
+    }
+    match result {
+        Err(e) if include.continue_on_error => {
+            tracing::debug!("esi:include {} failed: {:#}", include.src, e);
+            Ok(Bytes::new())
+        }
+        result => result,
+    }
+}
+
+async fn fetch_src<F, Fut>(src: &str, base: &Uri, fetch: &F) -> Result<Bytes>
+where
+    F: Fn(Uri) -> Fut,
+    Fut: Future<Output = Result<Bytes>>,
+{
+    fetch(resolve(src, base)?).await
+}
+
+// 相对地址按模板所在的源站解析
+fn resolve(src: &str, base: &Uri) -> Result<Uri> {
+    if src.contains("://") {
+        return Ok(src.parse()?);
+    }
+    let (Some(scheme), Some(authority)) = (base.scheme_str(), base.authority()) else {
+        bail!("cannot resolve esi:include {}", src);
+    };
+    let path = if src.starts_with('/') {
+        src.to_string()
+    } else {
+        let dir = &base.path()[..base.path().rfind('/').map(|i| i + 1).unwrap_or(0)];
+        format!("{}{}", dir, src)
+    };
+    Ok(format!("{}://{}{}", scheme, authority, path).parse()?)
+}
@@ -0,0 +1,162 @@
+use std::io::Write;
+use std::sync::atomic::Ordering;
+
+use anyhow::Result;
+use bytes::Bytes;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use hyper::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH,
+    CONTENT_RANGE, ETAG, RANGE, VARY,
+};
+use hyper::{Body, Method, Request, Response, StatusCode};
+
+use crate::cache::{capture_headers, is_weak, CacheEntry, CacheMeta, ProxyCache};
+use crate::config::{CompressionConfig, Config};
+use crate::debug;
+use crate::metrics::METRICS;
+
+// 代理压缩时同一个 URL 的两个缓存版本。server 计算缓存键时放入请求扩展，
+// 写缓存时两个版本一起保存，之后 gzip 与 identity 客户端各自命中自己的版本
+#[derive(Clone, Debug)]
+pub struct GzipVariants {
+    pub identity_key: String,
+    pub gzip_key: String,
+    // 客户端接受 gzip（Accept-Encoding 已规范化）
+    pub gzip: bool,
+    pub config: CompressionConfig,
+}
+
+impl GzipVariants {
+    // 未开启压缩，或不是完整的 GET 时返回 None
+    pub fn for_request(config: &Config, req: &Request<Body>) -> Option<Self> {
+        if !config.cache.compression.enabled
+            || req.method() != Method::GET
+            || req.headers().contains_key(RANGE)
+        {
+            return None;
+        }
+        let key = |encoding: &'static str| {
+            let mut headers = req.headers().clone();
+            headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(encoding));
+            config.request_cache_key(req.method(), req.uri(), &headers)
+        };
+        Some(GzipVariants {
+            identity_key: key("identity"),
+            gzip_key: key("gzip"),
+            gzip: req.headers().get(ACCEPT_ENCODING).is_some_and(|v| v == "gzip"),
+            config: config.cache.compression.clone(),
+        })
+    }
+
+    // 源站返回了未编码的完整 200 响应，类型与大小在压缩范围内，且没有要求 no-transform
+    pub fn applies(&self, status: StatusCode, headers: &HeaderMap, entry: &CacheEntry) -> bool {
+        let no_transform = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"));
+        let len = entry.content.len() as u64;
+        status == StatusCode::OK
+            && entry.meta.is_complete
+            && !headers.contains_key(CONTENT_ENCODING)
+            && !headers.contains_key(CONTENT_RANGE)
+            && !no_transform
+            && self.config.compresses(&entry.meta.content_type)
+            && (self.config.min_bytes..=self.config.max_bytes).contains(&len)
+    }
+
+    // 压缩后两个版本一起写入缓存，按客户端接受的编码返回其中一个
+    pub async fn store(
+        self,
+        cache: &ProxyCache,
+        entry: CacheEntry,
+        headers: HeaderMap,
+    ) -> Result<Response<Body>> {
+        let content = entry.content.clone();
+        let gzipped = Bytes::from(tokio::task::spawn_blocking(move || gzip(&content)).await??);
+        METRICS.cache_variants_stored.fetch_add(1, Ordering::Relaxed);
+        METRICS
+            .cache_variant_identity_bytes
+            .fetch_add(entry.content.len() as u64, Ordering::Relaxed);
+        METRICS
+            .cache_variant_gzip_bytes
+            .fetch_add(gzipped.len() as u64, Ordering::Relaxed);
+        debug::record(|d| d.store = Some("stored-variants"));
+
+        let identity_headers = vary(headers);
+        let gzip_headers = gzip_headers(identity_headers.clone());
+        let gzip_entry = CacheEntry {
+            meta: CacheMeta {
+                total_size: Some(gzipped.len() as u64),
+                etag: entry.meta.etag.as_deref().map(weaken),
+                sha256: None,
+                headers: capture_headers(&gzip_headers),
+                ..entry.meta.clone()
+            },
+            content: gzipped,
+        };
+        let identity_entry = CacheEntry {
+            meta: CacheMeta {
+                headers: capture_headers(&identity_headers),
+                ..entry.meta
+            },
+            content: entry.content,
+        };
+
+        let (content, headers) = if self.gzip {
+            (gzip_entry.content.clone(), gzip_headers)
+        } else {
+            (identity_entry.content.clone(), identity_headers)
+        };
+        cache.set(self.identity_key, identity_entry).await?;
+        cache.set(self.gzip_key, gzip_entry).await?;
+        let len = content.len();
+        let mut response = Response::builder().status(StatusCode::OK).body(Body::from(content))?;
+        *response.headers_mut() = headers;
+        // 返回的版本长度已知，之后包装响应体时不会退回分块编码
+        response.headers_mut().insert(CONTENT_LENGTH, HeaderValue::from(len));
+        Ok(response)
+    }
+}
+
+fn gzip(content: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::with_capacity(content.len() / 2), Compression::default());
+    encoder.write_all(content)?;
+    encoder.finish()
+}
+
+// 两个版本的响应都随 Accept-Encoding 变化
+fn vary(mut headers: HeaderMap) -> HeaderMap {
+    headers.remove(CONTENT_LENGTH);
+    let listed = headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|name| name.trim().eq_ignore_ascii_case("accept-encoding") || name.trim() == "*");
+    if !listed {
+        headers.append(VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+    headers
+}
+
+// gzip 版本与源站的内容不是同一个表示，强 ETag 改为弱 ETag；
+// 重新验证时源站按弱比较仍能认出它并返回 304
+fn gzip_headers(mut headers: HeaderMap) -> HeaderMap {
+    headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    let etag = headers.get(ETAG).and_then(|v| v.to_str().ok()).map(weaken);
+    if let Some(etag) = etag.and_then(|etag| HeaderValue::from_str(&etag).ok()) {
+        headers.insert(ETAG, etag);
+    }
+    headers
+}
+
+fn weaken(etag: &str) -> String {
+    if is_weak(etag) {
+        etag.to_string()
+    } else {
+        format!("W/{}", etag.trim())
+    }
+}
@@ -0,0 +1,385 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use hyper::header::HeaderValue;
+use hyper::{Body, Request, Response};
+
+use crate::cache::{
+    capture_headers, is_weak, lifetime, verify_origin_digest, ByteRanges, CacheEntry,
+    CacheMeta, ProxyCache,
+};
+use crate::config::{CachePolicy, FetchPolicy};
+use crate::constants::{META_VERSION, UPSTREAM_BODY_RESUMES};
+use crate::debug;
+use crate::metrics::METRICS;
+use crate::upstream::HttpClient;
+use crate::utils::{
+    clone_request, fetch_with_retry, header_string, resume_request, sniff_content_type, Redirected,
+};
+
+use super::{check_response_complete, content_range, get_total_size, stitchable_len, GzipVariants};
+
+// 获取根据请求的 range 情况来获取数据
+pub async fn fetch_and_cache_full_response(
+    client: &HttpClient,
+    req: Request<Body>,
+    cache: Arc<ProxyCache>,
+    cache_key: String,
+    policy: CachePolicy,
+) -> Result<Response<Body>> {
+    let resp = fetch_with_retry(client, &req, policy.fetch).await?;
+    cache_full_response(client, req, resp, cache, cache_key, policy).await
+}
+
+// 将源站返回的完整响应写入缓存并返回给客户端
+pub async fn cache_full_response(
+    client: &HttpClient,
+    req: Request<Body>,
+    resp: Response<Body>,
+    cache: Arc<ProxyCache>,
+    cache_key: String,
+    policy: CachePolicy,
+) -> Result<Response<Body>> {
+    let status = resp.status();
+    let mut headers = resp.headers().clone();
+    let redirected = resp.extensions().get::<Redirected>().cloned();
+
+    // 源站声明的大小已超过限制：不读入内存，直接透传
+    let declared_len = headers
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if status.is_success() && declared_len.map(|len| len > policy.max_object_bytes).unwrap_or(false) {
+        debug::record(|d| d.store = Some("too-large"));
+        let (parts, body) = resp.into_parts();
+        let body = resuming(client, &req, status, &headers, body, policy.fetch).await?;
+        return Ok(Response::from_parts(parts, Body::wrap_stream(body)));
+    }
+
+    // 超过大小限制的对象的部分响应：缓存的片段永远拼不成完整对象，206 原样透传
+    if let Some((_, _, Some(total))) = content_range(&headers) {
+        if status == hyper::StatusCode::PARTIAL_CONTENT && total > policy.max_object_bytes {
+            debug::record(|d| {
+                d.range = Some("passthrough");
+                d.store = Some("too-large");
+            });
+            METRICS.range_passthrough.fetch_add(1, Ordering::Relaxed);
+            return Ok(resp);
+        }
+    }
+
+    // 带凭据请求的响应没有明确允许共享缓存，直接透传
+    if !policy.may_store(&headers, cache.clock().now_secs()) {
+        debug::record(|d| {
+            d.lookup = Some("bypass");
+            d.store = Some("not-shareable");
+        });
+        return Ok(resp);
+    }
+
+    if status.is_success() {
+        // 处理成功响应
+        let mut content_type = headers
+            .get(hyper::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        // 只有从对象开头开始的响应才能识别类型
+        let starts_at_zero = status == hyper::StatusCode::OK
+            || content_range(&headers).is_some_and(|(start, _, _)| start == 0);
+        let mut sniff = policy.sniff_content_type && starts_at_zero;
+        let mut sniffed = None;
+
+        let mut body = Vec::new();
+        let mut stream = resuming(client, &req, status, &headers, resp.into_body(), policy.fetch).await?;
+
+        // 读取响应主体
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                // 续传也失败：已收到的前缀写入缓存，之后的请求（或重启后的续传）从断点补齐
+                Err(e) => {
+                    if status == hyper::StatusCode::OK {
+                        let meta = response_meta(&req, &headers, content_type, &policy, cache.clock().now_secs());
+                        save_prefix(&cache, cache_key, &headers, meta, body).await?;
+                    }
+                    return Err(e.into());
+                }
+            };
+            if sniff && !chunk.is_empty() {
+                sniff = false;
+                let declared = headers.get(hyper::header::CONTENT_TYPE).and_then(|v| v.to_str().ok());
+                sniffed = sniff_content_type(declared, &chunk);
+                if let Some(detected) = sniffed {
+                    debug::record(|d| d.content_type = Some(detected));
+                    content_type = detected.to_string();
+                    headers.insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static(detected));
+                }
+            }
+            body.extend_from_slice(&chunk);
+
+            // 检查是否超过最大文件大小
+            if body.len() as u64 > policy.max_object_bytes {
+                // 如果主体大小超过限制，则不缓存，已读取的部分与剩余数据一起透传
+                debug::record(|d| d.store = Some("too-large"));
+                let prefix = futures::stream::once(async move { Ok(Bytes::from(body)) });
+                let mut response = Response::builder()
+                    .status(status)
+                    .body(Body::wrap_stream(prefix.chain(stream)))?;
+                *response.headers_mut() = headers;
+                return Ok(response);
+            }
+        }
+
+        // 检查是否完成
+        let mut is_complete = check_response_complete(&headers, body.len() as u64);
+
+        // 内容与源站声明的摘要不一致：重新获取一次，仍不一致则不返回给客户端
+        if is_complete
+            && cache.verify_checksums()
+            && verify_origin_digest(&headers, &body) == Some(false)
+        {
+            tracing::warn!("digest mismatch for {}, refetching", req.uri());
+            let retry = fetch_with_retry(client, &req, policy.fetch).await?;
+            if retry.status() != status {
+                bail!("digest mismatch for {}", req.uri());
+            }
+            let retry_headers = retry.headers().clone();
+            let retry_body = hyper::body::to_bytes(retry.into_body()).await?;
+            if verify_origin_digest(&retry_headers, &retry_body) == Some(false) {
+                bail!("digest mismatch for {} after refetch", req.uri());
+            }
+            headers = retry_headers;
+            body = retry_body.to_vec();
+            if let Some(detected) = sniffed {
+                headers.insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static(detected));
+            }
+        }
+
+        // 部分响应记录实际所在的字节区间，而不是当作从 0 开始的前缀
+        let mut ranges = None;
+        let total_size = match content_range(&headers) {
+            Some((start, _, total)) if status == hyper::StatusCode::PARTIAL_CONTENT => {
+                let present = ByteRanges::single(start, start + body.len() as u64);
+                is_complete = total.map(|total| present.covers(total)).unwrap_or(false);
+                if !is_complete {
+                    ranges = Some(present);
+                }
+                total
+            }
+            // 获取总资源大小
+            _ => get_total_size(client, &req, policy.fetch)
+                .await?
+                .or(Some(body.len() as u64)),
+        };
+
+        // 缓存响应
+        let entry = CacheEntry {
+            content: Bytes::from(body.clone()),
+            meta: CacheMeta {
+                is_complete,
+                total_size,
+                ranges,
+                ..response_meta(&req, &headers, content_type, &policy, cache.clock().now_secs())
+            },
+        };
+        // 跟随了重定向：按需要同时缓存在最终 URL 的键下，该条目续传与刷新时直接访问最终 URL
+        if let Some(Redirected { url, cache_key: Some(final_key) }) = redirected {
+            if final_key != cache_key {
+                let mut meta = entry.meta.clone();
+                meta.url = Some(url.to_string());
+                cache
+                    .set(final_key, CacheEntry { content: entry.content.clone(), meta })
+                    .await?;
+            }
+        }
+        // 代理压缩：identity 与 gzip 两个版本一起保存
+        let variants = req.extensions().get::<GzipVariants>().cloned();
+        if let Some(variants) = variants.filter(|v| v.applies(status, &headers, &entry)) {
+            return variants.store(&cache, entry, headers).await;
+        }
+        cache.set(cache_key, entry).await?;
+        debug::record(|d| d.store = Some("stored"));
+
+        // 构建响应
+        let mut response = Response::builder().status(status).body(Body::from(body))?;
+        *response.headers_mut() = headers;
+        Ok(response)
+    } else {
+        // 处理失败响应
+        debug::record(|d| d.store = Some("not-cacheable"));
+        let mut response = Response::builder().status(status).body(resp.into_body())?;
+        *response.headers_mut() = headers;
+        Ok(response)
+    }
+}
+
+// 按源站响应头生成缓存元数据，完整性、大小与区间由调用方填写
+fn response_meta(
+    req: &Request<Body>,
+    headers: &hyper::HeaderMap,
+    content_type: String,
+    policy: &CachePolicy,
+    now: u64,
+) -> CacheMeta {
+    CacheMeta {
+        content_type,
+        is_complete: false,
+        total_size: None,
+        etag: header_string(headers, hyper::header::ETAG),
+        last_modified: header_string(headers, hyper::header::LAST_MODIFIED),
+        sha256: None,
+        stored_at: Some(now),
+        freshness_secs: lifetime(headers, policy, now),
+        url: Some(req.uri().to_string()),
+        ranges: None,
+        generation: None,
+        invalidated: false,
+        headers: capture_headers(headers),
+        version: META_VERSION,
+    }
+}
+
+// 完整响应中途中断时保存已收到的前缀。续传缺失区间需要可靠的 If-Range 校验器和已知的总大小，
+// 缺少时保存的数据无法安全拼接，直接丢弃
+async fn save_prefix(
+    cache: &ProxyCache,
+    cache_key: String,
+    headers: &hyper::HeaderMap,
+    meta: CacheMeta,
+    body: Vec<u8>,
+) -> Result<()> {
+    let total = headers
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    let Some(total) = total.filter(|&total| !body.is_empty() && (body.len() as u64) < total) else {
+        return Ok(());
+    };
+    if meta.if_range_validator().is_none() {
+        return Ok(());
+    }
+    debug::record(|d| d.store = Some("partial-prefix"));
+    let received = body.len() as u64;
+    cache
+        .set(
+            cache_key,
+            CacheEntry {
+                content: Bytes::from(body),
+                meta: CacheMeta {
+                    total_size: Some(total),
+                    ranges: Some(ByteRanges::single(0, received)),
+                    ..meta
+                },
+            },
+        )
+        .await
+}
+
+// 源站返回的完整响应体：中途断开或卡住时从已收到的位置续传并接在后面，
+// 读取方（写缓存的缓冲或透传给客户端的流）看到的是一个连续的响应体；无法续传时返回原来的错误
+async fn resuming(
+    client: &HttpClient,
+    req: &Request<Body>,
+    status: hyper::StatusCode,
+    headers: &hyper::HeaderMap,
+    body: Body,
+    fetch: FetchPolicy,
+) -> Result<BoxStream<'static, Result<Bytes, hyper::Error>>> {
+    let state = Resuming {
+        body,
+        client: client.clone(),
+        req: clone_request(req).await?,
+        headers: headers.clone(),
+        received: 0,
+        fetch,
+        resumes: if status == hyper::StatusCode::OK { 0 } else { UPSTREAM_BODY_RESUMES },
+        failed: false,
+    };
+    let stream = futures::stream::unfold(state, |mut state| async move {
+        if state.failed {
+            return None;
+        }
+        loop {
+            match state.body.next().await? {
+                Ok(chunk) => {
+                    state.received += chunk.len() as u64;
+                    return Some((Ok(chunk), state));
+                }
+                Err(e) => {
+                    if state.resumes < UPSTREAM_BODY_RESUMES {
+                        state.resumes += 1;
+                        match resume_body(&state.client, &state.req, &state.headers, state.received, state.fetch).await {
+                            Ok(Some(resumed)) => {
+                                tracing::debug!("resuming {} at byte {}: {}", state.req.uri(), state.received, e);
+                                state.body = resumed;
+                                continue;
+                            }
+                            Ok(None) => {}
+                            Err(resume_error) => {
+                                tracing::debug!("failed to resume {}: {:#}", state.req.uri(), resume_error)
+                            }
+                        }
+                    }
+                    state.failed = true;
+                    return Some((Err(e), state));
+                }
+            }
+        }
+    });
+    Ok(stream.boxed())
+}
+
+struct Resuming {
+    body: Body,
+    client: HttpClient,
+    req: Request<Body>,
+    headers: hyper::HeaderMap,
+    received: u64,
+    fetch: FetchPolicy,
+    resumes: u32,
+    // 已经返回了无法续传的错误
+    failed: bool,
+}
+
+// 从 offset 处续传完整响应的剩余部分，附带 If-Range 确认源站对象未变化。
+// 大小未知、没有可用的校验器或源站返回的不是对应的片段时返回 None
+async fn resume_body(
+    client: &HttpClient,
+    req: &Request<Body>,
+    headers: &hyper::HeaderMap,
+    offset: u64,
+    fetch: FetchPolicy,
+) -> Result<Option<Body>> {
+    let total = headers
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    let Some(total) = total.filter(|&total| offset < total) else {
+        return Ok(None);
+    };
+    let etag = header_string(headers, hyper::header::ETAG);
+    let validator = etag
+        .clone()
+        .filter(|etag| !is_weak(etag))
+        .or_else(|| header_string(headers, hyper::header::LAST_MODIFIED));
+    let Some(validator) = validator else {
+        return Ok(None);
+    };
+
+    let resume = resume_request(req, offset, total - 1, Some(&validator))?;
+    let resp = fetch_with_retry(client, &resume, fetch).await?;
+    let expected = content_range(resp.headers())
+        .and_then(|returned| stitchable_len((offset, total - 1), Some(total), returned));
+    let returned_etag = header_string(resp.headers(), hyper::header::ETAG);
+    if resp.status() != hyper::StatusCode::PARTIAL_CONTENT
+        || expected != Some(total - offset)
+        || (returned_etag.is_some() && returned_etag != etag)
+    {
+        return Ok(None);
+    }
+    Ok(Some(resp.into_body()))
+}
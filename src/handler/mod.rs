@@ -1,5 +1,17 @@
+mod compress;
+mod full;
+mod passthrough;
 mod range;
 mod response;
+mod revalidate;
 
-pub use range::handle_range_request;
-pub use response::{check_response_complete, get_total_size};
+pub use compress::GzipVariants;
+pub use full::{cache_full_response, fetch_and_cache_full_response};
+pub use passthrough::{forward_request, PayloadTooLarge};
+pub use range::{
+    complete_response, handle_range_request, head_response, partial_response, slice_full_response,
+};
+pub use response::{
+    check_response_complete, content_range, get_origin_meta, get_total_size, stitchable_len,
+};
+pub use revalidate::{revalidate, Revalidated};
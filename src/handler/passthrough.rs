@@ -0,0 +1,131 @@
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use hyper::{Body, Request, Response, StatusCode};
+
+use crate::config::FetchPolicy;
+use crate::upstream::HttpClient;
+use crate::utils::fetch_replaying_body;
+
+// 请求体超过上限
+#[derive(Debug)]
+pub struct PayloadTooLarge(pub u64);
+
+impl std::fmt::Display for PayloadTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "request body exceeds {} bytes", self.0)
+    }
+}
+
+impl std::error::Error for PayloadTooLarge {}
+
+// 不参与缓存的请求（POST/PUT 等）：请求体边读边转发给源站，超过上限时中止。
+// 可重试的请求（OPTIONS 等方法或带幂等键）先读完请求体，失败时重发同一份内容
+pub async fn forward_request(
+    req: Request<Body>,
+    client: &HttpClient,
+    max_body_bytes: Option<u64>,
+    fetch: FetchPolicy,
+) -> Result<Response<Body>> {
+    if client.may_retry(&req) && fetch.max_retries > 0 {
+        return forward_with_retry(req, client, max_body_bytes, fetch).await;
+    }
+    let (parts, body) = req.into_parts();
+
+    let Some(limit) = max_body_bytes else {
+        return client.request(Request::from_parts(parts, body)).await;
+    };
+
+    // 声明了 Content-Length 时直接拒绝，不必读取请求体
+    let declared_len = parts
+        .headers
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if declared_len.map(|len| len > limit).unwrap_or(false) {
+        return Ok(payload_too_large(limit));
+    }
+
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let body = Body::wrap_stream(LimitedBody {
+        inner: body,
+        remaining: limit,
+        limit,
+        exceeded: exceeded.clone(),
+    });
+
+    match client.request(Request::from_parts(parts, body)).await {
+        Err(_) if exceeded.load(Ordering::Relaxed) => Ok(payload_too_large(limit)),
+        result => result,
+    }
+}
+
+async fn forward_with_retry(
+    req: Request<Body>,
+    client: &HttpClient,
+    max_body_bytes: Option<u64>,
+    fetch: FetchPolicy,
+) -> Result<Response<Body>> {
+    let (parts, body) = req.into_parts();
+    let body = match max_body_bytes {
+        Some(limit) => {
+            let limited = LimitedBody {
+                inner: body,
+                remaining: limit,
+                limit,
+                exceeded: Arc::new(AtomicBool::new(false)),
+            };
+            let mut chunks = Vec::new();
+            futures::pin_mut!(limited);
+            while let Some(chunk) = limited.next().await {
+                match chunk {
+                    Ok(chunk) => chunks.extend_from_slice(&chunk),
+                    Err(e) if e.is::<PayloadTooLarge>() => return Ok(payload_too_large(limit)),
+                    Err(e) => return Err(anyhow::anyhow!(e)),
+                }
+            }
+            Bytes::from(chunks)
+        }
+        None => hyper::body::to_bytes(body).await?,
+    };
+    let req = Request::from_parts(parts, Body::empty());
+    fetch_replaying_body(client, &req, body, fetch).await
+}
+
+fn payload_too_large(limit: u64) -> Response<Body> {
+    let mut response = Response::new(Body::from(PayloadTooLarge(limit).to_string()));
+    *response.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+    response
+}
+
+// 统计已读取的请求体大小，超过上限时返回错误终止上传
+struct LimitedBody {
+    inner: Body,
+    remaining: u64,
+    limit: u64,
+    exceeded: Arc<AtomicBool>,
+}
+
+impl Stream for LimitedBody {
+    type Item = Result<Bytes, Box<dyn std::error::Error + Send + Sync>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match ready!(self.inner.poll_next_unpin(cx)) {
+            Some(Ok(chunk)) => {
+                if chunk.len() as u64 > self.remaining {
+                    self.exceeded.store(true, Ordering::Relaxed);
+                    return Poll::Ready(Some(Err(Box::new(PayloadTooLarge(self.limit)))));
+                }
+                self.remaining -= chunk.len() as u64;
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Some(Err(e)) => Poll::Ready(Some(Err(Box::new(e)))),
+            None => Poll::Ready(None),
+        }
+    }
+}
@@ -1,110 +1,300 @@
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use anyhow::Result;
 use bytes::Bytes;
 use futures::StreamExt;
-use hyper::{Body, Client, Request, Response, StatusCode};
-use hyper_tls::HttpsConnector;
+use hyper::header::{HeaderMap, HeaderValue, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, IF_RANGE};
+use hyper::{Body, Request, Response, StatusCode};
 
 use crate::cache::{CacheEntry, CacheMeta, ProxyCache};
-use crate::constants::MAX_FILE_SIZE;
-use crate::utils::fetch_with_retry;
+use crate::config::{CachePolicy, FetchPolicy};
+use crate::debug::{self, with_debug};
+use crate::metrics::METRICS;
+use crate::server;
+use crate::upstream::{current_priority, with_priority, HttpClient};
+use crate::utils::{fetch_with_retry, resume_request};
+
+use super::{cache_full_response, content_range, fetch_and_cache_full_response, stitchable_len};
+
+// 用缓存数据构建 206 响应，now 用于计算 Age
+pub fn partial_response(
+    meta: &CacheMeta,
+    start: u64,
+    end: u64,
+    data: Bytes,
+    now: u64,
+) -> Result<Response<Body>> {
+    let total = meta
+        .total_size
+        .map(|t| t.to_string())
+        .unwrap_or_else(|| "*".to_string());
+    let len = data.len();
+    let mut response = Response::builder()
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header(hyper::header::CONTENT_TYPE, meta.content_type_header())
+        .header(
+            hyper::header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, end, total),
+        )
+        .body(Body::from(data))?;
+    meta.insert_cached_headers(response.headers_mut(), now);
+    insert_length(response.headers_mut(), len as u64);
+    Ok(response)
+}
+
+// 用完整的缓存条目构建 200 响应
+pub fn complete_response(meta: &CacheMeta, content: Bytes, now: u64) -> Result<Response<Body>> {
+    let len = content.len();
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, meta.content_type_header())
+        .body(Body::from(content))?;
+    meta.insert_cached_headers(response.headers_mut(), now);
+    insert_length(response.headers_mut(), len as u64);
+    Ok(response)
+}
+
+// HEAD 命中：只用元数据生成响应头，Content-Length 为对象的完整大小；客户端持有的版本一致时返回 304
+pub fn head_response(meta: &CacheMeta, req: &Request<Body>, total: u64, now: u64) -> Result<Response<Body>> {
+    if meta.not_modified(req.headers()) {
+        let mut response = Response::builder().status(StatusCode::NOT_MODIFIED).body(Body::empty())?;
+        meta.insert_cached_headers(response.headers_mut(), now);
+        return Ok(response);
+    }
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, meta.content_type_header())
+        .body(Body::empty())?;
+    meta.insert_cached_headers(response.headers_mut(), now);
+    insert_length(response.headers_mut(), total);
+    Ok(response)
+}
+
+// 命中时明确给出长度：之后包装响应体（限速、统计）时不会退回分块编码，
+// 播放器据此显示进度并发起后续的范围请求
+fn insert_length(headers: &mut HeaderMap, len: u64) {
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(len));
+    headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+}
+
+// 源站忽略 Range 返回了完整的 200：从完整内容中截取客户端请求的部分，以 206 返回。
+// 长度未知、起点超出对象末尾或起点大于终点时无法构造 Content-Range，原样返回 200
+pub fn slice_full_response(response: Response<Body>, start: u64, end: u64) -> Result<Response<Body>> {
+    let total = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    let Some(total) =
+        total.filter(|&total| response.status() == StatusCode::OK && start < total && start <= end)
+    else {
+        return Ok(response);
+    };
+    let end = end.min(total - 1);
+    debug::record(|d| {
+        d.range.get_or_insert("ignored-by-origin");
+    });
+
+    let (mut parts, body) = response.into_parts();
+    parts.status = StatusCode::PARTIAL_CONTENT;
+    parts.headers.insert(
+        CONTENT_RANGE,
+        HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, total))?,
+    );
+    parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(end - start + 1));
+
+    // 按偏移裁剪每个数据块，读到结束位置后不再继续读取
+    let sliced = body
+        .scan(0u64, move |offset, chunk| {
+            let chunk_start = *offset;
+            let item = chunk.map(|chunk| {
+                let len = chunk.len() as u64;
+                *offset += len;
+                let from = start.saturating_sub(chunk_start).min(len);
+                let to = (end + 1).saturating_sub(chunk_start).min(len).max(from);
+                chunk.slice(from as usize..to as usize)
+            });
+            futures::future::ready((chunk_start <= end).then_some(item))
+        })
+        .filter(|chunk| futures::future::ready(!matches!(chunk, Ok(c) if c.is_empty())));
+    Ok(Response::from_parts(parts, Body::wrap_stream(sliced)))
+}
+
+// 不会被缓存的对象（超过大小限制等）：客户端的 Range 与 If-Range 原样转发给源站，
+// 206 直接流式返回，不预读、不读入内存，也不改成完整请求
+async fn passthrough_range(
+    client: &HttpClient,
+    req: Request<Body>,
+    fetch: FetchPolicy,
+) -> Result<Response<Body>> {
+    debug::record(|d| {
+        d.range = Some("passthrough");
+        d.store.get_or_insert("too-large");
+    });
+    METRICS.range_passthrough.fetch_add(1, Ordering::Relaxed);
+    fetch_with_retry(client, &req, fetch).await
+}
 
 pub async fn handle_range_request(
     range: (u64, u64),
     cached_entry: CacheEntry,
     req: Request<Body>,
-    client: Client<HttpsConnector<hyper::client::HttpConnector>>,
+    client: HttpClient,
     cache: Arc<ProxyCache>,
     cache_key: String,
+    policy: CachePolicy,
 ) -> Result<Response<Body>> {
-    let cached_len = cached_entry.content.len() as u64;
-    let (start, end) = range;
-
-    // 请求的范围在缓存范围内
-    if end <= cached_len {
-        
-        // 请求的范围已完全缓存
-        let slice = cached_entry.content.slice(start as usize..end as usize + 1);
-        
-        // 构建响应
-        let response = Response::builder()
-            .status(StatusCode::PARTIAL_CONTENT)
-            .header(
-                hyper::header::CONTENT_TYPE,
-                cached_entry.meta.content_type.parse::<hyper::header::HeaderValue>().unwrap(),
-            )
-            .header(
-                hyper::header::CONTENT_RANGE,
-                format!("bytes {}-{}/{}", start, end, cached_len),
-            )
-            .body(Body::from(slice))?;
-        Ok(response)
+    let (start, mut end) = range;
+    // 结束位置超出对象大小时截断到最后一个字节
+    if let Some(total) = cached_entry.meta.total_size.filter(|&total| total > 0) {
+        end = end.min(total - 1);
+    }
+
+    // 请求的范围已完全缓存
+    if let Some(slice) = cached_entry.slice(start, end) {
+        debug::record(|d| d.range = Some("cached"));
+        partial_response(&cached_entry.meta, start, end, slice, cache.clock().now_secs())
+    } else if cached_entry
+        .meta
+        .total_size
+        .is_some_and(|total| total > policy.max_object_bytes)
+    {
+        // 对象超过大小限制，缺失的部分永远不会补齐：不再按缓存续传，直接转发
+        passthrough_range(&client, req, policy.fetch).await
     } else {
-        // 需要获取额外的数据
-        let mut client_req = Request::builder()
-            .method(req.method())
-            .uri(req.uri())
-            .header(
-                hyper::header::RANGE,
-                format!("bytes={}-{}", cached_len, end),
-            )
-            .body(Body::empty())?;
-
-        *client_req.headers_mut() = req.headers().clone();
+        // 按客户端带宽多取一段后续数据写入缓存，对象大小已知时不超过末尾
+        let mut fetch_end = end.saturating_add(policy.read_ahead_bytes);
+        if let Some(total) = cached_entry.meta.total_size.filter(|&total| total > 0) {
+            fetch_end = fetch_end.min(total - 1);
+        }
+        if fetch_end > end {
+            debug::record(|d| d.read_ahead = Some(fetch_end - end));
+        }
+
+        // 区间的开头已经缓存：立即发送缓存的部分，同时向源站获取其余部分。
+        // 对象大小已知时结束位置已截断到末尾，事先发出的 Content-Range 与长度才准确
+        let cached_until = cached_entry
+            .ranges()
+            .run_end(start)
+            .filter(|&cached_until| cached_until <= end && cached_entry.meta.total_size.is_some());
+        if let Some(cached_until) = cached_until {
+            let fetched = (cached_until, fetch_end);
+            let client_req =
+                resume_request(&req, cached_until, fetch_end, cached_entry.meta.if_range_validator())?;
+            let stitch = Stitch {
+                entry: cached_entry,
+                cache,
+                cache_key,
+                policy,
+            };
+            return stream_cached_prefix((start, end), fetched, client_req, client, stitch);
+        }
+
+        // 需要获取缺失的数据，附带 If-Range 确认源站对象未变化
+        let client_req = resume_request(
+            &req,
+            start,
+            fetch_end,
+            cached_entry.meta.if_range_validator(),
+        )?;
 
         // 从源服务器获取数据
-        let resp = fetch_with_retry(&client, &client_req).await?;
-        
-        // 如果响应状态码为部分内容，则将数据与缓存数据合并后返回
-        if resp.status() == StatusCode::PARTIAL_CONTENT {
+        let resp = fetch_with_retry(&client, &client_req, policy.fetch).await?;
+
+        // 源站返回 200 说明对象已变化，不能与旧数据拼接，用新的完整响应替换缓存
+        // 客户端自己带了 If-Range 时，它持有的版本可能已经过时，按 RFC 返回完整内容
+        if resp.status() == StatusCode::OK {
+            let changed = !cached_entry.meta.same_representation(resp.headers());
+            debug::record(|d| d.range = Some(if changed { "changed" } else { "ignored-by-origin" }));
+            let conditional = req.headers().contains_key(IF_RANGE);
+            let response =
+                cache_full_response(&client, req, resp, cache, cache_key, policy).await?;
+            if conditional {
+                return Ok(response);
+            }
+            return slice_full_response(response, start, end);
+        }
+
+        // 源站忽略了 If-Range 却返回了另一个版本的片段，不能与旧数据拼接
+        if resp.status() == StatusCode::PARTIAL_CONTENT
+            && !cached_entry.meta.same_representation(resp.headers())
+        {
+            debug::record(|d| d.range = Some("changed"));
+            return fetch_and_cache_full_response(&client, req, cache, cache_key, policy).await;
+        }
+
+        // 如果响应状态码为部分内容，则将数据合并进缓存后返回
+        let returned = content_range(resp.headers());
+        if let (StatusCode::PARTIAL_CONTENT, Some(returned)) = (resp.status(), returned) {
+            // Content-Range 与请求的区间或已知的大小不符时不能拼接，丢弃旧数据重新获取
+            let total = cached_entry.meta.total_size;
+            let Some(expected) = stitchable_len((start, fetch_end), total, returned) else {
+                debug::record(|d| d.range = Some("misaligned"));
+                return fetch_and_cache_full_response(&client, req, cache, cache_key, policy).await;
+            };
+            debug::record(|d| d.range = Some("fetched"));
+            let headers = resp.headers().clone();
             let mut body = Vec::new();
             let mut stream = resp.into_body();
-            
-            // 读取响应主体
+
+            // 读取响应主体，超出声明的区间后不再继续读取
             while let Some(chunk) = stream.next().await {
-                let chunk = chunk?;
+                let chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    // 源站中途断开或卡住：已收到的数据仍写入缓存，下次请求从断点续传
+                    Err(e) => {
+                        if !body.is_empty() && policy.may_store(&headers, cache.clock().now_secs()) {
+                            let partial = cached_entry.merge(returned.0, &body, returned.2);
+                            if partial.content.len() as u64 <= policy.max_object_bytes {
+                                cache.set(cache_key, partial).await?;
+                            }
+                        }
+                        return Err(e.into());
+                    }
+                };
                 body.extend_from_slice(&chunk);
+                if body.len() as u64 > expected {
+                    break;
+                }
+            }
+            if body.len() as u64 != expected {
+                debug::record(|d| d.range = Some("misaligned"));
+                return fetch_and_cache_full_response(&client, req, cache, cache_key, policy).await;
             }
-            
-            // 将数据与缓存数据合并
-            let mut new_content = cached_entry.content.to_vec();
-            // 合并数据
-            new_content.extend_from_slice(&body);
-            
-            let content_type = cached_entry.meta.content_type.clone();
-            
-            // 更新缓存
-            if new_content.len()  <= MAX_FILE_SIZE {
-                
+
+            // 合并数据，记录新的字节区间
+            let new_entry = cached_entry.merge(returned.0, &body, returned.2);
+            // 预读的数据只写入缓存，客户端只收到它请求的部分
+            let requested = (fetch_end > end)
+                .then(|| new_entry.slice(start, end))
+                .flatten()
+                .map(|slice| (new_entry.meta.clone(), slice));
+
+            // 更新缓存（带凭据请求的响应须明确允许共享）
+            if new_entry.content.len() as u64 <= policy.max_object_bytes
+                && policy.may_store(&headers, cache.clock().now_secs())
+            {
                 // 缓存数据未超过最大文件大小，直接更新缓存
-                cache.set(
-                    cache_key,
-                    CacheEntry {
-                        content: Bytes::from(new_content.clone()),
-                        meta: CacheMeta {
-                            content_type: content_type.clone(),
-                            is_complete: end == new_content.len() as u64 - 1,
-                            total_size: Some(new_content.len() as u64),
-                        },
-                    },
-                ).await?;
+                cache.set(cache_key, new_entry).await?;
+                debug::record(|d| d.store = Some("stored"));
+            } else {
+                debug::record(|d| {
+                    d.store = Some(if policy.may_store(&headers, cache.clock().now_secs()) {
+                        "too-large"
+                    } else {
+                        "not-shareable"
+                    })
+                });
             }
-            
-            // 构建响应
-            let response_slice = new_content[start as usize..=end as usize].to_vec();
-            
-            // 构建响应
-            let response = Response::builder()
+            if let Some((meta, slice)) = requested {
+                return partial_response(&meta, start, end, slice, cache.clock().now_secs());
+            }
+
+            // 直接返回源站的部分响应
+            let mut response = Response::builder()
                 .status(StatusCode::PARTIAL_CONTENT)
-                .header(
-                    hyper::header::CONTENT_TYPE,
-                    content_type.parse::<hyper::header::HeaderValue>().unwrap(),
-                )
-                .header(
-                    hyper::header::CONTENT_RANGE,
-                    format!("bytes {}-{}/{}", start, end, new_content.len()),
-                )
-                .body(Body::from(response_slice))?;
+                .body(Body::from(body))?;
+            *response.headers_mut() = headers;
             Ok(response)
         } else {
             // 响应状态码不是部分内容，直接返回
@@ -112,3 +302,122 @@ pub async fn handle_range_request(
         }
     }
 }
+
+// 拼接响应时写回缓存所需的条目与策略
+struct Stitch {
+    entry: CacheEntry,
+    cache: Arc<ProxyCache>,
+    cache_key: String,
+    policy: CachePolicy,
+}
+
+// 先发送缓存中从 start 开始的连续部分，同时在后台获取 fetched 区间，拼接成同一个 206 响应体。
+// 响应头发出后才知道源站的回应，对象已变化或返回的区间不符时只能中断响应体，客户端会重新请求
+fn stream_cached_prefix(
+    (start, end): (u64, u64),
+    fetched: (u64, u64),
+    client_req: Request<Body>,
+    client: HttpClient,
+    stitch: Stitch,
+) -> Result<Response<Body>> {
+    let Some(prefix) = stitch.entry.slice(start, fetched.0 - 1) else {
+        anyhow::bail!("cached prefix of {} is missing", stitch.cache_key);
+    };
+    debug::record(|d| d.range = Some("streamed"));
+
+    let now = stitch.cache.clock().now_secs();
+    let (mut parts, _) = partial_response(&stitch.entry.meta, start, end, Bytes::new(), now)?.into_parts();
+    parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(end - start + 1));
+    let (mut sender, body) = Body::channel();
+
+    // 后台任务沿用请求的优先级、调试信息与整体时限
+    let debug = debug::current().unwrap_or_default();
+    let deadline = server::current_deadline();
+    tokio::spawn(with_debug(debug, with_priority(current_priority(), async move {
+        let uri = client_req.uri().clone();
+        let stitched = server::within_deadline(deadline, async {
+            // 源站的首字节到达之前客户端已经在接收缓存的部分
+            let (resp, sent) = tokio::join!(
+                fetch_with_retry(&client, &client_req, stitch.policy.fetch),
+                sender.send_data(prefix)
+            );
+            match (resp, sent) {
+                (Ok(resp), Ok(())) => send_suffix(&mut sender, resp, end, fetched, stitch).await,
+                (Err(e), _) => Err(e),
+                (_, Err(e)) => Err(e.into()),
+            }
+        });
+        if let Err(e) = stitched.await {
+            tracing::debug!("aborting streamed range of {}: {:#}", uri, e);
+            sender.abort();
+        }
+    })));
+    Ok(Response::from_parts(parts, body))
+}
+
+// 把源站返回的剩余部分接在缓存的前缀后面发给客户端（预读的数据不发送），并合并进缓存
+async fn send_suffix(
+    sender: &mut hyper::body::Sender,
+    resp: Response<Body>,
+    end: u64,
+    fetched: (u64, u64),
+    stitch: Stitch,
+) -> Result<()> {
+    let Stitch { entry: cached_entry, cache, cache_key, policy } = stitch;
+    let returned = content_range(resp.headers());
+    let changed = resp.status() == StatusCode::OK
+        || (resp.status() == StatusCode::PARTIAL_CONTENT
+            && !cached_entry.meta.same_representation(resp.headers()));
+    if changed {
+        // 源站对象已变化，已发出的前缀作废；清除缓存，客户端重试时获取新的版本
+        cache.purge(&cache_key).await?;
+        anyhow::bail!("origin object changed");
+    }
+    let expected = returned.and_then(|returned| stitchable_len(fetched, cached_entry.meta.total_size, returned));
+    let (StatusCode::PARTIAL_CONTENT, Some(returned), Some(expected)) = (resp.status(), returned, expected) else {
+        anyhow::bail!("origin returned {} instead of the missing range", resp.status());
+    };
+
+    let headers = resp.headers().clone();
+    let mut body = Vec::new();
+    let mut stream = resp.into_body();
+    let mut failure = None;
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                failure = Some(e.into());
+                break;
+            }
+        };
+        let offset = fetched.0 + body.len() as u64;
+        let wanted = (end + 1).saturating_sub(offset).min(chunk.len() as u64) as usize;
+        body.extend_from_slice(&chunk);
+        if body.len() as u64 > expected {
+            failure = Some(anyhow::anyhow!("origin returned more than the missing range"));
+            break;
+        }
+        if wanted > 0 {
+            if let Err(e) = sender.send_data(chunk.slice(..wanted)).await {
+                // 客户端已断开：已收到的数据仍写入缓存
+                failure = Some(e.into());
+                break;
+            }
+        }
+    }
+    if failure.is_none() && body.len() as u64 != expected {
+        failure = Some(anyhow::anyhow!("origin returned less than the missing range"));
+    }
+
+    // 源站中途断开、客户端断开时已收到的部分也写入缓存，下次从断点续传
+    if !body.is_empty() && (body.len() as u64) <= expected && policy.may_store(&headers, cache.clock().now_secs()) {
+        let entry = cached_entry.merge(returned.0, &body, returned.2);
+        if entry.content.len() as u64 <= policy.max_object_bytes {
+            cache.set(cache_key, entry).await?;
+        }
+    }
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
@@ -1,46 +1,120 @@
 use anyhow::Result;
-use hyper::{Body, Client, Request, header::HeaderMap};
-use hyper_tls::HttpsConnector;
+use hyper::{Body, Request, header::HeaderMap};
 
+use crate::config::FetchPolicy;
+use crate::upstream::{HttpClient, OriginMeta};
 use crate::utils::fetch_with_retry;
 
-pub async fn get_total_size(
-    client: &Client<HttpsConnector<hyper::client::HttpConnector>>,
+// 获取源站对象的元数据，结果按 URL 缓存一段时间
+pub async fn get_origin_meta(
+    client: &HttpClient,
     req: &Request<Body>,
-) -> Result<Option<u64>> {
+    fetch: FetchPolicy,
+) -> Result<OriginMeta> {
+    let url = req.uri().to_string();
+    if let Some(meta) = client.origin_meta().get(&url) {
+        return Ok(meta);
+    }
+
     let head_req = Request::builder()
         .method(hyper::Method::HEAD)
         .uri(req.uri())
         .body(Body::empty())?;
 
-    let resp = fetch_with_retry(client, &head_req).await?;
-    
+    let resp = fetch_with_retry(client, &head_req, fetch).await?;
+    let headers = resp.headers();
+
+    let meta = OriginMeta {
+        total_size: total_size_from_headers(headers),
+        etag: headers
+            .get(hyper::header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string()),
+        accept_ranges: headers
+            .get(hyper::header::ACCEPT_RANGES)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.eq_ignore_ascii_case("bytes"))
+            .unwrap_or(false),
+    };
+
+    // 只缓存成功的探测结果
+    if resp.status().is_success() {
+        client.origin_meta().put(url, meta.clone());
+    }
+    Ok(meta)
+}
+
+pub async fn get_total_size(
+    client: &HttpClient,
+    req: &Request<Body>,
+    fetch: FetchPolicy,
+) -> Result<Option<u64>> {
+    Ok(get_origin_meta(client, req, fetch).await?.total_size)
+}
+
+fn total_size_from_headers(headers: &HeaderMap) -> Option<u64> {
     // 先检查 Content-Range
-    if let Some(range) = resp.headers().get(hyper::header::CONTENT_RANGE) {
+    if let Some(range) = headers.get(hyper::header::CONTENT_RANGE) {
         if let Ok(range_str) = range.to_str() {
-            if let Some(total_size) = range_str.split('/').last() {
+            if let Some(total_size) = range_str.split('/').next_back() {
                 if let Ok(size) = total_size.parse::<u64>() {
-                    return Ok(Some(size));
+                    return Some(size);
                 }
             }
         }
     }
     
     // 再检查 Content-Length
-    if let Some(length) = resp.headers().get(hyper::header::CONTENT_LENGTH) {
+    if let Some(length) = headers.get(hyper::header::CONTENT_LENGTH) {
         if let Some(expected_len) = length.to_str().ok().and_then(|v| v.parse::<u64>().ok()) {
-            return Ok(Some(expected_len));
+            return Some(expected_len);
         }
     }
 
     // If no headers available, return None
-    Ok(None)
+    None
+}
+
+// 解析 Content-Range: bytes start-end/total，返回 (start, end, total)
+pub fn content_range(headers: &HeaderMap) -> Option<(u64, u64, Option<u64>)> {
+    let value = headers.get(hyper::header::CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let start = start.trim().parse::<u64>().ok()?;
+    let end = end.trim().parse::<u64>().ok()?;
+    if end < start {
+        return None;
+    }
+    Some((start, end, total.trim().parse::<u64>().ok()))
+}
+
+// 续传得到的 206 能否与缓存拼接：起点必须是请求的起点，终点不超过请求的终点，
+// 总大小与已知的一致且区间不越过对象末尾。返回应收到的字节数，不能拼接时返回 None
+pub fn stitchable_len(
+    requested: (u64, u64),
+    known_total: Option<u64>,
+    returned: (u64, u64, Option<u64>),
+) -> Option<u64> {
+    let (start, end) = requested;
+    let (returned_start, returned_end, returned_total) = returned;
+    if returned_start != start || returned_end > end {
+        return None;
+    }
+    if let (Some(known), Some(returned)) = (known_total, returned_total) {
+        if known != returned {
+            return None;
+        }
+    }
+    if returned_total.or(known_total).is_some_and(|total| returned_end >= total) {
+        return None;
+    }
+    Some(returned_end - returned_start + 1)
 }
 
 pub fn check_response_complete(headers: &HeaderMap, content_length: u64) -> bool {
     if let Some(content_range) = headers.get(hyper::header::CONTENT_RANGE) {
         if let Ok(range_str) = content_range.to_str() {
-            if let Some(total_size) = range_str.split('/').last() {
+            if let Some(total_size) = range_str.split('/').next_back() {
                 if let Ok(total) = total_size.parse::<u64>() {
                     return content_length == total;
                 }
@@ -0,0 +1,177 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use anyhow::Result;
+use hyper::header::{
+    HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, RANGE,
+};
+use hyper::{Body, Request, Response, StatusCode};
+
+use crate::cache::{lifetime, weak_match, CacheEntry, Joined, Leader, ProxyCache};
+use crate::config::CachePolicy;
+use crate::debug;
+use crate::metrics::METRICS;
+use crate::upstream::HttpClient;
+use crate::utils::{clone_request, fetch_with_retry, header_string};
+
+use super::{cache_full_response, fetch_and_cache_full_response};
+
+pub enum Revalidated {
+    // 源站确认未变化（或暂时不可用），继续使用缓存条目
+    Entry(CacheEntry),
+    // 源站返回了新内容，已写入缓存
+    Response(Response<Body>),
+}
+
+type RevalidationLeader = Leader<Option<CacheEntry>>;
+
+// 过期条目向源站发起条件请求：304 刷新新鲜期，200 替换缓存，
+// 源站出错时继续返回旧内容。同一条目同时过期的多个请求只发一个条件请求，
+// 其余请求等待它的结果；带凭据的请求结果不能共享，各自验证
+pub async fn revalidate(
+    client: &HttpClient,
+    req: &Request<Body>,
+    entry: CacheEntry,
+    cache: Arc<ProxyCache>,
+    cache_key: String,
+    policy: CachePolicy,
+) -> Result<Revalidated> {
+    let mut leader = None;
+    if !policy.authenticated {
+        match cache.revalidations().join(&format!("{} {}", req.method(), cache_key)) {
+            Joined::Leader(joined) => leader = Some(joined),
+            // 执行验证的请求中途放弃时，自己向源站验证
+            Joined::Follower(follower) => {
+                if let Some(verdict) = follower.wait().await {
+                    METRICS.revalidations_collapsed.fetch_add(1, Ordering::Relaxed);
+                    let freshness = if verdict.is_some() { "revalidated" } else { "stale" };
+                    debug::record(|d| d.freshness = Some(freshness));
+                    if verdict.is_some() {
+                        METRICS.cache_content_hits.fetch_add(1, Ordering::Relaxed);
+                    }
+                    return Ok(Revalidated::Entry(verdict.unwrap_or(entry)));
+                }
+            }
+        }
+    }
+    revalidate_with_origin(client, req, entry, cache, cache_key, policy, leader).await
+}
+
+// 公布验证结果：Some 为可以直接使用的新鲜条目，None 表示源站不可用、继续使用旧内容
+fn announce(leader: Option<RevalidationLeader>, verdict: Option<CacheEntry>) {
+    if let Some(leader) = leader {
+        leader.finish(verdict);
+    }
+}
+
+// 源站返回新内容后，等待者直接读取刚写入的条目；没有写入缓存时不公布，等待者各自验证
+async fn announce_stored(leader: Option<RevalidationLeader>, cache: &ProxyCache, cache_key: &str) {
+    let Some(leader) = leader else {
+        return;
+    };
+    let now = cache.clock().now_secs();
+    if let Some(entry) = cache.get(cache_key).await {
+        if entry.meta.is_complete && entry.meta.is_fresh(now) {
+            leader.finish(Some(entry));
+        }
+    }
+}
+
+async fn revalidate_with_origin(
+    client: &HttpClient,
+    req: &Request<Body>,
+    entry: CacheEntry,
+    cache: Arc<ProxyCache>,
+    cache_key: String,
+    policy: CachePolicy,
+    leader: Option<RevalidationLeader>,
+) -> Result<Revalidated> {
+    let mut conditional = clone_request(req).await?;
+    let headers = conditional.headers_mut();
+    // 客户端自己的条件头针对的是它手里的版本，不能用来判断缓存条目是否变化
+    for name in [RANGE, IF_RANGE, IF_NONE_MATCH, IF_MODIFIED_SINCE] {
+        headers.remove(name);
+    }
+    if let Some(etag) = entry.meta.etag.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+        headers.insert(IF_NONE_MATCH, etag);
+    }
+    if let Some(lm) = entry
+        .meta
+        .last_modified
+        .as_deref()
+        .and_then(|v| HeaderValue::from_str(v).ok())
+    {
+        headers.insert(IF_MODIFIED_SINCE, lm);
+    }
+
+    let resp = match fetch_with_retry(client, &conditional, policy.fetch).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            tracing::warn!("revalidation of {} failed, serving stale: {:#}", req.uri(), e);
+            debug::record(|d| d.freshness = Some("stale"));
+            announce(leader, None);
+            return Ok(Revalidated::Entry(entry));
+        }
+    };
+
+    match resp.status() {
+        StatusCode::NOT_MODIFIED => {
+            // If-None-Match 按弱比较匹配，304 带回的 ETag 与缓存不符时说明它针对的不是这个条目
+            let returned = header_string(resp.headers(), ETAG);
+            if let (Some(stored), Some(returned)) = (entry.meta.etag.as_deref(), &returned) {
+                if !weak_match(stored, returned) {
+                    tracing::warn!("304 for {} has a different ETag, refetching", req.uri());
+                    debug::record(|d| d.freshness = Some("refreshed"));
+                    let mut req = clone_request(req).await?;
+                    for name in [RANGE, IF_RANGE, IF_NONE_MATCH, IF_MODIFIED_SINCE] {
+                        req.headers_mut().remove(name);
+                    }
+                    let response =
+                        fetch_and_cache_full_response(client, req, cache.clone(), cache_key.clone(), policy)
+                            .await?;
+                    announce_stored(leader, &cache, &cache_key).await;
+                    return Ok(Revalidated::Response(response));
+                }
+            }
+            let now = cache.clock().now_secs();
+            let mut entry = entry;
+            entry.meta.stored_at = Some(now);
+            entry.meta.invalidated = false;
+            // 304 可能带回更新后的校验器（例如弱标签换成了强标签）以及新的 Date、Cache-Control
+            if returned.is_some() {
+                entry.meta.etag = returned;
+            }
+            if let Some(lm) = header_string(resp.headers(), LAST_MODIFIED) {
+                entry.meta.last_modified = Some(lm);
+            }
+            entry.meta.merge_headers(resp.headers());
+            // 304 没有携带新鲜度信息时沿用原来的新鲜期
+            entry.meta.freshness_secs =
+                lifetime(resp.headers(), &policy, now).or(entry.meta.freshness_secs);
+            // 带凭据请求得到的 304 没有明确允许共享时，只对本次请求生效
+            if policy.may_store(resp.headers(), now) {
+                cache.update_meta(cache_key, entry.clone()).await?;
+                debug::record(|d| d.store = Some("meta-updated"));
+                announce(leader, Some(entry.clone()));
+            } else {
+                debug::record(|d| d.store = Some("not-shareable"));
+            }
+            debug::record(|d| d.freshness = Some("revalidated"));
+            METRICS.cache_content_hits.fetch_add(1, Ordering::Relaxed);
+            Ok(Revalidated::Entry(entry))
+        }
+        StatusCode::OK => {
+            debug::record(|d| d.freshness = Some("refreshed"));
+            let req = clone_request(req).await?;
+            let response =
+                cache_full_response(client, req, resp, cache.clone(), cache_key.clone(), policy).await?;
+            announce_stored(leader, &cache, &cache_key).await;
+            Ok(Revalidated::Response(response))
+        }
+        status => {
+            tracing::warn!("revalidation of {} returned {}, serving stale", req.uri(), status);
+            debug::record(|d| d.freshness = Some("stale"));
+            announce(leader, None);
+            Ok(Revalidated::Entry(entry))
+        }
+    }
+}
@@ -1,5 +1,38 @@
+pub mod admin;
+pub mod audit;
+pub mod bandwidth;
 pub mod cache;
+pub mod cache_key;
+pub mod client;
+pub mod client_usage;
+pub mod clock;
+pub mod config;
+pub mod connector;
 pub mod constants;
+#[cfg(unix)]
+pub mod daemon;
+pub mod debug;
+pub mod decision_log;
+pub mod drain;
+pub mod esi;
 pub mod handler;
+pub mod listener;
+pub mod metrics;
+pub mod origin_auth;
+pub mod recent_requests;
+pub mod refresh;
+pub mod resume;
+pub mod rewrite;
+pub mod rpc;
+#[cfg(target_os = "linux")]
+pub mod sandbox;
 pub mod server;
+#[cfg(windows)]
+pub mod service;
+pub mod services;
+pub mod signed_url;
+pub mod target;
+#[cfg(unix)]
+pub mod upgrade;
+pub mod upstream;
 pub mod utils;
@@ -0,0 +1,318 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll, Waker};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::stream;
+use hyper::body::{HttpBody, SizeHint};
+use hyper::header::{HeaderMap, HeaderValue, CONNECTION};
+use hyper::server::accept::{self, Accept};
+use hyper::{Body, Response};
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{Instant, Sleep};
+use tokio_io_timeout::TimeoutStream;
+
+use crate::config::DownstreamConfig;
+use crate::drain::DRAIN;
+use crate::metrics::METRICS;
+
+// 客户端地址，作为请求扩展传给处理函数
+#[derive(Clone, Copy, Debug)]
+pub struct ClientAddr(pub SocketAddr);
+
+// 按 downstream 的设置创建监听 socket。收发缓冲区在 listen 之前设置，
+// 接受的连接继承它们，握手时据此协商窗口缩放
+pub fn bind(addr: SocketAddr, downstream: &DownstreamConfig) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    // 与 tokio 的 TcpListener::bind 一致：Unix 上允许重启后立即重新绑定
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    if let Some(bytes) = downstream.recv_buffer_bytes {
+        socket.set_recv_buffer_size(bytes)?;
+    }
+    if let Some(bytes) = downstream.send_buffer_bytes {
+        socket.set_send_buffer_size(bytes)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(downstream.listen_backlog.min(i32::MAX as u32) as i32)?;
+    TcpListener::from_std(socket.into())
+}
+
+// 接受客户端连接，为每个连接设置写超时，防止慢速客户端长期占用连接与缓冲区
+pub fn incoming(
+    listener: TcpListener,
+    downstream: &DownstreamConfig,
+) -> impl Accept<Conn = ClientConn, Error = io::Error> {
+    let write_timeout = downstream.write_timeout_secs.map(Duration::from_secs);
+    let header_read_timeout = Duration::from_secs(downstream.header_read_timeout_secs);
+    let keep_alive_timeout = Duration::from_secs(downstream.keep_alive_timeout_secs);
+    let tcp_keepalive = downstream.tcp_keepalive_secs.map(Duration::from_secs);
+    let conns = stream::unfold(listener, move |listener| async move {
+        loop {
+            match listener.accept().await {
+                Ok((tcp, _)) => {
+                    METRICS.downstream_connections_accepted.fetch_add(1, Ordering::Relaxed);
+                    METRICS.downstream_connections_active.fetch_add(1, Ordering::Relaxed);
+                    if let Some(time) = tcp_keepalive {
+                        let keepalive = TcpKeepalive::new().with_time(time);
+                        if let Err(e) = SockRef::from(&tcp).set_tcp_keepalive(&keepalive) {
+                            tracing::debug!("failed to enable TCP keepalive: {}", e);
+                        }
+                    }
+                    let mut inner = TimeoutStream::new(tcp);
+                    inner.set_write_timeout(write_timeout);
+                    let conn = ClientConn {
+                        inner: Box::pin(inner),
+                        activity: Arc::new(ConnActivity::new()),
+                        timer: Box::pin(tokio::time::sleep(header_read_timeout)),
+                        header_read_timeout,
+                        keep_alive_timeout,
+                    };
+                    return Some((Ok::<_, io::Error>(conn), listener));
+                }
+                Err(e) => {
+                    // 文件描述符耗尽等错误时稍后重试，而不是让整个服务退出
+                    METRICS.downstream_accept_errors.fetch_add(1, Ordering::Relaxed);
+                    tracing::warn!("accept error: {}", e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    });
+    accept::from_stream(conns)
+}
+
+// 客户端连接：没有请求在处理时限制读取等待时间。
+// 请求头开始到达后最多等待 header_read_timeout（防御 slowloris），
+// 两个请求之间的空闲时间最多为 keep_alive_timeout，超时后按对端关闭处理，连接正常结束
+pub struct ClientConn {
+    inner: Pin<Box<TimeoutStream<TcpStream>>>,
+    activity: Arc<ConnActivity>,
+    timer: Pin<Box<Sleep>>,
+    header_read_timeout: Duration,
+    keep_alive_timeout: Duration,
+}
+
+impl ClientConn {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.get_ref().peer_addr()
+    }
+
+    pub fn activity(&self) -> Arc<ConnActivity> {
+        self.activity.clone()
+    }
+}
+
+impl Drop for ClientConn {
+    fn drop(&mut self) {
+        METRICS.downstream_connections_active.fetch_sub(1, Ordering::Relaxed);
+        METRICS.downstream_connections_closed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl AsyncRead for ClientConn {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        if let Poll::Ready(result) = self.inner.as_mut().poll_read(cx, buf) {
+            if buf.filled().len() > filled {
+                self.activity.received();
+            }
+            return Poll::Ready(result);
+        }
+        let (header_read_timeout, keep_alive_timeout) =
+            (self.header_read_timeout, self.keep_alive_timeout);
+        let Some(deadline) =
+            self.activity.deadline(header_read_timeout, keep_alive_timeout, cx.waker())
+        else {
+            return Poll::Pending;
+        };
+        if self.timer.deadline() != deadline {
+            self.timer.as_mut().reset(deadline);
+        }
+        ready!(self.timer.as_mut().poll(cx));
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for ClientConn {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.inner.as_mut().poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.inner.as_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.inner.as_mut().poll_shutdown(cx)
+    }
+}
+
+// 连接上的请求进度：正在处理的请求数、已处理的请求数，以及开始等待下一个请求的时间
+pub struct ConnActivity {
+    state: Mutex<ActivityState>,
+}
+
+struct ActivityState {
+    in_flight: usize,
+    requests: u64,
+    idle_since: Instant,
+    // 空闲期间收到第一个字节（下一个请求开始到达）的时间
+    first_byte: Option<Instant>,
+    // 请求处理期间等待读取的任务，请求结束后唤醒它开始计时
+    reader: Option<Waker>,
+}
+
+impl ConnActivity {
+    fn new() -> Self {
+        ConnActivity {
+            state: Mutex::new(ActivityState {
+                in_flight: 0,
+                requests: 0,
+                idle_since: Instant::now(),
+                first_byte: None,
+                reader: None,
+            }),
+        }
+    }
+
+    // 开始处理一个请求，返回它是连接上的第几个请求；响应体发送完毕（或被丢弃）时结束
+    pub fn begin(self: &Arc<Self>) -> (u64, RequestGuard) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight += 1;
+        state.requests += 1;
+        METRICS.downstream_requests_active.fetch_add(1, Ordering::Relaxed);
+        (state.requests, RequestGuard(self.clone()))
+    }
+
+    fn end(&self) {
+        METRICS.downstream_requests_active.fetch_sub(1, Ordering::Relaxed);
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= 1;
+        if state.in_flight == 0 {
+            state.idle_since = Instant::now();
+            state.first_byte = None;
+            if let Some(reader) = state.reader.take() {
+                reader.wake();
+            }
+        }
+    }
+
+    fn received(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.in_flight == 0 && state.first_byte.is_none() {
+            state.first_byte = Some(Instant::now());
+        }
+    }
+
+    // 读取的截止时间，有请求在处理时不限制
+    fn deadline(
+        &self,
+        header_read_timeout: Duration,
+        keep_alive_timeout: Duration,
+        waker: &Waker,
+    ) -> Option<Instant> {
+        let mut state = self.state.lock().unwrap();
+        if state.in_flight > 0 {
+            state.reader = Some(waker.clone());
+            return None;
+        }
+        Some(match state.first_byte {
+            Some(first_byte) => first_byte + header_read_timeout,
+            None if state.requests == 0 => state.idle_since + header_read_timeout,
+            None => state.idle_since + keep_alive_timeout,
+        })
+    }
+}
+
+pub struct RequestGuard(Arc<ConnActivity>);
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.0.end();
+    }
+}
+
+// 响应体发送完毕前请求仍算作进行中，长时间的视频下载不会被当作空闲连接关闭。
+// 达到单连接请求数上限或正在排空时通知客户端关闭连接，否则告知长连接的保持时间与剩余请求数
+pub fn finish_response(
+    response: Response<Body>,
+    number: u64,
+    guard: RequestGuard,
+    downstream: &DownstreamConfig,
+) -> Response<GuardedBody> {
+    let (mut parts, body) = response.into_parts();
+    // 源站的连接管理头只描述源站与代理之间的连接
+    parts.headers.remove(CONNECTION);
+    parts.headers.remove("keep-alive");
+    let last = downstream.keep_alive_timeout_secs == 0
+        || DRAIN.is_draining()
+        || downstream.max_requests_per_connection.is_some_and(|max| number >= max);
+    if last {
+        parts.headers.insert(CONNECTION, HeaderValue::from_static("close"));
+    } else {
+        let hint = match downstream.max_requests_per_connection {
+            Some(max) => format!("timeout={}, max={}", downstream.keep_alive_timeout_secs, max - number),
+            None => format!("timeout={}", downstream.keep_alive_timeout_secs),
+        };
+        if let Ok(hint) = HeaderValue::from_str(&hint) {
+            parts.headers.insert("keep-alive", hint);
+        }
+    }
+    Response::from_parts(
+        parts,
+        GuardedBody {
+            inner: body,
+            _guard: guard,
+        },
+    )
+}
+
+// 保留原响应体的长度信息，hyper 仍按 Content-Length 发送
+pub struct GuardedBody {
+    inner: Body,
+    _guard: RequestGuard,
+}
+
+impl HttpBody for GuardedBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, hyper::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, hyper::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
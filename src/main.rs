@@ -1,36 +1,376 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use anyhow::Result;
-use hyper::Server;
-use hyper::service::{make_service_fn, service_fn};
-use hyper_tls::HttpsConnector;
+use clap::builder::RangedU64ValueParser;
+use clap::{Parser, Subcommand};
 
-use rust_proxy_server::cache::ProxyCache;
-use rust_proxy_server::server;
+use rust_proxy_server::cache::{archive, check_cache_dir, inspect, ProxyCache};
+use rust_proxy_server::clock::SystemClock;
+use rust_proxy_server::config::{Config, RuntimeConfig};
+use rust_proxy_server::constants::CACHE_DIR;
+use rust_proxy_server::recent_requests::RECENT_REQUESTS;
+use rust_proxy_server::{audit, client, decision_log, refresh, resume, services};
+#[cfg(windows)]
+use rust_proxy_server::service;
+#[cfg(unix)]
+use rust_proxy_server::daemon::Identity;
+#[cfg(unix)]
+use rust_proxy_server::{daemon, upgrade};
+#[cfg(not(unix))]
+type Identity = ();
+#[cfg(target_os = "linux")]
+use rust_proxy_server::sandbox;
 
-#[tokio::main]
-async fn main() -> Result<()> {
+// 线程数至少为 1，命令行传入 0 时在解析阶段报错
+fn thread_count() -> RangedU64ValueParser<usize> {
+    RangedU64ValueParser::new().range(1..)
+}
+
+#[derive(Parser)]
+#[command(version, about = "Caching HTTP proxy server")]
+struct Cli {
+    #[arg(long, global = true, help = "Path to the TOML configuration file")]
+    config: Option<PathBuf>,
+    #[arg(long, value_parser = thread_count(), help = "Async worker threads (overrides runtime.worker_threads)")]
+    worker_threads: Option<usize>,
+    #[arg(
+        long,
+        value_parser = thread_count(),
+        help = "Maximum threads for blocking file IO (overrides runtime.max_blocking_threads)"
+    )]
+    max_blocking_threads: Option<usize>,
+    #[cfg(unix)]
+    #[arg(long, help = "Detach and run in the background once started")]
+    daemon: bool,
+    #[cfg(unix)]
+    #[arg(long, help = "Write the process id to this file")]
+    pid_file: Option<PathBuf>,
+    #[cfg(unix)]
+    #[arg(long, help = "Switch to this user after binding the listen ports")]
+    user: Option<String>,
+    #[cfg(unix)]
+    #[arg(long, help = "Switch to this group after binding the listen ports")]
+    group: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Validate the configuration and print the effective config without starting")]
+    Check,
+    #[command(about = "Inspect or clean the on-disk cache while the server is stopped")]
+    Cache {
+        #[arg(long, global = true, default_value = CACHE_DIR, help = "Cache directory")]
+        dir: PathBuf,
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    #[cfg(windows)]
+    #[command(about = "Install, remove or run the proxy as a Windows service")]
+    Service {
+        #[command(subcommand)]
+        command: ServiceCommand,
+    },
+}
+
+#[cfg(windows)]
+#[derive(Subcommand)]
+enum ServiceCommand {
+    #[command(about = "Register an auto-start service that uses the given --config")]
+    Install,
+    #[command(about = "Stop and remove the service")]
+    Uninstall,
+    #[command(about = "Entry point used by the service control manager")]
+    Run,
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    #[command(about = "List entries with URL, size and completeness")]
+    Ls,
+    #[command(about = "Show an entry's metadata (by cache key or URL)")]
+    Show {
+        target: String,
+        #[arg(long, help = "Write the cached body to stdout instead")]
+        content: bool,
+    },
+    #[command(about = "Delete an entry (by cache key or URL)")]
+    Rm { target: String },
+    #[command(about = "Remove leftovers and evict oldest entries down to a size quota")]
+    Gc {
+        #[arg(long, help = "Evict least recently written entries until the cache fits")]
+        max_bytes: Option<u64>,
+        #[arg(long, help = "Also remove entries that are no longer fresh")]
+        expired: bool,
+    },
+    #[command(about = "Write entries and their metadata to a .tar.gz archive")]
+    Export {
+        file: PathBuf,
+        #[arg(long, help = "Only export entries whose URL contains this text")]
+        url_contains: Option<String>,
+        #[arg(long, help = "Skip partially downloaded entries")]
+        complete_only: bool,
+    },
+    #[command(about = "Load entries from an archive created by `cache export`")]
+    Import {
+        file: PathBuf,
+        #[arg(long, help = "Replace entries that already exist")]
+        overwrite: bool,
+    },
+}
+
+// 开启 console 特性时同时供 tokio-console 连接（默认 127.0.0.1:6669，可用 TOKIO_CONSOLE_BIND 修改，
+// 在独立的线程中运行）；运行日志仍只输出 INFO 及以上。作为 Windows 服务运行时没有控制台，写入事件日志
+fn init_tracing(cli: &Cli) -> Result<()> {
+    #[cfg(windows)]
+    if let Some(Command::Service { command: ServiceCommand::Run }) = &cli.command {
+        use tracing_subscriber::filter::LevelFilter;
+        use tracing_subscriber::prelude::*;
+        tracing_subscriber::registry()
+            .with(LevelFilter::INFO)
+            .with(service::EventLogLayer::new()?)
+            .init();
+        return Ok(());
+    }
+    #[cfg(not(windows))]
+    let _ = cli;
+    #[cfg(feature = "console")]
+    {
+        use tracing_subscriber::filter::LevelFilter;
+        use tracing_subscriber::prelude::*;
+        tracing_subscriber::registry()
+            .with(console_subscriber::spawn())
+            .with(tracing_subscriber::fmt::layer().with_filter(LevelFilter::INFO))
+            .init();
+    }
+    #[cfg(not(feature = "console"))]
     tracing_subscriber::fmt::init();
+    Ok(())
+}
 
-    let https = HttpsConnector::new();
-    let client = hyper::Client::builder().build::<_, hyper::Body>(https);
-    let cache = Arc::new(ProxyCache::new().await?);
+// 运行时按配置创建，因此不使用 #[tokio::main]；缓存维护与 check 子命令不需要运行时
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    init_tracing(&cli)?;
+    // 离线缓存工具不依赖代理能否启动，配置缺失或无效时也可使用
+    if let Some(Command::Cache { dir, command }) = &cli.command {
+        return cache_command(dir, command, cli.config.as_deref());
+    }
 
-    let make_svc = make_service_fn(move |_| {
-        let client = client.clone();
-        let cache = cache.clone();
-        
-        async move {
-            Ok::<_, anyhow::Error>(service_fn(move |req| {
-                server::handle_request(req, cache.clone(), client.clone())
-            }))
+    let mut config = match &cli.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    if let Some(threads) = cli.worker_threads {
+        config.runtime.worker_threads = Some(threads);
+    }
+    if let Some(threads) = cli.max_blocking_threads {
+        config.runtime.max_blocking_threads = Some(threads);
+    }
+    #[cfg(unix)]
+    {
+        config.process.daemon |= cli.daemon;
+        if let Some(path) = cli.pid_file {
+            config.process.pid_file = Some(path);
+        }
+        if let Some(user) = cli.user {
+            config.process.user = Some(user);
         }
-    });
+        if let Some(group) = cli.group {
+            config.process.group = Some(group);
+        }
+    }
+    let warnings = config.validate()?;
+
+    if let Some(Command::Check) = cli.command {
+        return check(&config, &warnings);
+    }
+    for warning in &warnings {
+        tracing::warn!("config: {}", warning);
+    }
+
+    #[cfg(windows)]
+    if let Some(Command::Service { command }) = &cli.command {
+        return match command {
+            ServiceCommand::Install => service::install(cli.config.as_deref()),
+            ServiceCommand::Uninstall => service::uninstall(),
+            ServiceCommand::Run => {
+                // 服务管理器以 System32 为工作目录启动服务；配置中的相对路径（缓存目录、日志文件）
+                // 改为相对配置文件所在目录，没有配置文件时相对可执行文件所在目录
+                let base = match &cli.config {
+                    Some(path) => path.parent().map(Path::to_path_buf),
+                    None => std::env::current_exe()?.parent().map(Path::to_path_buf),
+                };
+                if let Some(base) = base.filter(|base| !base.as_os_str().is_empty()) {
+                    std::env::set_current_dir(base)?;
+                }
+                service::run(move || build_runtime(&config.runtime)?.block_on(serve(config, ())))
+            }
+        };
+    }
+
+    // 升级启动的新进程已经在后台，不再 fork
+    #[cfg(unix)]
+    if config.process.daemon && !upgrade::is_successor() {
+        daemon::daemonize()?;
+    }
+    #[cfg(unix)]
+    let _pid_file = config.process.pid_file.as_deref().map(daemon::PidFile::create).transpose()?;
+    // 开启沙箱后无法读取用户数据库，事先解析要切换的用户与组
+    #[cfg(unix)]
+    let identity = daemon::identity(&config.process)?;
+    #[cfg(not(unix))]
+    #[allow(clippy::let_unit_value)]
+    let identity = ();
+    #[cfg(target_os = "linux")]
+    sandbox::restrict_filesystem(&config, Path::new(CACHE_DIR), identity)?;
+
+    build_runtime(&config.runtime)?.block_on(serve(config, identity))
+}
 
-    let addr = ([127, 0, 0, 1], 3000).into();
-    let server = Server::bind(&addr).serve(make_svc);
+fn build_runtime(config: &RuntimeConfig) -> Result<tokio::runtime::Runtime> {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all().thread_name(&config.thread_name);
+    if let Some(threads) = config.worker_threads {
+        builder.worker_threads(threads);
+    }
+    if let Some(threads) = config.max_blocking_threads {
+        builder.max_blocking_threads(threads);
+    }
+    if let Some(bytes) = config.thread_stack_bytes {
+        builder.thread_stack_size(bytes);
+    }
+    Ok(builder.build()?)
+}
+
+async fn serve(config: Config, identity: Identity) -> Result<()> {
+    // 以 root 启动时绑定端口后立即切换用户，之后创建的缓存目录与日志文件都属于该用户
+    let listeners = services::bind(&config).await?;
+    #[cfg(unix)]
+    daemon::drop_privileges(identity)?;
+    #[cfg(not(unix))]
+    let () = identity;
+    decision_log::init(&config.decision_log).await?;
+    audit::init(&config.admin.audit_log).await?;
+    RECENT_REQUESTS.set_capacity(config.admin.recent_requests);
+    let client = client::build(&config)?;
+    let cache = ProxyCache::builder().config(config.cache.clone());
+    // 升级启动的新进程等旧进程停止写盘、释放缓存目录锁之后才打开缓存
+    #[cfg(unix)]
+    let cache = if upgrade::is_successor() {
+        use rust_proxy_server::cache::CacheLock;
+        use rust_proxy_server::constants::UPGRADE_READY_TIMEOUT_SECONDS;
+        let timeout = std::time::Duration::from_secs(UPGRADE_READY_TIMEOUT_SECONDS);
+        cache.lock(CacheLock::acquire(Path::new(CACHE_DIR), timeout).await?)
+    } else {
+        cache
+    };
+    let cache = Arc::new(cache.build().await?);
+
+    let config = Arc::new(config);
+
+    if config.cache.refresh.enabled {
+        refresh::spawn(cache.clone(), client.clone(), config.clone());
+    }
+    if config.cache.resume.enabled {
+        resume::spawn(cache.clone(), client.clone(), config.clone());
+    }
+
+    services::run(listeners, config.clone(), cache.clone(), client.clone()).await?;
 
-    println!("Proxy server running on http://{}", addr);
+    // 仍在后台进行的下载保存已收到的部分，开启续传时记录下来供重启后继续
+    resume::interrupt(&cache, &client, &config).await?;
 
-    server.await?;
+    // 退出前等待后台写盘完成
+    cache.flush().await?;
+    Ok(())
+}
+
+// check 子命令：配置可以解析且通过校验、缓存目录可写，然后输出合并默认值后的完整配置
+fn check(config: &Config, warnings: &[String]) -> Result<()> {
+    check_cache_dir(Path::new(CACHE_DIR))?;
+    for warning in warnings {
+        eprintln!("warning: {}", warning);
+    }
+    print!("{}", toml::to_string_pretty(&config.redacted())?);
+    eprintln!("configuration OK");
+    Ok(())
+}
+
+// 按 URL 查找条目时才读取配置（其中的缓存键策略），按缓存键查找与其他子命令不需要
+fn find_entry(dir: &Path, target: &str, config: Option<&Path>) -> Result<inspect::EntryInfo> {
+    let config = match config {
+        Some(path) if target.contains("://") => Config::load(path)?,
+        _ => Config::default(),
+    };
+    inspect::find(dir, target, &config)
+}
+
+fn cache_command(dir: &Path, command: &CacheCommand, config: Option<&Path>) -> Result<()> {
+    match command {
+        CacheCommand::Ls => {
+            for entry in inspect::list(dir)? {
+                let (complete, url) = match &entry.meta {
+                    Some(meta) => (
+                        if meta.is_complete { "complete" } else { "partial" },
+                        meta.url.as_deref().unwrap_or("-"),
+                    ),
+                    None => ("no-meta", "-"),
+                };
+                println!("{}  {:>12}  {:<8}  {}", entry.key, entry.size, complete, url);
+            }
+        }
+        CacheCommand::Show { target, content } => {
+            let entry = find_entry(dir, target, config)?;
+            if *content {
+                std::io::stdout().write_all(&entry.read_content(dir)?)?;
+            } else {
+                println!("key: {}", entry.key);
+                println!("size: {}", entry.size);
+                if let Some(chunks) = entry.chunks {
+                    println!("chunks: {}", chunks);
+                }
+                match &entry.meta {
+                    Some(meta) => println!("{}", serde_json::to_string_pretty(meta)?),
+                    None => println!("meta: missing"),
+                }
+            }
+        }
+        CacheCommand::Rm { target } => {
+            let entry = find_entry(dir, target, config)?;
+            let freed = inspect::remove(dir, &entry)?;
+            println!("removed {} ({} bytes)", entry.key, freed);
+        }
+        CacheCommand::Gc { max_bytes, expired } => {
+            let report = inspect::gc(dir, *max_bytes, *expired, &SystemClock)?;
+            println!(
+                "removed {} files, truncated {} entries, freed {} bytes",
+                report.removed, report.truncated, report.freed_bytes
+            );
+        }
+        CacheCommand::Export {
+            file,
+            url_contains,
+            complete_only,
+        } => {
+            let exported = archive::export(dir, file, |entry| {
+                let Some(meta) = &entry.meta else {
+                    return false;
+                };
+                let url_ok = match url_contains {
+                    Some(text) => meta.url.as_deref().is_some_and(|url| url.contains(text.as_str())),
+                    None => true,
+                };
+                url_ok && (meta.is_complete || !complete_only)
+            })?;
+            println!("exported {} entries to {}", exported, file.display());
+        }
+        CacheCommand::Import { file, overwrite } => {
+            let imported = archive::import(dir, file, *overwrite)?;
+            println!("imported {} entries from {}", imported, file.display());
+        }
+    }
     Ok(())
 }
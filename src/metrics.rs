@@ -0,0 +1,713 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use hyper::{Body, Request, Response, StatusCode};
+use serde::Serialize;
+
+use crate::config::{MetricsConfig, MetricsLabel};
+use crate::constants::{METRICS_LATENCY_BUCKETS, METRICS_MAX_LABEL_VALUES};
+use crate::drain::DRAIN;
+
+// 全局计数器，以 Prometheus 文本格式输出
+pub struct Metrics {
+    pub upstream_requests: AtomicU64,
+    pub upstream_connections_opened: AtomicU64,
+    pub upstream_connections_active: AtomicI64,
+    // 客户端请求从收到到响应体发送完毕
+    pub downstream_requests_active: AtomicI64,
+    // 代理、管理与指标端口上接受、当前打开与已关闭的客户端连接，以及 accept 失败次数
+    pub downstream_connections_accepted: AtomicU64,
+    pub downstream_connections_active: AtomicI64,
+    pub downstream_connections_closed: AtomicU64,
+    pub downstream_accept_errors: AtomicU64,
+    pub upstream_connect_errors: AtomicU64,
+    // 与源站的 TLS 握手失败（客户端一侧不终结 TLS）
+    pub upstream_tls_handshake_failures: AtomicU64,
+    pub upstream_requests_shed: AtomicU64,
+    pub client_aborts: AtomicU64,
+    pub peer_hits: AtomicU64,
+    pub upstream_retries: AtomicU64,
+    pub revalidations_collapsed: AtomicU64,
+    pub range_passthrough: AtomicU64,
+    // 读取了内容的完整命中，与只用元数据回答的 HEAD 命中
+    pub cache_content_hits: AtomicU64,
+    pub cache_meta_hits: AtomicU64,
+    pub cache_disk_full: AtomicI64,
+    pub cache_write_errors: AtomicU64,
+    pub cache_writes_skipped: AtomicU64,
+    pub cache_emergency_evictions: AtomicU64,
+    pub cache_quota_evictions: AtomicU64,
+    // 等待磁盘 IO 许可的操作数，以及排队次数与累计等待时间（微秒）
+    pub cache_disk_io_queued: AtomicI64,
+    pub cache_disk_io_waits: AtomicU64,
+    pub cache_disk_io_wait_micros: AtomicU64,
+    pub cache_packed_objects: AtomicI64,
+    pub cache_pack_compactions: AtomicU64,
+    // 后台整理删除的过期条目、校验不一致被删除的条目，以及因负载高推迟的次数
+    pub cache_expired_swept: AtomicU64,
+    pub cache_corrupt_entries: AtomicU64,
+    pub cache_janitor_deferred: AtomicU64,
+    // 代理压缩后同时保存的 identity / gzip 版本：次数与两种版本累计写入的字节数
+    pub cache_variants_stored: AtomicU64,
+    pub cache_variant_identity_bytes: AtomicU64,
+    pub cache_variant_gzip_bytes: AtomicU64,
+    // (源站, 失败分类) -> 次数
+    pub upstream_errors: Mutex<BTreeMap<(String, &'static str), u64>>,
+    pub cache_traffic: Mutex<TrafficTable>,
+}
+
+// (路由名或源站 host, 内容类别, 缓存结果) -> 请求数与响应字节数
+pub struct TrafficTable {
+    // 按路由名还是源站 host 区分，以及取值个数的上限
+    label: MetricsLabel,
+    max_labels: usize,
+    labels: BTreeSet<String>,
+    entries: BTreeMap<(String, &'static str, &'static str), Traffic>,
+    // 标签 -> 从收到请求到发出响应头的耗时
+    latency: BTreeMap<String, Histogram>,
+}
+
+// 延迟直方图，桶的上界为 METRICS_LATENCY_BUCKETS，最后一个桶是 +Inf
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Histogram {
+    buckets: [u64; METRICS_LATENCY_BUCKETS.len() + 1],
+    count: u64,
+    sum_secs: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        let bucket = METRICS_LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| secs <= bound)
+            .unwrap_or(METRICS_LATENCY_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum_secs += secs;
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Traffic {
+    pub requests: u64,
+    pub bytes: u64,
+}
+
+// 按 host 或内容类别汇总的命中情况
+#[derive(Debug, Default, Serialize)]
+pub struct TrafficSummary {
+    // 缓存结果（hit / partial / miss / bypass 等）-> 请求数与字节数
+    pub results: BTreeMap<&'static str, Traffic>,
+    pub hit_ratio: f64,
+    pub byte_hit_ratio: f64,
+    // 平均响应耗时（毫秒），只有按路由或 host 的汇总有
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_latency_ms: Option<f64>,
+}
+
+// 按 metrics.label 的设置，by_host 与 by_route 只有一个有内容；by_host 总是输出，与之前的格式兼容
+#[derive(Debug, Default, Serialize)]
+pub struct TrafficStats {
+    pub by_host: BTreeMap<String, TrafficSummary>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub by_route: BTreeMap<String, TrafficSummary>,
+    pub by_mime: BTreeMap<&'static str, TrafficSummary>,
+    pub variants: VariantStats,
+}
+
+// 代理压缩同时保存两个版本的开销：gzip 版本额外占用的缓存空间相对 identity 版本的比例
+#[derive(Debug, Default, Serialize)]
+pub struct VariantStats {
+    pub stored: u64,
+    pub identity_bytes: u64,
+    pub gzip_bytes: u64,
+    pub extra_storage_ratio: f64,
+}
+
+pub static METRICS: Metrics = Metrics {
+    upstream_requests: AtomicU64::new(0),
+    upstream_connections_opened: AtomicU64::new(0),
+    upstream_connections_active: AtomicI64::new(0),
+    downstream_requests_active: AtomicI64::new(0),
+    downstream_connections_accepted: AtomicU64::new(0),
+    downstream_connections_active: AtomicI64::new(0),
+    downstream_connections_closed: AtomicU64::new(0),
+    downstream_accept_errors: AtomicU64::new(0),
+    upstream_connect_errors: AtomicU64::new(0),
+    upstream_tls_handshake_failures: AtomicU64::new(0),
+    upstream_requests_shed: AtomicU64::new(0),
+    client_aborts: AtomicU64::new(0),
+    peer_hits: AtomicU64::new(0),
+    upstream_retries: AtomicU64::new(0),
+    revalidations_collapsed: AtomicU64::new(0),
+    range_passthrough: AtomicU64::new(0),
+    cache_content_hits: AtomicU64::new(0),
+    cache_meta_hits: AtomicU64::new(0),
+    cache_disk_full: AtomicI64::new(0),
+    cache_write_errors: AtomicU64::new(0),
+    cache_writes_skipped: AtomicU64::new(0),
+    cache_emergency_evictions: AtomicU64::new(0),
+    cache_quota_evictions: AtomicU64::new(0),
+    cache_disk_io_queued: AtomicI64::new(0),
+    cache_disk_io_waits: AtomicU64::new(0),
+    cache_disk_io_wait_micros: AtomicU64::new(0),
+    cache_packed_objects: AtomicI64::new(0),
+    cache_pack_compactions: AtomicU64::new(0),
+    cache_expired_swept: AtomicU64::new(0),
+    cache_corrupt_entries: AtomicU64::new(0),
+    cache_janitor_deferred: AtomicU64::new(0),
+    cache_variants_stored: AtomicU64::new(0),
+    cache_variant_identity_bytes: AtomicU64::new(0),
+    cache_variant_gzip_bytes: AtomicU64::new(0),
+    upstream_errors: Mutex::new(BTreeMap::new()),
+    cache_traffic: Mutex::new(TrafficTable {
+        label: MetricsLabel::Host,
+        max_labels: METRICS_MAX_LABEL_VALUES,
+        labels: BTreeSet::new(),
+        entries: BTreeMap::new(),
+        latency: BTreeMap::new(),
+    }),
+};
+
+impl Metrics {
+    // 启动时按配置设置流量与延迟指标的标签；已记录的数据清空
+    pub fn configure(&self, config: &MetricsConfig) {
+        let mut traffic = self.cache_traffic.lock().unwrap();
+        traffic.label = config.label;
+        traffic.max_labels = config.max_label_values;
+        traffic.labels.clear();
+        traffic.entries.clear();
+        traffic.latency.clear();
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        counter(
+            &mut out,
+            "proxy_upstream_requests_total",
+            "Requests sent to upstream origins",
+            self.upstream_requests.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_upstream_connections_opened_total",
+            "New upstream connections established by the pool",
+            self.upstream_connections_opened.load(Ordering::Relaxed),
+        );
+        gauge(
+            &mut out,
+            "proxy_upstream_connections_active",
+            "Upstream connections currently open (idle or in use)",
+            self.upstream_connections_active.load(Ordering::Relaxed),
+        );
+        gauge(
+            &mut out,
+            "proxy_downstream_requests_active",
+            "Client requests being handled, until their response body is sent",
+            self.downstream_requests_active.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_downstream_connections_accepted_total",
+            "Client connections accepted on all listeners",
+            self.downstream_connections_accepted.load(Ordering::Relaxed),
+        );
+        gauge(
+            &mut out,
+            "proxy_downstream_connections_active",
+            "Client connections currently open",
+            self.downstream_connections_active.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_downstream_connections_closed_total",
+            "Client connections closed",
+            self.downstream_connections_closed.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_downstream_accept_errors_total",
+            "Failed accept calls, for example when file descriptors run out",
+            self.downstream_accept_errors.load(Ordering::Relaxed),
+        );
+        gauge(
+            &mut out,
+            "proxy_draining",
+            "Whether the instance is draining before a restart",
+            DRAIN.is_draining() as i64,
+        );
+        counter(
+            &mut out,
+            "proxy_upstream_connect_errors_total",
+            "Failed upstream connection attempts",
+            self.upstream_connect_errors.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_upstream_tls_handshake_failures_total",
+            "Failed TLS handshakes with upstream origins",
+            self.upstream_tls_handshake_failures.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_upstream_requests_shed_total",
+            "Upstream requests rejected because the per-host concurrency limit was saturated",
+            self.upstream_requests_shed.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_client_aborts_total",
+            "Requests abandoned by the client before a response was sent",
+            self.client_aborts.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_peer_hits_total",
+            "Local cache misses served from a sibling proxy",
+            self.peer_hits.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_upstream_retries_total",
+            "Upstream request attempts that were retries of a failed attempt",
+            self.upstream_retries.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_revalidations_collapsed_total",
+            "Stale-entry revalidations answered by another request's conditional request",
+            self.revalidations_collapsed.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_range_passthrough_total",
+            "Range requests for uncacheable objects forwarded to the origin as-is",
+            self.range_passthrough.load(Ordering::Relaxed),
+        );
+        let name = "proxy_cache_hits_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Complete cache hits by what was read: content, or metadata only for HEAD requests\n# TYPE {} counter",
+            name, name
+        );
+        let _ = writeln!(out, "{}{{source=\"content\"}} {}", name, self.cache_content_hits.load(Ordering::Relaxed));
+        let _ = writeln!(out, "{}{{source=\"metadata\"}} {}", name, self.cache_meta_hits.load(Ordering::Relaxed));
+        gauge(
+            &mut out,
+            "proxy_cache_disk_full",
+            "1 while disk writes are suspended because the cache disk is full",
+            self.cache_disk_full.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_cache_write_errors_total",
+            "Cache entries that failed to be written to disk",
+            self.cache_write_errors.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_cache_writes_skipped_total",
+            "Cache entries kept in memory only because the cache disk was full",
+            self.cache_writes_skipped.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_cache_emergency_evictions_total",
+            "Entries removed from disk to recover from a full cache disk",
+            self.cache_emergency_evictions.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_cache_quota_evictions_total",
+            "Entries removed from disk to keep the cache within max_disk_bytes",
+            self.cache_quota_evictions.load(Ordering::Relaxed),
+        );
+        gauge(
+            &mut out,
+            "proxy_cache_disk_io_queue_depth",
+            "Cache disk operations waiting for the disk IO concurrency limit",
+            self.cache_disk_io_queued.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_cache_disk_io_waits_total",
+            "Cache disk operations that had to wait for the disk IO concurrency limit",
+            self.cache_disk_io_waits.load(Ordering::Relaxed),
+        );
+        let name = "proxy_cache_disk_io_wait_seconds_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Time cache disk operations spent waiting for the disk IO concurrency limit\n# TYPE {} counter\n{} {}",
+            name,
+            name,
+            name,
+            self.cache_disk_io_wait_micros.load(Ordering::Relaxed) as f64 / 1e6
+        );
+        gauge(
+            &mut out,
+            "proxy_cache_packed_objects",
+            "Small cache objects stored in pack segment files",
+            self.cache_packed_objects.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_cache_pack_compactions_total",
+            "Pack segment files rewritten to reclaim space from stale records",
+            self.cache_pack_compactions.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_cache_expired_swept_total",
+            "Expired cache entries removed by the background janitor",
+            self.cache_expired_swept.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_cache_corrupt_entries_total",
+            "Cache entries discarded by the background integrity scan",
+            self.cache_corrupt_entries.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_cache_janitor_deferred_total",
+            "Background janitor tasks postponed because of high load",
+            self.cache_janitor_deferred.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "proxy_cache_variants_stored_total",
+            "Responses compressed by the proxy and stored as both identity and gzip variants",
+            self.cache_variants_stored.load(Ordering::Relaxed),
+        );
+        let name = "proxy_cache_variant_bytes_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Bytes written to the cache for proxy-compressed responses by variant\n# TYPE {} counter",
+            name, name
+        );
+        let _ = writeln!(out, "{}{{encoding=\"identity\"}} {}", name, self.cache_variant_identity_bytes.load(Ordering::Relaxed));
+        let _ = writeln!(out, "{}{{encoding=\"gzip\"}} {}", name, self.cache_variant_gzip_bytes.load(Ordering::Relaxed));
+        runtime_metrics(&mut out);
+        let name = "proxy_upstream_errors_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Upstream failures by origin and kind (dns, connect, tls, timeout, reset, 5xx, other)\n# TYPE {} counter",
+            name, name
+        );
+        for ((origin, kind), value) in self.upstream_errors.lock().unwrap().iter() {
+            let _ = writeln!(out, "{}{{origin=\"{}\",kind=\"{}\"}} {}", name, escape_label(origin), kind, value);
+        }
+        let traffic = self.cache_traffic.lock().unwrap();
+        let label = match traffic.label {
+            MetricsLabel::Route => "route",
+            MetricsLabel::Host => "host",
+        };
+        for (name, help, value) in [
+            (
+                "proxy_cache_requests_total",
+                "Proxied requests by route (or origin host), content type family and cache result",
+                (|t: &Traffic| t.requests) as fn(&Traffic) -> u64,
+            ),
+            (
+                "proxy_cache_response_bytes_total",
+                "Response body bytes by route (or origin host), content type family and cache result",
+                |t: &Traffic| t.bytes,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+            for ((key, mime, result), t) in traffic.entries.iter() {
+                let _ = writeln!(
+                    out,
+                    "{}{{{}=\"{}\",mime=\"{}\",result=\"{}\"}} {}",
+                    name, label, escape_label(key), mime, result, value(t)
+                );
+            }
+        }
+        let name = "proxy_cache_hit_ratio";
+        let _ = writeln!(
+            out,
+            "# HELP {} Share of requests answered entirely from cache, by route (or origin host)\n# TYPE {} gauge",
+            name, name
+        );
+        for (key, summary) in traffic.summaries() {
+            let _ = writeln!(out, "{}{{{}=\"{}\"}} {}", name, label, escape_label(&key), summary.hit_ratio);
+        }
+        let name = "proxy_request_duration_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {} Time until response headers were sent, by route (or origin host)\n# TYPE {} histogram",
+            name, name
+        );
+        for (key, histogram) in traffic.latency.iter() {
+            let key = escape_label(key);
+            let mut cumulative = 0;
+            for (i, count) in histogram.buckets.iter().enumerate() {
+                cumulative += count;
+                let le = METRICS_LATENCY_BUCKETS
+                    .get(i)
+                    .map(|bound| bound.to_string())
+                    .unwrap_or_else(|| "+Inf".to_string());
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{{}=\"{}\",le=\"{}\"}} {}",
+                    name, label, key, le, cumulative
+                );
+            }
+            let _ = writeln!(out, "{}_sum{{{}=\"{}\"}} {}", name, label, key, histogram.sum_secs);
+            let _ = writeln!(out, "{}_count{{{}=\"{}\"}} {}", name, label, key, histogram.count);
+        }
+        out
+    }
+
+    // 记录一次代理请求；标签的取值个数超过上限后新出现的值归入 "other"，避免序列数无限增长
+    pub fn record_traffic(
+        &self,
+        label: &str,
+        content_type: &str,
+        result: &'static str,
+        bytes: u64,
+        latency: Duration,
+    ) {
+        let mut traffic = self.cache_traffic.lock().unwrap();
+        let mut label = label.to_string();
+        if !traffic.labels.contains(&label) {
+            if traffic.labels.len() < traffic.max_labels {
+                traffic.labels.insert(label.clone());
+            } else {
+                label = "other".to_string();
+            }
+        }
+        let entry = traffic
+            .entries
+            .entry((label.clone(), mime_family(content_type), result))
+            .or_default();
+        entry.requests += 1;
+        entry.bytes += bytes;
+        traffic.latency.entry(label).or_default().observe(latency.as_secs_f64());
+    }
+
+    // 管理接口 /stats 使用的汇总
+    pub fn traffic_stats(&self) -> TrafficStats {
+        let traffic = self.cache_traffic.lock().unwrap();
+        let mut stats = TrafficStats::default();
+        for ((_, mime, result), t) in traffic.entries.iter() {
+            let entry = stats.by_mime.entry(mime).or_default().results.entry(result).or_default();
+            entry.requests += t.requests;
+            entry.bytes += t.bytes;
+        }
+        for summary in stats.by_mime.values_mut() {
+            summary.update_ratios();
+        }
+        let by_label = match traffic.label {
+            MetricsLabel::Route => &mut stats.by_route,
+            MetricsLabel::Host => &mut stats.by_host,
+        };
+        *by_label = traffic.summaries();
+        let identity_bytes = self.cache_variant_identity_bytes.load(Ordering::Relaxed);
+        let gzip_bytes = self.cache_variant_gzip_bytes.load(Ordering::Relaxed);
+        stats.variants = VariantStats {
+            stored: self.cache_variants_stored.load(Ordering::Relaxed),
+            identity_bytes,
+            gzip_bytes,
+            extra_storage_ratio: ratio(gzip_bytes, identity_bytes),
+        };
+        stats
+    }
+
+    pub fn upstream_error(&self, origin: &str, kind: &'static str) {
+        *self
+            .upstream_errors
+            .lock()
+            .unwrap()
+            .entry((origin.to_string(), kind))
+            .or_default() += 1;
+    }
+}
+
+impl TrafficTable {
+    // 每个标签值的命中情况与平均耗时
+    fn summaries(&self) -> BTreeMap<String, TrafficSummary> {
+        let mut summaries: BTreeMap<String, TrafficSummary> = BTreeMap::new();
+        for ((label, _, result), t) in self.entries.iter() {
+            let entry = summaries.entry(label.clone()).or_default().results.entry(result).or_default();
+            entry.requests += t.requests;
+            entry.bytes += t.bytes;
+        }
+        for (label, summary) in summaries.iter_mut() {
+            summary.update_ratios();
+            summary.avg_latency_ms = self
+                .latency
+                .get(label)
+                .filter(|histogram| histogram.count > 0)
+                .map(|histogram| histogram.sum_secs * 1000.0 / histogram.count as f64);
+        }
+        summaries
+    }
+}
+
+impl TrafficSummary {
+    // 只有完整命中算作命中，部分命中仍需回源
+    fn update_ratios(&mut self) {
+        let total = self.results.values().fold(Traffic::default(), |acc, t| Traffic {
+            requests: acc.requests + t.requests,
+            bytes: acc.bytes + t.bytes,
+        });
+        let hit = self.results.get("hit").copied().unwrap_or_default();
+        self.hit_ratio = ratio(hit.requests, total.requests);
+        self.byte_hit_ratio = ratio(hit.bytes, total.bytes);
+    }
+}
+
+fn ratio(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 / total as f64
+    }
+}
+
+// 按 Content-Type 归类，区分视频与接口等流量
+pub fn mime_family(content_type: &str) -> &'static str {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let (kind, subtype) = mime.split_once('/').unwrap_or((mime.as_str(), ""));
+    match kind {
+        "video" => "video",
+        "audio" => "audio",
+        "image" => "image",
+        _ if subtype.contains("mpegurl") || subtype == "dash+xml" => "video",
+        _ if subtype == "json" || subtype.ends_with("+json") || subtype.ends_with("xml") => "api",
+        "text" if subtype == "html" || subtype == "css" => "web",
+        _ if subtype == "javascript" => "web",
+        "text" => "text",
+        _ => "other",
+    }
+}
+
+// tokio 运行时的状态，用于排查磁盘 IO 或锁竞争阻塞工作线程造成的停顿；不在运行时中调用时不输出
+fn runtime_metrics(out: &mut String) {
+    let Ok(handle) = tokio::runtime::Handle::try_current() else {
+        return;
+    };
+    let runtime = handle.metrics();
+    gauge(out, "proxy_runtime_workers", "Worker threads of the async runtime", runtime.num_workers() as i64);
+    gauge(out, "proxy_runtime_alive_tasks", "Tasks that have been spawned and not yet completed", runtime.num_alive_tasks() as i64);
+    gauge(
+        out,
+        "proxy_runtime_global_queue_depth",
+        "Tasks waiting in the runtime's shared run queue",
+        runtime.global_queue_depth() as i64,
+    );
+    #[cfg(tokio_unstable)]
+    unstable_runtime_metrics(out, &runtime);
+}
+
+// 上次抓取时各工作线程的 poll 次数与 park/unpark 次数
+#[cfg(tokio_unstable)]
+static WORKER_PROGRESS: Mutex<Vec<(u64, u64)>> = Mutex::new(Vec::new());
+
+// 只有以 --cfg tokio_unstable 编译时才有的指标：阻塞线程池与各工作线程的队列、忙碌时间。
+// 没有 park 的工作线程自上次抓取以来 poll 次数没有变化，说明一直卡在同一次 poll 中（同步 IO 或锁）
+#[cfg(tokio_unstable)]
+fn unstable_runtime_metrics(out: &mut String, runtime: &tokio::runtime::RuntimeMetrics) {
+    gauge(
+        out,
+        "proxy_runtime_blocking_threads",
+        "Threads in the blocking pool (spawn_blocking, file IO)",
+        runtime.num_blocking_threads() as i64,
+    );
+    gauge(
+        out,
+        "proxy_runtime_idle_blocking_threads",
+        "Idle threads in the blocking pool",
+        runtime.num_idle_blocking_threads() as i64,
+    );
+    gauge(
+        out,
+        "proxy_runtime_blocking_queue_depth",
+        "Blocking tasks waiting for a thread in the blocking pool",
+        runtime.blocking_queue_depth() as i64,
+    );
+    counter(
+        out,
+        "proxy_runtime_budget_forced_yields_total",
+        "Times a task was forced to yield after exhausting its budget",
+        runtime.budget_forced_yield_count(),
+    );
+
+    let workers = runtime.num_workers();
+    let progress: Vec<(u64, u64)> = (0..workers)
+        .map(|worker| (runtime.worker_poll_count(worker), runtime.worker_park_unpark_count(worker)))
+        .collect();
+    let previous = std::mem::replace(&mut *WORKER_PROGRESS.lock().unwrap(), progress.clone());
+    let blocked = progress
+        .iter()
+        .zip(previous.iter())
+        // park/unpark 次数为奇数时工作线程处于 park 状态
+        .filter(|(now, before)| now == before && now.1 % 2 == 0)
+        .count();
+    gauge(
+        out,
+        "proxy_runtime_workers_blocked",
+        "Worker threads that are not parked but have not completed a poll since the previous scrape",
+        blocked as i64,
+    );
+
+    let name = "proxy_runtime_worker_local_queue_depth";
+    let _ = writeln!(out, "# HELP {} Tasks waiting in each worker's local run queue\n# TYPE {} gauge", name, name);
+    for worker in 0..workers {
+        let _ = writeln!(out, "{}{{worker=\"{}\"}} {}", name, worker, runtime.worker_local_queue_depth(worker));
+    }
+    let name = "proxy_runtime_worker_polls_total";
+    let _ = writeln!(out, "# HELP {} Tasks polled by each worker\n# TYPE {} counter", name, name);
+    for (worker, (polls, _)) in progress.iter().enumerate() {
+        let _ = writeln!(out, "{}{{worker=\"{}\"}} {}", name, worker, polls);
+    }
+    let name = "proxy_runtime_worker_busy_seconds_total";
+    let _ = writeln!(out, "# HELP {} Time each worker spent polling tasks\n# TYPE {} counter", name, name);
+    for worker in 0..workers {
+        let busy = runtime.worker_total_busy_duration(worker).as_secs_f64();
+        let _ = writeln!(out, "{}{{worker=\"{}\"}} {}", name, worker, busy);
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, value);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: i64) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge\n{} {}", name, help, name, name, value);
+}
+
+// 标签值中的反斜杠、双引号与换行按 Prometheus 文本格式转义（路由名来自配置，可以包含任意字符）
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// 指标与健康检查（metrics.listen，或未单独监听时代理端口上的 origin-form 请求）
+pub async fn handle_metrics_request(req: Request<Body>) -> Result<Response<Body>> {
+    match req.uri().path() {
+        "/metrics" => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(hyper::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(Body::from(METRICS.render()))?),
+        // 排空期间让负载均衡器把实例摘除
+        "/health" if DRAIN.is_draining() => Ok(Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body(Body::from("draining"))?),
+        "/health" => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .body(Body::from("ok"))?),
+        _ => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())?),
+    }
+}
@@ -0,0 +1,427 @@
+mod oauth2;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+use hmac::{Hmac, Mac};
+use hyper::body::HttpBody;
+use hyper::client::connect::Connect;
+use hyper::header::{HeaderName, HeaderValue, AUTHORIZATION, HOST, RANGE};
+use hyper::{Body, Client, Request, Uri};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::{Config, RouteConfig};
+use crate::constants::OAUTH_REFRESH_BEFORE_SECS;
+use crate::upstream::origin_of;
+
+pub use oauth2::TokenManager;
+
+// 回源请求的签名方式
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OriginAuthScheme {
+    // AWS Signature Version 4（S3 及兼容的对象存储）
+    Sigv4,
+    // <header>: [<key_id>:]hex(HMAC-SHA256(secret, "<方法>\n<host>\n<path?query>\n<时间>"))，
+    // <date_header>: <unix 时间>
+    Hmac,
+    // OAuth2 client credentials：从 token_url 获取访问令牌，以 Authorization: Bearer 发送
+    Oauth2,
+}
+
+// 路由级别的回源签名：代理持有凭据访问私有存储桶，内部客户端使用不带签名的 URL
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OriginAuthConfig {
+    pub scheme: OriginAuthScheme,
+    // sigv4 的 access key id；hmac 写在签名之前的密钥 id，可为空；oauth2 的 client id
+    pub key_id: String,
+    // sigv4 的 secret access key；hmac 的密钥；oauth2 的 client secret
+    pub secret: String,
+    // 临时凭据的 x-amz-security-token
+    pub session_token: Option<String>,
+    pub region: String,
+    pub service: String,
+    // hmac 签名与时间戳所在的请求头
+    pub header: String,
+    pub date_header: String,
+    // oauth2 的令牌端点与可选的 scope / audience
+    pub token_url: String,
+    pub scope: Option<String>,
+    pub audience: Option<String>,
+    // 访问令牌剩余有效期不足该值（秒）时提前刷新，最多提前有效期的一半
+    pub refresh_before_secs: u64,
+}
+
+impl Default for OriginAuthConfig {
+    fn default() -> Self {
+        OriginAuthConfig {
+            scheme: OriginAuthScheme::Sigv4,
+            key_id: String::new(),
+            secret: String::new(),
+            session_token: None,
+            region: "us-east-1".to_string(),
+            service: "s3".to_string(),
+            header: "Authorization".to_string(),
+            date_header: "X-Date".to_string(),
+            token_url: String::new(),
+            scope: None,
+            audience: None,
+            refresh_before_secs: OAUTH_REFRESH_BEFORE_SECS,
+        }
+    }
+}
+
+const AMZ_DATE: HeaderName = HeaderName::from_static("x-amz-date");
+const AMZ_CONTENT_SHA256: HeaderName = HeaderName::from_static("x-amz-content-sha256");
+const AMZ_SECURITY_TOKEN: HeaderName = HeaderName::from_static("x-amz-security-token");
+
+impl OriginAuthConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.secret.is_empty() {
+            bail!("origin_auth.secret must be set");
+        }
+        match self.scheme {
+            OriginAuthScheme::Sigv4 => {
+                if self.key_id.is_empty() {
+                    bail!("origin_auth.key_id must be set for sigv4");
+                }
+                if self.region.is_empty() || self.service.is_empty() {
+                    bail!("origin_auth.region and origin_auth.service must be set for sigv4");
+                }
+            }
+            OriginAuthScheme::Hmac => {
+                for header in [&self.header, &self.date_header] {
+                    if HeaderName::from_bytes(header.as_bytes()).is_err() {
+                        bail!("origin_auth: invalid header name {:?}", header);
+                    }
+                }
+            }
+            OriginAuthScheme::Oauth2 => {
+                if self.key_id.is_empty() {
+                    bail!("origin_auth.key_id must be set to the oauth2 client id");
+                }
+                let valid = self
+                    .token_url
+                    .parse::<Uri>()
+                    .is_ok_and(|uri| uri.scheme().is_some() && uri.host().is_some());
+                if !valid {
+                    bail!("origin_auth.token_url must be an absolute URL for oauth2");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn redact(&mut self) {
+        if !self.secret.is_empty() {
+            self.secret = "<redacted>".to_string();
+        }
+        if self.session_token.is_some() {
+            self.session_token = Some("<redacted>".to_string());
+        }
+    }
+
+    // 为即将发往源站的请求签名；客户端带来的同名凭据头被覆盖。
+    // oauth2 的令牌需要异步获取，由 OriginSigners 写入
+    pub fn sign(&self, req: &mut Request<Body>, now: SystemTime) {
+        let secs = now.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let Some(host) = request_host(req) else {
+            return;
+        };
+        // 签名覆盖 Host，显式写出，避免与 hyper 按 URL 补上的值不一致
+        let Ok(host_value) = HeaderValue::from_str(&host) else {
+            return;
+        };
+        req.headers_mut().insert(HOST, host_value);
+        match self.scheme {
+            OriginAuthScheme::Sigv4 => self.sign_sigv4(req, &host, secs),
+            OriginAuthScheme::Hmac => self.sign_hmac(req, &host, secs),
+            OriginAuthScheme::Oauth2 => {}
+        }
+    }
+
+    fn sign_sigv4(&self, req: &mut Request<Body>, host: &str, secs: u64) {
+        let amz_date = amz_date(secs);
+        let date = &amz_date[..8];
+        // 没有请求体时签名空串的哈希，否则不签请求体（流式转发时拿不到完整内容）
+        let payload_hash = if req.body().is_end_stream() {
+            hex::encode(Sha256::digest(b""))
+        } else {
+            "UNSIGNED-PAYLOAD".to_string()
+        };
+        let headers = req.headers_mut();
+        headers.insert(AMZ_DATE, header_value(&amz_date));
+        headers.insert(AMZ_CONTENT_SHA256, header_value(&payload_hash));
+        if let Some(token) = &self.session_token {
+            headers.insert(AMZ_SECURITY_TOKEN, header_value(token));
+        }
+
+        // 按名称排序的签名头；Range 由续传与分段请求设置，一并签名
+        let mut signed = vec![("host", host.to_string())];
+        if let Some(range) = req.headers().get(RANGE).and_then(|v| v.to_str().ok()) {
+            signed.push(("range", range.trim().to_string()));
+        }
+        signed.push(("x-amz-content-sha256", payload_hash.clone()));
+        signed.push(("x-amz-date", amz_date.clone()));
+        if let Some(token) = &self.session_token {
+            signed.push(("x-amz-security-token", token.clone()));
+        }
+        let canonical_headers: String = signed
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value))
+            .collect();
+        let signed_headers = signed
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            req.method(),
+            canonical_path(req.uri().path(), self.service != "s3"),
+            canonical_query(req.uri().query().unwrap_or("")),
+            canonical_headers,
+            signed_headers,
+            payload_hash
+        );
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, self.service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let mut key = hmac_sha256(format!("AWS4{}", self.secret).as_bytes(), date.as_bytes());
+        for part in [self.region.as_str(), self.service.as_str(), "aws4_request"] {
+            key = hmac_sha256(&key, part.as_bytes());
+        }
+        let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.key_id, scope, signed_headers, signature
+        );
+        req.headers_mut().insert(AUTHORIZATION, header_value(&authorization));
+    }
+
+    fn sign_hmac(&self, req: &mut Request<Body>, host: &str, secs: u64) {
+        let (Ok(header), Ok(date_header)) = (
+            HeaderName::from_bytes(self.header.as_bytes()),
+            HeaderName::from_bytes(self.date_header.as_bytes()),
+        ) else {
+            return;
+        };
+        let path = req.uri().path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+        let message = format!("{}\n{}\n{}\n{}", req.method(), host, path, secs);
+        let signature = hex::encode(hmac_sha256(self.secret.as_bytes(), message.as_bytes()));
+        let value = if self.key_id.is_empty() {
+            signature
+        } else {
+            format!("{}:{}", self.key_id, signature)
+        };
+        req.headers_mut().insert(header, header_value(&value));
+        req.headers_mut().insert(date_header, header_value(&secs.to_string()));
+    }
+}
+
+// 配置了 origin_auth 的路由，按请求实际发往的地址选择签名方式
+pub struct OriginSigners {
+    routes: Vec<RouteConfig>,
+    // 与 routes 一一对应，oauth2 路由缓存的访问令牌
+    tokens: Vec<Option<TokenManager>>,
+}
+
+impl OriginSigners {
+    // 没有路由配置签名时返回 None
+    pub fn new(config: &Config) -> Option<Self> {
+        config
+            .routes
+            .iter()
+            .any(|route| route.origin_auth.is_some())
+            .then(|| OriginSigners {
+                routes: config.routes.clone(),
+                tokens: config
+                    .routes
+                    .iter()
+                    .map(|route| {
+                        route
+                            .origin_auth
+                            .as_ref()
+                            .filter(|auth| auth.scheme == OriginAuthScheme::Oauth2)
+                            .map(|auth| TokenManager::new(auth.clone()))
+                    })
+                    .collect(),
+            })
+    }
+
+    // 令牌端点不可用时返回错误，请求不会不带凭据地发往源站
+    pub async fn sign<C>(&self, req: &mut Request<Body>, now: SystemTime, client: &Client<C>) -> Result<()>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let Some(i) = self.route(req.uri()) else {
+            return Ok(());
+        };
+        match (&self.tokens[i], &self.routes[i].origin_auth) {
+            (Some(tokens), _) => {
+                let secs = now.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+                let token = tokens.token(client, secs).await?;
+                req.headers_mut()
+                    .insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", token))?);
+            }
+            (None, Some(auth)) => auth.sign(req, now),
+            (None, None) => {}
+        }
+        Ok(())
+    }
+
+    // 源站返回 401 时丢弃该路由缓存的访问令牌；authorization 为被拒绝的请求实际携带的值
+    pub async fn rejected(&self, uri: &Uri, authorization: Option<&HeaderValue>) {
+        let Some(tokens) = self.route(uri).and_then(|i| self.tokens[i].as_ref()) else {
+            return;
+        };
+        let rejected = authorization
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        if let Some(rejected) = rejected {
+            tokens.invalidate(rejected).await;
+        }
+    }
+
+    // 与 Config::route 相同按顺序取第一个匹配的路由；请求可能已被改写到路由的
+    // 某个源站或 connect_to 地址，这些地址同样属于该路由
+    fn route(&self, uri: &Uri) -> Option<usize> {
+        let origin = origin_of(uri);
+        let authority = uri.authority().map(|a| a.as_str()).unwrap_or_default();
+        self.routes.iter().position(|route| {
+            let path_ok = route
+                .path_prefix
+                .as_ref()
+                .is_none_or(|prefix| uri.path().starts_with(prefix.as_str()));
+            let rewritten = route.origins.iter().any(|o| o.trim_end_matches('/') == origin)
+                || route.connect_to.is_some_and(|addr| addr.to_string() == authority);
+            route.matches(uri) || (path_ok && rewritten)
+        })
+    }
+}
+
+// 发往源站的 Host：connect_to 改写过地址时为请求头中的域名，否则与 hyper 一样取自 URL
+fn request_host(req: &Request<Body>) -> Option<String> {
+    if let Some(host) = req.headers().get(HOST).and_then(|v| v.to_str().ok()) {
+        return Some(host.to_string());
+    }
+    let host = req.uri().host()?;
+    let default_port = match req.uri().scheme_str() {
+        Some("https") => 443,
+        _ => 80,
+    };
+    Some(match req.uri().port_u16() {
+        Some(port) if port != default_port => format!("{}:{}", host, port),
+        _ => host.to_string(),
+    })
+}
+
+fn header_value(value: &str) -> HeaderValue {
+    HeaderValue::from_str(value).unwrap_or_else(|_| HeaderValue::from_static(""))
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// YYYYMMDD'T'HHMMSS'Z'（UTC）
+fn amz_date(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // Howard Hinnant 的 civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+// 每段先解码再按 SigV4 的规则编码；S3 以外的服务对路径再编码一次
+fn canonical_path(path: &str, double_encode: bool) -> String {
+    if path.is_empty() {
+        return "/".to_string();
+    }
+    path.split('/')
+        .map(|segment| {
+            let encoded = encode(&decode(segment));
+            if double_encode {
+                encode(encoded.as_bytes())
+            } else {
+                encoded
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn canonical_query(query: &str) -> String {
+    let mut pairs: Vec<(String, String)> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (encode(&decode(name)), encode(&decode(value)))
+        })
+        .collect();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// 除 A-Z a-z 0-9 - _ . ~ 外全部编码为 %XX（大写）
+fn encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    for &b in bytes {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+fn decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper::client::connect::Connect;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Client, Request};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use super::{encode, OriginAuthConfig};
+use crate::constants::{OAUTH_FAILURE_BACKOFF_SECS, OAUTH_TOKEN_TIMEOUT_SECS};
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+#[derive(Clone)]
+struct Token {
+    value: String,
+    // 开始刷新的 Unix 时间（秒）；令牌端点没有给出有效期时为 None，直到源站返回 401 才换新
+    refresh_at: Option<u64>,
+}
+
+#[derive(Default)]
+struct State {
+    token: Option<Token>,
+    // 最近一次获取失败的时间与原因，退避期间的请求直接失败，不再排队访问令牌端点
+    failed: Option<(u64, String)>,
+}
+
+// client credentials 模式的访问令牌：缓存到临近过期再刷新，
+// 同一时刻只有一个请求去令牌端点，其他请求等待它的结果
+pub struct TokenManager {
+    config: OriginAuthConfig,
+    state: Mutex<State>,
+}
+
+impl TokenManager {
+    pub fn new(config: OriginAuthConfig) -> Self {
+        TokenManager {
+            config,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    // 当前可用的访问令牌，到了刷新时间先刷新
+    pub async fn token<C>(&self, client: &Client<C>, now: u64) -> Result<String>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let mut state = self.state.lock().await;
+        let fresh = state
+            .token
+            .as_ref()
+            .is_some_and(|t| t.refresh_at.is_none_or(|refresh_at| now < refresh_at));
+        if !fresh {
+            // 令牌端点刚失败过：排在后面的请求不再依次等待各自的超时
+            if let Some((at, error)) = &state.failed {
+                if now < at + OAUTH_FAILURE_BACKOFF_SECS {
+                    bail!("{}", error);
+                }
+            }
+            match self.fetch(client, now).await {
+                Ok(token) => {
+                    state.token = Some(token);
+                    state.failed = None;
+                }
+                Err(e) => {
+                    state.failed = Some((now, format!("{:#}", e)));
+                    return Err(e);
+                }
+            }
+        }
+        Ok(state.token.as_ref().map(|t| t.value.clone()).unwrap_or_default())
+    }
+
+    // 源站拒绝了令牌（例如被提前吊销），下一个请求重新获取；
+    // 被拒绝的令牌已经换成新的时保留新令牌
+    pub async fn invalidate(&self, rejected: &str) {
+        let mut state = self.state.lock().await;
+        if state.token.as_ref().is_some_and(|t| t.value == rejected) {
+            state.token = None;
+        }
+    }
+
+    async fn fetch<C>(&self, client: &Client<C>, now: u64) -> Result<Token>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let config = &self.config;
+        let mut form = "grant_type=client_credentials".to_string();
+        for (name, value) in [("scope", &config.scope), ("audience", &config.audience)] {
+            if let Some(value) = value {
+                form.push_str(&format!("&{}={}", name, encode(value.as_bytes())));
+            }
+        }
+        // client_secret_basic：id 与密钥按表单编码后放入 Basic 认证
+        let credentials = format!(
+            "{}:{}",
+            encode(config.key_id.as_bytes()),
+            encode(config.secret.as_bytes())
+        );
+        let req = Request::post(&config.token_url)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(AUTHORIZATION, format!("Basic {}", STANDARD.encode(credentials)))
+            .body(Body::from(form))?;
+        let response = tokio::time::timeout(
+            Duration::from_secs(OAUTH_TOKEN_TIMEOUT_SECS),
+            async {
+                let response = client.request(req).await?;
+                let status = response.status();
+                let body = hyper::body::to_bytes(response.into_body()).await?;
+                anyhow::Ok((status, body))
+            },
+        )
+        .await
+        .with_context(|| format!("token request to {} timed out", config.token_url))?;
+        let (status, body) =
+            response.with_context(|| format!("token request to {} failed", config.token_url))?;
+        if !status.is_success() {
+            bail!("token endpoint {} returned {}", config.token_url, status);
+        }
+        let response: TokenResponse = serde_json::from_slice(&body)
+            .with_context(|| format!("invalid token response from {}", config.token_url))?;
+        tracing::debug!("fetched an access token from {}", config.token_url);
+        // 有效期不长于 refresh_before_secs 时提前量最多为有效期的一半，否则每个请求都会重新获取
+        let refresh_at = response
+            .expires_in
+            .map(|secs| now + secs - config.refresh_before_secs.min(secs / 2));
+        Ok(Token {
+            value: response.access_token,
+            refresh_at,
+        })
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::constants::RECENT_REQUESTS_KEPT;
+use crate::debug::DebugInfo;
+
+// 最近处理的请求，管理接口 /debug/requests 从新到旧返回，不需要打开详细日志就能查看近期流量；
+// 只保存在内存中，超过容量时丢弃最旧的
+pub struct RecentRequests {
+    capacity: AtomicUsize,
+    requests: Mutex<VecDeque<RequestTrace>>,
+}
+
+pub static RECENT_REQUESTS: RecentRequests = RecentRequests::new(RECENT_REQUESTS_KEPT);
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RequestTrace {
+    pub time: u64,
+    pub method: String,
+    pub uri: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<IpAddr>,
+    // hit / partial / miss / bypass 等，未走到缓存查找时为 none
+    pub cache: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<&'static str>,
+    pub timing: Timing,
+}
+
+// 各阶段耗时（毫秒）；total 到响应头返回为止，不包括传输响应体
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Timing {
+    pub total_ms: u64,
+    // 等待源站并发名额
+    pub queue_ms: u64,
+    // 等待源站响应头，多次回源（重试、续传）时累加
+    pub upstream_ms: u64,
+    pub upstream_requests: u32,
+}
+
+impl Timing {
+    pub fn new(total_ms: u64, info: &DebugInfo) -> Self {
+        Timing {
+            total_ms,
+            queue_ms: info.queue_ms,
+            upstream_ms: info.upstream_ms,
+            upstream_requests: info.upstream_requests,
+        }
+    }
+}
+
+impl RecentRequests {
+    pub const fn new(capacity: usize) -> Self {
+        RecentRequests {
+            capacity: AtomicUsize::new(capacity),
+            requests: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    // 0 表示不记录
+    pub fn set_capacity(&self, capacity: usize) {
+        self.capacity.store(capacity, Ordering::Relaxed);
+        let mut requests = self.requests.lock().unwrap();
+        let excess = requests.len().saturating_sub(capacity);
+        requests.drain(..excess);
+    }
+
+    pub fn push(&self, trace: RequestTrace) {
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        if capacity == 0 {
+            return;
+        }
+        let mut requests = self.requests.lock().unwrap();
+        while requests.len() >= capacity {
+            requests.pop_front();
+        }
+        requests.push_back(trace);
+    }
+
+    // 从新到旧，最多 limit 个
+    pub fn list(&self, limit: usize) -> Vec<RequestTrace> {
+        self.requests.lock().unwrap().iter().rev().take(limit).cloned().collect()
+    }
+}
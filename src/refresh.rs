@@ -0,0 +1,77 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::{Body, Request};
+
+use crate::cache::{CacheMeta, ProxyCache};
+use crate::config::Config;
+use crate::handler::revalidate;
+use crate::metrics::METRICS;
+use crate::upstream::{apply_connect_to, with_priority, HttpClient, Priority};
+
+// 提前刷新调度：定期挑选即将过期的热门条目，在回源流量空闲时重新验证
+pub fn spawn(cache: Arc<ProxyCache>, client: HttpClient, config: Arc<Config>) {
+    let refresh = config.cache.refresh.clone();
+    tokio::spawn(async move {
+        let interval = Duration::from_secs(refresh.interval_secs.max(1));
+        let mut last_requests = METRICS.upstream_requests.load(Ordering::Relaxed);
+        loop {
+            cache.clock().sleep(interval).await;
+
+            let requests = METRICS.upstream_requests.load(Ordering::Relaxed);
+            let rps = requests.saturating_sub(last_requests) as f64 / interval.as_secs_f64();
+            last_requests = requests;
+            let hot = cache.popularity().hot_entries(refresh.min_hits);
+            cache.popularity().decay();
+            if rps > refresh.idle_max_rps {
+                continue;
+            }
+
+            let now = cache.clock().now_secs();
+            let mut refreshed = 0;
+            for (key, uri, _) in hot {
+                if refreshed >= refresh.max_per_tick {
+                    break;
+                }
+                let Some(entry) = cache.get(&key).await else {
+                    continue;
+                };
+                if !entry.meta.is_complete || !expiring_soon(&entry.meta, now, refresh.ahead_fraction) {
+                    continue;
+                }
+                let Ok(mut req) = Request::get(uri.clone()).body(Body::empty()) else {
+                    continue;
+                };
+                apply_connect_to(&mut req, &config);
+                let policy = config.cache_policy(&uri);
+                // 刷新请求不应挤占客户端请求的上游并发
+                let result = with_priority(
+                    Priority::Low,
+                    revalidate(&client, &req, entry, cache.clone(), key, policy),
+                )
+                .await;
+                if let Err(e) = result {
+                    tracing::debug!("background refresh of {} failed: {:#}", uri, e);
+                }
+                refreshed += 1;
+            }
+            if refreshed > 0 {
+                tracing::debug!("refreshed {} hot cache entries", refreshed);
+            }
+        }
+    });
+}
+
+// 剩余新鲜期低于新鲜期的 ahead_fraction，已经过期或被软删除
+fn expiring_soon(meta: &CacheMeta, now: u64, ahead_fraction: f64) -> bool {
+    if meta.invalidated {
+        return true;
+    }
+    let (Some(stored_at), Some(freshness)) = (meta.stored_at, meta.freshness_secs) else {
+        return false;
+    };
+    let expires_at = stored_at.saturating_add(freshness);
+    let ahead = (freshness as f64 * ahead_fraction) as u64;
+    now.saturating_add(ahead) >= expires_at
+}
@@ -0,0 +1,86 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use crate::cache::ProxyCache;
+use crate::config::Config;
+use crate::constants::PENDING_DOWNLOADS_FILE;
+use crate::server;
+use crate::upstream::{with_client, with_priority, HttpClient, Priority};
+
+// 重启后续传：退出时中断仍在进行的下载，已收到的数据作为部分内容写入缓存，
+// URL 记录在缓存目录中；下次启动时在后台重新请求这些 URL，只补齐缺失的区间
+
+fn journal_path(cache_dir: &Path) -> PathBuf {
+    cache_dir.join(PENDING_DOWNLOADS_FILE)
+}
+
+// 退出前调用（客户端连接已全部结束）：中断下载并等待它们保存数据，最多等待 shutdown_grace_secs
+pub async fn interrupt(cache: &ProxyCache, client: &HttpClient, config: &Config) -> Result<()> {
+    let urls = client.downloads().interrupt();
+    let grace = Duration::from_secs(config.cache.resume.shutdown_grace_secs);
+    let started = Instant::now();
+    while !client.downloads().is_empty() && started.elapsed() < grace {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    if !config.cache.resume.enabled || urls.is_empty() {
+        return Ok(());
+    }
+    let path = journal_path(cache.dir());
+    tokio::fs::write(&path, serde_json::to_vec(&urls)?)
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    tracing::info!("recorded {} interrupted downloads for resumption", urls.len());
+    Ok(())
+}
+
+// 启动时读取上次退出记录的 URL，逐个以低优先级续传；已完整的条目跳过
+pub fn spawn(cache: Arc<ProxyCache>, client: HttpClient, config: Arc<Config>) {
+    tokio::spawn(async move {
+        let path = journal_path(cache.dir());
+        let urls: Vec<String> = match tokio::fs::read(&path).await {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                tracing::warn!("ignoring invalid {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(_) => return,
+        };
+        // 续传中再次被中断的下载会重新记录
+        let _ = tokio::fs::remove_file(&path).await;
+
+        for url in urls {
+            let Ok(uri) = url.parse::<hyper::Uri>() else {
+                continue;
+            };
+            // 任何一个版本（客户端类别、压缩编码）已经完整都不再续传
+            let mut complete = false;
+            for key in config.cache_key_variants(&uri) {
+                if cache.get(&key).await.is_some_and(|entry| entry.meta.is_complete) {
+                    complete = true;
+                    break;
+                }
+            }
+            if complete {
+                continue;
+            }
+            tracing::info!("resuming interrupted download of {}", url);
+            let gone = Arc::new(AtomicBool::new(true));
+            let result = with_client(
+                gone,
+                Some(url.clone()),
+                with_priority(
+                    Priority::Low,
+                    server::prefetch(uri, cache.clone(), client.clone(), config.clone()),
+                ),
+            )
+            .await;
+            match result {
+                Ok(status) => tracing::debug!("resumed {}: {}", url, status),
+                Err(e) => tracing::warn!("failed to resume {}: {:#}", url, e),
+            }
+        }
+    });
+}
@@ -0,0 +1,179 @@
+use anyhow::{Context, Result};
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use hyper::header::{HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::{Body, Method, Response, StatusCode, Uri};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::constants::{REWRITE_CONTENT_TYPES, REWRITE_MAX_BODY_SIZE};
+
+// 响应体替换规则，例如把播放列表里的绝对分片地址改写为经过代理的地址
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RewriteRule {
+    pub pattern: String,
+    // 正则模式下可以使用 $1、${name} 引用捕获组
+    pub replacement: String,
+    // 为 false 时按普通字符串替换
+    pub regex: bool,
+    #[serde(skip)]
+    compiled: Option<Regex>,
+}
+
+impl RewriteRule {
+    // 启动时编译正则，配置错误在启动时暴露
+    pub fn compile(&mut self) -> Result<()> {
+        if self.regex {
+            let regex = Regex::new(&self.pattern)
+                .with_context(|| format!("invalid rewrite pattern {}", self.pattern))?;
+            self.compiled = Some(regex);
+        }
+        Ok(())
+    }
+
+    fn apply(&self, text: &str) -> String {
+        match &self.compiled {
+            Some(regex) => regex.replace_all(text, self.replacement.as_str()).into_owned(),
+            None => text.replace(&self.pattern, &self.replacement),
+        }
+    }
+}
+
+// 请求 URL 的改写规则：正则匹配规范化后的完整 URL（如 http://cdn-a.example.com/v/1.ts），
+// 重定向客户端，或者在计算缓存键与回源之前把请求改写到另一个 URL
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UrlRule {
+    pub pattern: String,
+    // 可以使用 $1、${name} 引用捕获组；改写的结果必须是 http/https 的绝对 URL
+    pub replacement: String,
+    pub action: UrlAction,
+    #[serde(skip)]
+    compiled: Option<Regex>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UrlAction {
+    // 内部改写，客户端看不到
+    #[default]
+    Rewrite,
+    // 302
+    Redirect,
+    // 301
+    PermanentRedirect,
+}
+
+pub enum UrlRewrite {
+    Redirect(StatusCode, String),
+    Rewrite(String),
+}
+
+impl UrlRule {
+    pub fn new(pattern: &str, replacement: &str, action: UrlAction) -> Result<Self> {
+        let mut rule = UrlRule {
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            action,
+            compiled: None,
+        };
+        rule.compile()?;
+        Ok(rule)
+    }
+
+    pub fn compile(&mut self) -> Result<()> {
+        let regex = Regex::new(&self.pattern)
+            .with_context(|| format!("invalid URL rewrite pattern {}", self.pattern))?;
+        self.compiled = Some(regex);
+        Ok(())
+    }
+
+    // 只替换第一处匹配，未匹配时返回 None
+    fn apply(&self, url: &str) -> Option<String> {
+        let captures = self.compiled.as_ref()?.captures(url)?;
+        let matched = captures.get(0)?;
+        let mut replaced = String::new();
+        captures.expand(&self.replacement, &mut replaced);
+        Some(format!("{}{}{}", &url[..matched.start()], replaced, &url[matched.end()..]))
+    }
+}
+
+// 按顺序匹配路由的 URL 改写规则，第一条命中的规则生效
+pub fn rewrite_url(uri: &Uri, rules: &[UrlRule]) -> Option<UrlRewrite> {
+    let url = uri.to_string();
+    rules.iter().find_map(|rule| {
+        let target = rule.apply(&url)?;
+        Some(match rule.action {
+            UrlAction::Rewrite => UrlRewrite::Rewrite(target),
+            UrlAction::Redirect => UrlRewrite::Redirect(StatusCode::FOUND, target),
+            UrlAction::PermanentRedirect => UrlRewrite::Redirect(StatusCode::MOVED_PERMANENTLY, target),
+        })
+    })
+}
+
+// 对文本类响应（播放列表、HTML）按路由规则替换内容，并重新计算 Content-Length。
+// 只改写非 HEAD 请求的 200 响应：HEAD 没有响应体，206 只是部分内容，改写后与
+// Content-Range 对不上
+pub async fn rewrite_response(
+    method: &Method,
+    response: Response<Body>,
+    rules: &[RewriteRule],
+) -> Result<Response<Body>> {
+    if rules.is_empty() || method == Method::HEAD || !should_rewrite(&response) {
+        return Ok(response);
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let mut stream = body;
+    let mut buf = BytesMut::new();
+    while let Some(chunk) = stream.next().await {
+        buf.extend_from_slice(&chunk?);
+        // 超出上限的响应不再改写，已读取的部分与剩余数据一起透传
+        if buf.len() as u64 > REWRITE_MAX_BODY_SIZE {
+            let prefix = futures::stream::once(async move { Ok::<_, hyper::Error>(buf.freeze()) });
+            return Ok(Response::from_parts(parts, Body::wrap_stream(prefix.chain(stream))));
+        }
+    }
+
+    let body = match std::str::from_utf8(&buf) {
+        Ok(text) => Bytes::from(
+            rules
+                .iter()
+                .fold(text.to_string(), |text, rule| rule.apply(&text)),
+        ),
+        // 非 UTF-8 内容原样返回
+        Err(_) => buf.freeze(),
+    };
+    parts
+        .headers
+        .insert(CONTENT_LENGTH, HeaderValue::from(body.len() as u64));
+    // 内容已变化，源站的校验值不再适用
+    parts.headers.remove(hyper::header::ETAG);
+    parts.headers.remove("content-md5");
+    parts.headers.remove("digest");
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+fn should_rewrite(response: &Response<Body>) -> bool {
+    if response.status() != StatusCode::OK || response.headers().contains_key(CONTENT_ENCODING) {
+        return false;
+    }
+    let declared_len = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if declared_len.map(|len| len > REWRITE_MAX_BODY_SIZE).unwrap_or(false) {
+        return false;
+    }
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    REWRITE_CONTENT_TYPES
+        .iter()
+        .any(|ty| content_type.starts_with(ty))
+}
@@ -0,0 +1,225 @@
+use anyhow::Result;
+use bytes::Bytes;
+use hyper::{Body, Response, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::admin::{self, PurgeBy};
+use crate::audit::{self, Caller};
+use crate::cache::ProxyCache;
+use crate::client_usage::CLIENT_USAGE;
+use crate::config::{AdminRole, Config};
+use crate::drain::DRAIN;
+use crate::metrics::METRICS;
+use crate::recent_requests::RECENT_REQUESTS;
+use crate::upstream::HttpClient;
+
+// JSON-RPC 2.0 规定的错误码
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+// 服务器自定义的错误码：调用者的角色不允许执行这个方法
+const FORBIDDEN: i64 = -32001;
+
+// 改变状态的方法，只有 admin 角色可以调用
+const MUTATING_METHODS: &[&str] = &[
+    "cache.purge",
+    "cache.purge_tag",
+    "cache.flush",
+    "downloads.cancel",
+    "drain.start",
+    "drain.stop",
+];
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PurgeParams {
+    urls: Vec<String>,
+    #[serde(default)]
+    soft: bool,
+}
+
+#[derive(Deserialize)]
+struct PurgeTagParams {
+    tags: Vec<String>,
+    #[serde(default)]
+    soft: bool,
+}
+
+#[derive(Deserialize)]
+struct CancelParams {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct RecentParams {
+    limit: Option<usize>,
+}
+
+// POST /rpc：JSON-RPC 2.0，支持批量调用与通知（没有 id 的调用不返回结果）。
+// 调用本身的错误放在响应体中，HTTP 状态码总是 200；全部是通知时返回 204
+pub async fn handle(
+    body: &[u8],
+    caller: &Caller,
+    cache: &ProxyCache,
+    client: &HttpClient,
+    config: &Config,
+) -> Result<Response<Body>> {
+    let reply = match serde_json::from_slice::<Value>(body) {
+        Err(err) => Some(error_response(Value::Null, RpcError::new(PARSE_ERROR, err.to_string()))),
+        Ok(Value::Array(calls)) if calls.is_empty() => Some(error_response(
+            Value::Null,
+            RpcError::new(INVALID_REQUEST, "empty batch"),
+        )),
+        Ok(Value::Array(calls)) => {
+            let mut replies = Vec::new();
+            for call in calls {
+                replies.extend(dispatch(call, caller, cache, client, config).await);
+            }
+            (!replies.is_empty()).then_some(Value::Array(replies))
+        }
+        Ok(call) => dispatch(call, caller, cache, client, config).await,
+    };
+    let Some(reply) = reply else {
+        return Ok(Response::builder().status(StatusCode::NO_CONTENT).body(Body::empty())?);
+    };
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&reply)?))?)
+}
+
+// 执行一次调用，通知返回 None
+async fn dispatch(
+    call: Value,
+    caller: &Caller,
+    cache: &ProxyCache,
+    client: &HttpClient,
+    config: &Config,
+) -> Option<Value> {
+    let Value::Object(mut call) = call else {
+        return Some(error_response(Value::Null, RpcError::new(INVALID_REQUEST, "call must be an object")));
+    };
+    let id = call.remove("id");
+    let notification = id.is_none();
+    let id = id.unwrap_or(Value::Null);
+    let method = match (call.get("jsonrpc"), call.get("method")) {
+        (Some(Value::String(version)), Some(Value::String(method))) if version == "2.0" => method.clone(),
+        _ => {
+            return Some(error_response(id, RpcError::new(INVALID_REQUEST, "expected jsonrpc 2.0 and a method")));
+        }
+    };
+    let params = call.remove("params").unwrap_or(Value::Null);
+    let result = call_method(&method, params, caller, cache, client, config).await;
+    if notification {
+        return None;
+    }
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => error_response(id, err),
+    })
+}
+
+async fn call_method(
+    method: &str,
+    params: Value,
+    caller: &Caller,
+    cache: &ProxyCache,
+    client: &HttpClient,
+    config: &Config,
+) -> Result<Value, RpcError> {
+    if MUTATING_METHODS.contains(&method) && caller.principal.role != AdminRole::Admin {
+        return Err(RpcError::new(FORBIDDEN, format!("{} requires the admin role", method)));
+    }
+    match method {
+        // 与 POST /cache/purge 相同，同样转发给 admin.peers
+        "cache.purge" => {
+            let params: PurgeParams = parse_params(params)?;
+            let body = Bytes::from(params.urls.join("\n"));
+            let report = admin::audited_purge(caller, cache, config, PurgeBy::Url, body, params.soft, false)
+                .await
+                .map_err(internal)?;
+            to_value(&report)
+        }
+        // 与 POST /cache/purge-tag 相同
+        "cache.purge_tag" => {
+            let params: PurgeTagParams = parse_params(params)?;
+            let body = Bytes::from(params.tags.join("\n"));
+            let report = admin::audited_purge(caller, cache, config, PurgeBy::Tag, body, params.soft, false)
+                .await
+                .map_err(internal)?;
+            to_value(&report)
+        }
+        "cache.flush" => {
+            let result = cache.flush().await;
+            audit::record(caller, "cache.flush", &[], &[], &audit::outcome(&result)).await;
+            result.map_err(internal)?;
+            Ok(Value::Null)
+        }
+        "stats.get" => to_value(&METRICS.traffic_stats()),
+        "stats.clients" => to_value(&CLIENT_USAGE.stats(cache.clock().now_secs())),
+        "downloads.list" => to_value(&client.downloads().list()),
+        "downloads.cancel" => {
+            let params: CancelParams = parse_params(params)?;
+            let cancelled = client.downloads().cancel(params.id);
+            let result = if cancelled { "ok" } else { "not found" };
+            audit::record(caller, "downloads.cancel", &[params.id.to_string()], &[], result).await;
+            Ok(Value::Bool(cancelled))
+        }
+        "drain.start" => {
+            DRAIN.start(cache.clock().now_secs());
+            audit::record(caller, "drain.start", &[], &[], "ok").await;
+            to_value(&DRAIN.status())
+        }
+        "drain.stop" => {
+            DRAIN.stop();
+            audit::record(caller, "drain.stop", &[], &[], "ok").await;
+            to_value(&DRAIN.status())
+        }
+        "drain.status" => to_value(&DRAIN.status()),
+        "debug.requests" => {
+            // 参数可以省略
+            let params: Option<RecentParams> = parse_params(params)?;
+            let limit = params.and_then(|params| params.limit).unwrap_or(usize::MAX);
+            to_value(&RECENT_REQUESTS.list(limit))
+        }
+        // 配置只在启动时读取，不提供 config.set
+        "config.get" => to_value(&config.redacted()),
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("unknown method {}", method))),
+    }
+}
+
+fn parse_params<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|err| RpcError::new(INVALID_PARAMS, err.to_string()))
+}
+
+fn to_value<T: serde::Serialize>(value: &T) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|err| internal(err.into()))
+}
+
+fn internal(err: anyhow::Error) -> RpcError {
+    RpcError::new(INTERNAL_ERROR, format!("{:#}", err))
+}
+
+fn error_response(id: Value, err: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": err.code, "message": err.message },
+    })
+}
@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use landlock::{
+    path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus, ABI,
+};
+use seccompiler::{SeccompAction, SeccompFilter, TargetArch};
+
+use crate::config::{Config, SandboxConfig};
+use crate::constants::SANDBOX_READ_PATHS;
+use crate::daemon::Identity;
+
+// landlock 的限制只作用于调用它的线程及之后创建的线程，因此在创建运行时之前调用。
+// 缓存目录与日志文件必须在限制之前存在，不存在时先创建，并交给切换后的用户；
+// 之后无法再读取用户数据库，uid 与 gid 由调用方事先解析
+pub fn restrict_filesystem(config: &Config, cache_dir: &Path, (uid, gid): Identity) -> Result<()> {
+    let sandbox = &config.sandbox;
+    if !sandbox.enabled || !sandbox.landlock {
+        return Ok(());
+    }
+    let mut created = Vec::new();
+    if !cache_dir.exists() {
+        std::fs::create_dir_all(cache_dir)
+            .with_context(|| format!("failed to create cache dir {}", cache_dir.display()))?;
+        created.push(cache_dir.to_path_buf());
+    }
+    let mut files: Vec<&PathBuf> = Vec::new();
+    if config.decision_log.enabled {
+        files.push(&config.decision_log.path);
+    }
+    if config.admin.audit_log.enabled {
+        files.push(&config.admin.audit_log.path);
+    }
+    if config.process.daemon {
+        files.extend(&config.process.log_file);
+    }
+    for file in &files {
+        if !file.exists() {
+            std::fs::File::create(file).with_context(|| format!("failed to create {}", file.display()))?;
+            created.push(file.to_path_buf());
+        }
+    }
+    if uid.is_some() || gid.is_some() {
+        for path in &created {
+            std::os::unix::fs::chown(path, uid, gid)
+                .with_context(|| format!("failed to change owner of {}", path.display()))?;
+        }
+    }
+
+    let mut write_paths: Vec<&Path> = vec![cache_dir, Path::new("/dev/null")];
+    write_paths.extend(files.iter().map(|file| file.as_path()));
+    // 退出时删除 pid 文件需要其所在目录的写权限
+    if let Some(dir) = config.process.pid_file.as_deref().and_then(Path::parent) {
+        write_paths.push(if dir.as_os_str().is_empty() { Path::new(".") } else { dir });
+    }
+    write_paths.extend(sandbox.write_paths.iter().map(PathBuf::as_path));
+    let mut read_paths: Vec<&Path> = SANDBOX_READ_PATHS.iter().map(Path::new).collect();
+    read_paths.extend(config.tls_files().into_iter().map(PathBuf::as_path));
+    read_paths.extend(sandbox.read_paths.iter().map(PathBuf::as_path));
+
+    // 不存在的路径跳过；内核不支持的访问类型按内核支持的部分生效
+    let abi = ABI::V3;
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))?
+        .create()?
+        .add_rules(path_beneath_rules(write_paths, AccessFs::from_all(abi)))?
+        .add_rules(path_beneath_rules(read_paths, AccessFs::from_read(abi)))?
+        .restrict_self()
+        .context("failed to enable landlock")?;
+    match status.ruleset {
+        RulesetStatus::FullyEnforced => tracing::info!("sandbox: filesystem access restricted"),
+        RulesetStatus::PartiallyEnforced => {
+            tracing::warn!("sandbox: filesystem access partially restricted (old kernel)")
+        }
+        RulesetStatus::NotEnforced => {
+            tracing::warn!("sandbox: landlock is not supported by the kernel, filesystem access is not restricted")
+        }
+    }
+    Ok(())
+}
+
+// 启动完成（端口已绑定、已切换用户）后调用，对所有线程生效；
+// 禁止的系统调用返回 EPERM 而不是终止进程，被拒绝的操作以普通错误的形式出现在日志中
+pub fn restrict_syscalls(config: &SandboxConfig) -> Result<()> {
+    if !config.enabled || !config.seccomp {
+        return Ok(());
+    }
+    let Ok(arch) = TargetArch::try_from(std::env::consts::ARCH) else {
+        tracing::warn!("sandbox: seccomp is not supported on {}", std::env::consts::ARCH);
+        return Ok(());
+    };
+    let rules: BTreeMap<i64, _> = DENIED_SYSCALLS.iter().map(|syscall| (*syscall, Vec::new())).collect();
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::Allow,
+        SeccompAction::Errno(libc::EPERM as u32),
+        arch,
+    )?;
+    let program: seccompiler::BpfProgram = filter.try_into()?;
+    seccompiler::apply_filter_all_threads(&program).context("failed to enable seccomp")?;
+    tracing::info!("sandbox: system calls restricted");
+    Ok(())
+}
+
+// 执行程序、调试或读写其他进程、挂载与命名空间、内核模块与 BPF、密钥、切换身份、修改系统设置
+const DENIED_SYSCALLS: &[i64] = &[
+    libc::SYS_execve,
+    libc::SYS_execveat,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_name_to_handle_at,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_kexec_load,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_userfaultfd,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_setuid,
+    libc::SYS_setgid,
+    libc::SYS_setreuid,
+    libc::SYS_setregid,
+    libc::SYS_setresuid,
+    libc::SYS_setresgid,
+    libc::SYS_setgroups,
+    libc::SYS_setfsuid,
+    libc::SYS_setfsgid,
+    libc::SYS_personality,
+    libc::SYS_reboot,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_acct,
+    libc::SYS_quotactl,
+    libc::SYS_settimeofday,
+    libc::SYS_clock_settime,
+    libc::SYS_adjtimex,
+    libc::SYS_sethostname,
+    libc::SYS_setdomainname,
+];
@@ -1,25 +1,642 @@
 use anyhow::Result;
 use bytes::Bytes;
 use futures::StreamExt;
-use hyper::{Body, Client, Request, Response, StatusCode};
-use hyper_tls::HttpsConnector;
+use hyper::body::HttpBody;
+use hyper::header::HeaderValue;
+use hyper::{Body, Request, Response, StatusCode, Version};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::cache::{CacheEntry, CacheMeta, ProxyCache};
-use crate::constants::MAX_FILE_SIZE;
-use crate::handler::{check_response_complete, get_total_size, handle_range_request};
-use crate::utils::{self, fetch_with_retry, generate_cache_key, parse_range};
+use crate::admin;
+use crate::bandwidth::{self, CLIENT_BANDWIDTH};
+use crate::cache::{strip_surrogate_headers, CacheMeta, ProxyCache};
+use crate::cache_key::normalize_accept_encoding;
+use crate::client_usage::{self, ClientId, CLIENT_USAGE};
+use crate::config::Config;
+use crate::constants::{
+    DEBUG_HEADER, MAX_RESUME_GAPS, PEER_HEADER, PLAYLIST_EXTENSIONS, PRIORITY_HEADER, SHARD_HEADER,
+    UPSTREAM_ERROR_HEADER, VIA_PSEUDONYM,
+};
+use crate::debug::{self, with_debug, DebugHandle};
+use crate::decision_log::{self, Decision};
+use crate::esi;
+use crate::listener::ClientAddr;
+use crate::handler::{
+    cache_full_response, complete_response, content_range, fetch_and_cache_full_response,
+    forward_request, get_total_size, handle_range_request, head_response, partial_response, revalidate,
+    slice_full_response, stitchable_len, GzipVariants, Revalidated,
+};
+use crate::metrics::{handle_metrics_request, METRICS};
+use crate::recent_requests::{RequestTrace, Timing, RECENT_REQUESTS};
+use crate::rewrite::{rewrite_response, rewrite_url, UrlRewrite};
+use crate::target::{self, Target};
+use crate::upstream::{
+    apply_connect_to, rewrite_to_origin, with_client, with_priority, HttpClient, Priority, UpstreamBusy, UpstreamError,
+    UpstreamErrorKind,
+};
+use crate::utils::{fetch_with_retry, parse_range, resume_request};
 
 pub async fn handle_request(
+    mut req: Request<Body>,
+    cache: Arc<ProxyCache>,
+    client: HttpClient,
+    config: Arc<Config>,
+) -> Result<Response<Body>> {
+    let via = config.via_enabled().then(|| via_value(req.version()));
+    // 绝对形式与源形式（反向代理）的请求统一改写为规范的绝对 URL，其余的是发给代理自身的请求
+    match target::normalize(&mut req, &config) {
+        Target::BadRequest(reason) => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(reason))?);
+        }
+        Target::Forbidden(reason) => {
+            return Ok(Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::from(reason))?);
+        }
+        Target::LoopDetected => {
+            return Ok(Response::builder()
+                .status(StatusCode::LOOP_DETECTED)
+                .body(Body::from("request loop detected"))?);
+        }
+        // 路由的 URL 改写规则在计算缓存键之前执行：重定向直接返回，改写后按新的 URL 继续处理
+        Target::Proxy => {
+            // 签名针对客户端请求的 URL，在改写之前按原 URL 所属路由校验，
+            // 改写到不受保护的路由也不能绕过；签名无效或过期时在访问源站之前拒绝
+            let signed_url = config.route(req.uri()).and_then(|route| route.signed_url.as_ref());
+            if signed_url.is_some_and(|signed_url| !signed_url.verify(req.uri(), cache.clock().as_ref())) {
+                return Ok(Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .body(Body::from("invalid or expired signature"))?);
+            }
+            let rules = config
+                .route(req.uri())
+                .map(|route| route.url_rewrites.as_slice())
+                .unwrap_or_default();
+            match rewrite_url(req.uri(), rules) {
+                Some(UrlRewrite::Redirect(status, location)) => {
+                    return Ok(Response::builder()
+                        .status(status)
+                        .header(hyper::header::LOCATION, location)
+                        .body(Body::empty())?);
+                }
+                Some(UrlRewrite::Rewrite(target)) => {
+                    let valid = target::retarget(&mut req, &target);
+                    if !valid {
+                        tracing::warn!("URL rewrite of {} produced invalid target {}", req.uri(), target);
+                        return Ok(Response::builder()
+                            .status(StatusCode::INTERNAL_SERVER_ERROR)
+                            .body(Body::from("invalid rewrite target"))?);
+                    }
+                }
+                None => {}
+            }
+        }
+        Target::Local => {}
+    }
+    let uri = req.uri().clone();
+    // 发给代理自身的请求不经过代理，不加 Via
+    let via = via.filter(|_| uri.host().is_some());
+    if let Some(via) = &via {
+        req.headers_mut().append(hyper::header::VIA, via.clone());
+    }
+    let method = req.method().clone();
+    let started = Instant::now();
+    let sampled = decision_log::sampled();
+    let deadline = config.request_deadline(&uri);
+    let in_background = config.downstream.complete_in_background;
+    let client_addr = req.extensions().get::<ClientAddr>().copied();
+    let clock = cache.clock().clone();
+    // 按客户端统计流量，当天配额已用完的客户端直接拒绝；发给代理自身的请求不计入
+    let usage = (config.client_usage.enabled && uri.host().is_some()).then(|| {
+        let ip = client_addr.map(|ClientAddr(addr)| addr.ip());
+        Arc::<[ClientId]>::from(client_usage::client_ids(ip, req.headers(), &config.client_usage.users))
+    });
+    if let Some(ids) = &usage {
+        if let Some(quota) = config.client_usage.daily_quota_bytes {
+            if CLIENT_USAGE.over_quota(ids, quota, clock.now_secs()) {
+                return Ok(client_usage::quota_exceeded(clock.now_secs()));
+            }
+        }
+        req = client_usage::count_request(req, ids.clone(), clock.clone());
+    }
+    let priority = request_priority(&mut req, &config);
+    let debug = req
+        .headers_mut()
+        .remove(DEBUG_HEADER)
+        .filter(|v| v.as_bytes() == b"1")
+        .map(|_| DebugHandle::default());
+
+    // 客户端断开时 hyper 会丢弃这个 future，上游请求与重试随之取消；
+    // 开启后台完成时请求在独立任务中运行，断开后仍继续下载并写入缓存
+    let mut guard = AbortGuard::new(uri.clone());
+    let client_gone = guard.client_gone.clone();
+    // 完整下载的 GET 在退出时被中断的话，重启后可以按 URL 续传
+    let resume_url = (method == hyper::Method::GET && !req.headers().contains_key(hyper::header::RANGE))
+        .then(|| uri.to_string());
+    let debug_handle = debug.clone().unwrap_or_default();
+    let lookup = debug_handle.clone();
+    let route_config = config.clone();
+    // 各层处理的 future 很大，装箱后调试构建也能放进默认 2 MB 的工作线程栈
+    let work = async move {
+        let request = with_debug(
+            debug_handle,
+            with_priority(
+                priority,
+                with_client(
+                    client_gone,
+                    resume_url,
+                    with_deadline(deadline, Box::pin(serve_request(req, cache, client, config))),
+                ),
+            ),
+        );
+        if in_background {
+            tokio::spawn(request).await?
+        } else {
+            request.await
+        }
+    };
+    let result = match deadline {
+        Some(deadline) => match tokio::time::timeout(deadline, work).await {
+            Ok(result) => result,
+            Err(_) => Err(DeadlineExceeded(deadline).into()),
+        },
+        None => work.await,
+    };
+    guard.finish();
+
+    // 响应体改写在缓存之后进行，缓存中保存源站的原始内容
+    let rules = route_config
+        .route(&uri)
+        .map(|route| route.rewrites.as_slice())
+        .unwrap_or_default();
+    let result = match result {
+        Ok(response) => rewrite_response(&method, response, rules).await,
+        Err(e) => Err(e),
+    };
+
+    let mut response = match result {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::warn!("request to {} failed: {:#}", uri, e);
+            error_response(&e)?
+        }
+    };
+    if let Some(via) = via {
+        response.headers_mut().append(hyper::header::VIA, via);
+    }
+    if route_config.cache.surrogate_headers {
+        strip_surrogate_headers(response.headers_mut());
+    }
+    if let Some(debug) = debug {
+        debug.lock().unwrap().apply(&mut response);
+    }
+    // 发给代理自身的请求（指标等）不计入
+    if uri.host().is_some() {
+        let label = route_config.metrics_label(&uri);
+        record_traffic(&label, lookup.lock().unwrap().lookup, &response, started.elapsed());
+        let info = lookup.lock().unwrap();
+        RECENT_REQUESTS.push(RequestTrace {
+            time: clock.now_secs(),
+            method: method.to_string(),
+            uri: route_config.loggable_uri(&uri),
+            status: response.status().as_u16(),
+            client: client_addr.map(|ClientAddr(addr)| addr.ip()),
+            cache: info.lookup.unwrap_or("none"),
+            store: info.store,
+            timing: Timing::new(started.elapsed().as_millis() as u64, &info),
+        });
+    }
+    if sampled {
+        decision_log::write(&Decision {
+            time: clock.now_secs(),
+            method: method.as_str(),
+            uri: &route_config.loggable_uri(&uri),
+            status: response.status().as_u16(),
+            duration_ms: started.elapsed().as_millis() as u64,
+            info: &lookup.lock().unwrap(),
+        });
+    }
+    // 预读窗口依据客户端实测带宽
+    if let (true, Some(ClientAddr(addr))) = (route_config.cache.read_ahead.enabled, client_addr) {
+        response = bandwidth::measure(response, addr.ip());
+    }
+    if let Some(ids) = usage {
+        response = client_usage::count_response(response, ids, clock);
+    }
+    Ok(response)
+}
+
+// RFC 9110 7.6.3：收到请求所用的协议版本加上代理的名字
+fn via_value(version: Version) -> HeaderValue {
+    let version = match version {
+        Version::HTTP_10 => "1.0",
+        Version::HTTP_2 => "2",
+        _ => "1.1",
+    };
+    HeaderValue::from_str(&format!("{} {}", version, VIA_PSEUDONYM)).unwrap()
+}
+
+// 按路由（或源站）与内容类别统计缓存结果；未走到缓存查找（如上游出错）的请求记为 none
+fn record_traffic(label: &str, lookup: Option<&'static str>, response: &Response<Body>, latency: Duration) {
+    let content_type = response
+        .headers()
+        .get(hyper::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let bytes = response
+        .headers()
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .or_else(|| HttpBody::size_hint(response.body()).exact())
+        .unwrap_or(0);
+    METRICS.record_traffic(label, content_type, lookup.unwrap_or("none"), bytes, latency);
+}
+
+// 优先级来源：X-Proxy-Priority 请求头 > 路由配置 > 播放列表等交互型资源
+fn request_priority(req: &mut Request<Body>, config: &Config) -> Priority {
+    // 该请求头只给代理使用，不转发给源站
+    if let Some(value) = req.headers_mut().remove(PRIORITY_HEADER) {
+        if let Some(priority) = value.to_str().ok().and_then(Priority::parse) {
+            return priority;
+        }
+    }
+    if let Some(priority) = config.route(req.uri()).and_then(|route| route.priority) {
+        return priority;
+    }
+    let path = req.uri().path();
+    if PLAYLIST_EXTENSIONS.iter().any(|ext| path.ends_with(ext)) {
+        return Priority::High;
+    }
+    Priority::Normal
+}
+
+// 没有 If-Range，或 If-Range 仍指向缓存的对象
+fn if_range_matches(req: &Request<Body>, meta: &CacheMeta) -> bool {
+    req.headers()
+        .get(hyper::header::IF_RANGE)
+        .map(|v| v.to_str().map(|v| meta.if_range_matches(v)).unwrap_or(false))
+        .unwrap_or(true)
+}
+
+// Cache-Control: only-if-cached，或来自兄弟代理的查询
+fn only_if_cached(req: &mut Request<Body>) -> bool {
+    let from_peer = req.headers_mut().remove(PEER_HEADER).is_some();
+    from_peer
+        || req
+            .headers()
+            .get_all(hyper::header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|directive| directive.trim().eq_ignore_ascii_case("only-if-cached"))
+}
+
+fn select_origin(req: &mut Request<Body>, config: &Config, client: &HttpClient) {
+    let Some(route) = config.route(req.uri()) else {
+        return;
+    };
+    let Some(origin) = client.origins().select(&route.origins) else {
+        return;
+    };
+    if let Some(uri) = rewrite_to_origin(req.uri(), origin) {
+        *req.uri_mut() = uri;
+        // Host 由 hyper 按新的源站地址生成
+        req.headers_mut().remove(hyper::header::HOST);
+    }
+}
+
+// 请求处理超过整体时限
+#[derive(Debug)]
+pub struct DeadlineExceeded(pub Duration);
+
+impl std::fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "request exceeded deadline of {:?}", self.0)
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+// 请求的整体时限与到期的时刻
+#[derive(Clone, Copy, Debug)]
+pub struct RequestDeadline {
+    limit: Duration,
+    at: tokio::time::Instant,
+}
+
+tokio::task_local! {
+    static DEADLINE: Option<RequestDeadline>;
+}
+
+async fn with_deadline<F: std::future::Future>(limit: Option<Duration>, fut: F) -> F::Output {
+    let deadline = limit.map(|limit| RequestDeadline {
+        limit,
+        at: tokio::time::Instant::now() + limit,
+    });
+    DEADLINE.scope(deadline, fut).await
+}
+
+// 当前请求的时限，交给响应头发出后仍在后台发送响应体的任务
+pub fn current_deadline() -> Option<RequestDeadline> {
+    DEADLINE.try_with(|deadline| *deadline).ok().flatten()
+}
+
+// 在请求剩余的时限内执行
+pub async fn within_deadline<T>(
+    deadline: Option<RequestDeadline>,
+    fut: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    match deadline {
+        Some(deadline) => match tokio::time::timeout_at(deadline.at, fut).await {
+            Ok(result) => result,
+            Err(_) => Err(DeadlineExceeded(deadline.limit).into()),
+        },
+        None => fut.await,
+    }
+}
+
+// 处理完成前被丢弃说明客户端已断开
+struct AbortGuard {
+    uri: hyper::Uri,
+    finished: bool,
+    // 断开后仍在后台进行的下载据此标记为后台下载
+    client_gone: Arc<AtomicBool>,
+}
+
+impl AbortGuard {
+    fn new(uri: hyper::Uri) -> Self {
+        AbortGuard {
+            uri,
+            finished: false,
+            client_gone: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn finish(&mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for AbortGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.client_gone.store(true, Ordering::Relaxed);
+            METRICS.client_aborts.fetch_add(1, Ordering::Relaxed);
+            tracing::debug!("client disconnected before response for {}", self.uri);
+        }
+    }
+}
+
+// 将处理过程中的错误转换为返回给客户端的响应
+fn error_response(e: &anyhow::Error) -> Result<Response<Body>> {
+    let upstream = e.downcast_ref::<UpstreamError>();
+    let status = if e.is::<UpstreamBusy>() {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else if e.is::<DeadlineExceeded>()
+        || upstream.map(|u| u.kind == UpstreamErrorKind::Timeout).unwrap_or(false)
+    {
+        StatusCode::GATEWAY_TIMEOUT
+    } else {
+        StatusCode::BAD_GATEWAY
+    };
+    let mut response = Response::builder().status(status);
+    // 上游失败的分类，便于区分 DNS 故障、连接失败与源站 5xx
+    if let Some(upstream) = upstream {
+        response = response.header(UPSTREAM_ERROR_HEADER, upstream.kind.as_str());
+    }
+    Ok(response.body(Body::from(e.to_string()))?)
+}
+
+// 获取响应（模板本身按正常流程缓存），开启 ESI 的路由在返回前拼装片段
+async fn serve_request(
     req: Request<Body>,
     cache: Arc<ProxyCache>,
-    client: Client<HttpsConnector<hyper::client::HttpConnector>>,
+    client: HttpClient,
+    config: Arc<Config>,
 ) -> Result<Response<Body>> {
-    // 生成缓存键
-    let cache_key = generate_cache_key(req.uri());
+    let uri = req.uri().clone();
+    let response = Box::pin(proxy_request(req, cache.clone(), client.clone(), config.clone())).await?;
+    if !config.route(&uri).map(|route| route.esi).unwrap_or(false) {
+        return Ok(response);
+    }
+    esi::assemble(response, &uri, |src| {
+        fetch_fragment(src, cache.clone(), client.clone(), config.clone())
+    })
+    .await
+}
+
+// 片段按自身所属路由的策略缓存，片段内的 ESI 标签不再展开
+async fn fetch_fragment(
+    uri: hyper::Uri,
+    cache: Arc<ProxyCache>,
+    client: HttpClient,
+    config: Arc<Config>,
+) -> Result<Bytes> {
+    let req = Request::get(uri.clone()).body(Body::empty())?;
+    let response = Box::pin(proxy_request(req, cache, client, config)).await?;
+    if !response.status().is_success() {
+        anyhow::bail!("esi:include {} returned {}", uri, response.status());
+    }
+    Ok(hyper::body::to_bytes(response.into_body()).await?)
+}
+
+// 不经过客户端连接获取一个对象并写入缓存（重启后续传），读完响应体才算完成
+pub async fn prefetch(
+    uri: hyper::Uri,
+    cache: Arc<ProxyCache>,
+    client: HttpClient,
+    config: Arc<Config>,
+) -> Result<StatusCode> {
+    let req = Request::get(uri).body(Body::empty())?;
+    let response = Box::pin(proxy_request(req, cache, client, config)).await?;
+    let status = response.status();
+    let mut body = response.into_body();
+    while let Some(chunk) = body.next().await {
+        chunk?;
+    }
+    Ok(status)
+}
+
+async fn proxy_request(
+    mut req: Request<Body>,
+    cache: Arc<ProxyCache>,
+    client: HttpClient,
+    config: Arc<Config>,
+) -> Result<Response<Body>> {
+    // 发给代理自身的请求（非绝对 URI）：指标没有单独监听时在代理端口上提供
+    if req.uri().authority().is_none() {
+        if config.metrics.listen.is_none() {
+            if !admin::metrics_authorized(&req, &config) {
+                return admin::unauthorized();
+            }
+            return handle_metrics_request(req).await;
+        }
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())?);
+    }
+
+    // 只有 GET/HEAD 走缓存，其余方法连同请求体直接转发
+    if req.method() != hyper::Method::GET && req.method() != hyper::Method::HEAD {
+        debug::record(|d| d.lookup = Some("bypass"));
+        apply_connect_to(&mut req, &config);
+        let fetch = config.cache_policy(req.uri()).fetch;
+        return forward_request(req, &client, config.downstream.max_request_body_bytes, fetch).await;
+    }
+
+    if config.cache.key.normalize_accept_encoding {
+        normalize_accept_encoding(req.headers_mut());
+    }
+
+    // 生成缓存键，签名参数与路由配置忽略的查询参数不参与
+    let cache_key = config.request_cache_key(req.method(), req.uri(), req.headers());
+    if let Some(variants) = GzipVariants::for_request(&config, &req) {
+        req.extensions_mut().insert(variants);
+    }
+    let mut policy = config.cache_policy(req.uri());
+    policy.authenticated = req.headers().contains_key(hyper::header::AUTHORIZATION)
+        && !config.route(req.uri()).is_some_and(|route| route.cache_authenticated);
+    if let Some(ClientAddr(addr)) = req.extensions().get::<ClientAddr>() {
+        policy.read_ahead_bytes =
+            CLIENT_BANDWIDTH.read_ahead_bytes(addr.ip(), &config.cache.read_ahead);
+    }
+
+    // 兄弟代理的查询只读缓存，不回源
+    let only_if_cached = only_if_cached(&mut req);
+
+    // 分片模式：其他实例转发来的请求一定由本实例处理，避免循环转发
+    let from_shard = req.headers_mut().remove(SHARD_HEADER).is_some();
+    if !only_if_cached && !from_shard {
+        if let Some(peers) = client.peers() {
+            if let Some(resp) = peers.forward_to_owner(&cache_key, &req).await {
+                debug::record(|d| d.lookup = Some("shard"));
+                return Ok(resp);
+            }
+        }
+    }
+
+    // 分块存储的大对象：新鲜的范围请求只读取覆盖该范围的块
+    let requested_range = req
+        .headers()
+        .get(hyper::header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_range);
+    if let Some((start, end)) = requested_range {
+        if let Some((meta, end, data)) = cache.get_range(&cache_key, start, end).await {
+            if meta.is_fresh(cache.clock().now_secs()) && if_range_matches(&req, &meta) {
+                debug::record(|d| {
+                    d.cache_key = Some(cache_key.clone());
+                    d.lookup = Some(if meta.is_complete { "hit" } else { "partial" });
+                    d.complete = Some(meta.is_complete);
+                    d.cached_ranges = meta.ranges.clone();
+                    d.total_size = meta.total_size;
+                    d.freshness = Some("fresh");
+                    d.range = Some("cached-chunks");
+                });
+                if meta.is_complete {
+                    METRICS.cache_content_hits.fetch_add(1, Ordering::Relaxed);
+                }
+                cache.popularity().record_hit(&cache_key, req.uri());
+                return partial_response(&meta, start, end, data, cache.clock().now_secs());
+            }
+        }
+    }
+
+    // HEAD 只需要元数据：新鲜的完整条目不读取内容，也不访问源站
+    if req.method() == hyper::Method::HEAD && requested_range.is_none() {
+        let now = cache.clock().now_secs();
+        let meta = cache.get_meta(&cache_key).await.filter(|meta| meta.is_complete && meta.is_fresh(now));
+        if let Some(meta) = meta {
+            if let Some(total) = meta.total_size {
+                debug::record(|d| {
+                    d.cache_key = Some(cache_key.clone());
+                    d.lookup = Some("hit");
+                    d.complete = Some(true);
+                    d.total_size = Some(total);
+                    d.freshness = Some("fresh");
+                });
+                METRICS.cache_meta_hits.fetch_add(1, Ordering::Relaxed);
+                cache.popularity().record_hit(&cache_key, req.uri());
+                return head_response(&meta, &req, total, now);
+            }
+        }
+    }
 
     // 检查缓存是否存在
-    if let Some(cached_entry) = cache.get(&cache_key).await {
+    let cached = cache.get(&cache_key).await;
+    debug::record(|d| {
+        d.cache_key = Some(cache_key.clone());
+        d.lookup = Some("miss");
+        if let Some(entry) = &cached {
+            d.lookup = Some(if entry.meta.is_complete { "hit" } else { "partial" });
+            d.complete = Some(entry.meta.is_complete);
+            d.cached_ranges = Some(entry.ranges());
+            d.total_size = entry.meta.total_size;
+            d.freshness = Some(if entry.meta.is_fresh(cache.clock().now_secs()) { "fresh" } else { "stale" });
+        }
+    });
+    if let Some(entry) = &cached {
+        // 过期的条目要等重新验证成功后才算命中（见 revalidate）
+        if entry.meta.is_complete && entry.meta.is_fresh(cache.clock().now_secs()) {
+            METRICS.cache_content_hits.fetch_add(1, Ordering::Relaxed);
+        }
+        cache.popularity().record_hit(&cache_key, req.uri());
+    }
+    if only_if_cached && !cached.as_ref().map(|e| e.meta.is_complete).unwrap_or(false) {
+        return Ok(Response::builder()
+            .status(StatusCode::GATEWAY_TIMEOUT)
+            .body(Body::empty())?);
+    }
+
+    // 本地未命中：先询问兄弟代理
+    let peer_resp = match (&cached, client.peers()) {
+        (None, Some(peers)) => peers.lookup(&req).await,
+        _ => None,
+    };
+
+    // 多源站路由：缓存键仍使用原始 URL，请求发往延迟最低的源站
+    select_origin(&mut req, &config, &client);
+    apply_connect_to(&mut req, &config);
+
+    if let Some(resp) = peer_resp {
+        debug::record(|d| d.lookup = Some("peer"));
+        return Box::pin(cache_full_response(&client, req, resp, cache, cache_key, policy)).await;
+    }
+
+    // 完整条目已过期：先向源站重新验证
+    let cached = match cached {
+        Some(entry)
+            if entry.meta.is_complete && !entry.meta.is_fresh(cache.clock().now_secs()) && !only_if_cached =>
+        {
+            match Box::pin(revalidate(&client, &req, entry, cache.clone(), cache_key.clone(), policy)).await? {
+                Revalidated::Entry(entry) => Some(entry),
+                Revalidated::Response(response) => return Ok(response),
+            }
+        }
+        cached => cached,
+    };
+
+    if let Some(cached_entry) = cached {
+        // If-Range 与缓存的对象不符：按 RFC 忽略 Range，返回完整内容
+        if !if_range_matches(&req, &cached_entry.meta) {
+            debug::record(|d| d.range = Some("if-range-mismatch"));
+            req.headers_mut().remove(hyper::header::RANGE);
+            req.headers_mut().remove(hyper::header::IF_RANGE);
+        }
+
+        // 客户端持有的版本与缓存一致
+        if cached_entry.meta.is_complete && cached_entry.meta.not_modified(req.headers()) {
+            let mut response = Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())?;
+            cached_entry.meta.insert_cached_headers(response.headers_mut(), cache.clock().now_secs());
+            return Ok(response);
+        }
+
         // 检查是否有范围请求
         if let Some(range_header) = req.headers().get(hyper::header::RANGE) {
             // 处理范围请求
@@ -27,14 +644,15 @@ pub async fn handle_request(
                 // 解析范围请求
                 if let Some(range) = parse_range(range_str) {
                     // 处理范围请求
-                    return handle_range_request(
+                    return Box::pin(handle_range_request(
                         range,
                         cached_entry,
                         req,
                         client,
                         cache,
                         cache_key,
-                    )
+                        policy,
+                    ))
                     .await;
                 }
             }
@@ -42,183 +660,120 @@ pub async fn handle_request(
         // 如果没有范围请求，检查是否完整
         } else if cached_entry.meta.is_complete {
             // 返回完整的缓存响应
-            let response = Response::builder()
-                .status(StatusCode::OK)
-                .header(
-                    hyper::header::CONTENT_TYPE,
-                    cached_entry
-                        .meta
-                        .content_type
-                        .parse::<hyper::header::HeaderValue>()
-                        .unwrap(),
-                )
-                .body(Body::from(cached_entry.content))?;
-            return Ok(response);
+            return complete_response(&cached_entry.meta, cached_entry.content, cache.clock().now_secs());
         
         // 处理不完整的缓存
         } else {
-            // 获取缓存长度
-            let cached_len = cached_entry.content.len() as u64;
-
             // 获取总资源大小
             let total_size = if let Some(size) = cached_entry.meta.total_size {
                 size
             } else {
-                get_total_size(&client, &req).await?.unwrap_or(0)
+                get_total_size(&client, &req, policy.fetch).await?.unwrap_or(0)
             };
 
-            if total_size > 0 {
-                if cached_len >= total_size {
-                    // 缓存实际上已完成
-                    let response = Response::builder()
-                        .status(StatusCode::OK)
-                        .header(
-                            hyper::header::CONTENT_TYPE,
-                            cached_entry
-                                .meta
-                                .content_type
-                                .parse::<hyper::header::HeaderValue>()
-                                .unwrap(),
-                        )
-                        .body(Body::from(cached_entry.content))?;
-                    return Ok(response);
-                } else {
-                    // 获取剩余部分
-                    let mut client_req = Request::builder()
-                        .method(req.method())
-                        .uri(req.uri())
-                        .header(
-                            hyper::header::RANGE,
-                            format!("bytes={}-{}", cached_len, total_size - 1),
-                        )
-                        .body(Body::empty())?;
-
-                    *client_req.headers_mut() = req.headers().clone();
-
-                    // 获取剩余部分
-                    let resp = fetch_with_retry(&client, &client_req).await?;
-                    if resp.status() == StatusCode::PARTIAL_CONTENT {
-                        let mut remaining_data = Vec::new();
-                        let mut stream = resp.into_body();
-
-                        while let Some(chunk) = stream.next().await {
-                            let chunk = chunk?;
-                            remaining_data.extend_from_slice(&chunk);
-
-                            // 检查合并后的总大小是否超过限制
-                            if (cached_len + remaining_data.len() as u64) > total_size as u64 {
-                                // 如果超过限制，返回原始的完整请求
-                                return fetch_and_cache_full_response(
-                                    &client, req, cache, cache_key,
-                                )
-                                .await;
+            // 超过可缓存大小或空洞过多的对象不再续传，交给下面的完整请求
+            let gaps = cached_entry.ranges().gaps(total_size);
+            if total_size > 0
+                && total_size <= policy.max_object_bytes
+                && gaps.len() <= MAX_RESUME_GAPS
+            {
+                let mut entry = cached_entry;
+                let mut filled = false;
+                for (gap_start, gap_end) in gaps {
+                    // 逐个补齐缺失区间，附带 If-Range 确认源站对象未变化
+                    let client_req = resume_request(
+                        &req,
+                        gap_start,
+                        gap_end - 1,
+                        entry.meta.if_range_validator(),
+                    )?;
+                    let resp = match fetch_with_retry(&client, &client_req, policy.fetch).await {
+                        Ok(resp) => resp,
+                        // 已补齐的区间先保存，下次从剩余的空洞继续
+                        Err(e) => {
+                            if filled {
+                                cache.set(cache_key, entry).await?;
                             }
+                            return Err(e);
                         }
+                    };
+
+                    // 源站返回 200 说明对象已变化，已缓存的区间作废，用新的完整响应替换缓存
+                    if resp.status() == StatusCode::OK {
+                        return Box::pin(cache_full_response(
+                            &client, req, resp, cache, cache_key, policy,
+                        ))
+                        .await;
+                    }
+
+                    // 源站返回的区间或版本与请求不符，或不允许共享存储时放弃续传，
+                    // 重新获取完整对象
+                    let expected = content_range(resp.headers()).and_then(|returned| {
+                        stitchable_len((gap_start, gap_end - 1), Some(total_size), returned)
+                    });
+                    if resp.status() != StatusCode::PARTIAL_CONTENT
+                        || expected != Some(gap_end - gap_start)
+                        || !entry.meta.same_representation(resp.headers())
+                        || !policy.may_store(resp.headers(), cache.clock().now_secs())
+                    {
+                        return Box::pin(fetch_and_cache_full_response(
+                            &client, req, cache, cache_key, policy,
+                        ))
+                        .await;
+                    }
 
-                        // 合并缓存数据和新数据
-                        let mut complete_data = cached_entry.content.to_vec();
-                        complete_data.extend_from_slice(&remaining_data);
-
-                        // 更新缓存
-                        let new_cache_entry = CacheEntry {
-                            content: Bytes::from(complete_data.clone()),
-                            meta: CacheMeta {
-                                content_type: cached_entry.meta.content_type.clone(),
-                                is_complete: true,
-                                total_size: Some(total_size),
-                            },
+                    let mut data = Vec::new();
+                    let mut stream = resp.into_body();
+                    while let Some(chunk) = stream.next().await {
+                        let chunk = match chunk {
+                            Ok(chunk) => chunk,
+                            // 源站中途断开或卡住：保存已补齐的部分，下次请求从断点续传
+                            Err(e) => {
+                                if !data.is_empty() {
+                                    entry = entry.merge(gap_start, &data, Some(total_size));
+                                }
+                                cache.set(cache_key, entry).await?;
+                                return Err(e.into());
+                            }
                         };
-                        cache.set(cache_key, new_cache_entry).await?;
-
-                        // 返回完整响应
-                        let response = Response::builder()
-                            .status(StatusCode::OK)
-                            .header(
-                                hyper::header::CONTENT_TYPE,
-                                cached_entry
-                                    .meta
-                                    .content_type
-                                    .parse::<hyper::header::HeaderValue>()
-                                    .unwrap(),
-                            )
-                            .body(Body::from(complete_data))?;
-                        return Ok(response);
+                        data.extend_from_slice(&chunk);
+
+                        // 返回的数据超出缺失区间，说明对象大小已变化
+                        if data.len() as u64 > gap_end - gap_start {
+                            return Box::pin(fetch_and_cache_full_response(
+                                &client, req, cache, cache_key, policy,
+                            ))
+                            .await;
+                        }
                     }
+                    entry = entry.merge(gap_start, &data, Some(total_size));
+                    filled = true;
                 }
-            }
-        }
-    }
 
-    // 如果上述所有情况都不满足，获取根据请求的 range 情况来获取数据
-    fetch_and_cache_full_response(&client, req, cache, cache_key).await
-}
+                // 所有空洞已补齐（否则源站提前截断，重新获取完整对象）
+                if entry.meta.is_complete {
+                    let meta = entry.meta.clone();
+                    let content = entry.content.clone();
+                    cache.set(cache_key, entry).await?;
 
-// 获取根据请求的 range 情况来获取数据
-async fn fetch_and_cache_full_response(
-    client: &Client<HttpsConnector<hyper::client::HttpConnector>>,
-    req: Request<Body>,
-    cache: Arc<ProxyCache>,
-    cache_key: String,
-) -> Result<Response<Body>> {
-    let resp = fetch_with_retry(&client, &req).await?;
-    let status = resp.status();
-    let headers = resp.headers().clone();
-
-    if status.is_success() {
-        // 处理成功响应
-        let content_type = headers
-            .get(hyper::header::CONTENT_TYPE)
-            .and_then(|v| v.to_str().ok())
-            .unwrap_or("application/octet-stream")
-            .to_string();
-
-        let mut body = Vec::new();
-        let mut stream = resp.into_body();
-
-        // 读取响应主体
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk?;
-            body.extend_from_slice(&chunk);
-
-            // 检查是否超过最大文件大小
-            if body.len() as u64 > MAX_FILE_SIZE as u64 {
-                // 如果主体大小超过限制，则返回而不缓存
-                let response = Response::builder().status(status).body(Body::from(body))?;
-                return Ok(response);
+                    // 返回完整响应
+                    return complete_response(&meta, content, cache.clock().now_secs());
+                }
             }
         }
+    }
 
-        // 检查是否完成
-        let is_complete = check_response_complete(&headers, body.len() as u64);
-
-        // 获取总资源大小
-        let total_size = get_total_size(&client, &req)
-            .await?
-            .or_else(|| Some(body.len() as u64));
-
-        // 缓存响应
-        cache
-            .set(
-                cache_key,
-                CacheEntry {
-                    content: Bytes::from(body.clone()),
-                    meta: CacheMeta {
-                        content_type,
-                        is_complete,
-                        total_size,
-                    },
-                },
-            )
-            .await?;
-
-        // 构建响应
-        let mut response = Response::builder().status(status).body(Body::from(body))?;
-        *response.headers_mut() = headers;
-        Ok(response)
-    } else {
-        // 处理失败响应
-        let mut response = Response::builder().status(status).body(resp.into_body())?;
-        *response.headers_mut() = headers;
-        Ok(response)
+    // 如果上述所有情况都不满足，获取根据请求的 range 情况来获取数据；
+    // 源站不支持 Range 而返回了完整内容时，仍按客户端请求的范围返回 206
+    let requested = req
+        .headers()
+        .get(hyper::header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_range)
+        .filter(|_| !req.headers().contains_key(hyper::header::IF_RANGE));
+    let response = Box::pin(fetch_and_cache_full_response(&client, req, cache, cache_key, policy)).await?;
+    match requested {
+        Some((start, end)) => slice_full_response(response, start, end),
+        None => Ok(response),
     }
 }
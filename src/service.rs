@@ -0,0 +1,224 @@
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tokio::sync::Notify;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context as LayerContext;
+use tracing_subscriber::Layer;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceInfo,
+    ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_dispatcher;
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_sys::Win32::Foundation::HANDLE;
+use windows_sys::Win32::System::EventLog::{
+    DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE,
+    EVENTLOG_WARNING_TYPE,
+};
+
+use crate::constants::{SERVICE_DISPLAY_NAME, SERVICE_NAME, SERVICE_STOP_WAIT_SECONDS};
+
+// Windows 服务：由服务控制管理器（SCM）以 `--config <文件> service run` 启动，
+// 停止与关机请求等同于 Ctrl-C，正在处理的请求完成、缓存落盘后再报告已停止
+
+// SCM 发来的停止请求；在服务开始等待之前到达时保留下来
+static STOP: Notify = Notify::const_new();
+
+type Serve = Box<dyn FnOnce() -> Result<()> + Send>;
+
+// service_main 在 SCM 的线程中调用，要运行的服务由 run 交给它
+static SERVE: Mutex<Option<Serve>> = Mutex::new(None);
+
+windows_service::define_windows_service!(ffi_service_main, service_main);
+
+// 连接 SCM 并运行 serve，直到服务停止；不是由 SCM 启动时返回错误
+pub fn run(serve: impl FnOnce() -> Result<()> + Send + 'static) -> Result<()> {
+    *SERVE.lock().unwrap() = Some(Box::new(serve));
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+        .context("failed to connect to the service control manager (start the service with `sc start`)")?;
+    Ok(())
+}
+
+// 服务停止时完成，供监听的退出信号使用
+pub async fn stopped() {
+    STOP.notified().await
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        tracing::error!("service failed: {:#}", e);
+    }
+}
+
+fn run_service() -> Result<()> {
+    let handle = service_control_handler::register(SERVICE_NAME, |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            STOP.notify_one();
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })?;
+    handle.set_service_status(status(
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        0,
+    ))?;
+    let serve = SERVE.lock().unwrap().take();
+    let result = serve.map(|serve| serve()).unwrap_or(Ok(()));
+    let exit_code = if result.is_ok() { 0 } else { 1 };
+    handle.set_service_status(status(ServiceState::Stopped, ServiceControlAccept::empty(), exit_code))?;
+    result
+}
+
+fn status(state: ServiceState, controls_accepted: ServiceControlAccept, exit_code: u32) -> ServiceStatus {
+    ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted,
+        exit_code: ServiceExitCode::Win32(exit_code),
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    }
+}
+
+// 注册为开机自动启动的服务，以 LocalSystem 运行，启动参数中带上配置文件的绝对路径
+pub fn install(config: Option<&std::path::Path>) -> Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+    let mut launch_arguments = Vec::new();
+    if let Some(config) = config {
+        let config = std::fs::canonicalize(config)
+            .with_context(|| format!("failed to resolve config file {}", config.display()))?;
+        launch_arguments.push(OsString::from("--config"));
+        launch_arguments.push(config.into_os_string());
+    }
+    launch_arguments.extend(["service", "run"].map(OsString::from));
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from(SERVICE_DISPLAY_NAME),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments,
+        dependencies: Vec::new(),
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG)
+        .context("failed to create the service (run as Administrator)")?;
+    service.set_description("Caching HTTP proxy server")?;
+    Ok(())
+}
+
+// 先停止正在运行的服务，再删除注册
+pub fn uninstall() -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager
+        .open_service(
+            SERVICE_NAME,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )
+        .context("failed to open the service (is it installed? run as Administrator)")?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+        for _ in 0..SERVICE_STOP_WAIT_SECONDS {
+            if service.query_status()?.current_state == ServiceState::Stopped {
+                break;
+            }
+            std::thread::sleep(Duration::from_secs(1));
+        }
+    }
+    service.delete()?;
+    Ok(())
+}
+
+// 把 INFO 及以上的运行日志写入 Windows 事件日志（应用程序日志，来源为服务名）。
+// 没有注册消息文件，事件查看器会提示找不到描述，日志内容仍在事件数据中
+pub struct EventLogLayer {
+    source: EventSource,
+}
+
+struct EventSource(HANDLE);
+
+// 事件日志句柄可以在线程之间共享使用
+unsafe impl Send for EventSource {}
+unsafe impl Sync for EventSource {}
+
+impl Drop for EventSource {
+    fn drop(&mut self) {
+        // SAFETY: 句柄由 RegisterEventSourceW 返回，只在这里释放一次
+        unsafe { DeregisterEventSource(self.0) };
+    }
+}
+
+impl EventLogLayer {
+    pub fn new() -> Result<Self> {
+        let name = wide(SERVICE_NAME);
+        // SAFETY: name 是以 0 结尾的 UTF-16 字符串，调用期间有效
+        let handle = unsafe { RegisterEventSourceW(std::ptr::null(), name.as_ptr()) };
+        if handle.is_null() {
+            return Err(std::io::Error::last_os_error()).context("failed to register event source");
+        }
+        Ok(EventLogLayer {
+            source: EventSource(handle),
+        })
+    }
+}
+
+impl<S: Subscriber> Layer<S> for EventLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: LayerContext<'_, S>) {
+        let kind = match *event.metadata().level() {
+            Level::ERROR => EVENTLOG_ERROR_TYPE,
+            Level::WARN => EVENTLOG_WARNING_TYPE,
+            Level::INFO => EVENTLOG_INFORMATION_TYPE,
+            _ => return,
+        };
+        let mut message = MessageVisitor(String::new());
+        event.record(&mut message);
+        let text = wide(&message.0);
+        let strings = [text.as_ptr()];
+        // SAFETY: 句柄有效；strings 中的字符串以 0 结尾，调用期间有效
+        unsafe {
+            ReportEventW(
+                self.source.0,
+                kind,
+                0,
+                0,
+                std::ptr::null_mut(),
+                1,
+                0,
+                strings.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+    }
+}
+
+// message 字段在前，其余字段以 name=value 附在后面
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.0.insert_str(0, &format!("{:?}", value));
+        } else {
+            let _ = write!(self.0, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+fn wide(value: &str) -> Vec<u16> {
+    value.encode_utf16().chain(std::iter::once(0)).collect()
+}
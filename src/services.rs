@@ -0,0 +1,251 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use futures::future::{try_join_all, BoxFuture};
+use futures::FutureExt;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+
+use crate::admin;
+use crate::cache::ProxyCache;
+use crate::config::{Config, DownstreamConfig};
+use crate::listener::{self, ClientAddr, ClientConn};
+use crate::metrics::{handle_metrics_request, METRICS};
+use crate::server;
+use crate::upstream::HttpClient;
+#[cfg(target_os = "linux")]
+use crate::sandbox;
+#[cfg(windows)]
+use crate::service;
+#[cfg(unix)]
+use crate::{daemon, upgrade};
+
+// 代理、管理接口与指标的监听 socket
+pub struct Listeners {
+    proxy: TcpListener,
+    admin: Option<TcpListener>,
+    metrics: Option<TcpListener>,
+}
+
+// 先绑定所有端口，任何一个失败都不启动；在切换到普通用户之前调用，可以绑定低端口
+pub async fn bind(config: &Config) -> Result<Listeners> {
+    let downstream = &config.downstream;
+    let proxy = bind_addr(config.listen, downstream)?;
+    let admin = match config.admin.listen {
+        Some(addr) => Some(bind_addr(addr, downstream)?),
+        None => None,
+    };
+    let metrics = match config.metrics.listen {
+        Some(addr) => Some(bind_addr(addr, downstream)?),
+        None => None,
+    };
+    Ok(Listeners { proxy, admin, metrics })
+}
+
+// 代理、管理接口与指标分别监听，各自有独立的处理链：
+// 代理端口负责转发与缓存，管理端口先校验 token 与角色，指标端口按 metrics.require_token 校验
+pub async fn run(
+    listeners: Listeners,
+    config: Arc<Config>,
+    cache: Arc<ProxyCache>,
+    client: HttpClient,
+) -> Result<()> {
+    let Listeners {
+        proxy: proxy_listener,
+        admin: admin_listener,
+        metrics: metrics_listener,
+    } = listeners;
+
+    #[cfg(unix)]
+    {
+        upgrade::finish_startup();
+        daemon::finish_startup(&config.process)?;
+    }
+    #[cfg(target_os = "linux")]
+    sandbox::restrict_syscalls(&config.sandbox)?;
+
+    METRICS.configure(&config.metrics);
+
+    // 收到退出信号时所有监听同时优雅退出：不再接受新连接，已有连接处理完当前请求后关闭
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let listeners = [Some(&proxy_listener), admin_listener.as_ref(), metrics_listener.as_ref()];
+    let stop = stop_signal(listeners.into_iter().flatten().collect(), cache.clone())?;
+    tokio::spawn(async move {
+        stop.await;
+        let _ = shutdown_tx.send(true);
+    });
+
+    let downstream = config.downstream.clone();
+    let mut services: Vec<BoxFuture<'static, Result<()>>> = Vec::new();
+
+    let proxy = {
+        let (cache, client, config) = (cache.clone(), client.clone(), config.clone());
+        move |req| server::handle_request(req, cache.clone(), client.clone(), config.clone())
+    };
+    services.push(
+        serve("Proxy server", proxy_listener, downstream.clone(), shutdown_rx.clone(), proxy)
+            .boxed(),
+    );
+
+    if let Some(listener) = admin_listener {
+        let (cache, client, config) = (cache.clone(), client.clone(), config.clone());
+        let admin = move |mut req: Request<Body>| {
+            let (cache, client, config) = (cache.clone(), client.clone(), config.clone());
+            async move {
+                let Some(principal) = admin::authenticate(&req, &config.admin) else {
+                    return admin::unauthorized();
+                };
+                req.extensions_mut().insert(principal);
+                admin::handle_admin_request(req, cache, client, config).await
+            }
+        };
+        services.push(
+            serve("Admin API", listener, downstream.clone(), shutdown_rx.clone(), admin).boxed(),
+        );
+    }
+
+    if let Some(listener) = metrics_listener {
+        let metrics = move |req: Request<Body>| {
+            let authorized = admin::metrics_authorized(&req, &config);
+            async move {
+                if !authorized {
+                    return admin::unauthorized();
+                }
+                handle_metrics_request(req).await
+            }
+        };
+        services.push(serve("Metrics", listener, downstream, shutdown_rx, metrics).boxed());
+    }
+
+    try_join_all(services).await?;
+    Ok(())
+}
+
+// 升级后的新进程直接使用旧进程交来的 socket
+fn bind_addr(addr: std::net::SocketAddr, downstream: &DownstreamConfig) -> Result<TcpListener> {
+    #[cfg(unix)]
+    if let Some(listener) = upgrade::take_listener(addr)? {
+        return Ok(listener);
+    }
+    listener::bind(addr, downstream).with_context(|| format!("failed to listen on {}", addr))
+}
+
+// Ctrl-C，或者 SIGUSR2 触发的升级成功（新进程已接管监听 socket）。
+// 升级前先停止写盘并释放缓存目录锁，新进程取得锁后才打开缓存；升级失败时重新取得锁
+#[cfg(unix)]
+type Handoff = Vec<(std::net::SocketAddr, std::os::fd::RawFd)>;
+
+#[cfg(unix)]
+fn stop_signal(listeners: Vec<&TcpListener>, cache: Arc<ProxyCache>) -> Result<impl Future<Output = ()> + Send> {
+    use std::os::fd::AsRawFd;
+    use std::time::Duration;
+    use tokio::signal::unix::{signal, SignalKind};
+    use crate::constants::UPGRADE_READY_TIMEOUT_SECONDS;
+
+    let handoff: Handoff = listeners
+        .iter()
+        .map(|listener| Ok((listener.local_addr()?, listener.as_raw_fd())))
+        .collect::<Result<_>>()?;
+    let mut upgrade_signal = signal(SignalKind::user_defined2())?;
+    Ok(async move {
+        loop {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => return,
+                _ = upgrade_signal.recv() => {
+                    if let Err(e) = cache.release_dir().await {
+                        tracing::error!("upgrade failed, keep serving: {:#}", e);
+                        continue;
+                    }
+                    match upgrade::spawn_successor(&handoff).await {
+                        Ok(()) => return,
+                        Err(e) => {
+                            tracing::error!("upgrade failed, keep serving: {:#}", e);
+                            // 新进程可能仍持有锁，等它退出
+                            let timeout = Duration::from_secs(UPGRADE_READY_TIMEOUT_SECONDS);
+                            if let Err(e) = cache.reclaim_dir(timeout).await {
+                                tracing::error!("cache stays memory-only: {:#}", e);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+// 作为 Windows 服务运行时，服务管理器的停止请求与 Ctrl-C 一样平滑退出
+#[cfg(windows)]
+fn stop_signal(_listeners: Vec<&TcpListener>, _cache: Arc<ProxyCache>) -> Result<impl Future<Output = ()> + Send> {
+    Ok(async {
+        tokio::select! {
+            Ok(()) = tokio::signal::ctrl_c() => {}
+            _ = service::stopped() => {}
+        }
+    })
+}
+
+#[cfg(not(any(unix, windows)))]
+fn stop_signal(_listeners: Vec<&TcpListener>, _cache: Arc<ProxyCache>) -> Result<impl Future<Output = ()> + Send> {
+    Ok(async {
+        let _ = tokio::signal::ctrl_c().await;
+    })
+}
+
+async fn serve<H, F>(
+    name: &'static str,
+    listener: TcpListener,
+    downstream: DownstreamConfig,
+    mut shutdown: watch::Receiver<bool>,
+    handler: H,
+) -> Result<()>
+where
+    H: Fn(Request<Body>) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = Result<Response<Body>>> + Send + 'static,
+{
+    let addr = listener.local_addr()?;
+    let incoming = listener::incoming(listener, &downstream);
+    let keep_alive = downstream.keep_alive_timeout_secs > 0;
+    let max_buf_size = downstream.max_header_bytes.max(8192);
+    let downstream = Arc::new(downstream);
+    let make_svc = make_service_fn(move |conn: &ClientConn| {
+        let handler = handler.clone();
+        let downstream = downstream.clone();
+        let client_addr = conn.peer_addr().ok().map(ClientAddr);
+        let activity = conn.activity();
+        async move {
+            Ok::<_, anyhow::Error>(service_fn(move |mut req: Request<Body>| {
+                if let Some(client_addr) = client_addr {
+                    req.extensions_mut().insert(client_addr);
+                }
+                let (number, guard) = activity.begin();
+                // 处理函数的 future 很大，放到堆上，避免外层 future 在栈上多次拷贝
+                let response = Box::pin(handler(req));
+                let downstream = downstream.clone();
+                async move {
+                    let response = response.await?;
+                    Ok::<_, anyhow::Error>(listener::finish_response(
+                        response,
+                        number,
+                        guard,
+                        &downstream,
+                    ))
+                }
+            }))
+        }
+    });
+    // 请求头读取超时与长连接空闲超时由 ClientConn 处理
+    let server = Server::builder(incoming)
+        .http1_keepalive(keep_alive)
+        .http1_max_buf_size(max_buf_size)
+        .serve(make_svc)
+        .with_graceful_shutdown(async move {
+            let _ = shutdown.wait_for(|stop| *stop).await;
+        });
+
+    println!("{} running on http://{}", name, addr);
+    server.await?;
+    Ok(())
+}
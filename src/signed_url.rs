@@ -0,0 +1,271 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use hyper::Uri;
+use md5::Md5;
+use rsa::pkcs1v15::{Signature, VerifyingKey};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::signature::Verifier;
+use rsa::RsaPublicKey;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::clock::Clock;
+
+// 签名 URL 的格式
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SignedUrlFormat {
+    // ?expires=<unix 时间>&signature=<hex(HMAC-SHA256(secret, path?query))>
+    Hmac,
+    // nginx secure_link：?md5=<base64url(md5(template))>&expires=<unix 时间>
+    SecureLink,
+    // CloudFront 签名 URL（canned / custom policy，RSA-SHA1）
+    Cloudfront,
+}
+
+// 路由级别的签名 URL 校验设置
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SignedUrlConfig {
+    pub format: SignedUrlFormat,
+    // hmac / secure_link 使用的密钥
+    pub secret: String,
+    // secure_link 的签名原文模板，支持 $expires、$uri、$secret
+    pub secure_link_template: String,
+    // CloudFront Key-Pair-Id -> PEM 公钥文件路径
+    pub public_keys: BTreeMap<String, String>,
+    // 启动时从 public_keys 加载
+    #[serde(skip)]
+    keys: HashMap<String, RsaPublicKey>,
+}
+
+impl Default for SignedUrlConfig {
+    fn default() -> Self {
+        SignedUrlConfig {
+            format: SignedUrlFormat::Hmac,
+            secret: String::new(),
+            secure_link_template: "$expires$uri $secret".to_string(),
+            public_keys: BTreeMap::new(),
+            keys: HashMap::new(),
+        }
+    }
+}
+
+impl SignedUrlConfig {
+    // 读取 CloudFront 公钥，配置错误在启动时暴露
+    pub fn load_keys(&mut self) -> Result<()> {
+        for (id, path) in &self.public_keys {
+            let pem = std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read public key {}", path))?;
+            let key = RsaPublicKey::from_public_key_pem(&pem)
+                .with_context(|| format!("failed to parse public key {}", path))?;
+            self.keys.insert(id.clone(), key);
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match self.format {
+            SignedUrlFormat::Hmac | SignedUrlFormat::SecureLink if self.secret.is_empty() => {
+                bail!("signed_url.secret must be set")
+            }
+            SignedUrlFormat::Cloudfront if self.public_keys.is_empty() => {
+                bail!("signed_url.public_keys must list at least one key")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn redact(&mut self) {
+        if !self.secret.is_empty() {
+            self.secret = "<redacted>".to_string();
+        }
+    }
+
+    // 签名参数，不参与缓存键
+    fn signature_params(&self) -> &'static [&'static str] {
+        match self.format {
+            SignedUrlFormat::Hmac => &["expires", "signature"],
+            SignedUrlFormat::SecureLink => &["md5", "expires"],
+            SignedUrlFormat::Cloudfront => &["Expires", "Signature", "Key-Pair-Id", "Policy"],
+        }
+    }
+
+    // 去掉签名参数后的 URI，同一对象不同签名共用一个缓存条目
+    pub fn strip_signature(&self, uri: &Uri) -> Uri {
+        let params = self.signature_params();
+        let (kept, _) = split_query(uri, params);
+        with_query(uri, &kept)
+    }
+
+    // 有效期按缓存的时钟判断，与条目的新鲜度使用同一个时间来源
+    pub fn verify(&self, uri: &Uri, clock: &dyn Clock) -> bool {
+        let now = clock.now_secs();
+        let (kept, signed) = split_query(uri, self.signature_params());
+        let param = |name: &str| signed.get(name).map(String::as_str);
+        match self.format {
+            SignedUrlFormat::Hmac => {
+                let (Some(expires), Some(signature)) = (param("expires"), param("signature")) else {
+                    return false;
+                };
+                if !not_expired(expires, now) {
+                    return false;
+                }
+                // 签名覆盖路径与除 signature 外的全部查询参数（包含 expires）
+                let mut query = kept.clone();
+                query.push(format!("expires={}", expires));
+                let message = format!("{}?{}", uri.path(), query.join("&"));
+                let Ok(signature) = hex::decode(signature) else {
+                    return false;
+                };
+                let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+                    .expect("hmac accepts any key length");
+                mac.update(message.as_bytes());
+                mac.verify_slice(&signature).is_ok()
+            }
+            SignedUrlFormat::SecureLink => {
+                let (Some(expires), Some(md5)) = (param("expires"), param("md5")) else {
+                    return false;
+                };
+                if !not_expired(expires, now) {
+                    return false;
+                }
+                let message = self
+                    .secure_link_template
+                    .replace("$expires", expires)
+                    .replace("$uri", uri.path())
+                    .replace("$secret", &self.secret);
+                let expected = URL_SAFE_NO_PAD.encode(Md5::digest(message.as_bytes()));
+                constant_time_eq(expected.as_bytes(), md5.as_bytes())
+            }
+            SignedUrlFormat::Cloudfront => {
+                let (Some(signature), Some(key_id)) = (param("Signature"), param("Key-Pair-Id"))
+                else {
+                    return false;
+                };
+                let Some(key) = self.keys.get(key_id) else {
+                    return false;
+                };
+                let resource = with_query(uri, &kept).to_string();
+                let policy = match (param("Policy"), param("Expires")) {
+                    (Some(policy), _) => {
+                        match cloudfront_decode(policy).and_then(|p| String::from_utf8(p).ok()) {
+                            Some(policy) => policy,
+                            None => return false,
+                        }
+                    }
+                    (None, Some(expires)) => format!(
+                        r#"{{"Statement":[{{"Resource":"{}","Condition":{{"DateLessThan":{{"AWS:EpochTime":{}}}}}}}]}}"#,
+                        resource, expires
+                    ),
+                    (None, None) => return false,
+                };
+                let Some(signature) = cloudfront_decode(signature) else {
+                    return false;
+                };
+                let Ok(signature) = Signature::try_from(signature.as_slice()) else {
+                    return false;
+                };
+                if VerifyingKey::<Sha1>::new(key.clone())
+                    .verify(policy.as_bytes(), &signature)
+                    .is_err()
+                {
+                    return false;
+                }
+                policy_allows(&policy, &resource, now)
+            }
+        }
+    }
+}
+
+fn not_expired(expires: &str, now: u64) -> bool {
+    expires.parse::<u64>().map(|e| now < e).unwrap_or(false)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// 将查询参数拆为保留的原始片段与签名参数
+fn split_query(uri: &Uri, params: &[&str]) -> (Vec<String>, HashMap<String, String>) {
+    let mut kept = Vec::new();
+    let mut signed = HashMap::new();
+    for pair in uri.query().unwrap_or("").split('&').filter(|p| !p.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        if params.contains(&name) {
+            signed.insert(name.to_string(), value.to_string());
+        } else {
+            kept.push(pair.to_string());
+        }
+    }
+    (kept, signed)
+}
+
+fn with_query(uri: &Uri, query: &[String]) -> Uri {
+    let path_and_query = if query.is_empty() {
+        uri.path().to_string()
+    } else {
+        format!("{}?{}", uri.path(), query.join("&"))
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    Uri::from_parts(parts).unwrap_or_else(|_| uri.clone())
+}
+
+// CloudFront 使用的 URL 安全 base64 变体
+fn cloudfront_decode(value: &str) -> Option<Vec<u8>> {
+    let standard: String = value
+        .chars()
+        .map(|c| match c {
+            '-' => '+',
+            '_' => '=',
+            '~' => '/',
+            c => c,
+        })
+        .collect();
+    STANDARD.decode(standard).ok()
+}
+
+// 检查 policy 的 Resource（支持 * 通配）与有效期
+fn policy_allows(policy: &str, resource: &str, now: u64) -> bool {
+    let Ok(policy) = serde_json::from_str::<serde_json::Value>(policy) else {
+        return false;
+    };
+    let Some(statement) = policy["Statement"].get(0) else {
+        return false;
+    };
+    let pattern = statement["Resource"].as_str().unwrap_or("*");
+    let condition = &statement["Condition"];
+    let Some(expires) = condition["DateLessThan"]["AWS:EpochTime"].as_u64() else {
+        return false;
+    };
+    let started = condition["DateGreaterThan"]["AWS:EpochTime"]
+        .as_u64()
+        .map(|start| now >= start)
+        .unwrap_or(true);
+    now < expires && started && wildcard_match(pattern, resource)
+}
+
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        if i == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    rest.is_empty()
+}
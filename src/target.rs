@@ -0,0 +1,186 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use hyper::header::{HeaderValue, HOST, VIA};
+use hyper::http::uri::{Authority, Scheme};
+use hyper::{Body, HeaderMap, Request, Uri, Version};
+
+use crate::config::{Config, ProxyMode};
+use crate::constants::VIA_PSEUDONYM;
+
+// 请求目标的规范化：正向代理收到绝对形式（http://host/path），反向代理与透明代理收到源形式（/path）加 Host。
+// 都转换为同一个规范 URL，缓存键、路由匹配与回源都以它为准；接受哪种形式由 mode 决定
+pub enum Target {
+    // 已改写为规范的绝对 URL
+    Proxy,
+    // 发给代理自身的请求（指标等）
+    Local,
+    // 无法确定目标
+    BadRequest(&'static str),
+    // 当前模式或访问控制不接受的请求
+    Forbidden(&'static str),
+    // 请求已经经过本代理，再转发会形成环路
+    LoopDetected,
+}
+
+pub fn normalize(req: &mut Request<Body>, config: &Config) -> Target {
+    let uri = if req.uri().authority().is_some() {
+        if config.mode == ProxyMode::Reverse {
+            return Target::Forbidden("forward proxy requests are not accepted");
+        }
+        // RFC 9112 3.2.2：绝对形式的请求以 URI 中的主机为准，忽略 Host
+        canonical(req.uri())
+    } else if config.mode == ProxyMode::Forward {
+        return Target::Local;
+    } else {
+        let host = match req.headers().get(HOST) {
+            Some(host) => host.to_str().ok().and_then(|h| h.parse::<Authority>().ok()),
+            // HTTP/1.1 要求携带 Host，HTTP/1.0 客户端可以省略
+            None if req.version() >= Version::HTTP_11 => {
+                return Target::BadRequest("missing Host header");
+            }
+            None => return Target::Local,
+        };
+        let Some(host) = host else {
+            return Target::BadRequest("invalid Host header");
+        };
+        let scheme = match config.mode {
+            // 发给代理自己的监听地址的请求不能再转发给自己
+            ProxyMode::Transparent if is_listen_addr(&host, config) => return Target::Local,
+            ProxyMode::Transparent => reverse_scheme(req.uri(), &host, config).unwrap_or("http"),
+            _ => match reverse_scheme(req.uri(), &host, config) {
+                Some(scheme) => scheme,
+                None => return Target::Local,
+            },
+        };
+        let path = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+        format!("{}://{}{}", scheme, host, path)
+            .parse::<Uri>()
+            .ok()
+            .and_then(|uri| canonical(&uri))
+    };
+    let Some(uri) = uri else {
+        return Target::BadRequest("invalid request target");
+    };
+    // 只比较监听地址发现不了经过域名、其他代理或兄弟实例绕回来的请求，Via 中已有本代理时拒绝
+    if looped(req.headers()) {
+        return Target::LoopDetected;
+    }
+    let routed = config.route(&uri).is_some_and(|route| route.host.is_some());
+    if config.private_targets_denied() && !routed && is_private_target(&uri) {
+        return Target::Forbidden("private and loopback targets are not allowed");
+    }
+    set_target(req, uri);
+    // 回源总是使用 HTTP/1.1，HTTP/1.0 客户端的响应版本由 hyper 降级
+    *req.version_mut() = Version::HTTP_11;
+    Target::Proxy
+}
+
+// 路由的 URL 改写规则给出的新目标，同样规范化；不是 http/https 的绝对 URL 时返回 false
+pub fn retarget(req: &mut Request<Body>, target: &str) -> bool {
+    match target.parse::<Uri>().ok().and_then(|uri| canonical(&uri)) {
+        Some(uri) => {
+            set_target(req, uri);
+            true
+        }
+        None => false,
+    }
+}
+
+// Host 总是与目标 URL 一致
+fn set_target(req: &mut Request<Body>, uri: Uri) {
+    if let Some(authority) = uri.authority().and_then(|a| HeaderValue::from_str(a.as_str()).ok()) {
+        req.headers_mut().insert(HOST, authority);
+    }
+    *req.uri_mut() = uri;
+}
+
+// 反向代理：只有 Host 命中了指定 host 的路由才转发，否则任何 Host 都会让代理变成开放代理
+fn reverse_scheme<'a>(uri: &Uri, host: &Authority, config: &'a Config) -> Option<&'a str> {
+    let route = config
+        .routes
+        .iter()
+        .filter(|route| route.host.as_deref().is_some_and(|h| h.eq_ignore_ascii_case(host.host())))
+        .find(|route| {
+            route
+                .path_prefix
+                .as_deref()
+                .is_none_or(|prefix| uri.path().starts_with(prefix))
+        })?;
+    Some(route.scheme.as_deref().unwrap_or("http"))
+}
+
+// 监听在所有地址上时只能识别出本机回环地址
+fn is_listen_addr(host: &Authority, config: &Config) -> bool {
+    let listen = config.listen;
+    if host.port_u16().unwrap_or(80) != listen.port() {
+        return false;
+    }
+    match host.host().trim_start_matches('[').trim_end_matches(']') {
+        "localhost" => true,
+        ip => ip
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip == listen.ip() || (listen.ip().is_unspecified() && ip.is_loopback())),
+    }
+}
+
+// Via 的每一跳为“协议版本 名字 [注释]”，名字与本代理相同即已经过本代理
+fn looped(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(VIA)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|hop| hop.split_whitespace().nth(1).is_some_and(|by| by.eq_ignore_ascii_case(VIA_PSEUDONYM)))
+}
+
+// 目标主机是 localhost、回环、私有网络、链路本地或未指定地址。只检查 URL 中的地址，
+// 解析到内网地址的域名不在此列；最后一段以数字开头却不是合法 IPv4 的主机（如 127.1、2130706433）
+// 会被解析器当作地址，同样拒绝
+pub(crate) fn is_private_target(uri: &Uri) -> bool {
+    let Some(host) = uri.host() else {
+        return false;
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host == "localhost" || host.ends_with(".localhost") {
+        return true;
+    }
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => is_private_v4(ip),
+        Ok(IpAddr::V6(ip)) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_private_v4(ip),
+            None => ip.is_loopback() || ip.is_unspecified() || ip.is_unique_local() || ip.is_unicast_link_local(),
+        },
+        Err(_) => host
+            .trim_end_matches('.')
+            .rsplit('.')
+            .next()
+            .is_some_and(|label| label.starts_with(|c: char| c.is_ascii_digit())),
+    }
+}
+
+fn is_private_v4(ip: Ipv4Addr) -> bool {
+    ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified()
+}
+
+// 协议与主机名小写，去掉默认端口，空路径补为 /
+fn canonical(uri: &Uri) -> Option<Uri> {
+    let scheme = uri.scheme_str()?.to_ascii_lowercase();
+    let authority = uri.authority()?;
+    let default_port = match scheme.as_str() {
+        "http" => 80,
+        "https" => 443,
+        _ => return None,
+    };
+    let host = authority.host().to_ascii_lowercase();
+    let authority = match authority.port_u16() {
+        Some(port) if port != default_port => format!("{}:{}", host, port),
+        _ => host,
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.scheme = Some(scheme.parse::<Scheme>().ok()?);
+    parts.authority = Some(authority.parse().ok()?);
+    if parts.path_and_query.as_ref().is_none_or(|p| p.as_str().is_empty()) {
+        parts.path_and_query = Some("/".parse().ok()?);
+    }
+    Uri::from_parts(parts).ok()
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, UnixStream};
+
+use crate::constants::{UPGRADE_LISTEN_FDS_ENV, UPGRADE_READY_FD_ENV, UPGRADE_READY_TIMEOUT_SECONDS};
+
+// 不停机升级：收到 SIGUSR2 时按原来的命令行启动新的可执行文件，监听 socket 通过文件描述符
+// 继承交给它。新进程就绪后旧进程不再接受新连接，处理完已有连接（包括正在播放的视频）后退出；
+// 两个进程共用同一个 socket，交接期间到达的连接在队列中等待新进程接受，不会被拒绝。
+// 缓存目录同一时刻只由一个进程写：旧进程先停止写盘并释放目录锁，新进程取得锁后才打开缓存
+
+// 上一个进程交给本进程的监听 socket：监听地址 -> 文件描述符
+static INHERITED: LazyLock<Mutex<HashMap<SocketAddr, RawFd>>> = LazyLock::new(|| {
+    let value = std::env::var(UPGRADE_LISTEN_FDS_ENV).unwrap_or_default();
+    let fds = value
+        .split(',')
+        .filter_map(|pair| {
+            let (addr, fd) = pair.split_once('=')?;
+            Some((addr.parse().ok()?, fd.parse().ok()?))
+        })
+        .collect();
+    Mutex::new(fds)
+});
+
+// 取出继承的、监听在 addr 上的 socket，没有时由调用者自己绑定
+pub fn take_listener(addr: SocketAddr) -> Result<Option<TcpListener>> {
+    let Some(fd) = INHERITED.lock().unwrap().remove(&addr) else {
+        return Ok(None);
+    };
+    set_cloexec(fd, true).with_context(|| format!("inherited socket {} for {} is not valid", fd, addr))?;
+    // SAFETY: 文件描述符由上一个进程通过环境变量交给本进程，只会被取出一次
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    listener.set_nonblocking(true)?;
+    Ok(Some(TcpListener::from_std(listener)?))
+}
+
+// 本进程是否由升级启动，接替仍在运行的上一个进程
+pub fn is_successor() -> bool {
+    std::env::var_os(UPGRADE_READY_FD_ENV).is_some()
+}
+
+// 所有端口绑定完成后调用：关闭配置中已不再使用的继承 socket，并通知上一个进程可以退出
+pub fn finish_startup() {
+    for (addr, fd) in INHERITED.lock().unwrap().drain() {
+        tracing::info!("closing inherited listener {} that is no longer configured", addr);
+        // SAFETY: 同上，取出后不再使用
+        drop(unsafe { std::net::TcpListener::from_raw_fd(fd) });
+    }
+    let Some(fd) = std::env::var(UPGRADE_READY_FD_ENV).ok().and_then(|fd| fd.parse::<RawFd>().ok()) else {
+        return;
+    };
+    // SAFETY: 同上
+    let mut ready = unsafe { std::os::unix::net::UnixStream::from_raw_fd(fd) };
+    if let Err(e) = ready.write_all(b"1") {
+        tracing::warn!("failed to notify the previous process: {}", e);
+    }
+}
+
+// 启动新进程并把监听 socket 交给它，等到它报告就绪才返回。
+// 失败（新进程启动失败、配置有误退出、超时）时旧进程继续服务
+pub async fn spawn_successor(listeners: &[(SocketAddr, RawFd)]) -> Result<()> {
+    let (mut ours, theirs) = UnixStream::pair()?;
+    let mut child = tokio::process::Command::from(successor_command(listeners, theirs.as_raw_fd())?)
+        .spawn()
+        .context("failed to start the new process")?;
+    drop(theirs);
+
+    let timeout = Duration::from_secs(UPGRADE_READY_TIMEOUT_SECONDS);
+    let mut byte = [0u8; 1];
+    tokio::select! {
+        read = ours.read(&mut byte) => {
+            if !matches!(read, Ok(1)) {
+                bail!("new process exited before it was ready");
+            }
+        }
+        status = child.wait() => bail!("new process exited with {}", status?),
+        _ = tokio::time::sleep(timeout) => {
+            let _ = child.kill().await;
+            bail!("new process was not ready after {}s", timeout.as_secs());
+        }
+    }
+    tracing::info!("upgrade: process {} took over the listeners", child.id().unwrap_or(0));
+    Ok(())
+}
+
+// 按原来的命令行启动，只有交接的文件描述符被新进程继承
+fn successor_command(listeners: &[(SocketAddr, RawFd)], ready_fd: RawFd) -> Result<Command> {
+    let mut fds: Vec<RawFd> = listeners.iter().map(|(_, fd)| *fd).collect();
+    fds.push(ready_fd);
+    let value: Vec<String> = listeners.iter().map(|(addr, fd)| format!("{}={}", addr, fd)).collect();
+
+    // 按 argv[0] 而不是 /proc/self/exe 启动：可执行文件被替换后，后者指向的是已删除的旧文件
+    let mut args = std::env::args_os();
+    let program = args.next().context("missing program name")?;
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .env(UPGRADE_LISTEN_FDS_ENV, value.join(","))
+        .env(UPGRADE_READY_FD_ENV, ready_fd.to_string());
+    // SAFETY: fork 之后只调用 fcntl，不分配内存
+    unsafe {
+        command.pre_exec(move || {
+            for fd in &fds {
+                set_cloexec(*fd, false)?;
+            }
+            Ok(())
+        });
+    }
+    Ok(command)
+}
+
+fn set_cloexec(fd: RawFd, cloexec: bool) -> io::Result<()> {
+    let flags = if cloexec { libc::FD_CLOEXEC } else { 0 };
+    // SAFETY: 只修改文件描述符的标志
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, flags) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
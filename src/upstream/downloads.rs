@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::Instant;
+
+use bytes::Bytes;
+use futures::task::AtomicWaker;
+use futures::{Stream, StreamExt};
+use hyper::{Body, Response};
+use serde::Serialize;
+
+// 发起下载的客户端请求
+#[derive(Clone)]
+struct ClientRequest {
+    // 客户端是否已断开（开启 complete_in_background 时下载在断开后继续）
+    gone: Arc<AtomicBool>,
+    // 重启后可以重新发起以续传的 URL，只有不带 Range 的 GET 请求才有
+    resume_url: Option<String>,
+}
+
+tokio::task_local! {
+    static CLIENT: ClientRequest;
+}
+
+// 在客户端请求的上下文中执行，期间开始的下载与该客户端关联
+pub async fn with_client<F: Future>(
+    gone: Arc<AtomicBool>,
+    resume_url: Option<String>,
+    fut: F,
+) -> F::Output {
+    CLIENT.scope(ClientRequest { gone, resume_url }, fut).await
+}
+
+// 代理正在退出，不再发起新的上游请求
+#[derive(Debug)]
+pub struct DownloadsInterrupted;
+
+impl std::fmt::Display for DownloadsInterrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "proxy is shutting down")
+    }
+}
+
+impl std::error::Error for DownloadsInterrupted {}
+
+// 正在从源站读取的响应体
+struct Download {
+    url: String,
+    total: Option<u64>,
+    started: Instant,
+    fetched: AtomicU64,
+    cancelled: AtomicBool,
+    // 取消时唤醒正在等待数据的读取方
+    waker: AtomicWaker,
+    // None 表示不是客户端请求发起的（如提前刷新）
+    client: Option<ClientRequest>,
+}
+
+impl Download {
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.waker.wake();
+    }
+}
+
+#[derive(Serialize)]
+pub struct DownloadStatus {
+    pub id: u64,
+    pub url: String,
+    pub fetched_bytes: u64,
+    pub total_bytes: Option<u64>,
+    pub elapsed_secs: f64,
+    pub bytes_per_sec: f64,
+    pub eta_secs: Option<f64>,
+    // 没有客户端在等待这次下载
+    pub background: bool,
+}
+
+#[derive(Default)]
+pub struct Downloads {
+    next_id: AtomicU64,
+    active: Mutex<HashMap<u64, Arc<Download>>>,
+    interrupted: AtomicBool,
+}
+
+impl Downloads {
+    // 登记响应体，进度随响应体被读取而更新，读完或被丢弃时注销
+    pub fn track(self: &Arc<Self>, url: String, response: Response<Body>) -> Response<Body> {
+        let total = response
+            .headers()
+            .get(hyper::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        if total == Some(0) {
+            return response;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let download = Arc::new(Download {
+            url,
+            total,
+            started: Instant::now(),
+            fetched: AtomicU64::new(0),
+            cancelled: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+            client: CLIENT.try_with(|client| client.clone()).ok(),
+        });
+        self.active.lock().unwrap().insert(id, download.clone());
+
+        let (parts, body) = response.into_parts();
+        let body = Body::wrap_stream(DownloadBody {
+            inner: body,
+            id,
+            download,
+            downloads: self.clone(),
+        });
+        Response::from_parts(parts, body)
+    }
+
+    pub fn list(&self) -> Vec<DownloadStatus> {
+        let active = self.active.lock().unwrap();
+        let mut list: Vec<DownloadStatus> = active
+            .iter()
+            .map(|(&id, download)| {
+                let fetched = download.fetched.load(Ordering::Relaxed);
+                let elapsed = download.started.elapsed().as_secs_f64();
+                let speed = if elapsed > 0.0 { fetched as f64 / elapsed } else { 0.0 };
+                let eta = download
+                    .total
+                    .filter(|_| speed > 0.0)
+                    .map(|total| total.saturating_sub(fetched) as f64 / speed);
+                DownloadStatus {
+                    id,
+                    url: download.url.clone(),
+                    fetched_bytes: fetched,
+                    total_bytes: download.total,
+                    elapsed_secs: elapsed,
+                    bytes_per_sec: speed,
+                    eta_secs: eta,
+                    background: download
+                        .client
+                        .as_ref()
+                        .map(|client| client.gone.load(Ordering::Relaxed))
+                        .unwrap_or(true),
+                }
+            })
+            .collect();
+        list.sort_by_key(|status| status.id);
+        list
+    }
+
+    // 下载在读取下一块数据时失败，不会写入缓存；下载已结束时返回 false
+    pub fn cancel(&self, id: u64) -> bool {
+        match self.active.lock().unwrap().get(&id) {
+            Some(download) => {
+                download.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // 退出前中断所有下载：读取方按源站中断处理，把已收到的数据作为部分内容写入缓存。
+    // 之后的上游请求直接失败。返回可在重启后续传的 URL
+    pub fn interrupt(&self) -> Vec<String> {
+        self.interrupted.store(true, Ordering::Relaxed);
+        let active = self.active.lock().unwrap();
+        let mut urls: Vec<String> = active
+            .values()
+            .filter_map(|download| {
+                download.cancel();
+                download.client.as_ref()?.resume_url.clone()
+            })
+            .collect();
+        urls.sort();
+        urls.dedup();
+        urls
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.active.lock().unwrap().is_empty()
+    }
+}
+
+struct DownloadBody {
+    inner: Body,
+    id: u64,
+    download: Arc<Download>,
+    downloads: Arc<Downloads>,
+}
+
+impl Stream for DownloadBody {
+    type Item = Result<Bytes, Box<dyn std::error::Error + Send + Sync>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // 先登记唤醒，避免检查之后才到达的取消被错过
+        self.download.waker.register(cx.waker());
+        if self.download.cancelled.load(Ordering::Relaxed) {
+            let error = io::Error::new(io::ErrorKind::Interrupted, "download cancelled");
+            return Poll::Ready(Some(Err(Box::new(error))));
+        }
+        match ready!(self.inner.poll_next_unpin(cx)) {
+            Some(Ok(chunk)) => {
+                self.download.fetched.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Some(Err(e)) => Poll::Ready(Some(Err(Box::new(e)))),
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+impl Drop for DownloadBody {
+    fn drop(&mut self) {
+        self.downloads.active.lock().unwrap().remove(&self.id);
+    }
+}
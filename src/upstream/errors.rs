@@ -0,0 +1,109 @@
+use std::io;
+
+use hyper_tls::native_tls;
+
+// 上游失败的分类，用于指标与错误响应，区分 DNS 故障与源站 5xx 等情况
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum UpstreamErrorKind {
+    Dns,
+    Connect,
+    Tls,
+    Timeout,
+    Reset,
+    Status5xx,
+    // 源站响应本身不合法（头部无法解析或超过上限）
+    InvalidResponse,
+    Other,
+}
+
+impl UpstreamErrorKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UpstreamErrorKind::Dns => "dns",
+            UpstreamErrorKind::Connect => "connect",
+            UpstreamErrorKind::Tls => "tls",
+            UpstreamErrorKind::Timeout => "timeout",
+            UpstreamErrorKind::Reset => "reset",
+            UpstreamErrorKind::Status5xx => "5xx",
+            UpstreamErrorKind::InvalidResponse => "invalid-response",
+            UpstreamErrorKind::Other => "other",
+        }
+    }
+
+    // 沿错误链查找最具体的原因
+    pub fn classify(e: &anyhow::Error) -> Self {
+        let mut kind = UpstreamErrorKind::Other;
+        for cause in e.chain() {
+            if cause.is::<InvalidResponse>() {
+                return UpstreamErrorKind::InvalidResponse;
+            }
+            if cause.downcast_ref::<native_tls::Error>().is_some() {
+                return UpstreamErrorKind::Tls;
+            }
+            // hyper 的 ConnectError 没有公开类型，只能按描述区分
+            if cause.to_string().starts_with("dns error") {
+                return UpstreamErrorKind::Dns;
+            }
+            if let Some(io) = cause.downcast_ref::<io::Error>() {
+                match io.kind() {
+                    io::ErrorKind::TimedOut => return UpstreamErrorKind::Timeout,
+                    io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof => return UpstreamErrorKind::Reset,
+                    io::ErrorKind::ConnectionRefused => return UpstreamErrorKind::Connect,
+                    _ => {}
+                }
+            }
+            if let Some(hyper) = cause.downcast_ref::<hyper::Error>() {
+                if hyper.is_timeout() {
+                    return UpstreamErrorKind::Timeout;
+                }
+                if hyper.is_incomplete_message() || hyper.is_closed() {
+                    return UpstreamErrorKind::Reset;
+                }
+                if hyper.is_parse() || hyper.is_parse_too_large() {
+                    return UpstreamErrorKind::InvalidResponse;
+                }
+                if hyper.is_connect() {
+                    kind = UpstreamErrorKind::Connect;
+                }
+            }
+        }
+        kind
+    }
+}
+
+// 重试耗尽后的上游错误，附带分类与源站
+#[derive(Debug)]
+pub struct UpstreamError {
+    pub kind: UpstreamErrorKind,
+    pub origin: String,
+    pub source: anyhow::Error,
+}
+
+impl std::fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "upstream {} error from {}: {}",
+            self.kind.as_str(),
+            self.origin,
+            self.source
+        )
+    }
+}
+
+impl std::error::Error for UpstreamError {}
+
+// 源站响应的头部超出限制
+#[derive(Debug)]
+pub struct InvalidResponse(pub String);
+
+impl std::fmt::Display for InvalidResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid upstream response: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidResponse {}
@@ -0,0 +1,241 @@
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+use crate::metrics::METRICS;
+
+// 请求优先级：并发已满时高优先级请求先获得上游连接
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    // 后台预取等批量下载
+    Low,
+    #[default]
+    Normal,
+    // 播放列表、小对象等交互请求
+    High,
+}
+
+impl Priority {
+    pub fn parse(value: &str) -> Option<Priority> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "low" | "bulk" => Some(Priority::Low),
+            "normal" => Some(Priority::Normal),
+            "high" | "interactive" => Some(Priority::High),
+            _ => None,
+        }
+    }
+}
+
+tokio::task_local! {
+    static PRIORITY: Priority;
+}
+
+// 在给定优先级下执行请求处理，期间发往上游的请求都使用该优先级
+pub async fn with_priority<F: Future>(priority: Priority, fut: F) -> F::Output {
+    PRIORITY.scope(priority, fut).await
+}
+
+pub fn current_priority() -> Priority {
+    PRIORITY.try_with(|p| *p).unwrap_or_default()
+}
+
+// 源站并发数已满且排队超时
+#[derive(Debug)]
+pub struct UpstreamBusy(pub String);
+
+impl fmt::Display for UpstreamBusy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "too many concurrent requests to {}", self.0)
+    }
+}
+
+impl std::error::Error for UpstreamBusy {}
+
+type Gates = Mutex<HashMap<String, Arc<PriorityGate>>>;
+
+// 每个源站一个按优先级排队的闸门，名额全部归还后移除
+pub struct HostLimiter {
+    max_per_host: usize,
+    queue_timeout: Option<Duration>,
+    hosts: Arc<Gates>,
+}
+
+impl HostLimiter {
+    pub fn new(max_per_host: usize, queue_timeout: Option<Duration>) -> Self {
+        HostLimiter {
+            max_per_host: max_per_host.max(1),
+            queue_timeout,
+            hosts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // 当前有请求在进行或排队的源站数
+    pub fn active_hosts(&self) -> usize {
+        self.hosts.lock().unwrap().len()
+    }
+
+    pub async fn acquire(&self, host: &str, priority: Priority) -> Result<GatePermit> {
+        // 持有 hosts 锁时取得许可，闸门不会在取得许可前被当作空闲移除
+        let waiting = {
+            let mut hosts = self.hosts.lock().unwrap();
+            let gate = hosts.entry(host.to_string()).or_insert_with(|| {
+                Arc::new(PriorityGate::new(host, self.max_per_host, Arc::downgrade(&self.hosts)))
+            });
+            match PriorityGate::try_acquire(gate, priority) {
+                Ok(permit) => return Ok(permit),
+                Err(waiting) => waiting,
+            }
+        };
+
+        match self.queue_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, waiting).await {
+                Ok(permit) => Ok(permit?),
+                Err(_) => {
+                    METRICS.upstream_requests_shed.fetch_add(1, Ordering::Relaxed);
+                    Err(UpstreamBusy(host.to_string()).into())
+                }
+            },
+            None => Ok(waiting.await?),
+        }
+    }
+}
+
+struct Waiter {
+    priority: Priority,
+    seq: u64,
+    tx: oneshot::Sender<GatePermit>,
+}
+
+// 优先级高的先出队，同优先级按到达顺序
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+struct GateState {
+    available: usize,
+    waiters: BinaryHeap<Waiter>,
+}
+
+pub struct PriorityGate {
+    host: String,
+    permits: usize,
+    state: Mutex<GateState>,
+    seq: AtomicU64,
+    // 所属 HostLimiter 的闸门表，空闲时从中移除自己
+    hosts: Weak<Gates>,
+}
+
+impl PriorityGate {
+    fn new(host: &str, permits: usize, hosts: Weak<Gates>) -> Self {
+        PriorityGate {
+            host: host.to_string(),
+            permits,
+            state: Mutex::new(GateState {
+                available: permits,
+                waiters: BinaryHeap::new(),
+            }),
+            seq: AtomicU64::new(0),
+            hosts,
+        }
+    }
+
+    // 有空闲名额时立即返回许可，否则登记排队并返回等待用的 receiver
+    fn try_acquire(
+        gate: &Arc<PriorityGate>,
+        priority: Priority,
+    ) -> std::result::Result<GatePermit, oneshot::Receiver<GatePermit>> {
+        let mut state = gate.state.lock().unwrap();
+        if state.available > 0 {
+            state.available -= 1;
+            return Ok(GatePermit::new(gate));
+        }
+        let (tx, rx) = oneshot::channel();
+        state.waiters.push(Waiter {
+            priority,
+            seq: gate.seq.fetch_add(1, Ordering::Relaxed),
+            tx,
+        });
+        Err(rx)
+    }
+
+    // 许可直接转交给优先级最高的等待者；等待者已放弃时跳过
+    fn release(gate: &Arc<PriorityGate>) {
+        {
+            let mut state = gate.state.lock().unwrap();
+            while let Some(waiter) = state.waiters.pop() {
+                match waiter.tx.send(GatePermit::new(gate)) {
+                    Ok(()) => return,
+                    // 未送达的许可不能触发释放，否则会在持锁时重入 release
+                    Err(mut permit) => {
+                        permit.gate.take();
+                    }
+                }
+            }
+            state.available += 1;
+            if state.available < gate.permits {
+                return;
+            }
+        }
+        Self::remove_if_idle(gate);
+    }
+
+    // 名额全部归还后从闸门表中移除，访问过的源站不会一直占用内存。
+    // 与 acquire 相同，先锁闸门表再锁状态，期间不会有新的请求取得这个闸门的许可
+    fn remove_if_idle(gate: &Arc<PriorityGate>) {
+        let Some(hosts) = gate.hosts.upgrade() else {
+            return;
+        };
+        let mut hosts = hosts.lock().unwrap();
+        let idle = gate.state.lock().unwrap().available == gate.permits;
+        if idle && hosts.get(&gate.host).is_some_and(|current| Arc::ptr_eq(current, gate)) {
+            hosts.remove(&gate.host);
+        }
+    }
+}
+
+pub struct GatePermit {
+    gate: Option<Arc<PriorityGate>>,
+}
+
+impl GatePermit {
+    fn new(gate: &Arc<PriorityGate>) -> Self {
+        GatePermit {
+            gate: Some(gate.clone()),
+        }
+    }
+}
+
+impl Drop for GatePermit {
+    fn drop(&mut self) {
+        if let Some(gate) = self.gate.take() {
+            PriorityGate::release(&gate);
+        }
+    }
+}
@@ -0,0 +1,342 @@
+mod downloads;
+mod errors;
+mod limiter;
+mod origins;
+mod peers;
+mod stall;
+mod tls;
+
+use std::num::NonZeroUsize;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use hyper::header::{HeaderMap, HeaderName, AUTHORIZATION};
+use hyper::{Body, Client, Method, Request, Response, StatusCode};
+use lru::LruCache;
+
+use crate::clock::{self, SharedClock};
+use crate::config::{Config, RedirectConfig};
+use crate::connector::TrackedConnector;
+use crate::constants::ORIGIN_META_CACHE_SIZE;
+use crate::debug;
+use crate::origin_auth::OriginSigners;
+
+pub use downloads::{with_client, DownloadStatus, Downloads, DownloadsInterrupted};
+pub use errors::{InvalidResponse, UpstreamError, UpstreamErrorKind};
+pub use limiter::{current_priority, with_priority, GatePermit, HostLimiter, Priority, UpstreamBusy};
+pub use origins::{apply_connect_to, origin_of, rewrite_to_origin, OriginSelector};
+pub use peers::PeerSet;
+pub use stall::BodyStalled;
+pub use tls::OriginTlsConnector;
+
+pub type InnerClient = Client<TrackedConnector<OriginTlsConnector>>;
+
+// 访问源站的客户端：在 hyper 连接池之上增加按源站的并发限制与延迟统计
+#[derive(Clone)]
+pub struct HttpClient {
+    inner: InnerClient,
+    limiter: Option<Arc<HostLimiter>>,
+    origin_meta: Arc<OriginMetaCache>,
+    origins: Arc<OriginSelector>,
+    peers: Option<Arc<PeerSet>>,
+    retry: Arc<RetryPolicy>,
+    downloads: Arc<Downloads>,
+    header_limits: HeaderLimits,
+    body_stall_timeout: Duration,
+    redirects: Arc<RedirectPolicy>,
+    // 路由配置的回源签名，每次发出请求（包括重试与续传）时重新签名
+    signers: Option<Arc<OriginSigners>>,
+    clock: SharedClock,
+}
+
+// 源站响应头部的上限
+#[derive(Clone, Copy)]
+struct HeaderLimits {
+    max_count: usize,
+    max_bytes: usize,
+}
+
+impl HeaderLimits {
+    fn check(&self, headers: &HeaderMap) -> Result<(), InvalidResponse> {
+        if headers.len() > self.max_count {
+            return Err(InvalidResponse(format!(
+                "{} header fields exceed the limit of {}",
+                headers.len(),
+                self.max_count
+            )));
+        }
+        let bytes: usize = headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum();
+        if bytes > self.max_bytes {
+            return Err(InvalidResponse(format!(
+                "{} bytes of headers exceed the limit of {}",
+                bytes, self.max_bytes
+            )));
+        }
+        Ok(())
+    }
+}
+
+// 跟随源站重定向的设置；需要按最终 URL 缓存时保留一份配置用于计算缓存键
+pub struct RedirectPolicy {
+    pub config: RedirectConfig,
+    key_config: Option<Config>,
+    // 正向与透明代理默认不访问内网，跟随重定向时每一跳同样检查
+    pub(crate) deny_private: bool,
+}
+
+impl RedirectPolicy {
+    // 最终 URL 的缓存键按原请求的方法与请求头计算，与原请求属于同一个变体
+    pub fn cache_key<B>(&self, req: &Request<B>) -> Option<String> {
+        self.key_config
+            .as_ref()
+            .map(|config| config.request_cache_key(req.method(), req.uri(), req.headers()))
+    }
+}
+
+// 只有幂等的请求可以在失败后自动重发
+struct RetryPolicy {
+    methods: Vec<Method>,
+    idempotency_key: Option<HeaderName>,
+}
+
+impl HttpClient {
+    pub fn new(inner: InnerClient, config: &Config) -> Self {
+        let upstream = &config.upstream;
+        let limiter = upstream.max_concurrent_per_host.map(|max| {
+            Arc::new(HostLimiter::new(
+                max,
+                upstream.queue_timeout_secs.map(Duration::from_secs),
+            ))
+        });
+        let origin_meta = Arc::new(OriginMetaCache::new(Duration::from_secs(
+            upstream.head_cache_ttl_secs,
+        )));
+
+        // 配置了多个源站的路由需要测量延迟
+        let origins = Arc::new(OriginSelector::new(
+            config
+                .routes
+                .iter()
+                .filter(|route| route.origins.len() > 1)
+                .flat_map(|route| route.origins.iter().cloned()),
+        ));
+        if !origins.origins().is_empty() {
+            origins::spawn_prober(
+                origins.clone(),
+                inner.clone(),
+                Duration::from_secs(upstream.origin_probe_interval_secs),
+            );
+        }
+
+        let peers = PeerSet::new(&config.peers).map(Arc::new);
+
+        // 配置已在启动时校验过
+        let retry = Arc::new(RetryPolicy {
+            methods: upstream
+                .retry_methods
+                .iter()
+                .filter_map(|m| Method::from_bytes(m.as_bytes()).ok())
+                .collect(),
+            idempotency_key: upstream
+                .idempotency_key_header
+                .as_ref()
+                .and_then(|h| HeaderName::from_bytes(h.as_bytes()).ok()),
+        });
+
+        HttpClient {
+            inner,
+            limiter,
+            origin_meta,
+            origins,
+            peers,
+            retry,
+            downloads: Arc::new(Downloads::default()),
+            header_limits: HeaderLimits {
+                max_count: upstream.max_response_headers,
+                max_bytes: upstream.max_response_header_bytes,
+            },
+            body_stall_timeout: Duration::from_secs(upstream.body_stall_timeout_secs),
+            redirects: Arc::new(RedirectPolicy {
+                config: upstream.redirects.clone(),
+                key_config: upstream.redirects.cache_final_url.then(|| config.clone()),
+                deny_private: config.private_targets_denied(),
+            }),
+            signers: OriginSigners::new(config).map(Arc::new),
+            clock: clock::system(),
+        }
+    }
+
+    // 重试退避使用的时间来源
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn clock(&self) -> &SharedClock {
+        &self.clock
+    }
+
+    pub fn redirects(&self) -> &RedirectPolicy {
+        &self.redirects
+    }
+
+    // 正在从源站读取的响应体
+    pub fn downloads(&self) -> &Downloads {
+        &self.downloads
+    }
+
+    // 方法在重试列表中，或客户端携带了幂等键
+    pub fn may_retry<B>(&self, req: &Request<B>) -> bool {
+        self.retry.methods.contains(req.method())
+            || self
+                .retry
+                .idempotency_key
+                .as_ref()
+                .is_some_and(|header| req.headers().contains_key(header))
+    }
+
+    // 兄弟代理，未配置时为 None
+    pub fn peers(&self) -> Option<&PeerSet> {
+        self.peers.as_deref()
+    }
+
+    pub fn origins(&self) -> &OriginSelector {
+        &self.origins
+    }
+
+    // HEAD 探测结果缓存
+    pub fn origin_meta(&self) -> &OriginMetaCache {
+        &self.origin_meta
+    }
+
+    pub async fn request(&self, mut req: Request<Body>) -> Result<Response<Body>> {
+        // 退出过程中不再回源，未完成的部分留到重启后续传
+        if self.downloads.is_interrupted() {
+            return Err(DownloadsInterrupted.into());
+        }
+        let origin = origin_of(req.uri());
+        let url = req.uri().to_string();
+        let is_head = req.method() == Method::HEAD;
+        let queued = Instant::now();
+        let permit = match &self.limiter {
+            Some(limiter) => {
+                let host = req
+                    .uri()
+                    .authority()
+                    .map(|a| a.as_str().to_string())
+                    .unwrap_or_default();
+                Some(limiter.acquire(&host, current_priority()).await?)
+            }
+            None => None,
+        };
+        // 排队之后再签名，签名时间与实际发出的时间一致；获取令牌的 future 较大，装箱后不占调用方的栈
+        if let Some(signers) = &self.signers {
+            Box::pin(signers.sign(&mut req, self.clock.now(), &self.inner)).await?;
+        }
+        let signed = self
+            .signers
+            .is_some()
+            .then(|| (req.uri().clone(), req.headers().get(AUTHORIZATION).cloned()));
+
+        let started = Instant::now();
+        let result = self.inner.request(req).await;
+        if let (Some(signers), Some((uri, authorization)), Ok(response)) = (&self.signers, &signed, &result) {
+            if response.status() == StatusCode::UNAUTHORIZED {
+                Box::pin(signers.rejected(uri, authorization.as_ref())).await;
+            }
+        }
+        self.origins.observe(&origin, started.elapsed(), result.is_ok());
+        debug::record(|d| {
+            d.queue_ms += started.duration_since(queued).as_millis() as u64;
+            d.upstream_ms += started.elapsed().as_millis() as u64;
+        });
+        let mut resp = result?;
+        self.header_limits.check(resp.headers())?;
+        if !is_head {
+            resp = self.downloads.track(url, resp);
+            resp = stall::watch(resp, self.body_stall_timeout);
+        }
+
+        let Some(permit) = permit else {
+            return Ok(resp);
+        };
+
+        // 主体读完（或被丢弃）之前仍计入并发数
+        let (parts, body) = resp.into_parts();
+        let body = Body::wrap_stream(PermitBody {
+            inner: body,
+            permit: Some(permit),
+        });
+        Ok(Response::from_parts(parts, body))
+    }
+}
+
+// 源站对象的元数据（来自 HEAD 响应）
+#[derive(Clone, Debug, Default)]
+pub struct OriginMeta {
+    pub total_size: Option<u64>,
+    pub etag: Option<String>,
+    pub accept_ranges: bool,
+}
+
+// 按 URL 缓存 HEAD 结果，避免同一对象反复向源站发起 HEAD 请求
+pub struct OriginMetaCache {
+    ttl: Duration,
+    entries: Mutex<LruCache<String, (Instant, OriginMeta)>>,
+}
+
+impl OriginMetaCache {
+    pub fn new(ttl: Duration) -> Self {
+        OriginMetaCache {
+            ttl,
+            entries: Mutex::new(LruCache::new(
+                NonZeroUsize::new(ORIGIN_META_CACHE_SIZE).unwrap(),
+            )),
+        }
+    }
+
+    pub fn get(&self, url: &str) -> Option<OriginMeta> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(url) {
+            Some((stored_at, meta)) if stored_at.elapsed() < self.ttl => Some(meta.clone()),
+            Some(_) => {
+                entries.pop(url);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn put(&self, url: String, meta: OriginMeta) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        self.entries.lock().unwrap().put(url, (Instant::now(), meta));
+    }
+}
+
+// 读到主体末尾或出错时立即归还许可
+struct PermitBody {
+    inner: Body,
+    permit: Option<GatePermit>,
+}
+
+impl Stream for PermitBody {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = ready!(self.inner.poll_next_unpin(cx));
+        if !matches!(item, Some(Ok(_))) {
+            self.permit.take();
+        }
+        Poll::Ready(item)
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use hyper::{Body, Request, Uri};
+
+use crate::config::Config;
+
+use crate::constants::ORIGIN_LATENCY_EWMA_ALPHA;
+
+use super::InnerClient;
+
+#[derive(Clone, Copy, Debug, Default)]
+struct OriginStats {
+    // 响应延迟的指数加权移动平均（毫秒），None 表示尚未测量
+    ewma_ms: Option<f64>,
+}
+
+// 为配置了多个源站的路由选择延迟最低的源站
+#[derive(Default)]
+pub struct OriginSelector {
+    stats: Mutex<HashMap<String, OriginStats>>,
+}
+
+impl OriginSelector {
+    pub fn new(origins: impl IntoIterator<Item = String>) -> Self {
+        OriginSelector {
+            stats: Mutex::new(
+                origins
+                    .into_iter()
+                    .map(|origin| (origin, OriginStats::default()))
+                    .collect(),
+            ),
+        }
+    }
+
+    // 未测量过的源站优先，以便尽快获得它的延迟数据
+    pub fn select<'a>(&self, origins: &'a [String]) -> Option<&'a String> {
+        let stats = self.stats.lock().unwrap();
+        origins.iter().min_by(|a, b| {
+            let latency = |origin: &String| {
+                stats
+                    .get(origin.as_str())
+                    .and_then(|s| s.ewma_ms)
+                    .unwrap_or(0.0)
+            };
+            latency(a).total_cmp(&latency(b))
+        })
+    }
+
+    // 只记录配置中的源站，避免正向代理流量让统计表无限增长
+    pub fn observe(&self, origin: &str, elapsed: Duration, ok: bool) {
+        let mut stats = self.stats.lock().unwrap();
+        let Some(entry) = stats.get_mut(origin) else {
+            return;
+        };
+        // 失败按超时惩罚处理，让该源站暂时排到后面
+        let sample = if ok {
+            elapsed.as_secs_f64() * 1000.0
+        } else {
+            elapsed.as_secs_f64().max(10.0) * 1000.0
+        };
+        entry.ewma_ms = Some(match entry.ewma_ms {
+            Some(prev) => prev + ORIGIN_LATENCY_EWMA_ALPHA * (sample - prev),
+            None => sample,
+        });
+    }
+
+    pub fn origins(&self) -> Vec<String> {
+        self.stats.lock().unwrap().keys().cloned().collect()
+    }
+
+    pub fn latency_ms(&self, origin: &str) -> Option<f64> {
+        self.stats.lock().unwrap().get(origin).and_then(|s| s.ewma_ms)
+    }
+}
+
+// "scheme://authority"，与路由配置中的源站写法一致
+pub fn origin_of(uri: &Uri) -> String {
+    match (uri.scheme_str(), uri.authority()) {
+        (Some(scheme), Some(authority)) => format!("{}://{}", scheme, authority),
+        _ => String::new(),
+    }
+}
+
+// 将请求改写到选中的源站，保留路径与查询参数
+pub fn rewrite_to_origin(uri: &Uri, origin: &str) -> Option<Uri> {
+    let origin: Uri = origin.parse().ok()?;
+    let mut parts = uri.clone().into_parts();
+    parts.scheme = origin.scheme().cloned();
+    parts.authority = origin.authority().cloned();
+    Uri::from_parts(parts).ok()
+}
+
+// 路由配置了 connect_to 时把请求发往该地址，Host 仍为源站域名（SNI 由连接器按地址选择）
+pub fn apply_connect_to(req: &mut Request<Body>, config: &Config) {
+    let Some(route) = config.route(req.uri()) else {
+        return;
+    };
+    let (Some(addr), Some(host)) = (route.connect_to, route.origin_host()) else {
+        return;
+    };
+    let host = match req.uri().port_u16() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    };
+    let origin = format!("{}://{}", req.uri().scheme_str().unwrap_or("http"), addr);
+    let (Some(uri), Ok(host)) = (rewrite_to_origin(req.uri(), &origin), host.parse()) else {
+        return;
+    };
+    *req.uri_mut() = uri;
+    req.headers_mut().insert(hyper::header::HOST, host);
+}
+
+// 后台定期用 HEAD 探测所有源站，未被选中的源站也能持续更新延迟
+pub(crate) fn spawn_prober(selector: Arc<OriginSelector>, client: InnerClient, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            for origin in selector.origins() {
+                let Ok(req) = Request::head(format!("{}/", origin)).body(Body::empty()) else {
+                    continue;
+                };
+                let started = Instant::now();
+                let result = tokio::time::timeout(interval, client.request(req)).await;
+                let ok = matches!(result, Ok(Ok(_)));
+                selector.observe(&origin, started.elapsed(), ok);
+            }
+        }
+    });
+}
@@ -0,0 +1,235 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::future::select_ok;
+use hyper::client::connect::{Connected, Connection};
+use hyper::header::{HeaderValue, CACHE_CONTROL, VIA};
+use hyper::service::Service;
+use hyper::{Body, Client, Request, Response, StatusCode, Uri};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+
+use crate::config::PeersConfig;
+use crate::constants::{PEER_HEADER, SHARD_HEADER, SHARD_VIRTUAL_NODES};
+use crate::metrics::METRICS;
+
+// 兄弟代理：本地未命中时先询问其他代理实例是否已缓存该对象
+pub struct PeerSet {
+    peers: Vec<Peer>,
+    timeout: Duration,
+    // 分片模式下的一致性哈希环
+    ring: Option<HashRing>,
+}
+
+struct Peer {
+    addr: String,
+    client: Client<PeerConnector>,
+}
+
+impl PeerSet {
+    pub fn new(config: &PeersConfig) -> Option<Self> {
+        if config.addrs.is_empty() {
+            return None;
+        }
+        let peers = config
+            .addrs
+            .iter()
+            .map(|addr| Peer {
+                addr: addr.clone(),
+                client: Client::builder().build(PeerConnector { addr: addr.clone() }),
+            })
+            .collect();
+        let ring = match (&config.shard, &config.self_addr) {
+            (true, Some(self_addr)) => Some(HashRing::new(self_addr, &config.addrs)),
+            (true, None) => {
+                tracing::warn!("peers.shard requires peers.self_addr, sharding disabled");
+                None
+            }
+            (false, _) => None,
+        };
+        Some(PeerSet {
+            peers,
+            timeout: Duration::from_millis(config.lookup_timeout_ms),
+            ring,
+        })
+    }
+
+    // 并发询问所有兄弟代理，返回第一个命中的完整响应
+    pub async fn lookup(&self, req: &Request<Body>) -> Option<Response<Body>> {
+        // 分片模式下对象只缓存在所属实例上，询问其他实例没有意义
+        if self.ring.is_some() {
+            return None;
+        }
+        let lookups = self.peers.iter().map(|peer| Box::pin(peer.fetch(req)));
+        match tokio::time::timeout(self.timeout, select_ok(lookups)).await {
+            Ok(Ok((resp, _))) => {
+                METRICS.peer_hits.fetch_add(1, Ordering::Relaxed);
+                Some(resp)
+            }
+            _ => None,
+        }
+    }
+
+    // 分片模式：对象不属于本实例时转发给所属实例，转发失败则返回 None 由本地处理
+    pub async fn forward_to_owner(
+        &self,
+        cache_key: &str,
+        req: &Request<Body>,
+    ) -> Option<Response<Body>> {
+        let owner = self.ring.as_ref()?.owner(cache_key)?;
+        let peer = &self.peers[owner];
+        let mut shard_req = Request::new(Body::empty());
+        *shard_req.method_mut() = req.method().clone();
+        *shard_req.uri_mut() = req.uri().clone();
+        *shard_req.headers_mut() = req.headers().clone();
+        // 集群内部的转发不算一跳，否则所属实例会按 Via 当作环路拒绝
+        shard_req.headers_mut().remove(VIA);
+        shard_req
+            .headers_mut()
+            .insert(SHARD_HEADER, HeaderValue::from_static("1"));
+
+        match peer.client.request(shard_req).await {
+            Ok(resp) => Some(resp),
+            Err(e) => {
+                tracing::warn!(
+                    "shard owner {} unavailable, serving locally: {}",
+                    peer.addr,
+                    e
+                );
+                None
+            }
+        }
+    }
+}
+
+// 一致性哈希环：每个成员放置多个虚拟节点，成员增减时只有少量对象换主
+struct HashRing {
+    // (哈希值, 成员下标)，None 表示本实例
+    points: Vec<(u64, Option<usize>)>,
+}
+
+impl HashRing {
+    fn new(self_addr: &str, peers: &[String]) -> Self {
+        let members = std::iter::once((self_addr, None)).chain(
+            peers
+                .iter()
+                .enumerate()
+                .map(|(i, addr)| (addr.as_str(), Some(i))),
+        );
+        let mut points: Vec<(u64, Option<usize>)> = members
+            .flat_map(|(addr, member)| {
+                (0..SHARD_VIRTUAL_NODES)
+                    .map(move |vnode| (ring_hash(&format!("{}#{}", addr, vnode)), member))
+            })
+            .collect();
+        points.sort_unstable_by_key(|(hash, _)| *hash);
+        HashRing { points }
+    }
+
+    fn owner(&self, key: &str) -> Option<usize> {
+        let hash = ring_hash(key);
+        let idx = self.points.partition_point(|(point, _)| *point < hash);
+        self.points[idx % self.points.len()].1
+    }
+}
+
+// 各实例必须算出相同的哈希，因此不使用带随机种子的 std 哈希
+fn ring_hash(value: &str) -> u64 {
+    let digest = Sha256::digest(value.as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+impl Peer {
+    async fn fetch(&self, req: &Request<Body>) -> Result<Response<Body>, ()> {
+        let mut peer_req = Request::new(Body::empty());
+        *peer_req.method_mut() = req.method().clone();
+        *peer_req.uri_mut() = req.uri().clone();
+        *peer_req.headers_mut() = req.headers().clone();
+        // 与转发给所属实例时一样去掉 Via
+        peer_req.headers_mut().remove(VIA);
+        // 只取兄弟代理的缓存，不让它再去访问源站或其他兄弟代理
+        peer_req
+            .headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_static("only-if-cached"));
+        peer_req
+            .headers_mut()
+            .insert(PEER_HEADER, HeaderValue::from_static("1"));
+
+        match self.client.request(peer_req).await {
+            Ok(resp) if resp.status() == StatusCode::OK => Ok(resp),
+            Ok(_) => Err(()),
+            Err(e) => {
+                tracing::debug!("peer {} lookup failed: {}", self.addr, e);
+                Err(())
+            }
+        }
+    }
+}
+
+// 总是连接到兄弟代理，并以代理方式（绝对 URI）发送请求
+#[derive(Clone)]
+struct PeerConnector {
+    addr: String,
+}
+
+impl Service<Uri> for PeerConnector {
+    type Response = PeerStream;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<PeerStream>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _uri: Uri) -> Self::Future {
+        let addr = self.addr.clone();
+        Box::pin(async move {
+            let stream = TcpStream::connect(addr).await?;
+            stream.set_nodelay(true)?;
+            Ok(PeerStream { inner: stream })
+        })
+    }
+}
+
+struct PeerStream {
+    inner: TcpStream,
+}
+
+impl Connection for PeerStream {
+    fn connected(&self) -> Connected {
+        Connected::new().proxy(true)
+    }
+}
+
+impl AsyncRead for PeerStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for PeerStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,70 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use hyper::{Body, Response};
+use tokio::time::{Instant, Sleep};
+
+// 源站发送响应头之后迟迟不发送主体数据
+#[derive(Debug)]
+pub struct BodyStalled(pub Duration);
+
+impl std::fmt::Display for BodyStalled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no body data from upstream for {}s", self.0.as_secs())
+    }
+}
+
+impl std::error::Error for BodyStalled {}
+
+// 读取方等待源站数据超过 timeout 时以超时错误结束主体，读取方不会永远挂起。
+// 计时从每次开始等待时算起，读取方（慢速客户端）自己处理数据花的时间不计入
+pub fn watch(response: Response<Body>, timeout: Duration) -> Response<Body> {
+    let (parts, body) = response.into_parts();
+    let body = Body::wrap_stream(StallBody {
+        inner: body,
+        timeout,
+        deadline: Box::pin(tokio::time::sleep(timeout)),
+        waiting: false,
+        stalled: false,
+    });
+    Response::from_parts(parts, body)
+}
+
+struct StallBody {
+    inner: Body,
+    timeout: Duration,
+    deadline: Pin<Box<Sleep>>,
+    // 上一次 poll 返回 Pending，仍在同一次等待中
+    waiting: bool,
+    stalled: bool,
+}
+
+impl Stream for StallBody {
+    type Item = Result<Bytes, io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.stalled {
+            return Poll::Ready(None);
+        }
+        if let Poll::Ready(item) = self.inner.poll_next_unpin(cx) {
+            self.waiting = false;
+            return Poll::Ready(item.map(|chunk| chunk.map_err(io::Error::other)));
+        }
+        if !self.waiting {
+            self.waiting = true;
+            let deadline = Instant::now() + self.timeout;
+            self.deadline.as_mut().reset(deadline);
+        }
+        if self.deadline.as_mut().poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        self.stalled = true;
+        let stalled = BodyStalled(self.timeout);
+        Poll::Ready(Some(Err(io::Error::new(io::ErrorKind::TimedOut, stalled))))
+    }
+}
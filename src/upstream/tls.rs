@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use anyhow::{Context as _, Result};
+use hyper::client::connect::{Connected, Connection};
+use hyper::client::HttpConnector;
+use hyper::service::Service;
+use hyper::Uri;
+use hyper_tls::native_tls::{self, Certificate, Identity, Protocol};
+use hyper_tls::MaybeHttpsStream;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_native_tls::TlsConnector;
+
+use crate::config::{Config, TlsVersion, UpstreamHttpVersion};
+use crate::metrics::METRICS;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// 按源站选择 TLS 设置的连接器：配置了覆盖的源站使用独立的 TlsConnector，其余使用全局设置
+#[derive(Clone)]
+pub struct OriginTlsConnector {
+    http: HttpConnector,
+    default: TlsConnector,
+    // host 或 host:port -> 连接器
+    origins: Arc<HashMap<String, TlsConnector>>,
+    // 路由的 connect_to 地址 -> 握手时使用的 SNI（同时用于证书校验与选择 TLS 设置）
+    server_names: Arc<HashMap<String, String>>,
+}
+
+impl OriginTlsConnector {
+    pub fn new(http: HttpConnector, config: &Config) -> Result<Self> {
+        let tls = &config.upstream.tls;
+        let alpn = config.upstream.http_version == UpstreamHttpVersion::Alpn;
+        let default = connector(&tls.ca_files, tls.min_version, None, false, alpn)?;
+        let mut origins = HashMap::new();
+        for origin in &tls.origins {
+            let ca_files: Vec<PathBuf> = tls.ca_files.iter().chain(&origin.ca_files).cloned().collect();
+            let identity = match (&origin.client_cert, &origin.client_key) {
+                (Some(cert), Some(key)) => Some((cert.clone(), key.clone())),
+                _ => None,
+            };
+            let connector = connector(
+                &ca_files,
+                origin.min_version.or(tls.min_version),
+                identity,
+                origin.insecure_skip_verify,
+                alpn && !origin.http1_only,
+            )
+            .with_context(|| format!("invalid TLS settings for {}", origin.host))?;
+            origins.insert(origin.host.to_ascii_lowercase(), connector);
+        }
+        let server_names = config
+            .routes
+            .iter()
+            .filter_map(|route| Some((route.connect_to?.to_string(), route.origin_host()?.to_string())))
+            .collect();
+        Ok(OriginTlsConnector {
+            http,
+            default,
+            origins: Arc::new(origins),
+            server_names: Arc::new(server_names),
+        })
+    }
+
+    // 先匹配 host:port，再匹配 host
+    fn select(&self, host: &str, port: Option<u16>) -> &TlsConnector {
+        let host = host.to_ascii_lowercase();
+        port.and_then(|port| self.origins.get(&format!("{}:{}", host, port)))
+            .or_else(|| self.origins.get(&host))
+            .unwrap_or(&self.default)
+    }
+}
+
+impl Service<Uri> for OriginTlsConnector {
+    type Response = OriginStream;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, BoxError>> + Send>>;
+
+    // HttpConnector 总是就绪
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let is_https = uri.scheme_str() == Some("https");
+        // 指定了连接地址的路由用配置的域名握手，而不是 URI 中的 IP
+        let server_name = uri
+            .authority()
+            .and_then(|authority| self.server_names.get(authority.as_str()))
+            .cloned()
+            .unwrap_or_else(|| {
+                uri.host()
+                    .unwrap_or_default()
+                    .trim_matches(|c| c == '[' || c == ']')
+                    .to_string()
+            });
+        let tls = self.select(&server_name, uri.port_u16()).clone();
+        let connecting = self.http.call(uri);
+        Box::pin(async move {
+            let tcp = connecting.await?;
+            if !is_https {
+                return Ok(OriginStream(MaybeHttpsStream::Http(tcp)));
+            }
+            let tls = tls.connect(&server_name, tcp).await.inspect_err(|_| {
+                METRICS.upstream_tls_handshake_failures.fetch_add(1, Ordering::Relaxed);
+            })?;
+            Ok(OriginStream(MaybeHttpsStream::Https(tls)))
+        })
+    }
+}
+
+// 到源站的连接。握手时协商出 h2 的连接需要告诉 hyper 在上面使用 HTTP/2
+pub struct OriginStream(MaybeHttpsStream<TcpStream>);
+
+impl Connection for OriginStream {
+    fn connected(&self) -> Connected {
+        let connected = self.0.connected();
+        match &self.0 {
+            MaybeHttpsStream::Https(tls)
+                if tls.get_ref().negotiated_alpn().ok().flatten().as_deref() == Some(b"h2") =>
+            {
+                connected.negotiated_h2()
+            }
+            _ => connected,
+        }
+    }
+}
+
+impl AsyncRead for OriginStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for OriginStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.0.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+fn connector(
+    ca_files: &[PathBuf],
+    min_version: Option<TlsVersion>,
+    identity: Option<(PathBuf, PathBuf)>,
+    insecure: bool,
+    alpn: bool,
+) -> Result<TlsConnector> {
+    let mut builder = native_tls::TlsConnector::builder();
+    for path in ca_files {
+        let pem = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        for cert in split_pem(&pem) {
+            builder.add_root_certificate(
+                Certificate::from_pem(cert.as_bytes())
+                    .with_context(|| format!("invalid certificate in {}", path.display()))?,
+            );
+        }
+    }
+    builder.min_protocol_version(min_version.map(|version| match version {
+        TlsVersion::Tls10 => Protocol::Tlsv10,
+        TlsVersion::Tls11 => Protocol::Tlsv11,
+        TlsVersion::Tls12 => Protocol::Tlsv12,
+    }));
+    if let Some((cert, key)) = identity {
+        let cert_pem = std::fs::read(&cert).with_context(|| format!("failed to read {}", cert.display()))?;
+        let key_pem = std::fs::read(&key).with_context(|| format!("failed to read {}", key.display()))?;
+        builder.identity(Identity::from_pkcs8(&cert_pem, &key_pem).context("invalid client certificate")?);
+    }
+    if insecure {
+        builder.danger_accept_invalid_certs(true);
+        builder.danger_accept_invalid_hostnames(true);
+    }
+    if alpn {
+        builder.request_alpns(&["h2", "http/1.1"]);
+    }
+    Ok(TlsConnector::from(builder.build()?))
+}
+
+// CA 文件可能包含多个 PEM 证书，native-tls 每次只解析一个
+fn split_pem(pem: &[u8]) -> Vec<String> {
+    const END: &str = "-----END CERTIFICATE-----";
+    let text = String::from_utf8_lossy(pem);
+    text.split_inclusive(END)
+        .filter(|block| block.contains("-----BEGIN CERTIFICATE-----"))
+        .map(|block| block.trim().to_string())
+        .collect()
+}
@@ -1,11 +1,20 @@
 use anyhow::Result;
-use hyper::{body, Body, Client, Request, Response};
-use hyper_tls::HttpsConnector;
+use bytes::Bytes;
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, COOKIE, HOST, LOCATION, PROXY_AUTHORIZATION,
+};
+use hyper::{body, Body, Method, Request, Response, Uri};
 use sha2::{Digest, Sha256};
-use std::{mem, time::Duration};
-use tokio::time::sleep;
+use std::{mem, sync::atomic::Ordering, time::Duration};
 
-use crate::constants::{MAX_RETRIES, RETRY_DELAY_MS, TIMEOUT_SECONDS};
+use crate::upstream::{
+    origin_of, DownloadsInterrupted, HttpClient, UpstreamBusy, UpstreamError, UpstreamErrorKind,
+};
+use crate::config::FetchPolicy;
+use crate::constants::RETRY_DELAY_MS;
+use crate::debug;
+use crate::metrics::METRICS;
+use crate::target::is_private_target;
 
 pub fn generate_cache_key(uri: &hyper::Uri) -> String {
     let mut hasher = Sha256::new();
@@ -13,6 +22,26 @@ pub fn generate_cache_key(uri: &hyper::Uri) -> String {
     hex::encode(hasher.finalize())
 }
 
+// 只保留满足条件的查询参数（按参数名判断），其余部分不变
+pub fn retain_query_params(uri: &hyper::Uri, keep: impl Fn(&str) -> bool) -> hyper::Uri {
+    let Some(query) = uri.query() else {
+        return uri.clone();
+    };
+    let kept: Vec<&str> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter(|pair| keep(pair.split_once('=').map_or(*pair, |(name, _)| name)))
+        .collect();
+    let path_and_query = if kept.is_empty() {
+        uri.path().to_string()
+    } else {
+        format!("{}?{}", uri.path(), kept.join("&"))
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    hyper::Uri::from_parts(parts).unwrap_or_else(|_| uri.clone())
+}
+
 pub fn parse_range(range: &str) -> Option<(u64, u64)> {
     let range = range.trim_start_matches("bytes=");
     let mut parts = range.split('-');
@@ -21,34 +50,220 @@ pub fn parse_range(range: &str) -> Option<(u64, u64)> {
     Some((start, end))
 }
 
+pub fn header_string(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+// 源站没有声明类型或只声明为通用二进制时，按响应体开头的特征字节识别的类型
+pub fn sniff_content_type(declared: Option<&str>, prefix: &[u8]) -> Option<&'static str> {
+    let media_type = declared
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+    if !matches!(media_type.as_str(), "" | "application/octet-stream" | "binary/octet-stream") {
+        return None;
+    }
+    infer::get(prefix).map(|kind| kind.mime_type())
+}
+
+// 构造续传请求：保留客户端的请求头，再设置 Range 与 If-Range，
+// 源站对象发生变化时 If-Range 使其返回完整的 200 响应
+pub fn resume_request(
+    req: &Request<Body>,
+    start: u64,
+    end: u64,
+    validator: Option<&str>,
+) -> Result<Request<Body>> {
+    let mut resume_req = Request::builder()
+        .method(req.method())
+        .uri(req.uri())
+        .body(Body::empty())?;
+    *resume_req.headers_mut() = req.headers().clone();
+
+    let headers = resume_req.headers_mut();
+    headers.insert(
+        hyper::header::RANGE,
+        format!("bytes={}-{}", start, end).parse()?,
+    );
+    headers.remove(hyper::header::IF_RANGE);
+    if let Some(validator) = validator {
+        headers.insert(hyper::header::IF_RANGE, validator.parse()?);
+    }
+    Ok(resume_req)
+}
+
+// 源站重定向的最终目标，附在跟随重定向后得到的响应上
+#[derive(Clone, Debug)]
+pub struct Redirected {
+    pub url: Uri,
+    // 开启 cache_final_url 时最终 URL 自己的缓存键
+    pub cache_key: Option<String>,
+}
+
+// 回源：失败时按策略重试，开启后跟随源站的重定向（只对 GET/HEAD）
 pub async fn fetch_with_retry(
-    client: &Client<HttpsConnector<hyper::client::HttpConnector>>,
+    client: &HttpClient,
     req: &Request<Body>,
+    fetch: FetchPolicy,
 ) -> Result<Response<Body>> {
+    let mut response = fetch_attempts(client, req, &Bytes::new(), fetch).await?;
+    let redirects = client.redirects();
+    let policy = &redirects.config;
+    if !policy.follow || (req.method() != Method::GET && req.method() != Method::HEAD) {
+        return Ok(response);
+    }
+    let mut current = clone_request(req).await?;
+    let mut hops = 0;
+    while let Some(target) = redirect_target(&current, &response) {
+        if hops >= policy.max_hops {
+            tracing::debug!("too many redirects from {}", req.uri());
+            break;
+        }
+        // 相对地址按当前请求的 URI 解析，与之同源（协议、主机与端口都相同）时沿用原来的 Host
+        let same_origin = target.scheme() == current.uri().scheme() && target.authority() == current.uri().authority();
+        let same_host = same_origin || target.host() == effective_host(&current).as_deref();
+        if policy.same_host_only && !same_host {
+            break;
+        }
+        // 公网源站不能把代理引到本机或内网，重定向原样返回给客户端
+        if redirects.deny_private && !same_origin && is_private_target(&target) {
+            tracing::debug!("not following redirect from {} to private target {}", current.uri(), target);
+            break;
+        }
+        hops += 1;
+        debug::record(|d| d.redirects += 1);
+        // 凭据不带到其他源
+        if !same_origin {
+            current.headers_mut().remove(AUTHORIZATION);
+            current.headers_mut().remove(PROXY_AUTHORIZATION);
+            current.headers_mut().remove(COOKIE);
+            if let Some(authority) = target.authority().and_then(|a| HeaderValue::from_str(a.as_str()).ok()) {
+                current.headers_mut().insert(HOST, authority);
+            }
+        }
+        *current.uri_mut() = target;
+        response = fetch_attempts(client, &current, &Bytes::new(), fetch).await?;
+    }
+    if hops > 0 {
+        let url = current.uri().clone();
+        let cache_key = client.redirects().cache_key(&current);
+        response.extensions_mut().insert(Redirected { url, cache_key });
+    }
+    Ok(response)
+}
+
+// 重定向响应中 Location 指向的绝对 URL；相对地址按请求的 URI 解析（经 connect_to 时即实际连接的地址），
+// 不使用 Host，否则下一跳的主机由客户端决定
+fn redirect_target(req: &Request<Body>, response: &Response<Body>) -> Option<Uri> {
+    let status = response.status();
+    let redirect = matches!(status.as_u16(), 301 | 302 | 303 | 307 | 308);
+    if !redirect {
+        return None;
+    }
+    let location = response.headers().get(LOCATION)?.to_str().ok()?;
+    let scheme = req.uri().scheme_str().unwrap_or("http");
+    let host = req.uri().authority()?.as_str();
+    let target = if let Some(rest) = location.strip_prefix("//") {
+        format!("{}://{}", scheme, rest)
+    } else if location.starts_with('/') {
+        format!("{}://{}{}", scheme, host, location)
+    } else if location.contains("://") {
+        location.to_string()
+    } else {
+        let path = req.uri().path();
+        let dir = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+        format!("{}://{}{}{}", scheme, host, dir, location)
+    };
+    let uri: Uri = target.parse().ok()?;
+    matches!(uri.scheme_str(), Some("http") | Some("https")).then_some(uri)
+}
+
+// 请求实际访问的主机名：经 connect_to 改写时 URI 中是地址，主机名在 Host 中
+fn effective_host(req: &Request<Body>) -> Option<String> {
+    let host = req.headers().get(HOST).and_then(|v| v.to_str().ok());
+    match host.and_then(|h| h.parse::<hyper::http::uri::Authority>().ok()) {
+        Some(authority) => Some(authority.host().to_string()),
+        None => req.uri().host().map(str::to_string),
+    }
+}
+
+// 已读入内存的请求体（可重试的其他方法）：失败时按策略重发，每次带上同一份请求体，不跟随重定向
+pub async fn fetch_replaying_body(
+    client: &HttpClient,
+    req: &Request<Body>,
+    body: Bytes,
+    fetch: FetchPolicy,
+) -> Result<Response<Body>> {
+    fetch_attempts(client, req, &body, fetch).await
+}
+
+// 每次尝试发送 req 的请求行与头部，以及 body 的副本
+async fn fetch_attempts(
+    client: &HttpClient,
+    req: &Request<Body>,
+    body: &Bytes,
+    fetch: FetchPolicy,
+) -> Result<Response<Body>> {
+    let origin = origin_of(req.uri());
+    // 非幂等请求重发可能在源站产生重复的副作用，失败时直接返回
+    let max_retries = if client.may_retry(req) { fetch.max_retries } else { 0 };
+    debug::record(|d| d.retryable = Some(max_retries > 0));
     let mut retries = 0;
     loop {
-        let cloned_req = clone_request(req).await.unwrap();
-            
-        match tokio::time::timeout(
-            Duration::from_secs(TIMEOUT_SECONDS),
-            client.request(cloned_req),
-        )
-        .await
+        let mut cloned_req = clone_request(req).await.unwrap();
+        *cloned_req.body_mut() = Body::from(body.clone());
+        METRICS.upstream_requests.fetch_add(1, Ordering::Relaxed);
+        if retries > 0 {
+            METRICS.upstream_retries.fetch_add(1, Ordering::Relaxed);
+        }
+        debug::record(|d| {
+            d.upstream_requests += 1;
+            if retries > 0 {
+                d.retries += 1;
+            }
+        });
+
+        let (kind, error) = match tokio::time::timeout(fetch.timeout, client.request(cloned_req)).await
         {
-            Ok(Ok(response)) => return Ok(response),
-            Ok(Err(e)) => {
-                if retries >= MAX_RETRIES {
-                    return Err(e.into());
+            Ok(Ok(response)) => {
+                // 5xx 仍原样返回给客户端，只计入错误分类
+                if response.status().is_server_error() {
+                    METRICS.upstream_error(&origin, UpstreamErrorKind::Status5xx.as_str());
                 }
+                return Ok(response);
             }
-            Err(_) => {
-                if retries >= MAX_RETRIES {
-                    return Err(anyhow::anyhow!("Request timed out after {} retries", MAX_RETRIES));
-                }
+            // 排队超时说明源站已饱和，重试只会加重拥塞；代理正在退出时也不再重试
+            Ok(Err(e)) if e.is::<UpstreamBusy>() || e.is::<DownloadsInterrupted>() => {
+                return Err(e)
+            }
+            Ok(Err(e)) => (UpstreamErrorKind::classify(&e), e),
+            Err(_) => (
+                UpstreamErrorKind::Timeout,
+                anyhow::anyhow!("no response within {:?}", fetch.timeout),
+            ),
+        };
+        METRICS.upstream_error(&origin, kind.as_str());
+        tracing::debug!(
+            "upstream {} error from {} (attempt {}): {:#}",
+            kind.as_str(),
+            origin,
+            retries + 1,
+            error
+        );
+        // 源站返回的头部不合法时重试也得到同样的结果
+        if retries >= max_retries || kind == UpstreamErrorKind::InvalidResponse {
+            return Err(UpstreamError {
+                kind,
+                origin,
+                source: error,
             }
+            .into());
         }
         retries += 1;
-        sleep(Duration::from_millis(RETRY_DELAY_MS)).await;
+        client.clock().sleep(Duration::from_millis(RETRY_DELAY_MS)).await;
     }
 }
 
@@ -60,7 +275,7 @@ pub async fn clone_request(req: &Request<Body>) -> Result<Request<Body>> {
    *new_req.method_mut() = req.method().clone();
    *new_req.uri_mut() = req.uri().clone();
    *new_req.headers_mut() = req.headers().clone();
-   *new_req.version_mut() = req.version().clone();
+   *new_req.version_mut() = req.version();
    
    Ok(new_req)
 }
@@ -75,7 +290,7 @@ pub async fn clone_request_with_body(req: &mut Request<Body>) -> Result<Request<
    *new_req.method_mut() = req.method().clone();
    *new_req.uri_mut() = req.uri().clone();
    *new_req.headers_mut() = req.headers().clone();
-   *new_req.version_mut() = req.version().clone();
+   *new_req.version_mut() = req.version();
    
    Ok(new_req)
 }
@@ -0,0 +1,125 @@
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use rust_proxy_server::admin::{self, authenticate};
+use rust_proxy_server::cache::ProxyCache;
+use rust_proxy_server::client;
+use rust_proxy_server::config::{AdminConfig, AdminRole, AdminToken, Config};
+
+fn bearer(token: &str) -> Request<Body> {
+    Request::get("/stats")
+        .header(hyper::header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[test]
+fn tokens_map_to_named_roles() {
+    let admin = AdminConfig {
+        token: Some("legacy".to_string()),
+        tokens: vec![
+            AdminToken {
+                name: "grafana".to_string(),
+                token: "dash".to_string(),
+                role: AdminRole::ReadOnly,
+            },
+            AdminToken {
+                name: "ops".to_string(),
+                token: "root".to_string(),
+                role: AdminRole::Admin,
+            },
+        ],
+        ..AdminConfig::default()
+    };
+
+    let dashboard = authenticate(&bearer("dash"), &admin).unwrap();
+    assert_eq!((dashboard.name.as_str(), dashboard.role), ("grafana", AdminRole::ReadOnly));
+    let ops = authenticate(&bearer("root"), &admin).unwrap();
+    assert_eq!((ops.name.as_str(), ops.role), ("ops", AdminRole::Admin));
+    // 旧的 admin.token 仍然是 admin
+    assert_eq!(authenticate(&bearer("legacy"), &admin).unwrap().role, AdminRole::Admin);
+    assert!(authenticate(&bearer("wrong"), &admin).is_none());
+    assert!(authenticate(&Request::get("/stats").body(Body::empty()).unwrap(), &admin).is_none());
+}
+
+#[test]
+fn no_tokens_means_anonymous_admin() {
+    let principal = authenticate(&bearer("anything"), &AdminConfig::default()).unwrap();
+    assert_eq!((principal.name.as_str(), principal.role), ("anonymous", AdminRole::Admin));
+}
+
+#[tokio::test]
+async fn purges_are_forwarded_with_an_admin_token() {
+    // 其他实例：记录收到的 Authorization
+    let seen: Arc<Mutex<Vec<Option<String>>>> = Arc::default();
+    let log = seen.clone();
+    let make = make_service_fn(move |_| {
+        let log = log.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let authorization = req
+                    .headers()
+                    .get(hyper::header::AUTHORIZATION)
+                    .map(|v| v.to_str().unwrap().to_string());
+                log.lock().unwrap().push(authorization);
+                async { Ok::<_, Infallible>(Response::new(Body::from("{}"))) }
+            }))
+        }
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+    let peer = server.local_addr();
+    tokio::spawn(server);
+
+    // 只有 tokens，没有旧的 admin.token
+    let config = Config::parse(&format!(
+        r#"
+        [admin]
+        listen = "127.0.0.1:0"
+        peers = ["{}"]
+
+        [[admin.tokens]]
+        name = "grafana"
+        token = "dash"
+        role = "read_only"
+
+        [[admin.tokens]]
+        name = "ops"
+        token = "root"
+        role = "admin"
+        "#,
+        peer
+    ))
+    .unwrap();
+    config.validate().unwrap();
+    assert_eq!(config.admin.peer_token(), Some("root"));
+    let config = Arc::new(config);
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ProxyCache::builder().dir(dir.path()).build().await.unwrap());
+    let client = client::build(&config).unwrap();
+    let req = Request::post("/cache/purge").body(Body::from("http://origin.test/a")).unwrap();
+    let response = admin::handle_admin_request(req, cache, client, config).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+    assert_eq!(report["peers"][peer.to_string()], "ok");
+    assert_eq!(*seen.lock().unwrap(), vec![Some("Bearer root".to_string())]);
+}
+
+#[test]
+fn peers_require_a_token_with_the_admin_role() {
+    let config = Config::parse(
+        r#"
+        [admin]
+        peers = ["10.0.0.2:3001"]
+
+        [[admin.tokens]]
+        name = "grafana"
+        token = "dash"
+        role = "read_only"
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().is_err());
+}
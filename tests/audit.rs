@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request};
+use rust_proxy_server::admin::Principal;
+use rust_proxy_server::audit::{self, Caller};
+use rust_proxy_server::clock::MockClock;
+use rust_proxy_server::config::{AdminRole, AuditLogConfig};
+use rust_proxy_server::listener::ClientAddr;
+
+#[tokio::test]
+async fn admin_actions_are_appended_as_json_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("audit.jsonl");
+    audit::init(&AuditLogConfig {
+        enabled: true,
+        path: path.clone(),
+    })
+    .await
+    .unwrap();
+
+    let mut req = Request::post("/cache/purge").body(Body::empty()).unwrap();
+    req.extensions_mut().insert(Principal {
+        name: "token".to_string(),
+        role: AdminRole::Admin,
+    });
+    req.extensions_mut().insert(ClientAddr("10.0.0.7:51234".parse().unwrap()));
+    let caller = Caller::of(&req, Arc::new(MockClock::at_secs(1_700_000_000)));
+    audit::record(&caller, "cache.purge", &["http://a/b".to_string()], &["k1".to_string()], "ok").await;
+    audit::record(&caller, "drain.start", &[], &[], "ok").await;
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<serde_json::Value> = content.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["time"], 1_700_000_000);
+    assert_eq!(lines[0]["principal"], "token");
+    assert_eq!(lines[0]["client"], "10.0.0.7");
+    assert_eq!(lines[0]["action"], "cache.purge");
+    assert_eq!(lines[0]["targets"][0], "http://a/b");
+    assert_eq!(lines[0]["keys"][0], "k1");
+    assert_eq!(lines[1]["action"], "drain.start");
+    assert!(lines[1].get("keys").is_none());
+}
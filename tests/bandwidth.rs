@@ -0,0 +1,27 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use hyper::header::CONTENT_LENGTH;
+use hyper::{Body, Response, StatusCode};
+use rust_proxy_server::bandwidth::measure;
+
+const IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+#[tokio::test]
+async fn bodies_keep_their_length() {
+    let response = measure(Response::new(Body::from("hello")), IP);
+    assert_eq!(response.headers()[CONTENT_LENGTH], "5");
+    assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "hello");
+}
+
+#[test]
+fn empty_responses_are_left_untouched() {
+    // 204 不能带 Content-Length；HEAD 的 Content-Length 描述的是 GET 的响应体
+    let response = Response::builder().status(StatusCode::NO_CONTENT).body(Body::empty()).unwrap();
+    assert!(measure(response, IP).headers().get(CONTENT_LENGTH).is_none());
+
+    let response = Response::builder().header(CONTENT_LENGTH, "1000").body(Body::empty()).unwrap();
+    assert_eq!(measure(response, IP).headers()[CONTENT_LENGTH], "1000");
+
+    let response = Response::builder().status(StatusCode::NOT_MODIFIED).body(Body::empty()).unwrap();
+    assert!(measure(response, IP).headers().get(CONTENT_LENGTH).is_none());
+}
@@ -0,0 +1,133 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use hyper::{Body, Request, Uri};
+use rust_proxy_server::cache::ProxyCache;
+use rust_proxy_server::client;
+use rust_proxy_server::config::Config;
+use rust_proxy_server::constants::UPSTREAM_BODY_RESUMES;
+use rust_proxy_server::handler::fetch_and_cache_full_response;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+const LEN: usize = 40;
+
+fn content() -> Vec<u8> {
+    (0..LEN).map(|i| b'a' + (i % 26) as u8).collect()
+}
+
+// 每个响应最多发送 piece 字节就断开连接；带 Range 的请求从请求的位置返回 206。
+// 返回地址与收到的 GET 请求数（缓存时查询大小的 HEAD 不计）
+async fn origin(piece: usize) -> (SocketAddr, Arc<AtomicU32>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = Arc::new(AtomicU32::new(0));
+    let counter = requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let counter = counter.clone();
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        return;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let request = String::from_utf8_lossy(&request).to_lowercase();
+                let head_only = request.starts_with("head ");
+                if !head_only {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+                let start = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .and_then(|range| range.split('-').next())
+                    .and_then(|start| start.parse::<usize>().ok());
+                let head = match start {
+                    Some(start) => format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n",
+                        start,
+                        LEN - 1,
+                        LEN,
+                        LEN - start
+                    ),
+                    None => format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n", LEN),
+                };
+                let head = format!("{}ETag: \"v1\"\r\nConnection: close\r\n\r\n", head);
+                let start = start.unwrap_or(0);
+                let end = (start + piece).min(LEN);
+                socket.write_all(head.as_bytes()).await.unwrap();
+                if !head_only {
+                    socket.write_all(&content()[start..end]).await.unwrap();
+                }
+            });
+        }
+    });
+    (addr, requests)
+}
+
+// 整个对象的大小限制为 max_object_bytes，返回响应体（或读取时的错误）
+async fn fetch(addr: SocketAddr, max_object_bytes: u64) -> (anyhow::Result<Vec<u8>>, Arc<ProxyCache>, String) {
+    let config = Config::parse("[upstream]\nmax_retries = 0\n").unwrap();
+    config.validate().unwrap();
+    let client = client::build(&config).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ProxyCache::builder().dir(dir.path()).build().await.unwrap());
+    let uri: Uri = format!("http://{}/a", addr).parse().unwrap();
+    let key = config.cache_key(&uri);
+    let mut policy = config.cache_policy(&uri);
+    policy.max_object_bytes = max_object_bytes;
+    let req = Request::get(uri).body(Body::empty()).unwrap();
+    let body = async {
+        let response = fetch_and_cache_full_response(&client, req, cache.clone(), key.clone(), policy).await?;
+        Ok(hyper::body::to_bytes(response.into_body()).await?.to_vec())
+    }
+    .await;
+    (body, cache, key)
+}
+
+#[tokio::test]
+async fn cached_bodies_are_stitched_across_drops() {
+    // 第一个响应与三次续传各发送 10 字节，正好用完续传次数
+    let (addr, requests) = origin(10).await;
+    let (body, cache, key) = fetch(addr, 1 << 20).await;
+    assert_eq!(body.unwrap(), content());
+    assert_eq!(requests.load(Ordering::SeqCst), 1 + UPSTREAM_BODY_RESUMES);
+    let entry = cache.get(&key).await.unwrap();
+    assert!(entry.meta.is_complete);
+    assert_eq!(&entry.content[..], &content()[..]);
+}
+
+#[tokio::test]
+async fn too_large_bodies_are_stitched_while_streaming() {
+    let (addr, requests) = origin(10).await;
+    let (body, cache, key) = fetch(addr, 16).await;
+    assert_eq!(body.unwrap(), content());
+    assert_eq!(requests.load(Ordering::SeqCst), 1 + UPSTREAM_BODY_RESUMES);
+    assert!(cache.get(&key).await.is_none());
+}
+
+#[tokio::test]
+async fn resumes_stop_at_the_limit() {
+    // 每次只有 8 字节，三次续传之后仍不完整
+    for max_object_bytes in [1 << 20, 16] {
+        let (addr, requests) = origin(8).await;
+        let (body, cache, key) = fetch(addr, max_object_bytes).await;
+        assert!(body.is_err(), "max_object_bytes = {}", max_object_bytes);
+        assert_eq!(requests.load(Ordering::SeqCst), 1 + UPSTREAM_BODY_RESUMES);
+        // 缓存路径把已收到的前缀保存为不完整的条目，透传时不缓存
+        match cache.get(&key).await {
+            Some(entry) => {
+                assert!(max_object_bytes as usize > LEN);
+                assert!(!entry.meta.is_complete);
+                assert_eq!(&entry.content[..], &content()[..32]);
+            }
+            None => assert!((max_object_bytes as usize) < LEN),
+        }
+    }
+}
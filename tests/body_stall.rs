@@ -0,0 +1,60 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::StreamExt;
+use hyper::{Body, Request};
+use rust_proxy_server::client;
+use rust_proxy_server::config::Config;
+use rust_proxy_server::upstream::BodyStalled;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+// 先发送 hello，隔 pause 之后发送 world；pause 为 None 时不再发送
+async fn origin(pause: Option<Duration>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = [0u8; 1024];
+        let _ = socket.read(&mut request).await.unwrap();
+        socket
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello")
+            .await
+            .unwrap();
+        match pause {
+            Some(pause) => {
+                tokio::time::sleep(pause).await;
+                socket.write_all(b"world").await.unwrap();
+            }
+            None => tokio::time::sleep(Duration::from_secs(30)).await,
+        }
+        let _ = socket.read(&mut request).await;
+    });
+    addr
+}
+
+async fn fetch(addr: SocketAddr) -> Body {
+    let config = Config::parse("[upstream]\nbody_stall_timeout_secs = 1\nmax_retries = 0\n").unwrap();
+    config.validate().unwrap();
+    let client = client::build(&config).unwrap();
+    let req = Request::get(format!("http://{}/a", addr)).body(Body::empty()).unwrap();
+    client.request(req).await.unwrap().into_body()
+}
+
+// 客户端处理第一块数据用了超过超时的时间，之后源站很快送来剩余数据，不算停滞
+#[tokio::test]
+async fn slow_consumers_are_not_mistaken_for_stalls() {
+    let mut body = fetch(origin(Some(Duration::from_millis(1800))).await).await;
+    assert_eq!(&body.next().await.unwrap().unwrap()[..], b"hello");
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(&body.next().await.unwrap().unwrap()[..], b"world");
+}
+
+#[tokio::test]
+async fn stalled_origins_end_the_body() {
+    let mut body = fetch(origin(None).await).await;
+    assert_eq!(&body.next().await.unwrap().unwrap()[..], b"hello");
+    let err = body.next().await.unwrap().unwrap_err();
+    let source = std::error::Error::source(&err).unwrap();
+    assert!(source.downcast_ref::<std::io::Error>().unwrap().get_ref().unwrap().is::<BodyStalled>());
+}
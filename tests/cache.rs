@@ -0,0 +1,395 @@
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use bytes::Bytes;
+use rust_proxy_server::cache::{
+    inspect, ByteRanges, CacheEntry, CacheMeta, InFlight, Joined, ProxyCache, ShardedLru,
+};
+use rust_proxy_server::cache_key::is_valid_key;
+use rust_proxy_server::clock::{MockClock, SystemClock};
+
+fn meta(url: &str, total: u64, complete: bool) -> CacheMeta {
+    serde_json::from_value(serde_json::json!({
+        "content_type": "application/octet-stream",
+        "is_complete": complete,
+        "total_size": total,
+        "etag": "\"v1\"",
+        "url": url,
+        "version": 1,
+    }))
+    .unwrap()
+}
+
+fn entry(url: &str, len: usize) -> CacheEntry {
+    CacheEntry {
+        content: Bytes::from(vec![b'x'; len]),
+        meta: meta(url, len as u64, true),
+    }
+}
+
+// 磁盘上内容文件与分块目录的总大小（不含 .meta）
+fn content_bytes(dir: &Path) -> u64 {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .filter_map(|item| item.ok())
+        .filter(|item| item.path().extension().is_some_and(|ext| ext != "meta"))
+        .filter_map(|item| item.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+#[tokio::test]
+async fn entries_survive_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    cache.set("a".to_string(), entry("http://origin/a", 100)).await.unwrap();
+    cache.flush().await.unwrap();
+    drop(cache);
+
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    let restored = cache.get("a").await.unwrap();
+    assert_eq!(restored.content.len(), 100);
+    assert!(restored.meta.is_complete);
+    assert_eq!(restored.meta.url.as_deref(), Some("http://origin/a"));
+}
+
+#[tokio::test]
+async fn purge_removes_from_memory_and_disk() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    cache.set("a".to_string(), entry("http://origin/a", 100)).await.unwrap();
+    cache.flush().await.unwrap();
+    assert!(cache.purge("a").await.unwrap());
+    cache.flush().await.unwrap();
+    assert!(cache.get("a").await.is_none());
+    assert!(!cache.purge("a").await.unwrap());
+    assert!(!dir.path().join("a.meta").exists());
+}
+
+// 把键对应的内容文件与 .meta 的修改时间设为 secs
+fn set_modified(dir: &Path, key: &str, secs: u64) {
+    let time = UNIX_EPOCH + Duration::from_secs(secs);
+    for item in std::fs::read_dir(dir).unwrap().filter_map(|item| item.ok()) {
+        let name = item.file_name().to_string_lossy().into_owned();
+        if name.split('.').next() == Some(key) {
+            std::fs::File::options().write(true).open(item.path()).unwrap().set_modified(time).unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn disk_quota_evicts_oldest_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    for key in ["a", "b", "c", "d"] {
+        cache.set(key.to_string(), entry(key, 1000)).await.unwrap();
+    }
+    cache.flush().await.unwrap();
+    drop(cache);
+    // 修改时间相同时淘汰顺序不确定，显式设置为 a 最旧、d 最新
+    for (n, key) in ["a", "b", "c", "d"].into_iter().enumerate() {
+        set_modified(dir.path(), key, 1_700_000_000 + n as u64 * 60);
+    }
+
+    let cache = ProxyCache::builder()
+        .dir(dir.path())
+        .disk_bytes(2500)
+        .build()
+        .await
+        .unwrap();
+    // a 已加载到内存，淘汰时同样移除
+    assert!(cache.get("a").await.is_some());
+    // 重新打开后第一次写入时统计用量并淘汰
+    cache.set("e".to_string(), entry("e", 100)).await.unwrap();
+    cache.flush().await.unwrap();
+    assert!(content_bytes(dir.path()) <= 2500);
+    assert!(cache.get("a").await.is_none());
+    assert!(cache.get("b").await.is_none());
+    drop(cache);
+
+    // 重新打开后内存中没有条目，只能从磁盘读取
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    assert!(cache.get("a").await.is_none());
+    assert!(cache.get("b").await.is_none());
+    assert!(cache.get("c").await.is_some());
+    assert!(cache.get("d").await.is_some());
+    assert!(cache.get("e").await.is_some());
+}
+
+#[tokio::test]
+async fn partial_entries_keep_their_ranges() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    let partial = CacheEntry {
+        content: Bytes::new(),
+        meta: meta("http://origin/p", 1000, false),
+    }
+    .merge(200, &[b'y'; 300], Some(1000));
+    cache.set("p".to_string(), partial).await.unwrap();
+    cache.flush().await.unwrap();
+    drop(cache);
+
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    let restored = cache.get("p").await.unwrap();
+    assert!(!restored.meta.is_complete);
+    assert_eq!(restored.meta.ranges, Some(ByteRanges::single(200, 500)));
+    assert_eq!(restored.slice(250, 299).unwrap(), &[b'y'; 50][..]);
+    assert!(restored.slice(100, 250).is_none());
+}
+
+#[tokio::test]
+async fn nested_keys_are_stored_in_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    let key = "example~com/videos/a~mp4".to_string();
+    cache.set(key.clone(), entry("http://example.com/videos/a.mp4", 100)).await.unwrap();
+    cache.flush().await.unwrap();
+    assert!(dir.path().join("example~com/videos/a~mp4.meta").exists());
+    drop(cache);
+
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    assert_eq!(cache.get(&key).await.unwrap().content.len(), 100);
+    let entries = inspect::list(dir.path()).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].key, key);
+    assert!(entries[0].meta.is_some());
+
+    // 删除条目后 gc 清理留下的空目录
+    assert!(cache.purge(&key).await.unwrap());
+    cache.flush().await.unwrap();
+    inspect::gc(dir.path(), None, false, &SystemClock).unwrap();
+    assert!(!dir.path().join("example~com").exists());
+}
+
+#[tokio::test]
+async fn legacy_metadata_is_upgraded_and_unknown_versions_quarantined() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut legacy = serde_json::to_value(meta("http://origin/old", 5, true)).unwrap();
+    legacy.as_object_mut().unwrap().remove("version");
+    std::fs::write(dir.path().join("old.meta"), legacy.to_string()).unwrap();
+    std::fs::write(dir.path().join("old"), b"hello").unwrap();
+    let mut future = serde_json::to_value(meta("http://origin/new", 5, true)).unwrap();
+    future["version"] = serde_json::json!(99);
+    std::fs::write(dir.path().join("new.meta"), future.to_string()).unwrap();
+    std::fs::write(dir.path().join("new"), b"hello").unwrap();
+
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    let upgraded = cache.get("old").await.unwrap();
+    assert_eq!(upgraded.meta.version, 1);
+    assert_eq!(&upgraded.content[..], b"hello");
+    assert!(cache.get("new").await.is_none());
+    assert!(dir.path().join("quarantine/new.meta").exists());
+    assert!(dir.path().join("quarantine/new").exists());
+}
+
+#[tokio::test]
+async fn legacy_uppercase_keys_are_renamed() {
+    let dir = tempfile::tempdir().unwrap();
+    let meta = serde_json::to_string(&meta("http://Example.com:8080/A", 5, true)).unwrap();
+    std::fs::create_dir(dir.path().join("Example~com%3A8080")).unwrap();
+    std::fs::write(dir.path().join("Example~com%3A8080/A.meta"), &meta).unwrap();
+    std::fs::write(dir.path().join("Example~com%3A8080/A"), b"hello").unwrap();
+
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    let key = "%45xample~com%3A8080/%41";
+    assert!(is_valid_key(key));
+    assert_eq!(&cache.get(key).await.unwrap().content[..], b"hello");
+    assert!(!dir.path().join("Example~com%3A8080/A.meta").exists());
+}
+
+#[tokio::test]
+async fn in_flight_followers_share_the_leaders_result() {
+    let in_flight: InFlight<u32> = InFlight::new();
+    let Joined::Leader(leader) = in_flight.join("a") else {
+        panic!("first caller must lead");
+    };
+    let followers: Vec<_> = (0..3)
+        .map(|_| match in_flight.join("a") {
+            Joined::Follower(follower) => tokio::spawn(follower.wait()),
+            Joined::Leader(_) => panic!("later callers must follow"),
+        })
+        .collect();
+    // 其他键互不影响
+    assert!(matches!(in_flight.join("b"), Joined::Leader(_)));
+    leader.finish(7);
+    for follower in followers {
+        assert_eq!(follower.await.unwrap(), Some(7));
+    }
+
+    // 执行者放弃后等待者得到 None，下一个请求重新成为执行者
+    let Joined::Leader(leader) = in_flight.join("a") else {
+        panic!("finished operations must not linger");
+    };
+    let Joined::Follower(follower) = in_flight.join("a") else {
+        panic!("expected a follower");
+    };
+    drop(leader);
+    assert_eq!(follower.wait().await, None);
+    assert!(matches!(in_flight.join("a"), Joined::Leader(_)));
+}
+
+#[test]
+fn memory_budget_evicts_least_recently_used() {
+    let lru: ShardedLru<Vec<u8>> = ShardedLru::with_max_bytes(300, 1, |value| value.len());
+    lru.put("a".to_string(), vec![0; 100]);
+    lru.put("b".to_string(), vec![0; 100]);
+    lru.put("c".to_string(), vec![0; 100]);
+    // 访问 a 之后，b 成为最久未用的值
+    assert!(lru.get("a").is_some());
+    lru.put("d".to_string(), vec![0; 100]);
+    assert!(lru.get("b").is_none());
+    assert!(lru.get("a").is_some() && lru.get("c").is_some() && lru.get("d").is_some());
+
+    // 替换同一个 key 时按新值计算占用：a 增大 100 字节，淘汰最久未用的 c
+    lru.put("a".to_string(), vec![0; 200]);
+    assert!(lru.get("c").is_none());
+    assert!(lru.get("a").is_some() && lru.get("d").is_some());
+
+    // 超过预算的值不缓存，同名的旧值也被移除
+    lru.put("a".to_string(), vec![0; 400]);
+    assert!(lru.get("a").is_none());
+}
+
+#[tokio::test]
+async fn without_memory_content_hits_are_read_from_disk() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = rust_proxy_server::config::CacheConfig {
+        memory_content: false,
+        ..Default::default()
+    };
+    let cache = ProxyCache::builder().dir(dir.path()).config(config).build().await.unwrap();
+    cache.set("a".to_string(), entry("http://origin/a", 100)).await.unwrap();
+    cache.flush().await.unwrap();
+    assert_eq!(cache.get("a").await.unwrap().content.len(), 100);
+
+    // 磁盘上的文件删除后不会再从内存中命中
+    for item in std::fs::read_dir(dir.path()).unwrap() {
+        let path = item.unwrap().path();
+        if path.is_file() {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+    assert!(cache.get("a").await.is_none());
+}
+
+#[tokio::test]
+async fn get_meta_reads_metadata_without_content() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    cache.set("a".to_string(), entry("http://origin/a", 100)).await.unwrap();
+    assert_eq!(cache.get_meta("a").await.unwrap().total_size, Some(100));
+    cache.flush().await.unwrap();
+    drop(cache);
+
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    let meta = cache.get_meta("a").await.unwrap();
+    assert!(meta.is_complete);
+    assert_eq!(meta.url.as_deref(), Some("http://origin/a"));
+
+    // 内容文件已不存在时视为未缓存
+    remove_content_files(dir.path());
+    assert!(cache.get_meta("a").await.is_none());
+    assert!(cache.get_meta("missing").await.is_none());
+}
+
+// 删除磁盘上的内容文件，之后只有内存缓存中的条目还能读到
+fn remove_content_files(dir: &Path) {
+    for item in std::fs::read_dir(dir).unwrap().filter_map(|item| item.ok()) {
+        if item.path().extension().is_some_and(|ext| ext != "meta") && item.path().is_file() {
+            std::fs::remove_file(item.path()).unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn large_disk_hits_need_repeated_reads_for_memory() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = rust_proxy_server::config::CacheConfig::default();
+    config.memory_admission.max_object_bytes = 100;
+    config.memory_admission.min_hits = 2;
+    let cache = ProxyCache::builder().dir(dir.path()).config(config.clone()).build().await.unwrap();
+    cache.set("small".to_string(), entry("http://origin/small", 100)).await.unwrap();
+    cache.set("large".to_string(), entry("http://origin/large", 1000)).await.unwrap();
+    cache.set("hot".to_string(), entry("http://origin/hot", 1000)).await.unwrap();
+    cache.flush().await.unwrap();
+    drop(cache);
+
+    let cache = ProxyCache::builder().dir(dir.path()).config(config).build().await.unwrap();
+    for key in ["small", "large", "hot", "hot"] {
+        assert!(cache.get(key).await.is_some());
+    }
+    remove_content_files(dir.path());
+    assert!(cache.get("small").await.is_some());
+    assert!(cache.get("hot").await.is_some());
+    assert!(cache.get("large").await.is_none());
+}
+
+#[tokio::test]
+async fn chunked_ranges_are_verified_on_read() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = rust_proxy_server::config::CacheConfig {
+        verify_checksums: true,
+        verify_on_read: true,
+        chunk_bytes: 100,
+        ..Default::default()
+    };
+    let cache = ProxyCache::builder().dir(dir.path()).config(config.clone()).build().await.unwrap();
+    cache.set("a".to_string(), entry("http://origin/a", 1000)).await.unwrap();
+    cache.flush().await.unwrap();
+    drop(cache);
+
+    // 损坏其中一个块
+    let chunks = std::fs::read_dir(dir.path())
+        .unwrap()
+        .filter_map(|item| item.ok())
+        .map(|item| item.path())
+        .find(|path| path.is_dir())
+        .unwrap();
+    let chunk = std::fs::read_dir(chunks).unwrap().next().unwrap().unwrap().path();
+    std::fs::write(chunk, vec![b'y'; 100]).unwrap();
+
+    // 单独的块无法校验，范围读取交给完整读取，校验失败时视为未缓存
+    let cache = ProxyCache::builder().dir(dir.path()).config(config).build().await.unwrap();
+    assert!(cache.get_range("a", 0, 99).await.is_none());
+    assert!(cache.get("a").await.is_none());
+}
+
+#[tokio::test]
+async fn soft_purge_rewrites_only_the_metadata() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    cache.set("a".to_string(), entry("http://origin/a", 100)).await.unwrap();
+    cache.flush().await.unwrap();
+    drop(cache);
+
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    assert!(cache.soft_purge("a").await.unwrap());
+    assert!(!cache.soft_purge("missing").await.unwrap());
+    cache.flush().await.unwrap();
+    assert!(cache.get_meta("a").await.unwrap().invalidated);
+
+    // 内容没有被加载进内存
+    remove_content_files(dir.path());
+    assert!(cache.get("a").await.is_none());
+}
+
+#[tokio::test]
+async fn gc_judges_expiry_by_the_given_clock() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    let mut fresh = entry("http://origin/a", 100);
+    fresh.meta.stored_at = Some(1_000);
+    fresh.meta.freshness_secs = Some(60);
+    cache.set("a".to_string(), fresh).await.unwrap();
+    cache.flush().await.unwrap();
+    drop(cache);
+
+    let report = inspect::gc(dir.path(), None, true, &MockClock::at_secs(1_030)).unwrap();
+    assert_eq!(report.removed, 0);
+    assert_eq!(inspect::list(dir.path()).unwrap().len(), 1);
+
+    let report = inspect::gc(dir.path(), None, true, &MockClock::at_secs(1_100)).unwrap();
+    assert_eq!(report.removed, 1);
+    assert!(inspect::list(dir.path()).unwrap().is_empty());
+}
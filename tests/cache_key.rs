@@ -0,0 +1,191 @@
+use std::sync::Arc;
+
+use hyper::header::{HeaderValue, ACCEPT_ENCODING, USER_AGENT};
+use hyper::{HeaderMap, Method, Uri};
+use rust_proxy_server::cache_key::{
+    is_valid_key, normalize_accept_encoding, CacheKeyStrategy, ClientClass, KeyRequest,
+};
+use rust_proxy_server::config::{Config, KeyLayout};
+use rust_proxy_server::utils::generate_cache_key;
+
+fn uri(value: &str) -> Uri {
+    value.parse().unwrap()
+}
+
+fn gzip() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+    headers
+}
+
+#[test]
+fn default_key_matches_previous_versions() {
+    let config = Config::default();
+    let target = uri("http://example.com/videos/a.mp4?x=1");
+    assert_eq!(config.cache_key(&target), generate_cache_key(&target));
+    // 默认策略不区分方法与请求头
+    assert_eq!(
+        config.request_cache_key(&Method::HEAD, &target, &gzip()),
+        generate_cache_key(&target)
+    );
+}
+
+#[test]
+fn method_and_headers_split_entries() {
+    let mut config = Config::default();
+    config.cache.key.include_method = true;
+    config.cache.key.headers = vec!["accept-encoding".to_string()];
+    let target = uri("http://example.com/a.js");
+    let get = config.request_cache_key(&Method::GET, &target, &HeaderMap::new());
+    let head = config.request_cache_key(&Method::HEAD, &target, &HeaderMap::new());
+    let gzip = config.request_cache_key(&Method::GET, &target, &gzip());
+    assert_ne!(get, head);
+    assert_ne!(get, gzip);
+    assert_eq!(get, config.cache_key(&target));
+    assert!([get, head, gzip].iter().all(|key| is_valid_key(key)));
+}
+
+#[test]
+fn path_layout_is_readable() {
+    let mut config = Config::default();
+    config.cache.key.layout = KeyLayout::Path;
+    let key = config.cache_key(&uri("http://example.com:8080/videos/a_b.mp4?q=1"));
+    assert_eq!(key, "example~com%3A8080_videos_a%5Fb~mp4%3Fq%3D1");
+    assert!(is_valid_key(&key));
+
+    // 过长的路径截断后附加哈希，仍然互不相同
+    let long = |suffix: &str| {
+        config.cache_key(&uri(&format!("http://example.com/{}{}", "d/".repeat(200), suffix)))
+    };
+    let (a, b) = (long("a"), long("b"));
+    assert_ne!(a, b);
+    assert!(is_valid_key(&a) && is_valid_key(&b));
+    assert!(a.starts_with("example~com_d_d_"));
+}
+
+#[test]
+fn tree_layout_mirrors_url() {
+    let mut config = Config::default();
+    config.cache.key.layout = KeyLayout::Tree;
+    let key = |value: &str| config.cache_key(&uri(value));
+    assert_eq!(key("http://example.com/videos/a.mp4"), "example~com/videos/a~mp4");
+    assert_eq!(key("http://example.com:8080/"), "example~com%3A8080/_");
+    assert_eq!(key("http://example.com/docs/?page=2"), "example~com/docs/%3Fpage%3D2");
+    assert!(is_valid_key(&key("http://example.com/a/b/c")));
+    // 大写字母在所有系统上都编码，只有大小写不同的 URL 不会落到同一个文件
+    assert_eq!(key("http://example.com/Docs"), "example~com/%44ocs");
+    assert!(!is_valid_key("example~com/Docs"));
+
+    // 与缓存目录中的保留目录同名的主机、过深的路径改用哈希
+    let packs = uri("http://packs/a");
+    assert_eq!(config.cache_key(&packs), generate_cache_key(&packs));
+    let deep = uri(&format!("http://example.com/{}", "d/".repeat(40)));
+    assert_eq!(config.cache_key(&deep), generate_cache_key(&deep));
+}
+
+#[test]
+fn windows_device_names_are_hashed() {
+    let mut config = Config::default();
+    config.cache.key.layout = KeyLayout::Tree;
+    for value in ["http://example.com/nul", "http://example.com/docs/com1", "http://aux/a"] {
+        let target = uri(value);
+        assert_eq!(config.cache_key(&target), generate_cache_key(&target), "{}", value);
+    }
+    assert!(is_valid_key("example~com/com10"));
+    assert!(!is_valid_key("example~com/lpt9"));
+}
+
+struct TenantKey;
+
+impl CacheKeyStrategy for TenantKey {
+    fn key(&self, req: &KeyRequest) -> String {
+        let tenant = req.headers.get("x-tenant").and_then(|v| v.to_str().ok()).unwrap_or("public");
+        format!("{}-{}", tenant, generate_cache_key(req.uri))
+    }
+}
+
+struct UnsafeKey;
+
+impl CacheKeyStrategy for UnsafeKey {
+    fn key(&self, req: &KeyRequest) -> String {
+        format!("../{}", req.uri)
+    }
+}
+
+#[test]
+fn custom_strategy_overrides_config() {
+    let config = Config::default().with_cache_key_strategy(Arc::new(TenantKey));
+    let target = uri("http://example.com/a");
+    let mut headers = HeaderMap::new();
+    headers.insert("x-tenant", HeaderValue::from_static("acme"));
+    let key = config.request_cache_key(&Method::GET, &target, &headers);
+    assert_eq!(key, format!("acme-{}", generate_cache_key(&target)));
+    assert!(config.cache_key(&target).starts_with("public-"));
+}
+
+#[test]
+fn unsafe_custom_keys_are_hashed() {
+    let config = Config::default().with_cache_key_strategy(Arc::new(UnsafeKey));
+    let key = config.cache_key(&uri("http://example.com/a"));
+    assert_eq!(key.len(), 64);
+    assert!(key.bytes().all(|b| b.is_ascii_hexdigit()));
+}
+
+#[test]
+fn client_classes_partition_entries() {
+    let mut config = Config::default();
+    config.cache.key.client_classes = vec![
+        ClientClass::new("mobile", r"iphone|android.*mobile").unwrap(),
+        ClientClass::new("tv", r"smart-?tv").unwrap(),
+    ];
+    let target = uri("http://example.com/index.html");
+    let key = |user_agent: &str| {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_str(user_agent).unwrap());
+        config.request_cache_key(&Method::GET, &target, &headers)
+    };
+    let iphone = key("Mozilla/5.0 (iPhone; CPU iPhone OS 17_0)");
+    assert_eq!(iphone, key("Mozilla/5.0 (Linux; Android 14) Mobile Safari"));
+    assert_ne!(iphone, key("Mozilla/5.0 (SMART-TV; Linux)"));
+    // 未匹配任何类别的客户端与没有 User-Agent 的请求共用 default
+    let desktop = key("Mozilla/5.0 (Windows NT 10.0)");
+    assert_ne!(iphone, desktop);
+    assert_eq!(desktop, config.cache_key(&target));
+    // 开启类别后 default 的键也不同于之前的键，已有的缓存不再命中
+    assert_ne!(desktop, generate_cache_key(&target));
+
+    // 按 URL 清除时覆盖所有类别
+    let variants = config.cache_key_variants(&target);
+    assert_eq!(variants.len(), 3);
+    assert!(variants.contains(&iphone) && variants.contains(&desktop));
+}
+
+#[test]
+fn accept_encoding_collapses_to_two_variants() {
+    let normalized = |value: Option<&'static str>| {
+        let mut headers = HeaderMap::new();
+        if let Some(value) = value {
+            headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(value));
+        }
+        normalize_accept_encoding(&mut headers);
+        headers[ACCEPT_ENCODING].to_str().unwrap().to_string()
+    };
+    assert_eq!(normalized(Some("gzip, deflate, br, zstd")), "gzip");
+    assert_eq!(normalized(Some("br;q=1.0, GZIP;q=0.5")), "gzip");
+    assert_eq!(normalized(Some("*")), "gzip");
+    assert_eq!(normalized(Some("br")), "identity");
+    assert_eq!(normalized(Some("gzip;q=0, *")), "identity");
+    assert_eq!(normalized(None), "identity");
+
+    let mut config = Config::default();
+    config.cache.key.headers = vec!["accept-encoding".to_string()];
+    let target = uri("http://example.com/app.js");
+    let key = |value: &'static str| {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(value));
+        normalize_accept_encoding(&mut headers);
+        config.request_cache_key(&Method::GET, &target, &headers)
+    };
+    assert_eq!(key("gzip, deflate, br"), key("gzip, br, zstd"));
+    assert_ne!(key("gzip"), key("identity"));
+}
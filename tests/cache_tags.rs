@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use rust_proxy_server::cache::{parse_tags, CacheEntry, CacheMeta, ProxyCache};
+
+fn entry(url: &str, headers: &[(&str, &str)]) -> CacheEntry {
+    let headers: Vec<(String, String)> =
+        headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+    let meta: CacheMeta = serde_json::from_value(serde_json::json!({
+        "content_type": "text/plain",
+        "is_complete": true,
+        "total_size": 3,
+        "url": url,
+        "headers": headers,
+        "version": 1,
+    }))
+    .unwrap();
+    CacheEntry {
+        content: Bytes::from_static(b"abc"),
+        meta,
+    }
+}
+
+fn sorted(mut keys: Vec<String>) -> Vec<String> {
+    keys.sort();
+    keys
+}
+
+#[test]
+fn surrogate_key_and_cache_tag_are_parsed() {
+    let headers = vec![
+        ("surrogate-key".to_string(), "product-1  catalog".to_string()),
+        ("cache-tag".to_string(), "catalog, home ,".to_string()),
+        ("content-type".to_string(), "text/html".to_string()),
+    ];
+    assert_eq!(parse_tags(&headers), ["product-1", "catalog", "home"]);
+}
+
+#[tokio::test]
+async fn purge_by_tag_removes_tagged_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    cache.set("a".to_string(), entry("http://origin/a", &[("surrogate-key", "x y")])).await.unwrap();
+    cache.set("b".to_string(), entry("http://origin/b", &[("cache-tag", "x")])).await.unwrap();
+    cache.set("c".to_string(), entry("http://origin/c", &[])).await.unwrap();
+    assert_eq!(sorted(cache.tagged("x")), ["a", "b"]);
+    assert_eq!(cache.tagged("y"), ["a"]);
+
+    // 重新写入时替换原有的标签
+    cache.set("b".to_string(), entry("http://origin/b", &[("cache-tag", "z")])).await.unwrap();
+    assert_eq!(cache.tagged("x"), ["a"]);
+
+    for key in cache.tagged("x") {
+        assert!(cache.purge(&key).await.unwrap());
+    }
+    assert!(cache.get("a").await.is_none());
+    assert!(cache.tagged("y").is_empty());
+    assert!(cache.get("b").await.is_some());
+}
+
+#[tokio::test]
+async fn tag_index_is_rebuilt_on_open() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    cache.set("a".to_string(), entry("http://origin/a", &[("surrogate-key", "x")])).await.unwrap();
+    cache.flush().await.unwrap();
+    drop(cache);
+
+    // 索引在后台线程中重建
+    let cache = ProxyCache::builder().dir(dir.path()).build().await.unwrap();
+    for _ in 0..100 {
+        if !cache.tagged("x").is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(cache.tagged("x"), ["a"]);
+}
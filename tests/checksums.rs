@@ -0,0 +1,92 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use rust_proxy_server::cache::ProxyCache;
+use rust_proxy_server::config::Config;
+use rust_proxy_server::{client, server};
+
+// 返回带 Surrogate-Key 的可缓存内容，记录收到的 GET 请求数
+fn origin() -> (SocketAddr, Arc<AtomicU32>) {
+    let requests = Arc::new(AtomicU32::new(0));
+    let counter = requests.clone();
+    let make = make_service_fn(move |_| {
+        let counter = counter.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                if req.method() == Method::GET {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+                async {
+                    let response = Response::builder()
+                        .header("cache-control", "max-age=600")
+                        .header("surrogate-key", "t")
+                        .body(Body::from("hello world"));
+                    Ok::<_, Infallible>(response.unwrap())
+                }
+            }))
+        }
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    (addr, requests)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn corrupted_entries_are_dropped_and_fetched_again() {
+    let (addr, requests) = origin();
+    let config = Config::parse(
+        r#"
+        [cache]
+        verify_checksums = true
+        verify_on_read = true
+        memory_content = false
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ProxyCache::builder().config(config.cache.clone()).dir(dir.path()).build().await.unwrap());
+    let config = Arc::new(config);
+    let client = client::build(&config).unwrap();
+    let uri = format!("http://{}/a", addr);
+    let key = config.cache_key(&uri.parse().unwrap());
+    let get = || {
+        let req = Request::get(uri.as_str()).body(Body::empty()).unwrap();
+        let (cache, client, config) = (cache.clone(), client.clone(), config.clone());
+        async move {
+            let response = server::handle_request(req, cache.clone(), client, config).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "hello world");
+            cache.flush().await.unwrap();
+        }
+    };
+
+    get().await;
+    assert_eq!(cache.tagged("t"), std::slice::from_ref(&key));
+
+    // 改写磁盘上的内容文件
+    let content = std::fs::read_dir(dir.path())
+        .unwrap()
+        .filter_map(|item| item.ok())
+        .map(|item| item.path())
+        .find(|path| {
+            let name = path.file_name().unwrap().to_string_lossy();
+            name.starts_with(&key) && !name.ends_with(".meta")
+        })
+        .unwrap();
+    std::fs::write(&content, "hello WORLD").unwrap();
+
+    // 校验失败的条目连同标签一起移除，按标签清除时不会再找到它
+    assert!(cache.get(&key).await.is_none());
+    assert!(cache.tagged("t").is_empty());
+
+    // 下一次请求重新回源
+    get().await;
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+    assert_eq!(cache.tagged("t"), [key]);
+}
@@ -0,0 +1,89 @@
+use hyper::header::{HeaderValue, PROXY_AUTHORIZATION, RETRY_AFTER};
+use hyper::{HeaderMap, StatusCode};
+use rust_proxy_server::client_usage::{client_ids, quota_exceeded, ClientId, ClientUsage, Transfer};
+use rust_proxy_server::config::ClientUser;
+
+// 某天 UTC 零点
+const DAY: u64 = 1_700_006_400;
+
+fn ip(value: &str) -> ClientId {
+    ClientId::Ip(value.parse().unwrap())
+}
+
+fn out(bytes: u64) -> Transfer {
+    Transfer {
+        bytes_in: 0,
+        bytes_out: bytes,
+    }
+}
+
+#[test]
+fn windows_slide_with_time() {
+    let usage = ClientUsage::new(16);
+    let client = [ip("10.0.0.1")];
+    usage.record(&client, DAY + 10, out(100));
+    usage.record(&client, DAY + 50, Transfer { bytes_in: 5, bytes_out: 0 });
+
+    let stats = usage.stats(DAY + 60);
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].last_minute, Transfer { bytes_in: 5, bytes_out: 100 });
+
+    // 一分钟后只剩较新的一次，一小时后分钟窗口也清空
+    let stats = usage.stats(DAY + 75);
+    assert_eq!(stats[0].last_minute.total(), 5);
+    assert_eq!(stats[0].last_hour.total(), 105);
+    let stats = usage.stats(DAY + 3700);
+    assert_eq!(stats[0].last_hour.total(), 0);
+    assert_eq!(stats[0].last_day.total(), 105);
+}
+
+#[test]
+fn quota_resets_at_utc_midnight() {
+    let usage = ClientUsage::new(16);
+    let client = [ip("10.0.0.1")];
+    usage.record(&client, DAY + 100, out(600));
+    assert!(!usage.over_quota(&client, 1000, DAY + 100));
+    usage.record(&client, DAY + 200, out(400));
+    assert!(usage.over_quota(&client, 1000, DAY + 200));
+    // 其他客户端不受影响
+    assert!(!usage.over_quota(&[ip("10.0.0.2")], 1000, DAY + 200));
+
+    let response = quota_exceeded(DAY + 200);
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()[RETRY_AFTER], (86400 - 200).to_string().as_str());
+
+    assert!(!usage.over_quota(&client, 1000, DAY + 86400));
+    assert_eq!(usage.used_today(&client[0], DAY + 86400), 0);
+}
+
+fn users() -> Vec<ClientUser> {
+    vec![ClientUser {
+        name: "alice".to_string(),
+        password: "secret".to_string(),
+    }]
+}
+
+#[test]
+fn verified_users_are_tracked_by_name() {
+    let addr = Some("10.0.0.1".parse().unwrap());
+    let mut headers = HeaderMap::new();
+    // alice:secret
+    headers.insert(PROXY_AUTHORIZATION, HeaderValue::from_static("Basic YWxpY2U6c2VjcmV0"));
+    assert_eq!(client_ids(addr, &headers, &users()), vec![ClientId::Identity("alice".to_string())]);
+
+    // 密码不符（alice:x）、未配置的用户（bob:x）都按 IP 统计，换用户名不能绕过 IP 的配额
+    let usage = ClientUsage::new(16);
+    usage.record(&client_ids(addr, &HeaderMap::new(), &users()), DAY, out(1000));
+    for credentials in ["Basic YWxpY2U6eA==", "Basic Ym9iOng="] {
+        headers.insert(PROXY_AUTHORIZATION, HeaderValue::from_static(credentials));
+        let ids = client_ids(addr, &headers, &users());
+        assert_eq!(ids, vec![ip("10.0.0.1")]);
+        assert!(usage.over_quota(&ids, 1000, DAY));
+    }
+    // 没有配置用户时用户名一律不作为统计对象
+    headers.insert(PROXY_AUTHORIZATION, HeaderValue::from_static("Basic YWxpY2U6c2VjcmV0"));
+    assert_eq!(client_ids(addr, &headers, &[]), vec![ip("10.0.0.1")]);
+    assert_eq!(usage.stats(DAY).len(), 1);
+
+    assert_eq!(client_ids(None, &HeaderMap::new(), &users()), vec![]);
+}
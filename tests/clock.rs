@@ -0,0 +1,99 @@
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use bytes::Bytes;
+use hyper::header::{HeaderValue, AGE, CACHE_CONTROL, DATE, LAST_MODIFIED};
+use hyper::HeaderMap;
+use rust_proxy_server::cache::{lifetime, CacheEntry, CacheMeta, ProxyCache};
+use rust_proxy_server::clock::{Clock, MockClock};
+use rust_proxy_server::config::Config;
+
+const START: u64 = 1_700_000_000;
+
+fn meta(stored_at: u64, freshness: u64) -> CacheMeta {
+    serde_json::from_value(serde_json::json!({
+        "content_type": "text/plain",
+        "is_complete": true,
+        "total_size": 5,
+        "stored_at": stored_at,
+        "freshness_secs": freshness,
+        "url": "http://origin.test/a",
+        "version": 1,
+    }))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn sleep_waits_for_advance() {
+    let clock = Arc::new(MockClock::at_secs(START));
+    let sleeping = tokio::spawn(clock.sleep(Duration::from_secs(10)));
+    tokio::task::yield_now().await;
+
+    clock.advance(Duration::from_secs(9));
+    tokio::task::yield_now().await;
+    assert!(!sleeping.is_finished());
+
+    clock.advance(Duration::from_secs(1));
+    tokio::time::timeout(Duration::from_secs(1), sleeping)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(clock.now_secs(), START + 10);
+}
+
+#[tokio::test]
+async fn cached_entry_expires_on_mock_time() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = Arc::new(MockClock::at_secs(START));
+    let cache = ProxyCache::builder()
+        .dir(dir.path())
+        .clock(clock.clone())
+        .build()
+        .await
+        .unwrap();
+    let entry = CacheEntry {
+        content: Bytes::from_static(b"hello"),
+        meta: meta(cache.clock().now_secs(), 60),
+    };
+    cache.set("a".to_string(), entry).await.unwrap();
+
+    clock.advance(Duration::from_secs(59));
+    let entry = cache.get("a").await.unwrap();
+    assert!(entry.meta.is_fresh(cache.clock().now_secs()));
+
+    clock.advance(Duration::from_secs(1));
+    assert!(!entry.meta.is_fresh(cache.clock().now_secs()));
+}
+
+#[test]
+fn age_and_date_follow_clock() {
+    let clock = MockClock::at_secs(START);
+    let mut stored = meta(START, 300);
+    stored.headers = vec![("age".to_string(), "5".to_string())];
+    clock.advance(Duration::from_secs(42));
+
+    let mut headers = HeaderMap::new();
+    stored.insert_cached_headers(&mut headers, clock.now_secs());
+    assert_eq!(headers[AGE], "47");
+    let date = httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(START + 42));
+    assert_eq!(headers[DATE], date.as_str());
+}
+
+#[test]
+fn heuristic_lifetime_uses_clock() {
+    let clock = MockClock::at_secs(START);
+    let policy = Config::default().cache_policy(&"http://origin.test/a".parse().unwrap());
+    let modified = UNIX_EPOCH + Duration::from_secs(START - 1000);
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        LAST_MODIFIED,
+        HeaderValue::from_str(&httpdate::fmt_http_date(modified)).unwrap(),
+    );
+    let expected = ((1000.0 * policy.heuristic_fraction) as u64).min(policy.heuristic_max_secs);
+    assert_eq!(lifetime(&headers, &policy, clock.now_secs()), Some(expected));
+
+    // 明确的 max-age 不受时钟影响
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("max-age=30"));
+    clock.advance(Duration::from_secs(3600));
+    assert_eq!(lifetime(&headers, &policy, clock.now_secs()), Some(30));
+}
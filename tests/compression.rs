@@ -0,0 +1,216 @@
+use std::convert::Infallible;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use flate2::read::GzDecoder;
+use hyper::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, ETAG, VARY};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode, Uri};
+use rust_proxy_server::cache::ProxyCache;
+use rust_proxy_server::cache_key::normalize_accept_encoding;
+use rust_proxy_server::constants::DEBUG_HEADER;
+use rust_proxy_server::{admin, client, server};
+use rust_proxy_server::config::Config;
+use rust_proxy_server::handler::{fetch_and_cache_full_response, GzipVariants};
+use rust_proxy_server::metrics::METRICS;
+
+fn body() -> String {
+    "{\"items\": [1, 2, 3]}\n".repeat(200)
+}
+
+// 不压缩的源站：/api 返回 JSON，/video 返回二进制
+fn origin() -> SocketAddr {
+    let make = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+            let (content_type, content) = match req.uri().path() {
+                "/video" => ("video/mp4", vec![7u8; 4096]),
+                _ => ("application/json", body().into_bytes()),
+            };
+            let len = content.len();
+            let content = if req.method() == hyper::Method::HEAD { Vec::new() } else { content };
+            Ok::<_, Infallible>(
+                Response::builder()
+                    .header("content-type", content_type)
+                    .header("content-length", len)
+                    .header("etag", "\"v1\"")
+                    .header("cache-control", "max-age=60")
+                    .body(Body::from(content))
+                    .unwrap(),
+            )
+        }))
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+fn config() -> Config {
+    let config = Config::parse(
+        r#"
+        [cache.key]
+        normalize_accept_encoding = true
+        headers = ["accept-encoding"]
+
+        [cache.compression]
+        enabled = true
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+    config
+}
+
+fn request(config: &Config, uri: &Uri, accept: Option<&str>) -> (Request<Body>, String) {
+    let mut builder = Request::get(uri.clone());
+    if let Some(accept) = accept {
+        builder = builder.header(ACCEPT_ENCODING, accept);
+    }
+    let mut req = builder.body(Body::empty()).unwrap();
+    normalize_accept_encoding(req.headers_mut());
+    let key = config.request_cache_key(req.method(), req.uri(), req.headers());
+    if let Some(variants) = GzipVariants::for_request(config, &req) {
+        req.extensions_mut().insert(variants);
+    }
+    (req, key)
+}
+
+fn gunzip(data: &[u8]) -> String {
+    let mut out = String::new();
+    GzDecoder::new(data).read_to_string(&mut out).unwrap();
+    out
+}
+
+#[tokio::test]
+async fn a_miss_stores_identity_and_gzip_variants() {
+    let addr = origin();
+    let config = config();
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ProxyCache::builder().dir(dir.path()).build().await.unwrap());
+    let client = client::build(&config).unwrap();
+    let uri: Uri = format!("http://{}/api", addr).parse().unwrap();
+    let stored = METRICS.traffic_stats().variants.stored;
+
+    let (req, gzip_key) = request(&config, &uri, Some("gzip, deflate, br"));
+    let policy = config.cache_policy(&uri);
+    let response = fetch_and_cache_full_response(&client, req, cache.clone(), gzip_key.clone(), policy)
+        .await
+        .unwrap();
+    assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+    assert_eq!(response.headers()[VARY], "Accept-Encoding");
+    assert_eq!(response.headers()[ETAG], "W/\"v1\"");
+    let len = response.headers()[CONTENT_LENGTH].clone();
+    let sent = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(gunzip(&sent), body());
+    assert_eq!(len, sent.len().to_string().as_str());
+    assert!(METRICS.traffic_stats().variants.stored > stored);
+
+    // 两个版本都已缓存：gzip 版本保存压缩后的内容，identity 客户端命中原始内容
+    let gzip = cache.get(&gzip_key).await.unwrap();
+    assert_eq!(gzip.content, sent);
+    assert!(gzip.meta.headers.contains(&("content-encoding".to_string(), "gzip".to_string())));
+    let (_, identity_key) = request(&config, &uri, None);
+    assert_ne!(identity_key, gzip_key);
+    let identity = cache.get(&identity_key).await.unwrap();
+    assert_eq!(identity.content, body().as_bytes());
+    assert_eq!(identity.meta.etag.as_deref(), Some("\"v1\""));
+    assert!(!identity.meta.headers.iter().any(|(name, _)| name == "content-encoding"));
+}
+
+#[tokio::test]
+async fn purging_a_url_removes_both_variants() {
+    let addr = origin();
+    let config = Arc::new(config());
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ProxyCache::builder().dir(dir.path()).build().await.unwrap());
+    let client = client::build(&config).unwrap();
+    let uri: Uri = format!("http://{}/api", addr).parse().unwrap();
+
+    let (req, gzip_key) = request(&config, &uri, Some("gzip"));
+    let response = fetch_and_cache_full_response(&client, req, cache.clone(), gzip_key.clone(), config.cache_policy(&uri))
+        .await
+        .unwrap();
+    hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let (_, identity_key) = request(&config, &uri, None);
+    assert!(cache.get(&gzip_key).await.is_some());
+    assert!(cache.get(&identity_key).await.is_some());
+    cache.flush().await.unwrap();
+
+    let req = Request::post("/cache/purge").body(Body::from(uri.to_string())).unwrap();
+    let response = admin::handle_admin_request(req, cache.clone(), client, config).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(cache.get(&gzip_key).await.is_none());
+    assert!(cache.get(&identity_key).await.is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn hits_serve_each_variant_with_its_length() {
+    let addr = origin();
+    let config = Arc::new(config());
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ProxyCache::builder().dir(dir.path()).build().await.unwrap());
+    let client = client::build(&config).unwrap();
+    let get = |accept: Option<&str>| {
+        let mut builder = Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}/api", addr))
+            .header(DEBUG_HEADER, "1");
+        if let Some(accept) = accept {
+            builder = builder.header(ACCEPT_ENCODING, accept);
+        }
+        let req = builder.body(Body::empty()).unwrap();
+        server::handle_request(req, cache.clone(), client.clone(), config.clone())
+    };
+
+    let response = get(Some("gzip, br")).await.unwrap();
+    assert_eq!(response.headers()["x-proxy-cache"], "miss");
+    let gzipped = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+    for accept in [Some("gzip"), None] {
+        let response = get(accept).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-proxy-cache"], "hit");
+        let headers = response.headers().clone();
+        let sent = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(headers[CONTENT_LENGTH], sent.len().to_string().as_str());
+        if accept.is_some() {
+            assert_eq!(headers[CONTENT_ENCODING], "gzip");
+            assert_eq!(sent, gzipped);
+        } else {
+            assert!(!headers.contains_key(CONTENT_ENCODING));
+            assert_eq!(sent, body().as_bytes());
+        }
+    }
+}
+
+#[tokio::test]
+async fn binary_responses_are_stored_once() {
+    let addr = origin();
+    let config = config();
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ProxyCache::builder().dir(dir.path()).build().await.unwrap());
+    let client = client::build(&config).unwrap();
+    let uri: Uri = format!("http://{}/video", addr).parse().unwrap();
+
+    let (req, gzip_key) = request(&config, &uri, Some("gzip"));
+    let response = fetch_and_cache_full_response(&client, req, cache.clone(), gzip_key.clone(), config.cache_policy(&uri))
+        .await
+        .unwrap();
+    assert!(!response.headers().contains_key(CONTENT_ENCODING));
+    assert!(cache.get(&gzip_key).await.is_some());
+    let (_, identity_key) = request(&config, &uri, None);
+    assert!(cache.get(&identity_key).await.is_none());
+}
+
+#[test]
+fn compression_requires_accept_encoding_in_the_key() {
+    let config = Config::parse(
+        r#"
+        [cache.compression]
+        enabled = true
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().is_err());
+}
@@ -0,0 +1,77 @@
+use std::convert::Infallible;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Response, Server};
+use rust_proxy_server::config::Config;
+use rust_proxy_server::listener::{self, ClientConn};
+use rust_proxy_server::metrics::METRICS;
+use socket2::SockRef;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+fn config() -> Config {
+    let config = Config::parse(
+        r#"
+        [downstream]
+        listen_backlog = 4096
+        recv_buffer_bytes = 262144
+        send_buffer_bytes = 262144
+        tcp_keepalive_secs = 30
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+    config
+}
+
+#[tokio::test]
+async fn listener_applies_socket_options() {
+    let config = config();
+    let listener = listener::bind("127.0.0.1:0".parse().unwrap(), &config.downstream).unwrap();
+    let socket = SockRef::from(&listener);
+    // Linux 会把设置的值翻倍，只检查不小于配置
+    assert!(socket.recv_buffer_size().unwrap() >= 262144);
+    assert!(socket.send_buffer_size().unwrap() >= 262144);
+}
+
+#[tokio::test]
+async fn connections_are_counted_until_closed() {
+    let config = config();
+    let listener = listener::bind("127.0.0.1:0".parse().unwrap(), &config.downstream).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let make = make_service_fn(|_: &ClientConn| async {
+        Ok::<_, Infallible>(service_fn(|_| async { Ok::<_, Infallible>(Response::new(Body::from("ok"))) }))
+    });
+    tokio::spawn(Server::builder(listener::incoming(listener, &config.downstream)).serve(make));
+
+    let accepted = METRICS.downstream_connections_accepted.load(Ordering::Relaxed);
+    let closed = METRICS.downstream_connections_closed.load(Ordering::Relaxed);
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 200"));
+    assert!(METRICS.downstream_connections_accepted.load(Ordering::Relaxed) > accepted);
+
+    // 服务端关闭连接后 ClientConn 被释放
+    let mut waited = Duration::ZERO;
+    while METRICS.downstream_connections_closed.load(Ordering::Relaxed) == closed {
+        assert!(waited < Duration::from_secs(5));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        waited += Duration::from_millis(10);
+    }
+    assert!(METRICS.render().contains("proxy_downstream_connections_active"));
+}
+
+#[test]
+fn zero_socket_options_are_rejected() {
+    for option in ["listen_backlog", "recv_buffer_bytes", "tcp_keepalive_secs"] {
+        let config = Config::parse(&format!("[downstream]\n{} = 0\n", option)).unwrap();
+        assert!(config.validate().is_err(), "{}", option);
+    }
+}
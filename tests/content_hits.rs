@@ -0,0 +1,82 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hyper::header::{CACHE_CONTROL, ETAG, IF_NONE_MATCH};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use rust_proxy_server::cache::ProxyCache;
+use rust_proxy_server::clock::MockClock;
+use rust_proxy_server::config::Config;
+use rust_proxy_server::metrics::METRICS;
+use rust_proxy_server::{client, server};
+
+// /ok 的条件请求返回 304，/down 的条件请求返回 503
+fn origin() -> SocketAddr {
+    let make = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+            let response = if !req.headers().contains_key(IF_NONE_MATCH) {
+                Response::builder()
+                    .header(ETAG, "\"v1\"")
+                    .header(CACHE_CONTROL, "max-age=60")
+                    .body(Body::from("hello"))
+            } else if req.uri().path() == "/ok" {
+                Response::builder()
+                    .status(StatusCode::NOT_MODIFIED)
+                    .header(ETAG, "\"v1\"")
+                    .header(CACHE_CONTROL, "max-age=60")
+                    .body(Body::empty())
+            } else {
+                Response::builder().status(StatusCode::SERVICE_UNAVAILABLE).body(Body::empty())
+            };
+            Ok::<_, Infallible>(response.unwrap())
+        }))
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+fn hits() -> u64 {
+    METRICS.cache_content_hits.load(Ordering::Relaxed)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn only_fresh_or_revalidated_entries_count_as_content_hits() {
+    let addr = origin();
+    let config = Arc::new(Config::default());
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let clock = Arc::new(MockClock::at_secs(now));
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ProxyCache::builder().dir(dir.path()).clock(clock.clone()).build().await.unwrap());
+    let client = client::build(&config).unwrap();
+    let get = |path: &str| {
+        let req = Request::get(format!("http://{}{}", addr, path)).body(Body::empty()).unwrap();
+        let (cache, client, config) = (cache.clone(), client.clone(), config.clone());
+        async move {
+            let response = server::handle_request(req, cache, client, config).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            hyper::body::to_bytes(response.into_body()).await.unwrap();
+        }
+    };
+
+    for path in ["/ok", "/down"] {
+        get(path).await;
+        let before = hits();
+        get(path).await;
+        assert_eq!(hits(), before + 1, "fresh hit on {}", path);
+    }
+    clock.advance(Duration::from_secs(120));
+
+    // 源站出错时返回的旧内容不算命中
+    let before = hits();
+    get("/down").await;
+    assert_eq!(hits(), before);
+
+    // 重新验证成功才算一次命中
+    get("/ok").await;
+    assert_eq!(hits(), before + 1);
+}
@@ -0,0 +1,15 @@
+use rust_proxy_server::utils::sniff_content_type;
+
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+const MP4: &[u8] = b"\0\0\0\x20ftypisom\0\0\x02\0isomiso2avc1mp41";
+
+#[test]
+fn only_missing_or_generic_types_are_sniffed() {
+    assert_eq!(sniff_content_type(None, PNG), Some("image/png"));
+    assert_eq!(sniff_content_type(Some("application/octet-stream"), MP4), Some("video/mp4"));
+    assert_eq!(sniff_content_type(Some("Binary/Octet-Stream; charset=x"), PNG), Some("image/png"));
+    // 源站声明了具体类型时以源站为准
+    assert_eq!(sniff_content_type(Some("text/plain"), PNG), None);
+    // 识别不出来时保持原样
+    assert_eq!(sniff_content_type(None, b"plain text"), None);
+}
@@ -0,0 +1,64 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use rust_proxy_server::cache::ProxyCache;
+use rust_proxy_server::config::{Config, DecisionLogConfig};
+use rust_proxy_server::{client, decision_log, server};
+use sha2::Sha256;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn decisions_do_not_record_url_signatures() {
+    let make = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|_| async { Ok::<_, Infallible>(Response::new(Body::from("ok"))) }))
+    });
+    let origin = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+    let addr = origin.local_addr();
+    tokio::spawn(origin);
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("decisions.log");
+    decision_log::init(&DecisionLogConfig {
+        enabled: true,
+        path: path.clone(),
+        sample_rate: 1.0,
+    })
+    .await
+    .unwrap();
+    let config = Config::parse(
+        r#"
+        [[routes]]
+        path_prefix = "/private/"
+        signed_url = { secret = "s3cret" }
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+    let cache = Arc::new(ProxyCache::builder().dir(dir.path().join("cache")).build().await.unwrap());
+    let client = client::build(&config).unwrap();
+
+    let expires = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 3600;
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"s3cret").unwrap();
+    mac.update(format!("/private/a.ts?v=2&expires={}", expires).as_bytes());
+    let signature = hex::encode(mac.finalize().into_bytes());
+    let uri = format!("http://{}/private/a.ts?v=2&expires={}&signature={}", addr, expires, signature);
+    let req = Request::get(uri).body(Body::empty()).unwrap();
+    let response = server::handle_request(req, cache, client, Arc::new(config)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // 记录由后台任务写入
+    let mut lines = String::new();
+    for _ in 0..100 {
+        lines = std::fs::read_to_string(&path).unwrap();
+        if !lines.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let decision: serde_json::Value = serde_json::from_str(lines.lines().next().unwrap()).unwrap();
+    assert_eq!(decision["uri"], format!("http://{}/private/a.ts?v=2", addr));
+    assert!(!lines.contains(&signature));
+}
@@ -0,0 +1,177 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use hyper::header::CACHE_CONTROL;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use rust_proxy_server::cache::{CacheEntry, CacheMeta, Fault, FaultyDisk, ProxyCache};
+use rust_proxy_server::config::Config;
+use rust_proxy_server::{client, server};
+
+fn entry(byte: u8) -> CacheEntry {
+    let meta: CacheMeta = serde_json::from_value(serde_json::json!({
+        "content_type": "application/octet-stream",
+        "is_complete": true,
+        "total_size": 100,
+        "etag": "\"v1\"",
+        "version": 1,
+    }))
+    .unwrap();
+    CacheEntry {
+        content: Bytes::from(vec![byte; 100]),
+        meta,
+    }
+}
+
+fn on_disk(dir: &Path, key: &str) -> bool {
+    dir.join(format!("{}.meta", key)).exists()
+}
+
+// 失败的写入不留下临时文件
+fn leftovers(dir: &Path) -> Vec<String> {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .filter_map(|item| item.ok())
+        .map(|item| item.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".tmp"))
+        .collect()
+}
+
+async fn open(dir: &Path, disk: &Arc<FaultyDisk>) -> ProxyCache {
+    ProxyCache::builder().dir(dir).disk(disk.clone()).build().await.unwrap()
+}
+
+#[tokio::test]
+async fn full_disk_keeps_serving_from_memory_and_pauses_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    let disk = Arc::new(FaultyDisk::new());
+    let cache = open(dir.path(), &disk).await;
+
+    // 写盘失败不影响客户端：set 照常返回，条目从内存返回
+    disk.inject(Some(Fault::NoSpace));
+    assert!(cache.set("a".to_string(), entry(b'a')).await.is_ok());
+    cache.flush().await.unwrap();
+    assert!(!on_disk(dir.path(), "a"));
+    assert_eq!(cache.get("a").await.unwrap().content[0], b'a');
+
+    // 空间恢复后的一段时间内仍暂停写盘，只保留内存缓存
+    disk.inject(None);
+    cache.set("b".to_string(), entry(b'b')).await.unwrap();
+    cache.flush().await.unwrap();
+    assert!(!on_disk(dir.path(), "b"));
+    assert!(cache.get("b").await.is_some());
+    assert!(leftovers(dir.path()).is_empty());
+}
+
+#[tokio::test]
+async fn io_errors_skip_the_entry_without_pausing_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    let disk = Arc::new(FaultyDisk::new());
+    let cache = open(dir.path(), &disk).await;
+
+    disk.inject(Some(Fault::Io));
+    assert!(cache.set("a".to_string(), entry(b'a')).await.is_ok());
+    cache.flush().await.unwrap();
+    assert!(!on_disk(dir.path(), "a"));
+    assert!(cache.get("a").await.is_some());
+
+    disk.inject(None);
+    cache.set("b".to_string(), entry(b'b')).await.unwrap();
+    cache.flush().await.unwrap();
+    assert!(on_disk(dir.path(), "b"));
+}
+
+#[tokio::test]
+async fn partial_writes_never_replace_the_stored_version() {
+    let dir = tempfile::tempdir().unwrap();
+    let disk = Arc::new(FaultyDisk::new());
+    let cache = open(dir.path(), &disk).await;
+    cache.set("a".to_string(), entry(b'1')).await.unwrap();
+    cache.flush().await.unwrap();
+
+    disk.inject(Some(Fault::PartialWrite));
+    assert!(cache.set("a".to_string(), entry(b'2')).await.is_ok());
+    assert!(cache.set("new".to_string(), entry(b'n')).await.is_ok());
+    cache.flush().await.unwrap();
+    assert!(leftovers(dir.path()).is_empty());
+    drop(cache);
+
+    // 重启后读到的是完整的旧版本；从未完整写入的条目是未命中，请求转发到源站
+    disk.inject(None);
+    let cache = open(dir.path(), &disk).await;
+    let restored = cache.get("a").await.unwrap();
+    assert_eq!(restored.content, Bytes::from(vec![b'1'; 100]));
+    assert!(cache.get("new").await.is_none());
+}
+
+#[tokio::test]
+async fn full_disk_evicts_old_entries_from_memory_too() {
+    let dir = tempfile::tempdir().unwrap();
+    let disk = Arc::new(FaultyDisk::new());
+    let cache = open(dir.path(), &disk).await;
+    cache.set("old".to_string(), entry(b'o')).await.unwrap();
+    cache.flush().await.unwrap();
+    assert!(on_disk(dir.path(), "old"));
+
+    // 紧急淘汰删除磁盘上的旧条目，内存中也不再返回
+    disk.inject(Some(Fault::NoSpace));
+    cache.set("new".to_string(), entry(b'n')).await.unwrap();
+    cache.flush().await.unwrap();
+    assert!(!on_disk(dir.path(), "old"));
+    assert!(cache.get("old").await.is_none());
+    assert!(cache.get("new").await.is_some());
+}
+
+// 返回可缓存的 "hello"，记录收到的 GET 请求数（缓存时查询大小的 HEAD 不计）
+fn origin() -> (SocketAddr, Arc<AtomicU32>) {
+    let requests = Arc::new(AtomicU32::new(0));
+    let counter = requests.clone();
+    let make = make_service_fn(move |_| {
+        let counter = counter.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                if req.method() == Method::GET {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+                async {
+                    let response = Response::builder()
+                        .header(CACHE_CONTROL, "max-age=60")
+                        .body(Body::from("hello"));
+                    Ok::<_, Infallible>(response.unwrap())
+                }
+            }))
+        }
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    (addr, requests)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clients_are_served_while_the_disk_is_full() {
+    let (addr, requests) = origin();
+    let dir = tempfile::tempdir().unwrap();
+    let disk = Arc::new(FaultyDisk::new());
+    let cache = Arc::new(open(dir.path(), &disk).await);
+    let config = Arc::new(Config::default());
+    let client = client::build(&config).unwrap();
+    disk.inject(Some(Fault::NoSpace));
+
+    // 写盘失败不影响响应，第二次请求从内存命中
+    for _ in 0..2 {
+        let req = Request::get(format!("http://{}/a", addr)).body(Body::empty()).unwrap();
+        let response = server::handle_request(req, cache.clone(), client.clone(), config.clone()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "hello");
+        cache.flush().await.unwrap();
+    }
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+    let key = config.cache_key(&format!("http://{}/a", addr).parse().unwrap());
+    assert!(!on_disk(dir.path(), &key));
+    assert!(leftovers(dir.path()).is_empty());
+}
@@ -0,0 +1,21 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use rust_proxy_server::cache::DiskIoLimiter;
+use rust_proxy_server::metrics::METRICS;
+
+// 等待许可的请求被取消后排队深度恢复，不会让后台整理一直以为磁盘繁忙
+#[tokio::test]
+async fn cancelled_acquires_leave_the_queue() {
+    let limiter = DiskIoLimiter::new(1);
+    let held = limiter.acquire().await;
+    let queued = METRICS.cache_disk_io_queued.load(Ordering::Relaxed);
+
+    let waiting = tokio::time::timeout(Duration::from_millis(50), limiter.acquire()).await;
+    assert!(waiting.is_err());
+    assert_eq!(METRICS.cache_disk_io_queued.load(Ordering::Relaxed), queued);
+
+    drop(held);
+    let _permit = limiter.acquire().await;
+    assert_eq!(METRICS.cache_disk_io_queued.load(Ordering::Relaxed), queued);
+}
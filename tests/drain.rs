@@ -0,0 +1,24 @@
+use hyper::{Body, Request, StatusCode};
+use rust_proxy_server::drain::DRAIN;
+use rust_proxy_server::metrics::handle_metrics_request;
+
+async fn health() -> StatusCode {
+    let req = Request::get("/health").body(Body::empty()).unwrap();
+    handle_metrics_request(req).await.unwrap().status()
+}
+
+#[tokio::test]
+async fn draining_fails_health_checks_until_cancelled() {
+    assert_eq!(health().await, StatusCode::OK);
+    DRAIN.start(1_700_000_000);
+    // 重复开始不改变开始时间
+    DRAIN.start(1_700_000_100);
+    assert_eq!(health().await, StatusCode::SERVICE_UNAVAILABLE);
+    let status = DRAIN.status();
+    assert!(status.draining);
+    assert_eq!(status.since, Some(1_700_000_000));
+
+    DRAIN.stop();
+    assert_eq!(health().await, StatusCode::OK);
+    assert_eq!(DRAIN.status().since, None);
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::anyhow;
+use bytes::Bytes;
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE, ETAG, LAST_MODIFIED};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode, Uri};
+use rust_proxy_server::cache::ProxyCache;
+use rust_proxy_server::config::Config;
+use rust_proxy_server::esi;
+use rust_proxy_server::{client, server};
+
+fn html(body: &str) -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(ETAG, "\"t1\"")
+        .header(LAST_MODIFIED, "Tue, 01 Oct 2024 00:00:00 GMT")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+// 按固定内容应答片段请求，并记录请求过的地址
+async fn assemble(response: Response<Body>, fragments: &[(&str, &str)]) -> (anyhow::Result<Response<Body>>, Vec<String>) {
+    let fragments: HashMap<String, String> =
+        fragments.iter().map(|(uri, body)| (uri.to_string(), body.to_string())).collect();
+    let fetched = Mutex::new(Vec::new());
+    let base: Uri = "http://origin.test/pages/index.html".parse().unwrap();
+    let result = esi::assemble(response, &base, |uri: Uri| {
+        fetched.lock().unwrap().push(uri.to_string());
+        let fragment = fragments.get(&uri.to_string()).cloned();
+        async move { fragment.map(Bytes::from).ok_or_else(|| anyhow!("{} not found", uri)) }
+    })
+    .await;
+    (result, fetched.into_inner().unwrap())
+}
+
+async fn body(response: Response<Body>) -> String {
+    String::from_utf8(hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn includes_are_resolved_against_the_template() {
+    let template = r#"<p><esi:include src="/header"/></p><esi:include src="nav.html"></esi:include><esi:include src="http://other.test/ad" />"#;
+    let (result, fetched) = assemble(
+        html(template),
+        &[
+            ("http://origin.test/header", "H"),
+            ("http://origin.test/pages/nav.html", "N"),
+            ("http://other.test/ad", "A"),
+        ],
+    )
+    .await;
+    assert_eq!(
+        fetched,
+        ["http://origin.test/header", "http://origin.test/pages/nav.html", "http://other.test/ad"]
+    );
+    let response = result.unwrap();
+    assert_eq!(response.headers()[CONTENT_LENGTH], "10");
+    // 拼装后的内容不再对应源站的校验值
+    assert!(!response.headers().contains_key(ETAG));
+    assert!(!response.headers().contains_key(LAST_MODIFIED));
+    assert_eq!(body(response).await, "<p>H</p>NA");
+}
+
+#[tokio::test]
+async fn esi_comments_are_unwrapped_and_removals_dropped() {
+    let template = r#"a<!--esi <esi:include src="/x"/> -->b<esi:remove><a href="/x">x</a></esi:remove>c"#;
+    let (result, _) = assemble(html(template), &[("http://origin.test/x", "X")]).await;
+    assert_eq!(body(result.unwrap()).await, "a X bc");
+}
+
+#[tokio::test]
+async fn failed_includes_try_alt_then_continue_or_fail() {
+    let template = r#"<esi:include src="/missing" alt="/fallback"/>|<esi:include src="/gone" onerror="continue"/>|"#;
+    let (result, fetched) = assemble(html(template), &[("http://origin.test/fallback", "F")]).await;
+    assert_eq!(body(result.unwrap()).await, "F||");
+    assert!(fetched.contains(&"http://origin.test/missing".to_string()));
+
+    // 没有 alt 也没有 onerror="continue" 的片段失败时整个模板失败
+    let (result, _) = assemble(html(r#"<esi:include src="/gone"/>"#), &[]).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn non_html_passes_through_and_includes_are_limited() {
+    let json = Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"{"a":"<esi:include src=\"/x\"/>"}"#))
+        .unwrap();
+    let (result, fetched) = assemble(json, &[("http://origin.test/x", "X")]).await;
+    assert!(fetched.is_empty());
+    assert_eq!(body(result.unwrap()).await, r#"{"a":"<esi:include src=\"/x\"/>"}"#);
+
+    let many = r#"<esi:include src="/x"/>"#.repeat(33);
+    let (result, fetched) = assemble(html(&many), &[("http://origin.test/x", "X")]).await;
+    assert!(result.is_err());
+    assert!(fetched.is_empty());
+}
+
+type Requests = Arc<Mutex<HashMap<String, u32>>>;
+
+// /page 是包含两个片段的模板，/frag/* 返回片段名；按路径记录 GET 请求数
+fn origin() -> (SocketAddr, Requests) {
+    let requests: Requests = Arc::default();
+    let counter = requests.clone();
+    let make = make_service_fn(move |_| {
+        let counter = counter.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let path = req.uri().path().to_string();
+                if req.method() == Method::GET {
+                    *counter.lock().unwrap().entry(path.clone()).or_default() += 1;
+                }
+                async move {
+                    let response = match path.strip_prefix("/frag/") {
+                        Some(name) => Response::builder()
+                            .header("cache-control", "max-age=600")
+                            .header(CONTENT_TYPE, "text/html")
+                            .body(Body::from(name.to_string())),
+                        None => Response::builder()
+                            .header("cache-control", "max-age=600")
+                            .header(CONTENT_TYPE, "text/html")
+                            .body(Body::from(r#"[<esi:include src="/frag/a"/>|<esi:include src="frag/b"/>]"#)),
+                    };
+                    Ok::<_, Infallible>(response.unwrap())
+                }
+            }))
+        }
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    (addr, requests)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn templates_and_fragments_are_cached_separately() {
+    let (addr, requests) = origin();
+    let config = Config::parse(
+        r#"
+        [[routes]]
+        path_prefix = "/page"
+        esi = true
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+    let config = Arc::new(config);
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ProxyCache::builder().dir(dir.path()).build().await.unwrap());
+    let client = client::build(&config).unwrap();
+    let uri = format!("http://{}/page", addr);
+
+    for _ in 0..2 {
+        let req = Request::get(uri.as_str()).body(Body::empty()).unwrap();
+        let response = server::handle_request(req, cache.clone(), client.clone(), config.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body(response).await, "[a|b]");
+        cache.flush().await.unwrap();
+    }
+    // 第二次请求的模板与片段都来自缓存
+    let requests = requests.lock().unwrap().clone();
+    assert_eq!(requests, HashMap::from([("/page".to_string(), 1), ("/frag/a".to_string(), 1), ("/frag/b".to_string(), 1)]));
+
+    // 缓存中保存的是未拼装的模板
+    let template = cache.get(&config.cache_key(&uri.parse().unwrap())).await.unwrap();
+    assert!(String::from_utf8_lossy(&template.content).contains("<esi:include"));
+}
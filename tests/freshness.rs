@@ -0,0 +1,172 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hyper::header::{HeaderName, HeaderValue, CACHE_CONTROL, DATE, EXPIRES, IF_MODIFIED_SINCE, LAST_MODIFIED};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, HeaderMap, Method, Request, Response, Server, StatusCode};
+use rust_proxy_server::cache::{lifetime, ProxyCache};
+use rust_proxy_server::clock::MockClock;
+use rust_proxy_server::config::{CachePolicy, Config};
+use rust_proxy_server::constants::DEBUG_HEADER;
+use rust_proxy_server::{client, server};
+
+const NOW: u64 = 1_700_000_000;
+
+fn http_date(secs: u64) -> HeaderValue {
+    HeaderValue::from_str(&httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(secs))).unwrap()
+}
+
+fn headers(pairs: &[(HeaderName, HeaderValue)]) -> HeaderMap {
+    pairs.iter().cloned().collect()
+}
+
+fn policy(config: &str) -> CachePolicy {
+    let config = Config::parse(config).unwrap();
+    config.validate().unwrap();
+    config.cache_policy(&"http://origin.test/a".parse().unwrap())
+}
+
+#[test]
+fn explicit_lifetimes_take_precedence_over_the_heuristic() {
+    let policy = policy("");
+    let modified = (LAST_MODIFIED, http_date(NOW - 100_000));
+    let date = (DATE, http_date(NOW));
+    let expires = (EXPIRES, http_date(NOW + 300));
+
+    let all = headers(&[
+        (CACHE_CONTROL, HeaderValue::from_static("max-age=60, s-maxage=120")),
+        expires.clone(),
+        modified.clone(),
+        date.clone(),
+    ]);
+    assert_eq!(lifetime(&all, &policy, NOW), Some(120));
+    let max_age = headers(&[(CACHE_CONTROL, HeaderValue::from_static("max-age=60")), expires.clone(), modified.clone()]);
+    assert_eq!(lifetime(&max_age, &policy, NOW), Some(60));
+    // Expires 按源站的 Date 计算，不受两边时钟差异影响
+    let skewed = headers(&[expires.clone(), modified.clone(), (DATE, http_date(NOW + 100))]);
+    assert_eq!(lifetime(&skewed, &policy, NOW), Some(200));
+    let bad_expires = headers(&[(EXPIRES, HeaderValue::from_static("0")), modified.clone()]);
+    assert_eq!(lifetime(&bad_expires, &policy, NOW), Some(0));
+    let no_cache = headers(&[(CACHE_CONTROL, HeaderValue::from_static("no-cache")), modified]);
+    assert_eq!(lifetime(&no_cache, &policy, NOW), Some(0));
+}
+
+#[test]
+fn heuristic_is_a_capped_fraction_of_the_age() {
+    let policy = policy("[cache]\nheuristic_fraction = 0.1\nheuristic_max_secs = 3600\n");
+    let modified = |age: u64| headers(&[(LAST_MODIFIED, http_date(NOW - age)), (DATE, http_date(NOW))]);
+    assert_eq!(lifetime(&modified(1000), &policy, NOW), Some(100));
+    assert_eq!(lifetime(&modified(1_000_000), &policy, NOW), Some(3600));
+    // 修改时间在 Date 之后（时钟不一致）时不给新鲜期
+    let future = headers(&[(LAST_MODIFIED, http_date(NOW + 60)), (DATE, http_date(NOW))]);
+    assert_eq!(lifetime(&future, &policy, NOW), Some(0));
+    // 没有 Date 时以当前时间计算
+    let undated = headers(&[(LAST_MODIFIED, http_date(NOW - 1000))]);
+    assert_eq!(lifetime(&undated, &policy, NOW), Some(100));
+    // 没有任何新鲜度信息
+    assert_eq!(lifetime(&HeaderMap::new(), &policy, NOW), None);
+}
+
+#[test]
+fn routes_override_the_heuristic() {
+    let config = Config::parse(
+        r#"
+        [cache]
+        heuristic_fraction = 0.1
+
+        [[routes]]
+        path_prefix = "/static/"
+        heuristic_fraction = 0.5
+        heuristic_max_secs = 200
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+    let headers = headers(&[(LAST_MODIFIED, http_date(NOW - 1000)), (DATE, http_date(NOW))]);
+    let page = config.cache_policy(&"http://origin.test/page".parse().unwrap());
+    let asset = config.cache_policy(&"http://origin.test/static/app.js".parse().unwrap());
+    assert_eq!(lifetime(&headers, &page, NOW), Some(100));
+    assert_eq!(lifetime(&headers, &asset, NOW), Some(200));
+}
+
+// 只带 Last-Modified（比 Date 早 1000 秒）的源站，条件请求一律返回 304。
+// 分别记录普通 GET 与条件 GET 的次数
+fn origin(now: u64) -> (SocketAddr, Arc<AtomicU32>, Arc<AtomicU32>) {
+    let full = Arc::new(AtomicU32::new(0));
+    let conditional = Arc::new(AtomicU32::new(0));
+    let (full_count, conditional_count) = (full.clone(), conditional.clone());
+    let make = make_service_fn(move |_| {
+        let (full, conditional) = (full_count.clone(), conditional_count.clone());
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let revalidation = req.headers().contains_key(IF_MODIFIED_SINCE);
+                if req.method() == Method::GET {
+                    let counter = if revalidation { &conditional } else { &full };
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+                async move {
+                    let response = Response::builder()
+                        .header(DATE, http_date(now))
+                        .header(LAST_MODIFIED, http_date(now - 1000));
+                    let response = if revalidation {
+                        response.status(StatusCode::NOT_MODIFIED).body(Body::empty())
+                    } else {
+                        response.body(Body::from("hello world"))
+                    };
+                    Ok::<_, Infallible>(response.unwrap())
+                }
+            }))
+        }
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    (addr, full, conditional)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn heuristically_fresh_entries_expire_and_revalidate() {
+    // 源站的 Date 与代理的时钟一致，启发式新鲜期为 1000 * 0.1 = 100 秒
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let (addr, full, conditional) = origin(now);
+    let clock = Arc::new(MockClock::at_secs(now));
+    let config = Config::parse("[cache]\nheuristic_fraction = 0.1\n").unwrap();
+    config.validate().unwrap();
+    let config = Arc::new(config);
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ProxyCache::builder().dir(dir.path()).clock(clock.clone()).build().await.unwrap());
+    let client = client::build(&config).unwrap();
+    let uri = format!("http://{}/a", addr);
+    let get = || {
+        let req = Request::get(uri.as_str()).header(DEBUG_HEADER, "1").body(Body::empty()).unwrap();
+        let (cache, client, config) = (cache.clone(), client.clone(), config.clone());
+        async move {
+            let response = server::handle_request(req, cache.clone(), client, config).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let freshness = response.headers().get("x-proxy-freshness").map(|v| v.to_str().unwrap().to_string());
+            assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "hello world");
+            cache.flush().await.unwrap();
+            freshness
+        }
+    };
+
+    get().await;
+    let key = config.cache_key(&uri.parse().unwrap());
+    assert_eq!(cache.get(&key).await.unwrap().meta.freshness_secs, Some(100));
+
+    clock.advance(Duration::from_secs(99));
+    get().await;
+    assert_eq!((full.load(Ordering::SeqCst), conditional.load(Ordering::SeqCst)), (1, 0));
+
+    // 过期后先向源站验证，304 之后继续使用缓存的内容并重新开始计算新鲜期
+    clock.advance(Duration::from_secs(2));
+    assert_eq!(get().await.as_deref(), Some("revalidated"));
+    assert_eq!((full.load(Ordering::SeqCst), conditional.load(Ordering::SeqCst)), (1, 1));
+
+    clock.advance(Duration::from_secs(99));
+    get().await;
+    assert_eq!((full.load(Ordering::SeqCst), conditional.load(Ordering::SeqCst)), (1, 1));
+}
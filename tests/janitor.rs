@@ -0,0 +1,201 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use rust_proxy_server::cache::{inspect, sha256_hex, CacheEntry, CacheMeta, Load, ProxyCache};
+use rust_proxy_server::clock::MockClock;
+use rust_proxy_server::config::{CacheConfig, Config};
+use rust_proxy_server::metrics::METRICS;
+
+const START: u64 = 1_700_000_000;
+const DAY: u64 = 24 * 3600;
+
+// 测试中手动切换的负载信号
+#[derive(Default)]
+struct ManualLoad(AtomicBool);
+
+impl Load for ManualLoad {
+    fn busy(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+// 每个条目带一个与内容同名的 Surrogate-Key 标签
+fn entry(content: &'static [u8], stored_at: u64, freshness_secs: u64) -> CacheEntry {
+    let tag = String::from_utf8_lossy(content).to_string();
+    let meta: CacheMeta = serde_json::from_value(serde_json::json!({
+        "content_type": "text/plain",
+        "is_complete": true,
+        "total_size": content.len(),
+        "stored_at": stored_at,
+        "freshness_secs": freshness_secs,
+        "headers": [["surrogate-key", tag]],
+        "version": 1,
+    }))
+    .unwrap();
+    CacheEntry {
+        content: Bytes::from_static(content),
+        meta,
+    }
+}
+
+fn config(toml: &str) -> CacheConfig {
+    let config = Config::parse(toml).unwrap();
+    config.validate().unwrap();
+    config.cache
+}
+
+fn keys(dir: &Path) -> Vec<String> {
+    let mut keys: Vec<String> = inspect::list(dir).unwrap().into_iter().map(|entry| entry.key).collect();
+    keys.sort();
+    keys
+}
+
+// 整理在写盘任务与阻塞线程中执行，按真实时间等待结果
+async fn wait_until(mut done: impl FnMut() -> bool) {
+    for _ in 0..500 {
+        if done() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("janitor did not run");
+}
+
+#[tokio::test]
+async fn sweep_removes_long_expired_entries_unless_busy() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = Arc::new(MockClock::at_secs(START));
+    let load = Arc::new(ManualLoad::default());
+    let cache = ProxyCache::builder()
+        .config(config(
+            r#"
+            [cache.janitor]
+            sweep_interval_secs = 3600
+            expired_grace_secs = 86400
+            "#,
+        ))
+        .dir(dir.path())
+        .clock(clock.clone())
+        .load(load.clone())
+        .build()
+        .await
+        .unwrap();
+    // 过期两天、刚过期（仍在宽限期内）与新鲜的条目
+    cache.set("old".to_string(), entry(b"old", START - 2 * DAY, 60)).await.unwrap();
+    cache.set("stale".to_string(), entry(b"stale", START - 600, 60)).await.unwrap();
+    cache.set("fresh".to_string(), entry(b"fresh", START, DAY)).await.unwrap();
+    cache.flush().await.unwrap();
+
+    // 负载高时推迟，条目保留
+    load.0.store(true, Ordering::SeqCst);
+    // 抖动后的首次执行时间在 3240 到 3960 秒之间，推迟不到一个间隔
+    let deferred = METRICS.cache_janitor_deferred.load(Ordering::Relaxed);
+    clock.advance(Duration::from_secs(4000));
+    wait_until(|| METRICS.cache_janitor_deferred.load(Ordering::Relaxed) > deferred).await;
+    assert_eq!(keys(dir.path()), ["fresh", "old", "stale"]);
+
+    // 负载下降后稍后重试
+    load.0.store(false, Ordering::SeqCst);
+    clock.advance(Duration::from_secs(60));
+    wait_until(|| keys(dir.path()) == ["fresh", "stale"]).await;
+    assert!(METRICS.cache_expired_swept.load(Ordering::Relaxed) >= 1);
+    // 与 purge 一样，内存缓存与标签索引中也不再有被清理的条目
+    assert!(cache.get("old").await.is_none());
+    assert!(cache.tagged("old").is_empty());
+    assert_eq!(cache.tagged("stale"), ["stale"]);
+}
+
+#[tokio::test]
+async fn integrity_scan_discards_corrupt_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = Arc::new(MockClock::at_secs(START));
+    let cache = ProxyCache::builder()
+        .config(config(
+            r#"
+            [cache]
+            verify_checksums = true
+
+            [cache.janitor]
+            verify_interval_secs = 60
+            verify_batch = 1
+            "#,
+        ))
+        .dir(dir.path())
+        .clock(clock.clone())
+        .load(Arc::new(ManualLoad::default()))
+        .build()
+        .await
+        .unwrap();
+    cache.set("a".to_string(), entry(b"intact", START, DAY)).await.unwrap();
+    cache.set("b".to_string(), entry(b"damaged", START, DAY)).await.unwrap();
+    cache.flush().await.unwrap();
+
+    let damaged = inspect::list(dir.path()).unwrap().into_iter().find(|entry| entry.key == "b").unwrap();
+    assert_eq!(damaged.meta.as_ref().unwrap().sha256.as_deref(), Some(sha256_hex(b"damaged").as_str()));
+    std::fs::write(damaged.path(dir.path()), b"DAMAGED").unwrap();
+
+    // 每次只校验一个条目，第二次接着校验 b
+    let corrupt = METRICS.cache_corrupt_entries.load(Ordering::Relaxed);
+    for _ in 0..3 {
+        clock.advance(Duration::from_secs(90));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    wait_until(|| keys(dir.path()) == ["a"]).await;
+    assert!(METRICS.cache_corrupt_entries.load(Ordering::Relaxed) > corrupt);
+    assert!(cache.get("b").await.is_none());
+    assert!(cache.tagged("damaged").is_empty());
+}
+
+#[test]
+fn janitor_settings_are_validated() {
+    for toml in ["[cache.janitor]\njitter = 1.0\n", "[cache.janitor]\nsweep_interval_secs = 0\n"] {
+        assert!(Config::parse(toml).unwrap().validate().is_err(), "{}", toml);
+    }
+    let warnings = Config::parse("[cache.janitor]\nverify_interval_secs = 60\n")
+        .unwrap()
+        .validate()
+        .unwrap();
+    assert_eq!(warnings.len(), 1);
+}
+
+#[test]
+fn expired_entries_are_kept_unless_sweeping_is_enabled() {
+    assert_eq!(Config::default().cache.janitor.sweep_interval_secs, None);
+}
+
+// 持续高负载时最多推迟一个间隔，之后照常执行
+#[tokio::test]
+async fn sweep_runs_anyway_after_deferring_for_an_interval() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = Arc::new(MockClock::at_secs(START));
+    let load = Arc::new(ManualLoad::default());
+    load.0.store(true, Ordering::SeqCst);
+    let cache = ProxyCache::builder()
+        .config(config(
+            r#"
+            [cache.janitor]
+            jitter = 0.0
+            sweep_interval_secs = 3600
+            "#,
+        ))
+        .dir(dir.path())
+        .clock(clock.clone())
+        .load(load)
+        .build()
+        .await
+        .unwrap();
+    cache.set("old".to_string(), entry(b"old", START - 2 * DAY, 60)).await.unwrap();
+    cache.set("fresh".to_string(), entry(b"fresh", START, DAY)).await.unwrap();
+    cache.flush().await.unwrap();
+
+    let deferred = METRICS.cache_janitor_deferred.load(Ordering::Relaxed);
+    clock.advance(Duration::from_secs(3600));
+    wait_until(|| METRICS.cache_janitor_deferred.load(Ordering::Relaxed) > deferred).await;
+    assert_eq!(keys(dir.path()), ["fresh", "old"]);
+
+    clock.advance(Duration::from_secs(3600));
+    wait_until(|| keys(dir.path()) == ["fresh"]).await;
+}
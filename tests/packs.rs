@@ -0,0 +1,111 @@
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use rust_proxy_server::cache::{CacheEntry, CacheMeta, ProxyCache};
+use rust_proxy_server::clock::MockClock;
+use rust_proxy_server::config::{CacheConfig, Config};
+use rust_proxy_server::metrics::METRICS;
+
+const START: u64 = 1_700_000_000;
+
+// 段 8 KiB，有效数据低于一半的段会被压缩
+fn config() -> CacheConfig {
+    let config = Config::parse(
+        r#"
+        [cache.packing]
+        enabled = true
+        max_object_bytes = 6000
+        segment_bytes = 8192
+        compact_interval_secs = 60
+        min_live_ratio = 0.5
+
+        [cache.janitor]
+        jitter = 0.0
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+    config.cache
+}
+
+fn entry(len: usize) -> CacheEntry {
+    let meta: CacheMeta = serde_json::from_value(serde_json::json!({
+        "content_type": "application/octet-stream",
+        "is_complete": true,
+        "total_size": len,
+        "version": 1,
+    }))
+    .unwrap();
+    CacheEntry {
+        content: Bytes::from(vec![b'x'; len]),
+        meta,
+    }
+}
+
+async fn open(dir: &Path, clock: Arc<MockClock>) -> ProxyCache {
+    ProxyCache::builder().config(config()).dir(dir).clock(clock).build().await.unwrap()
+}
+
+#[tokio::test]
+async fn compaction_keeps_tombstones_for_older_segments() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = Arc::new(MockClock::at_secs(START));
+    let cache = open(dir.path(), clock.clone()).await;
+    // 段 1：a 与 k，删除 a 后仍有一半以上有效，不压缩
+    cache.set("a".to_string(), entry(2000)).await.unwrap();
+    cache.set("k".to_string(), entry(4000)).await.unwrap();
+    // 段 2：x 与 a、x 的墓碑，全部失效
+    cache.set("x".to_string(), entry(3000)).await.unwrap();
+    cache.flush().await.unwrap();
+    assert!(cache.purge("a").await.unwrap());
+    assert!(cache.purge("x").await.unwrap());
+    // 段 3：当前追加的段
+    cache.set("y".to_string(), entry(5000)).await.unwrap();
+    cache.flush().await.unwrap();
+
+    let compactions = METRICS.cache_pack_compactions.load(Ordering::Relaxed);
+    clock.advance(Duration::from_secs(61));
+    for _ in 0..500 {
+        if METRICS.cache_pack_compactions.load(Ordering::Relaxed) > compactions {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(METRICS.cache_pack_compactions.load(Ordering::Relaxed) > compactions);
+    cache.flush().await.unwrap();
+    drop(cache);
+
+    let cache = open(dir.path(), clock).await;
+    assert!(cache.get("a").await.is_none());
+    assert!(cache.get("x").await.is_none());
+    assert_eq!(cache.get("k").await.unwrap().content.len(), 4000);
+    assert_eq!(cache.get("y").await.unwrap().content.len(), 5000);
+}
+
+#[tokio::test]
+async fn corrupt_record_lengths_truncate_the_segment() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = Arc::new(MockClock::at_secs(START));
+    let cache = open(dir.path(), clock.clone()).await;
+    cache.set("a".to_string(), entry(2000)).await.unwrap();
+    cache.flush().await.unwrap();
+    drop(cache);
+
+    // 追加一条长度字段损坏的记录：元数据声称有 4 GiB，段中只剩几个字节
+    let segment = std::fs::read_dir(dir.path().join("packs")).unwrap().next().unwrap().unwrap().path();
+    let size = std::fs::metadata(&segment).unwrap().len();
+    let mut garbage = vec![1u8, 1, 0, b'b'];
+    garbage.extend_from_slice(&u32::MAX.to_le_bytes());
+    garbage.extend_from_slice(b"{}");
+    let mut content = std::fs::read(&segment).unwrap();
+    content.extend_from_slice(&garbage);
+    std::fs::write(&segment, content).unwrap();
+
+    let cache = open(dir.path(), clock).await;
+    assert_eq!(cache.get("a").await.unwrap().content.len(), 2000);
+    assert!(cache.get("b").await.is_none());
+    assert_eq!(std::fs::metadata(&segment).unwrap().len(), size);
+}
@@ -0,0 +1,281 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use rust_proxy_server::cache::ProxyCache;
+use rust_proxy_server::config::Config;
+use rust_proxy_server::constants::{DEBUG_HEADER, PEER_HEADER, SHARD_HEADER};
+use rust_proxy_server::upstream::HttpClient;
+use rust_proxy_server::{client, server};
+
+// 返回可缓存的内容，记录收到的 GET 请求数（HEAD 是写缓存时查询大小）
+fn origin() -> (SocketAddr, Arc<AtomicU32>) {
+    let requests = Arc::new(AtomicU32::new(0));
+    let counter = requests.clone();
+    let make = make_service_fn(move |_| {
+        let counter = counter.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                if req.method() == Method::GET {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+                async {
+                    let response = Response::builder()
+                        .header("cache-control", "max-age=600")
+                        .body(Body::from("hello world"));
+                    Ok::<_, Infallible>(response.unwrap())
+                }
+            }))
+        }
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    (addr, requests)
+}
+
+// 一个代理实例：自己的配置、缓存与上游客户端
+struct Proxy {
+    config: Arc<Config>,
+    cache: Arc<ProxyCache>,
+    client: HttpClient,
+    _dir: tempfile::TempDir,
+}
+
+impl Proxy {
+    async fn new(config: &str) -> Proxy {
+        let config = Config::parse(config).unwrap();
+        config.validate().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Arc::new(ProxyCache::builder().dir(dir.path()).build().await.unwrap());
+        Proxy {
+            client: client::build(&config).unwrap(),
+            config: Arc::new(config),
+            cache,
+            _dir: dir,
+        }
+    }
+
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        server::handle_request(req, self.cache.clone(), self.client.clone(), self.config.clone())
+            .await
+            .unwrap()
+    }
+
+    async fn get(&self, uri: &str) -> (StatusCode, String, String) {
+        let req = Request::get(uri).header(DEBUG_HEADER, "1").body(Body::empty()).unwrap();
+        let response = self.handle(req).await;
+        let status = response.status();
+        let lookup = response.headers()["x-proxy-cache"].to_str().unwrap().to_string();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        self.cache.flush().await.unwrap();
+        (status, lookup, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    // 在本地端口上对外服务，供其他实例作为兄弟代理访问
+    fn listen(self: &Arc<Self>) -> SocketAddr {
+        let (listener, addr) = listener();
+        self.serve(listener);
+        addr
+    }
+
+    fn serve(self: &Arc<Self>, listener: std::net::TcpListener) {
+        let proxy = self.clone();
+        let make = make_service_fn(move |_| {
+            let proxy = proxy.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let proxy = proxy.clone();
+                    async move { Ok::<_, Infallible>(proxy.handle(req).await) }
+                }))
+            }
+        });
+        tokio::spawn(Server::from_tcp(listener).unwrap().serve(make));
+    }
+}
+
+// 先占用端口，实例的配置里需要写上自己的地址
+fn listener() -> (std::net::TcpListener, SocketAddr) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let addr = listener.local_addr().unwrap();
+    (listener, addr)
+}
+
+fn peers_config(peer: SocketAddr, lookup_timeout_ms: u64) -> String {
+    format!(
+        "[peers]\naddrs = [\"{}\"]\nlookup_timeout_ms = {}\n",
+        peer, lookup_timeout_ms
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn local_misses_are_served_from_a_peer_cache() {
+    let (origin, requests) = origin();
+    let uri = format!("http://{}/a", origin);
+    let peer = Arc::new(Proxy::new("").await);
+    peer.get(&uri).await;
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+    let local = Proxy::new(&peers_config(peer.listen(), 1000)).await;
+    let (status, lookup, body) = local.get(&uri).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(lookup, "peer");
+    assert_eq!(body, "hello world");
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+    // 取回的对象写入本地缓存，之后不再询问兄弟代理
+    let (_, lookup, _) = local.get(&uri).await;
+    assert_eq!(lookup, "hit");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn peer_misses_fall_back_to_origin() {
+    let (origin, requests) = origin();
+    let uri = format!("http://{}/a", origin);
+    let peer = Arc::new(Proxy::new("").await);
+
+    let local = Proxy::new(&peers_config(peer.listen(), 1000)).await;
+    let (status, lookup, body) = local.get(&uri).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(lookup, "peer");
+    assert_eq!(body, "hello world");
+    // 兄弟代理只查自己的缓存，不会替查询方回源
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+    assert!(peer.cache.get(&peer.config.cache_key(&uri.parse().unwrap())).await.is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn slow_peers_are_not_waited_for() {
+    let (origin, requests) = origin();
+    let uri = format!("http://{}/a", origin);
+    // 接受连接但迟迟不应答的兄弟代理
+    let make = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|_req: Request<Body>| async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok::<_, Infallible>(Response::new(Body::from("too late")))
+        }))
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+    let peer = server.local_addr();
+    tokio::spawn(server);
+
+    let local = Proxy::new(&peers_config(peer, 100)).await;
+    let started = Instant::now();
+    let (status, _, body) = local.get(&uri).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "hello world");
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn peer_queries_only_read_the_cache() {
+    let (origin, requests) = origin();
+    let uri = format!("http://{}/a", origin);
+    let proxy = Proxy::new("").await;
+
+    let query = || Request::get(uri.as_str()).header(PEER_HEADER, "1").body(Body::empty()).unwrap();
+    let response = proxy.handle(query()).await;
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(requests.load(Ordering::SeqCst), 0);
+
+    proxy.get(&uri).await;
+    let response = proxy.handle(query()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "hello world");
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+fn shard_config(self_addr: SocketAddr, peer: SocketAddr) -> String {
+    format!(
+        "[peers]\naddrs = [\"{}\"]\nshard = true\nself_addr = \"{}\"\n",
+        peer, self_addr
+    )
+}
+
+// 两个分片实例，各自的配置里列出对方
+async fn shard_pair() -> (Arc<Proxy>, Arc<Proxy>) {
+    let (a_listener, a_addr) = listener();
+    let (b_listener, b_addr) = listener();
+    let a = Arc::new(Proxy::new(&shard_config(a_addr, b_addr)).await);
+    let b = Arc::new(Proxy::new(&shard_config(b_addr, a_addr)).await);
+    a.serve(a_listener);
+    b.serve(b_listener);
+    (a, b)
+}
+
+async fn cached_by(proxy: &Proxy, uri: &str) -> bool {
+    proxy.cache.get(&proxy.config.cache_key(&uri.parse().unwrap())).await.is_some()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn each_object_is_cached_by_its_owner_only() {
+    let (origin, requests) = origin();
+    let (a, b) = shard_pair().await;
+    let uris: Vec<String> = (0..32).map(|i| format!("http://{}/{}", origin, i)).collect();
+
+    let mut forwarded = 0;
+    for uri in &uris {
+        let (status, lookup, body) = a.get(uri).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "hello world");
+        if lookup == "shard" {
+            forwarded += 1;
+        }
+        // 另一个实例算出同一个所属实例，直接命中它的缓存
+        let (_, _, body) = b.get(uri).await;
+        assert_eq!(body, "hello world");
+    }
+    b.cache.flush().await.unwrap();
+    assert_eq!(requests.load(Ordering::SeqCst), uris.len() as u32);
+
+    let mut owned_by_a = 0;
+    for uri in &uris {
+        let (in_a, in_b) = (cached_by(&a, uri).await, cached_by(&b, uri).await);
+        assert!(in_a != in_b, "{} cached by both or neither", uri);
+        if in_a {
+            owned_by_a += 1;
+        }
+    }
+    // 对象分布在两个实例上，A 只缓存属于自己的部分，其余转发给 B
+    assert!(owned_by_a > 0 && owned_by_a < uris.len());
+    assert_eq!(forwarded, uris.len() - owned_by_a);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn forwarded_requests_are_served_by_the_receiver() {
+    let (origin, requests) = origin();
+    let (a, b) = shard_pair().await;
+    // 转发来的请求由收到它的实例处理，即使按哈希它属于另一个实例
+    for i in 0..8 {
+        let uri = format!("http://{}/{}", origin, i);
+        let req = Request::get(uri.as_str()).header(SHARD_HEADER, "1").body(Body::empty()).unwrap();
+        assert_eq!(b.handle(req).await.status(), StatusCode::OK);
+        b.cache.flush().await.unwrap();
+        assert!(cached_by(&b, &uri).await);
+        assert!(!cached_by(&a, &uri).await);
+    }
+    assert_eq!(requests.load(Ordering::SeqCst), 8);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unavailable_owners_are_served_locally() {
+    let (origin, requests) = origin();
+    let (_, self_addr) = listener();
+    // 占用后立即释放的端口：所属实例不可达
+    let (closed, peer) = listener();
+    drop(closed);
+    let local = Proxy::new(&shard_config(self_addr, peer)).await;
+    for i in 0..8 {
+        let (status, lookup, body) = local.get(&format!("http://{}/{}", origin, i)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_ne!(lookup, "shard");
+        assert_eq!(body, "hello world");
+    }
+    assert_eq!(requests.load(Ordering::SeqCst), 8);
+}
@@ -0,0 +1,28 @@
+#![cfg(unix)]
+
+use rust_proxy_server::daemon::PidFile;
+
+#[test]
+fn pid_file_is_written_and_removed() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("proxy.pid");
+    // 进程已经不存在的旧文件直接覆盖
+    std::fs::write(&path, "2147483646\n").unwrap();
+
+    let pid_file = PidFile::create(&path).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), format!("{}\n", std::process::id()));
+    drop(pid_file);
+    assert!(!path.exists());
+}
+
+#[test]
+fn running_instance_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("proxy.pid");
+    std::fs::write(&path, "1\n").unwrap();
+
+    let err = PidFile::create(&path).err().unwrap();
+    assert!(err.to_string().contains("another instance is running"));
+    // 不属于本进程的文件保留
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "1\n");
+}
@@ -0,0 +1,93 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rust_proxy_server::upstream::{HostLimiter, Priority, UpstreamBusy};
+
+// 名额已满时依次排入队列，每个请求取得许可后记录自己的名字
+async fn queue(limiter: &Arc<HostLimiter>, requests: &[(&'static str, Priority)]) -> Arc<Mutex<Vec<&'static str>>> {
+    let order = Arc::new(Mutex::new(Vec::new()));
+    for &(name, priority) in requests {
+        let limiter = limiter.clone();
+        let order = order.clone();
+        tokio::spawn(async move {
+            let _permit = limiter.acquire("origin.test", priority).await.unwrap();
+            order.lock().unwrap().push(name);
+        });
+        // 让新任务运行到排队为止，保证到达顺序
+        tokio::task::yield_now().await;
+    }
+    order
+}
+
+#[tokio::test]
+async fn waiters_are_served_by_priority_then_arrival() {
+    let limiter = Arc::new(HostLimiter::new(1, None));
+    let held = limiter.acquire("origin.test", Priority::Normal).await.unwrap();
+    let order = queue(
+        &limiter,
+        &[
+            ("low", Priority::Low),
+            ("normal-1", Priority::Normal),
+            ("high", Priority::High),
+            ("normal-2", Priority::Normal),
+        ],
+    )
+    .await;
+    assert!(order.lock().unwrap().is_empty());
+
+    drop(held);
+    while order.lock().unwrap().len() < 4 {
+        tokio::task::yield_now().await;
+    }
+    assert_eq!(*order.lock().unwrap(), ["high", "normal-1", "normal-2", "low"]);
+}
+
+#[tokio::test]
+async fn hosts_are_limited_independently() {
+    let limiter = HostLimiter::new(1, Some(Duration::from_millis(50)));
+    let _a = limiter.acquire("a.test", Priority::Normal).await.unwrap();
+    let _b = limiter.acquire("b.test", Priority::Normal).await.unwrap();
+    assert_eq!(limiter.active_hosts(), 2);
+}
+
+#[tokio::test]
+async fn queued_requests_are_shed_after_the_timeout() {
+    let limiter = HostLimiter::new(1, Some(Duration::from_millis(50)));
+    let held = limiter.acquire("origin.test", Priority::Normal).await.unwrap();
+    let err = limiter.acquire("origin.test", Priority::High).await.err().unwrap();
+    assert!(err.downcast_ref::<UpstreamBusy>().is_some());
+
+    // 放弃排队的请求不会占走释放出的名额
+    drop(held);
+    let _permit = limiter.acquire("origin.test", Priority::Low).await.unwrap();
+}
+
+#[tokio::test]
+async fn idle_hosts_are_forgotten() {
+    let limiter = Arc::new(HostLimiter::new(2, Some(Duration::from_millis(50))));
+    for i in 0..100 {
+        drop(limiter.acquire(&format!("origin-{}.test", i), Priority::Normal).await.unwrap());
+    }
+    assert_eq!(limiter.active_hosts(), 0);
+
+    // 仍有许可未归还或有请求排队时保留闸门
+    let first = limiter.acquire("origin.test", Priority::Normal).await.unwrap();
+    let second = limiter.acquire("origin.test", Priority::Normal).await.unwrap();
+    let order = queue(&limiter, &[("queued", Priority::Normal)]).await;
+    drop(first);
+    assert_eq!(limiter.active_hosts(), 1);
+    drop(second);
+    while order.lock().unwrap().is_empty() {
+        tokio::task::yield_now().await;
+    }
+    tokio::task::yield_now().await;
+    assert_eq!(limiter.active_hosts(), 0);
+
+    // 排队超时的请求也不会让闸门留下
+    let held = limiter.acquire("origin.test", Priority::Normal).await.unwrap();
+    let second = limiter.acquire("origin.test", Priority::Normal).await.unwrap();
+    assert!(limiter.acquire("origin.test", Priority::Normal).await.is_err());
+    drop(held);
+    drop(second);
+    assert_eq!(limiter.active_hosts(), 0);
+}
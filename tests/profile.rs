@@ -0,0 +1,31 @@
+use rust_proxy_server::config::{Config, Profile};
+
+#[test]
+fn low_memory_profile_fills_unset_values() {
+    let config = Config::parse(
+        r#"
+        profile = "low_memory"
+
+        [cache]
+        max_object_bytes = 4194304
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.profile, Profile::LowMemory);
+    assert!(!config.cache.memory_content);
+    assert_eq!(config.cache.write_queue_size, 8);
+    // 文件中写出的项优先于档位的默认值
+    assert_eq!(config.cache.max_object_bytes, 4194304);
+    assert_eq!(config.admin.recent_requests, 32);
+    config.validate().unwrap();
+}
+
+#[test]
+fn standard_profile_matches_defaults() {
+    let config = Config::parse("").unwrap();
+    let defaults = Config::default();
+    assert_eq!(config.profile, Profile::Standard);
+    assert!(config.cache.memory_content);
+    assert_eq!(config.cache.max_object_bytes, defaults.cache.max_object_bytes);
+    assert_eq!(config.cache.write_queue_size, defaults.cache.write_queue_size);
+}
@@ -0,0 +1,126 @@
+use hyper::header::{HOST, VIA};
+use hyper::{Body, Request};
+use rust_proxy_server::config::{Config, ProxyMode, RouteConfig};
+use rust_proxy_server::target::{normalize, Target};
+
+fn config(mode: ProxyMode) -> Config {
+    Config {
+        mode,
+        routes: vec![RouteConfig {
+            name: "site".to_string(),
+            host: Some("site.example.com".to_string()),
+            scheme: Some("https".to_string()),
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
+}
+
+fn absolute() -> Request<Body> {
+    Request::get("http://other.example.com/a").body(Body::empty()).unwrap()
+}
+
+fn origin_form(host: &str) -> Request<Body> {
+    Request::get("/a").header(HOST, host).body(Body::empty()).unwrap()
+}
+
+fn target(mode: ProxyMode, mut req: Request<Body>) -> (&'static str, String) {
+    let kind = match normalize(&mut req, &config(mode)) {
+        Target::Proxy => "proxy",
+        Target::Local => "local",
+        Target::BadRequest(_) => "bad-request",
+        Target::Forbidden(_) => "forbidden",
+        Target::LoopDetected => "loop",
+    };
+    (kind, req.uri().to_string())
+}
+
+#[test]
+fn mode_decides_which_request_forms_are_proxied() {
+    let proxied = |uri: &str| ("proxy", uri.to_string());
+    assert_eq!(target(ProxyMode::Auto, absolute()), proxied("http://other.example.com/a"));
+    assert_eq!(target(ProxyMode::Auto, origin_form("site.example.com")), proxied("https://site.example.com/a"));
+    assert_eq!(target(ProxyMode::Auto, origin_form("other.example.com")).0, "local");
+
+    assert_eq!(target(ProxyMode::Forward, absolute()).0, "proxy");
+    assert_eq!(target(ProxyMode::Forward, origin_form("site.example.com")).0, "local");
+
+    // 反向代理不能被当作开放的正向代理使用
+    assert_eq!(target(ProxyMode::Reverse, absolute()).0, "forbidden");
+    assert_eq!(target(ProxyMode::Reverse, origin_form("site.example.com")).0, "proxy");
+    assert_eq!(target(ProxyMode::Reverse, origin_form("other.example.com")).0, "local");
+
+    // 透明代理转发到任何 Host，路由指定的协议仍然生效；发给自己的请求不转发
+    assert_eq!(
+        target(ProxyMode::Transparent, origin_form("other.example.com")),
+        proxied("http://other.example.com/a")
+    );
+    assert_eq!(
+        target(ProxyMode::Transparent, origin_form("site.example.com")),
+        proxied("https://site.example.com/a")
+    );
+    assert_eq!(target(ProxyMode::Transparent, origin_form("127.0.0.1:3000")).0, "local");
+    assert_eq!(target(ProxyMode::Transparent, origin_form("localhost:3000")).0, "local");
+}
+
+#[test]
+fn mode_specific_options_are_validated() {
+    let mut forward = config(ProxyMode::Forward);
+    assert!(forward.validate().is_err());
+    forward.routes[0].scheme = None;
+    assert!(forward.validate().is_ok());
+
+    let mut reverse = config(ProxyMode::Reverse);
+    reverse.routes[0].host = None;
+    reverse.routes[0].scheme = None;
+    assert!(reverse.validate().is_err());
+
+    assert!(config(ProxyMode::Forward).via_enabled());
+    assert!(!config(ProxyMode::Auto).via_enabled());
+}
+
+#[test]
+fn requests_that_already_passed_the_proxy_are_rejected() {
+    let mut req = absolute();
+    req.headers_mut().insert(VIA, "1.1 edge, 1.1 rust-proxy-server (via dns)".parse().unwrap());
+    assert_eq!(target(ProxyMode::Auto, req).0, "loop");
+    let mut req = origin_form("other.example.com");
+    req.headers_mut().insert(VIA, "1.0 rust-proxy-server".parse().unwrap());
+    assert_eq!(target(ProxyMode::Transparent, req).0, "loop");
+
+    let mut req = absolute();
+    req.headers_mut().insert(VIA, "1.1 other-proxy".parse().unwrap());
+    assert_eq!(target(ProxyMode::Auto, req).0, "proxy");
+}
+
+#[test]
+fn forward_and_transparent_modes_deny_private_targets() {
+    let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+    for uri in [
+        "http://127.0.0.1/a",
+        "http://localhost:8080/a",
+        "http://10.1.2.3/a",
+        "http://192.168.0.1/a",
+        "http://169.254.169.254/latest/meta-data",
+        "http://[::1]/a",
+        "http://[::ffff:127.0.0.1]/a",
+        "http://[fd00::1]/a",
+        "http://127.1/a",
+        "http://2130706433/a",
+    ] {
+        assert_eq!(target(ProxyMode::Forward, get(uri)).0, "forbidden", "{}", uri);
+        assert_eq!(target(ProxyMode::Auto, get(uri)).0, "proxy", "{}", uri);
+    }
+    assert_eq!(target(ProxyMode::Forward, get("http://8.8.8.8/a")).0, "proxy");
+    assert_eq!(target(ProxyMode::Transparent, origin_form("10.0.0.1")).0, "forbidden");
+
+    // 可以明确打开或关闭；有 host 的路由命中的目标由配置决定，不受限制
+    let mut allowed = config(ProxyMode::Forward);
+    allowed.deny_private_targets = Some(false);
+    assert!(matches!(normalize(&mut get("http://10.1.2.3/a"), &allowed), Target::Proxy));
+    let mut denied = config(ProxyMode::Auto);
+    denied.deny_private_targets = Some(true);
+    assert!(matches!(normalize(&mut get("http://10.1.2.3/a"), &denied), Target::Forbidden(_)));
+    denied.routes[0].host = Some("10.1.2.3".to_string());
+    assert!(matches!(normalize(&mut get("http://10.1.2.3/a"), &denied), Target::Proxy));
+}
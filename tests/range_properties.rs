@@ -0,0 +1,219 @@
+use bytes::Bytes;
+use hyper::header::{HeaderValue, CONTENT_LENGTH, CONTENT_RANGE};
+use hyper::{Body, HeaderMap, Response, StatusCode};
+use proptest::prelude::*;
+use rust_proxy_server::cache::{ByteRanges, CacheEntry, CacheMeta};
+use rust_proxy_server::handler::{content_range, slice_full_response, stitchable_len};
+use rust_proxy_server::utils::parse_range;
+
+// 测试对象：第 i 个字节为 i % 251，任意区间的内容都可以直接算出
+fn byte_at(i: u64) -> u8 {
+    (i % 251) as u8
+}
+
+fn object(start: u64, end: u64) -> Vec<u8> {
+    (start..end).map(byte_at).collect()
+}
+
+fn empty_entry(total: Option<u64>) -> CacheEntry {
+    let meta: CacheMeta = serde_json::from_value(serde_json::json!({
+        "content_type": "application/octet-stream",
+        "is_complete": false,
+        "total_size": total,
+    }))
+    .unwrap();
+    CacheEntry {
+        content: Bytes::new(),
+        meta,
+    }
+}
+
+fn content_range_header(value: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Ok(value) = HeaderValue::from_str(value) {
+        headers.insert(CONTENT_RANGE, value);
+    }
+    headers
+}
+
+// 对象大小与对象内的若干次写入，每次写入 [start, start + len)
+fn object_writes(max_total: u64) -> impl Strategy<Value = (u64, Vec<(u64, u64)>)> {
+    (1..max_total).prop_flat_map(|total| {
+        let writes = prop::collection::vec((0..total, 0..total), 0..12).prop_map(move |writes| {
+            writes.into_iter().map(|(s, len)| (s, len.min(total - s))).collect()
+        });
+        (Just(total), writes)
+    })
+}
+
+// 从空条目开始依次合并写入
+fn apply(writes: &[(u64, u64)]) -> (ByteRanges, Bytes) {
+    writes
+        .iter()
+        .fold((ByteRanges::default(), Bytes::new()), |(ranges, content), &(start, len)| {
+            ranges.merge(&content, start, &object(start, start + len))
+        })
+}
+
+// 区间按起点排序、非空、互不重叠也不相邻，紧凑内容的长度与覆盖字节数一致
+fn assert_well_formed(ranges: &ByteRanges, content: &Bytes) {
+    let slice = ranges.as_slice();
+    for &(s, e) in slice {
+        assert!(s < e, "empty range in {:?}", slice);
+    }
+    for pair in slice.windows(2) {
+        assert!(pair[0].1 < pair[1].0, "overlapping or adjacent ranges in {:?}", slice);
+    }
+    assert_eq!(ranges.covered_bytes(), content.len() as u64);
+}
+
+proptest! {
+    #[test]
+    fn parse_range_round_trips(start in any::<u64>(), end in any::<u64>()) {
+        prop_assert_eq!(parse_range(&format!("bytes={}-{}", start, end)), Some((start, end)));
+    }
+
+    #[test]
+    fn parse_range_never_panics(value in ".*") {
+        let _ = parse_range(&value);
+    }
+
+    #[test]
+    fn parse_range_rejects_open_ended(start in any::<u64>()) {
+        prop_assert_eq!(parse_range(&format!("bytes={}-", start)), None);
+        prop_assert_eq!(parse_range(&format!("bytes=-{}", start)), None);
+    }
+
+    #[test]
+    fn content_range_round_trips(start in 0..u64::MAX / 2, len in 0..u64::MAX / 4, extra in 0..1000u64) {
+        let end = start + len;
+        let total = end + 1 + extra;
+        let headers = content_range_header(&format!("bytes {}-{}/{}", start, end, total));
+        prop_assert_eq!(content_range(&headers), Some((start, end, Some(total))));
+        // 总大小未知
+        let headers = content_range_header(&format!("bytes {}-{}/*", start, end));
+        prop_assert_eq!(content_range(&headers), Some((start, end, None)));
+    }
+
+    #[test]
+    fn content_range_rejects_inverted(start in 1..u64::MAX, back in 1..1000u64) {
+        let end = start.saturating_sub(back);
+        let headers = content_range_header(&format!("bytes {}-{}/*", start, end));
+        prop_assert_eq!(content_range(&headers), None);
+    }
+
+    #[test]
+    fn content_range_never_panics(value in "bytes [0-9 */-]{0,40}|.*") {
+        if let Some((start, end, _)) = content_range(&content_range_header(&value)) {
+            prop_assert!(start <= end);
+        }
+    }
+
+    #[test]
+    fn stitchable_len_matches_returned_range(
+        start in 0..10_000u64,
+        len in 1..10_000u64,
+        returned_len in 1..10_000u64,
+        total in prop::option::of(1..30_000u64),
+    ) {
+        let end = start + len - 1;
+        let returned_end = start + returned_len - 1;
+        if let Some(n) = stitchable_len((start, end), total, (start, returned_end, total)) {
+            prop_assert_eq!(n, returned_len);
+            prop_assert!(returned_end <= end);
+            prop_assert!(total.is_none_or(|total| returned_end < total));
+        }
+    }
+
+    #[test]
+    fn merged_ranges_match_model((total, writes) in object_writes(2000)) {
+        let mut covered = vec![false; total as usize];
+        let mut ranges = ByteRanges::default();
+        let mut content = Bytes::new();
+        for (start, len) in writes {
+            (ranges, content) = ranges.merge(&content, start, &object(start, start + len));
+            covered[start as usize..(start + len) as usize].fill(true);
+            assert_well_formed(&ranges, &content);
+        }
+
+        // 区间恰好是模型中已写入的字节
+        let mut expected = Vec::new();
+        let mut i = 0;
+        while i < total {
+            if covered[i as usize] {
+                let s = i;
+                while i < total && covered[i as usize] {
+                    i += 1;
+                }
+                expected.push((s, i));
+            } else {
+                i += 1;
+            }
+        }
+        prop_assert_eq!(ranges.as_slice(), expected.as_slice());
+
+        // 每个区间取出的都是对象对应位置的字节，空洞与区间互补
+        for &(s, e) in &expected {
+            prop_assert_eq!(ranges.slice(&content, s, e), Some(Bytes::from(object(s, e))));
+        }
+        let gap_bytes: u64 = ranges.gaps(total).iter().map(|(s, e)| e - s).sum();
+        prop_assert_eq!(gap_bytes + ranges.covered_bytes(), total);
+        prop_assert_eq!(ranges.covers(total), covered.iter().all(|&c| c));
+    }
+
+    #[test]
+    fn merge_order_does_not_matter((_, writes) in object_writes(500)) {
+        let mut reversed = writes.clone();
+        reversed.reverse();
+        prop_assert_eq!(apply(&writes), apply(&reversed));
+    }
+
+    #[test]
+    fn entry_completes_when_fully_covered(total in 1..1000u64, cut in 0..1000u64) {
+        let cut = cut.min(total);
+        let entry = empty_entry(Some(total)).merge(cut, &object(cut, total), None);
+        prop_assert_eq!(entry.meta.is_complete, cut == 0);
+        let entry = entry.merge(0, &object(0, cut), None);
+        prop_assert!(entry.meta.is_complete);
+        prop_assert!(entry.meta.ranges.is_none());
+        prop_assert_eq!(entry.content, Bytes::from(object(0, total)));
+    }
+
+    #[test]
+    fn truncate_packed_keeps_a_prefix((_, writes) in object_writes(500), keep in 0..600u64) {
+        let (ranges, content) = apply(&writes);
+        let keep = keep.min(content.len() as u64);
+        let truncated = ranges.truncate_packed(keep);
+        let prefix = content.slice(..keep as usize);
+        assert_well_formed(&truncated, &prefix);
+        for &(s, e) in truncated.as_slice() {
+            prop_assert!(ranges.contains(s, e));
+            prop_assert_eq!(truncated.slice(&prefix, s, e), Some(Bytes::from(object(s, e))));
+        }
+    }
+}
+
+fn full_response(len: usize) -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_LENGTH, len)
+        .body(Body::from(object(0, len as u64)))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn unsatisfiable_slices_serve_the_full_response() {
+    // 起点大于终点、起点在末尾或之后时原样返回完整的 200
+    for (start, end) in [(50, 10), (100, 150), (200, 300)] {
+        let response = slice_full_response(full_response(100), start, end).unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{}-{}", start, end);
+        assert_eq!(response.headers()[CONTENT_LENGTH], "100");
+        assert!(response.headers().get(CONTENT_RANGE).is_none());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body.len(), 100);
+    }
+    let sliced = slice_full_response(full_response(100), 90, 200).unwrap();
+    assert_eq!(sliced.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(sliced.headers()[CONTENT_RANGE], "bytes 90-99/100");
+    let body = hyper::body::to_bytes(sliced.into_body()).await.unwrap();
+    assert_eq!(&body[..], &object(90, 100)[..]);
+}
@@ -0,0 +1,328 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use hyper::header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use rust_proxy_server::cache::{ByteRanges, CacheEntry, CacheMeta, ProxyCache};
+use rust_proxy_server::config::Config;
+use rust_proxy_server::constants::DEBUG_HEADER;
+use rust_proxy_server::handler::{complete_response, partial_response, stitchable_len};
+use rust_proxy_server::{client, server};
+
+// 测试对象：第 i 个字节为 i % 251
+fn object(len: u64) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+fn partial_entry(data: &[u8], start: u64, end: u64, total: u64) -> CacheEntry {
+    let meta: CacheMeta = serde_json::from_value(serde_json::json!({
+        "content_type": "application/octet-stream",
+        "is_complete": false,
+        "total_size": total,
+    }))
+    .unwrap();
+    let entry = CacheEntry {
+        content: Bytes::new(),
+        meta,
+    };
+    entry.merge(start, &data[start as usize..end as usize], Some(total))
+}
+
+#[test]
+fn aligned_partial_is_stitchable() {
+    assert_eq!(stitchable_len((100, 199), Some(1000), (100, 199, Some(1000))), Some(100));
+    // 总大小未知时以源站声明的为准
+    assert_eq!(stitchable_len((100, 199), None, (100, 199, Some(1000))), Some(100));
+    // 请求越过对象末尾，源站截断到最后一个字节
+    assert_eq!(stitchable_len((900, 1099), Some(1000), (900, 999, Some(1000))), Some(100));
+}
+
+#[test]
+fn misaligned_start_is_rejected() {
+    assert_eq!(stitchable_len((100, 199), Some(1000), (0, 199, Some(1000))), None);
+    assert_eq!(stitchable_len((100, 199), Some(1000), (150, 199, Some(1000))), None);
+}
+
+#[test]
+fn overlong_or_inconsistent_partial_is_rejected() {
+    // 返回的区间超出请求的终点
+    assert_eq!(stitchable_len((100, 199), Some(1000), (100, 299, Some(1000))), None);
+    // 总大小与缓存记录的不同，对象已变化
+    assert_eq!(stitchable_len((100, 199), Some(1000), (100, 199, Some(2000))), None);
+    // 区间越过源站声明的对象末尾
+    assert_eq!(stitchable_len((100, 199), None, (100, 199, Some(150))), None);
+    assert_eq!(stitchable_len((100, 199), Some(150), (100, 199, None)), None);
+}
+
+#[test]
+fn overlapping_merge_does_not_grow_content() {
+    let data = object(1000);
+    let entry = partial_entry(&data, 0, 300, 1000);
+    // 与已缓存区间重叠的片段只追加超出的部分
+    let entry = entry.merge(200, &data[200..500], Some(1000));
+    assert_eq!(entry.content.len(), 500);
+    assert_eq!(entry.meta.ranges, Some(ByteRanges::single(0, 500)));
+    assert_eq!(entry.slice(0, 499).unwrap(), &data[0..500]);
+
+    // 完全落在已缓存区间内的片段不改变内容
+    let entry = entry.merge(100, &data[100..200], Some(1000));
+    assert_eq!(entry.content.len(), 500);
+}
+
+#[test]
+fn disjoint_merges_fill_gaps_until_complete() {
+    let data = object(1000);
+    let entry = partial_entry(&data, 600, 800, 1000);
+    let entry = entry.merge(0, &data[0..100], Some(1000));
+    assert_eq!(entry.content.len(), 300);
+    assert_eq!(entry.slice(650, 749).unwrap(), &data[650..750]);
+    assert!(entry.slice(50, 150).is_none());
+
+    let entry = entry.merge(100, &data[100..600], Some(1000));
+    let entry = entry.merge(800, &data[800..1000], Some(1000));
+    assert!(entry.meta.is_complete);
+    assert_eq!(entry.content.len(), 1000);
+    assert_eq!(&entry.content[..], &data[..]);
+}
+
+#[test]
+fn cached_run_ends_where_the_first_gap_begins() {
+    let data = object(1000);
+    let entry = partial_entry(&data, 100, 300, 1000).merge(500, &data[500..600], None);
+    let ranges = entry.ranges();
+    assert_eq!(ranges.run_end(100), Some(300));
+    assert_eq!(ranges.run_end(299), Some(300));
+    assert_eq!(ranges.run_end(300), None);
+    assert_eq!(ranges.run_end(550), Some(600));
+    assert_eq!(ranges.run_end(0), None);
+}
+
+#[test]
+fn hits_declare_their_length() {
+    let data = object(1000);
+    let mut entry = partial_entry(&data, 0, 1000, 1000);
+    // 源站保存的头不能覆盖实际发送的长度
+    entry.meta.headers = vec![("accept-ranges".to_string(), "none".to_string())];
+
+    let response = complete_response(&entry.meta, entry.content.clone(), 0).unwrap();
+    assert_eq!(response.headers()[CONTENT_LENGTH], "1000");
+    assert_eq!(response.headers()[ACCEPT_RANGES], "bytes");
+
+    let slice = entry.slice(100, 199).unwrap();
+    let response = partial_response(&entry.meta, 100, 199, slice, 0).unwrap();
+    assert_eq!(response.headers()[CONTENT_LENGTH], "100");
+    assert_eq!(response.headers()[CONTENT_RANGE], "bytes 100-199/1000");
+    assert_eq!(response.headers()[ACCEPT_RANGES], "bytes");
+}
+
+// 按 Range 返回测试对象的片段，每个响应延迟 delay
+fn origin(delay: Duration) -> SocketAddr {
+    let make = make_service_fn(move |_| async move {
+        Ok::<_, Infallible>(service_fn(move |req: Request<Body>| async move {
+            tokio::time::sleep(delay).await;
+            let range = req.headers()[RANGE].to_str().unwrap().trim_start_matches("bytes=").to_string();
+            let (start, end) = range.split_once('-').unwrap();
+            let (start, end): (usize, usize) = (start.parse().unwrap(), end.parse().unwrap());
+            let response = Response::builder()
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(CONTENT_RANGE, format!("bytes {}-{}/1000", start, end))
+                .header(ETAG, "\"v1\"")
+                .body(Body::from(object(1000)[start..=end].to_vec()));
+            Ok::<_, Infallible>(response.unwrap())
+        }))
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+// 缓存了前 500 字节，请求整个对象：先发送缓存的部分，其余部分向源站获取后接在后面
+async fn stream_range(delay: Duration, config: Config) -> (Response<Body>, Arc<ProxyCache>, String) {
+    let addr = origin(delay);
+    let config = Arc::new(config);
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ProxyCache::builder().dir(dir.path()).build().await.unwrap());
+    let uri = format!("http://{}/video", addr);
+    let mut entry = partial_entry(&object(1000), 0, 500, 1000);
+    entry.meta.etag = Some("\"v1\"".to_string());
+    let key = config.cache_key(&uri.parse().unwrap());
+    cache.set(key.clone(), entry).await.unwrap();
+
+    let req = Request::get(uri)
+        .header(RANGE, "bytes=0-999")
+        .header(DEBUG_HEADER, "1")
+        .body(Body::empty())
+        .unwrap();
+    let client = client::build(&config).unwrap();
+    let response = server::handle_request(req, cache.clone(), client, config).await.unwrap();
+    (response, cache, key)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn streamed_ranges_stitch_the_cached_prefix_and_the_fetched_rest() {
+    let (response, cache, key) = stream_range(Duration::ZERO, Config::default()).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()["x-proxy-range"], "streamed");
+    assert_eq!(response.headers()[CONTENT_LENGTH], "1000");
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&body[..], &object(1000)[..]);
+    // 获取的部分合并进缓存
+    cache.flush().await.unwrap();
+    assert!(cache.get(&key).await.unwrap().meta.is_complete);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn streamed_ranges_stop_at_the_request_deadline() {
+    let config = Config::parse("[downstream]\nrequest_deadline_secs = 1\n").unwrap();
+    config.validate().unwrap();
+    let started = Instant::now();
+    let (response, _, _) = stream_range(Duration::from_secs(5), config).await;
+    assert_eq!(response.headers()["x-proxy-range"], "streamed");
+    // 缓存的前缀已经发出，源站超过时限仍未返回时中断响应体
+    assert!(hyper::body::to_bytes(response.into_body()).await.is_err());
+    assert!(started.elapsed() < Duration::from_secs(4));
+}
+
+#[test]
+fn if_range_prefers_strong_etags() {
+    let mut meta: CacheMeta = serde_json::from_value(serde_json::json!({
+        "content_type": "application/octet-stream",
+        "is_complete": false,
+        "total_size": 1000,
+        "etag": "\"v1\"",
+        "last_modified": "Tue, 01 Oct 2024 00:00:00 GMT",
+    }))
+    .unwrap();
+    assert_eq!(meta.if_range_validator(), Some("\"v1\""));
+    // If-Range 不能使用弱标签，退回到 Last-Modified
+    meta.etag = Some("W/\"v1\"".to_string());
+    assert_eq!(meta.if_range_validator(), Some("Tue, 01 Oct 2024 00:00:00 GMT"));
+    meta.last_modified = None;
+    assert_eq!(meta.if_range_validator(), None);
+}
+
+// 源站当前的版本：ETag 与内容，可以在测试中途替换
+struct Version {
+    etag: &'static str,
+    content: Vec<u8>,
+}
+
+// 按 If-Range 应答的源站：校验器与当前 ETag 相同时返回片段，否则返回完整的当前版本。
+// 记录收到的每个 If-Range
+fn versioned_origin(version: Arc<Mutex<Version>>, if_ranges: Arc<Mutex<Vec<String>>>) -> SocketAddr {
+    let make = make_service_fn(move |_| {
+        let version = version.clone();
+        let if_ranges = if_ranges.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let version = version.clone();
+                let if_ranges = if_ranges.clone();
+                async move {
+                    let version = version.lock().unwrap();
+                    let if_range = req.headers().get(IF_RANGE).map(|v| v.to_str().unwrap().to_string());
+                    if let Some(if_range) = &if_range {
+                        if_ranges.lock().unwrap().push(if_range.clone());
+                    }
+                    let range = req.headers().get(RANGE).map(|v| v.to_str().unwrap().trim_start_matches("bytes="));
+                    let response = match range {
+                        Some(range) if if_range.as_deref().is_none_or(|v| v == version.etag) => {
+                            let (start, end) = range.split_once('-').unwrap();
+                            let (start, end): (usize, usize) = (start.parse().unwrap(), end.parse().unwrap());
+                            Response::builder()
+                                .status(StatusCode::PARTIAL_CONTENT)
+                                .header(CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, version.content.len()))
+                                .header(ETAG, version.etag)
+                                .body(Body::from(version.content[start..=end].to_vec()))
+                        }
+                        _ => Response::builder()
+                            .header(ETAG, version.etag)
+                            .header(LAST_MODIFIED, "Tue, 01 Oct 2024 00:00:00 GMT")
+                            .body(Body::from(version.content.clone())),
+                    };
+                    Ok::<_, Infallible>(response.unwrap())
+                }
+            }))
+        }
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+// 缓存了 v1 的 cached 区间，客户端请求整个对象
+// 返回源站收到的 If-Range 记录：流式拼接在后台回源，读完响应体后才能检查
+async fn resume(current: Version, cached: (u64, u64)) -> (Response<Body>, Arc<ProxyCache>, String, Arc<Mutex<Vec<String>>>) {
+    let if_ranges = Arc::new(Mutex::new(Vec::new()));
+    let addr = versioned_origin(Arc::new(Mutex::new(current)), if_ranges.clone());
+    let config = Arc::new(Config::default());
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ProxyCache::builder().dir(dir.path()).build().await.unwrap());
+    let uri = format!("http://{}/video", addr);
+    let mut entry = partial_entry(&object(1000), cached.0, cached.1, 1000);
+    entry.meta.etag = Some("\"v1\"".to_string());
+    let key = config.cache_key(&uri.parse().unwrap());
+    cache.set(key.clone(), entry).await.unwrap();
+
+    let req = Request::get(uri)
+        .header(RANGE, "bytes=0-999")
+        .header(DEBUG_HEADER, "1")
+        .body(Body::empty())
+        .unwrap();
+    let client = client::build(&config).unwrap();
+    let response = server::handle_request(req, cache.clone(), client, config).await.unwrap();
+    (response, cache, key, if_ranges)
+}
+
+fn changed_object() -> Vec<u8> {
+    (0..1000u32).map(|i| (i * 7 % 256) as u8).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unchanged_objects_are_stitched_after_if_range() {
+    let current = Version { etag: "\"v1\"", content: object(1000) };
+    let (response, cache, key, if_ranges) = resume(current, (500, 1000)).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()["x-proxy-range"], "fetched");
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&body[..], &object(1000)[..]);
+    // 续传请求带着缓存的 ETag
+    assert_eq!(*if_ranges.lock().unwrap(), ["\"v1\""]);
+
+    cache.flush().await.unwrap();
+    let entry = cache.get(&key).await.unwrap();
+    assert!(entry.meta.is_complete);
+    assert_eq!(&entry.content[..], &object(1000)[..]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn changed_objects_replace_the_cached_prefix() {
+    let current = Version { etag: "\"v2\"", content: changed_object() };
+    let (response, cache, key, if_ranges) = resume(current, (500, 1000)).await;
+    assert_eq!(*if_ranges.lock().unwrap(), ["\"v1\""]);
+    assert_eq!(response.headers()["x-proxy-range"], "changed");
+    // 旧的缓存数据不与新版本拼接，客户端收到的全部是新版本
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&body[..], &changed_object()[..]);
+
+    cache.flush().await.unwrap();
+    let entry = cache.get(&key).await.unwrap();
+    assert_eq!(entry.meta.etag.as_deref(), Some("\"v2\""));
+    assert_eq!(&entry.content[..], &changed_object()[..]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn changed_objects_abort_streamed_prefixes() {
+    let current = Version { etag: "\"v2\"", content: changed_object() };
+    let (response, cache, key, if_ranges) = resume(current, (0, 500)).await;
+    assert_eq!(response.headers()["x-proxy-range"], "streamed");
+    // 缓存的前缀已经发出，源站返回了新版本：中断响应体并清除旧的缓存
+    assert!(hyper::body::to_bytes(response.into_body()).await.is_err());
+    assert_eq!(*if_ranges.lock().unwrap(), ["\"v1\""]);
+    assert!(cache.get(&key).await.is_none());
+}
@@ -0,0 +1,88 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use rust_proxy_server::cache::ProxyCache;
+use rust_proxy_server::clock::MockClock;
+use rust_proxy_server::config::Config;
+use rust_proxy_server::recent_requests::{RecentRequests, RequestTrace, Timing, RECENT_REQUESTS};
+use rust_proxy_server::{client, server};
+use sha2::Sha256;
+
+fn trace(n: u64) -> RequestTrace {
+    RequestTrace {
+        time: 1_700_000_000 + n,
+        method: "GET".to_string(),
+        uri: format!("http://origin/{}", n),
+        status: 200,
+        client: None,
+        cache: "miss",
+        store: Some("stored"),
+        timing: Timing::default(),
+    }
+}
+
+#[test]
+fn keeps_the_newest_requests_newest_first() {
+    let recent = RecentRequests::new(3);
+    for n in 0..5 {
+        recent.push(trace(n));
+    }
+    let uris: Vec<_> = recent.list(usize::MAX).into_iter().map(|t| t.uri).collect();
+    assert_eq!(uris, ["http://origin/4", "http://origin/3", "http://origin/2"]);
+    assert_eq!(recent.list(1)[0].uri, "http://origin/4");
+
+    // 缩小容量时丢弃最旧的，0 之后不再记录
+    recent.set_capacity(1);
+    assert_eq!(recent.list(usize::MAX).len(), 1);
+    recent.set_capacity(0);
+    recent.push(trace(5));
+    assert!(recent.list(usize::MAX).is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn traces_drop_signatures_and_use_the_cache_clock() {
+    let make = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|_| async { Ok::<_, Infallible>(Response::new(Body::from("ok"))) }))
+    });
+    let origin = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+    let addr = origin.local_addr();
+    tokio::spawn(origin);
+
+    let config = Config::parse(
+        r#"
+        [[routes]]
+        name = "private"
+        path_prefix = "/private/"
+        signed_url = { secret = "s3cret" }
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let clock = Arc::new(MockClock::at_secs(1_700_000_000));
+    let cache = Arc::new(ProxyCache::builder().dir(dir.path()).clock(clock).build().await.unwrap());
+    let client = client::build(&config).unwrap();
+
+    // 签名覆盖路径与除 signature 外的全部查询参数
+    let expires = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 3600;
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"s3cret").unwrap();
+    mac.update(format!("/private/a.ts?v=2&expires={}", expires).as_bytes());
+    let signature = hex::encode(mac.finalize().into_bytes());
+    let uri = format!("http://{}/private/a.ts?v=2&expires={}&signature={}", addr, expires, signature);
+    let req = Request::get(uri).body(Body::empty()).unwrap();
+    let response = server::handle_request(req, cache, client, Arc::new(config)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let prefix = format!("http://{}/private/", addr);
+    let trace = RECENT_REQUESTS
+        .list(usize::MAX)
+        .into_iter()
+        .find(|trace| trace.uri.starts_with(&prefix))
+        .unwrap();
+    assert_eq!(trace.uri, format!("{}a.ts?v=2", prefix));
+    assert_eq!(trace.time, 1_700_000_000);
+}
@@ -0,0 +1,178 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use hyper::header::{AUTHORIZATION, COOKIE, HOST, LOCATION, PROXY_AUTHORIZATION};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode, Uri};
+use rust_proxy_server::client;
+use rust_proxy_server::config::Config;
+use rust_proxy_server::utils::fetch_with_retry;
+
+// 源站收到的一个请求：路径与是否带着各种凭据
+#[derive(Debug, PartialEq)]
+struct Seen {
+    path: String,
+    credentials: bool,
+}
+
+type Log = Arc<Mutex<Vec<Seen>>>;
+
+// /dir/start 重定向到相对路径 next，/away 重定向到 away 指定的地址，/loop/<n> 重定向到 /loop/<n+1>
+fn origin(away: Option<String>) -> (SocketAddr, Log) {
+    let log: Log = Arc::default();
+    let seen = log.clone();
+    let make = make_service_fn(move |_| {
+        let (seen, away) = (seen.clone(), away.clone());
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let (seen, away) = (seen.clone(), away.clone());
+                async move {
+                    let path = req.uri().path().to_string();
+                    let headers = req.headers();
+                    let credentials = [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE]
+                        .iter()
+                        .any(|name| headers.contains_key(name));
+                    seen.lock().unwrap().push(Seen {
+                        path: path.clone(),
+                        credentials,
+                    });
+                    let location = match path.as_str() {
+                        "/dir/start" => Some("next".to_string()),
+                        "/away" => away,
+                        _ => path
+                            .strip_prefix("/loop/")
+                            .and_then(|n| n.parse::<u32>().ok())
+                            .map(|n| format!("/loop/{}", n + 1)),
+                    };
+                    let response = match location {
+                        Some(location) => Response::builder()
+                            .status(StatusCode::FOUND)
+                            .header(LOCATION, location)
+                            .body(Body::empty()),
+                        None => Response::builder().body(Body::from(path)),
+                    };
+                    Ok::<_, Infallible>(response.unwrap())
+                }
+            }))
+        }
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    (addr, log)
+}
+
+fn config() -> Config {
+    let config = Config::parse(
+        r#"
+        [upstream.redirects]
+        follow = true
+        max_hops = 3
+        same_host_only = false
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+    config
+}
+
+fn request(uri: &Uri) -> Request<Body> {
+    Request::get(uri.clone())
+        .header(HOST, uri.authority().unwrap().as_str())
+        .header(AUTHORIZATION, "Bearer secret")
+        .header(PROXY_AUTHORIZATION, "Basic YWxpY2U6c2VjcmV0")
+        .header(COOKIE, "session=1")
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn seen(path: &str, credentials: bool) -> Seen {
+    Seen {
+        path: path.to_string(),
+        credentials,
+    }
+}
+
+async fn fetch(config: &Config, req: Request<Body>) -> Response<Body> {
+    let client = client::build(config).unwrap();
+    let fetch = config.cache_policy(req.uri()).fetch;
+    fetch_with_retry(&client, &req, fetch).await.unwrap()
+}
+
+#[tokio::test]
+async fn relative_locations_resolve_against_the_request_uri() {
+    let (addr, log) = origin(None);
+    let (other, other_log) = origin(None);
+    let config = config();
+    let uri: Uri = format!("http://{}/dir/start", addr).parse().unwrap();
+    // Host 指向另一个地址也不影响下一跳
+    let mut req = request(&uri);
+    req.headers_mut().insert(HOST, other.to_string().parse().unwrap());
+
+    let response = fetch(&config, req).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "/dir/next");
+    // 同源的下一跳保留凭据
+    assert_eq!(*log.lock().unwrap(), vec![seen("/dir/start", true), seen("/dir/next", true)]);
+    assert!(other_log.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn credentials_are_dropped_when_the_origin_changes() {
+    let (end, end_log) = origin(None);
+    // 主机相同、端口不同同样是另一个源
+    let (addr, log) = origin(Some(format!("http://{}/end", end)));
+    let uri: Uri = format!("http://{}/away", addr).parse().unwrap();
+
+    let response = fetch(&config(), request(&uri)).await;
+    assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "/end");
+    assert_eq!(*log.lock().unwrap(), vec![seen("/away", true)]);
+    assert_eq!(*end_log.lock().unwrap(), vec![seen("/end", false)]);
+
+    // 指向其他主机名
+    let (addr, log) = origin(Some(format!("http://localhost:{}/end", end.port())));
+    let uri: Uri = format!("http://{}/away", addr).parse().unwrap();
+    let response = fetch(&config(), request(&uri)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(*log.lock().unwrap(), vec![seen("/away", true)]);
+    assert_eq!(end_log.lock().unwrap().last(), Some(&seen("/end", false)));
+
+    // same_host_only 时不跟随到其他主机名，把重定向返回给客户端
+    let mut config = config();
+    config.upstream.redirects.same_host_only = true;
+    let response = fetch(&config, request(&uri)).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(end_log.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn redirects_stop_after_max_hops() {
+    let (addr, log) = origin(None);
+    let uri: Uri = format!("http://{}/loop/0", addr).parse().unwrap();
+
+    let response = fetch(&config(), request(&uri)).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(response.headers()[LOCATION], "/loop/4");
+    let paths: Vec<String> = log.lock().unwrap().iter().map(|seen| seen.path.clone()).collect();
+    assert_eq!(paths, ["/loop/0", "/loop/1", "/loop/2", "/loop/3"]);
+}
+
+#[tokio::test]
+async fn redirects_to_private_targets_are_not_followed() {
+    let (end, end_log) = origin(None);
+    let (addr, log) = origin(Some(format!("http://127.0.0.1:{}/end", end.port())));
+    // 源站本身由路由或测试直接访问；重定向把代理引向另一个内网地址时不跟随
+    let mut config = config();
+    config.deny_private_targets = Some(true);
+    let uri: Uri = format!("http://{}/away", addr).parse().unwrap();
+    let response = fetch(&config, request(&uri)).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(*log.lock().unwrap(), vec![seen("/away", true)]);
+    assert!(end_log.lock().unwrap().is_empty());
+
+    // 同一个源内的重定向照常跟随
+    let uri: Uri = format!("http://{}/dir/start", addr).parse().unwrap();
+    let response = fetch(&config, request(&uri)).await;
+    assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "/dir/next");
+}
@@ -0,0 +1,95 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use hyper::{Body, Method, Request, StatusCode};
+use rust_proxy_server::cache::ProxyCache;
+use rust_proxy_server::config::Config;
+use rust_proxy_server::{client, server};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+// 第一个连接读完请求后直接断开，之后的请求原样返回收到的请求体。返回地址与收到的请求数
+async fn origin() -> (SocketAddr, Arc<AtomicU32>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = Arc::new(AtomicU32::new(0));
+    let counter = requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let attempt = counter.fetch_add(1, Ordering::SeqCst);
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                let head_end = loop {
+                    if let Some(i) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break i + 4;
+                    }
+                    let n = socket.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        return;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                };
+                let head = String::from_utf8_lossy(&request[..head_end]).to_lowercase();
+                let len = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length: "))
+                    .and_then(|len| len.trim().parse::<usize>().ok())
+                    .unwrap_or(0);
+                while request.len() < head_end + len {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                if attempt == 0 {
+                    return;
+                }
+                let body = &request[head_end..head_end + len];
+                let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", len);
+                socket.write_all(response.as_bytes()).await.unwrap();
+                socket.write_all(body).await.unwrap();
+            });
+        }
+    });
+    (addr, requests)
+}
+
+// 经过代理发送带请求体的请求，返回状态码、响应体与源站收到的请求数
+async fn send(method: Method, idempotency_key: bool) -> (StatusCode, Vec<u8>, u32) {
+    let (addr, requests) = origin().await;
+    let config = Arc::new(Config::default());
+    let client = client::build(&config).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ProxyCache::builder().dir(dir.path()).build().await.unwrap());
+    let mut req = Request::builder().method(method).uri(format!("http://{}/a", addr));
+    if idempotency_key {
+        req = req.header("idempotency-key", "k1");
+    }
+    let req = req.body(Body::from("payload")).unwrap();
+    let response = server::handle_request(req, cache, client, config).await.unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec();
+    (status, body, requests.load(Ordering::SeqCst))
+}
+
+#[tokio::test]
+async fn retryable_methods_replay_the_request_body() {
+    let (status, body, requests) = send(Method::OPTIONS, false).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, b"payload");
+    assert_eq!(requests, 2);
+}
+
+#[tokio::test]
+async fn idempotency_keys_make_posts_retryable() {
+    let (status, body, requests) = send(Method::POST, true).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, b"payload");
+    assert_eq!(requests, 2);
+
+    // 没有幂等键的 POST 只发送一次
+    let (status, _, requests) = send(Method::POST, false).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(requests, 1);
+}
@@ -0,0 +1,122 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use hyper::{Body, Request, Uri};
+use rust_proxy_server::client;
+use rust_proxy_server::config::Config;
+use rust_proxy_server::utils::fetch_with_retry;
+use tokio::net::TcpListener;
+
+fn config() -> Config {
+    let config = Config::parse(
+        r#"
+        [upstream]
+        timeout_secs = 20
+
+        [downstream]
+        request_deadline_secs = 60
+
+        [[routes]]
+        name = "manifests"
+        path_prefix = "/live/"
+        timeout_secs = 1
+        max_retries = 0
+        max_object_bytes = 65536
+
+        [[routes]]
+        name = "archives"
+        path_prefix = "/archive/"
+        max_retries = 1
+        request_deadline_secs = 600
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+    config
+}
+
+// 接受连接后按 hold 决定立即断开还是一直不响应，返回已接受的连接数
+async fn origin(hold: bool) -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = accepted.clone();
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+            if hold {
+                tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_secs(30)).await;
+                    drop(socket);
+                });
+            }
+        }
+    });
+    (addr, accepted)
+}
+
+#[test]
+fn routes_override_timeouts_retries_and_size() {
+    let config = config();
+    let policy = |path: &str| config.cache_policy(&format!("http://cdn.example.com{}", path).parse().unwrap());
+
+    let live = policy("/live/index.m3u8");
+    assert_eq!(live.fetch.timeout, Duration::from_secs(1));
+    assert_eq!(live.fetch.max_retries, 0);
+    assert_eq!(live.max_object_bytes, 65536);
+
+    // 路由没有覆盖的项沿用全局设置
+    let archive = policy("/archive/all.tar");
+    assert_eq!(archive.fetch.timeout, Duration::from_secs(20));
+    assert_eq!(archive.fetch.max_retries, 1);
+    assert_eq!(archive.max_object_bytes, config.cache.max_object_bytes);
+    let other = policy("/other");
+    assert_eq!(other.fetch.max_retries, config.upstream.max_retries);
+
+    let deadline = |path: &str| config.request_deadline(&format!("http://cdn.example.com{}", path).parse::<Uri>().unwrap());
+    assert_eq!(deadline("/archive/all.tar"), Some(Duration::from_secs(600)));
+    assert_eq!(deadline("/live/index.m3u8"), Some(Duration::from_secs(60)));
+}
+
+#[test]
+fn zero_route_timeouts_are_rejected() {
+    let config = Config::parse(
+        r#"
+        [[routes]]
+        name = "live"
+        timeout_secs = 0
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().is_err());
+}
+
+#[tokio::test]
+async fn fetch_uses_the_route_timeout() {
+    let (addr, accepted) = origin(true).await;
+    let config = config();
+    let client = client::build(&config).unwrap();
+    let uri: Uri = format!("http://{}/live/index.m3u8", addr).parse().unwrap();
+    let req = Request::get(uri.clone()).body(Body::empty()).unwrap();
+
+    let started = Instant::now();
+    let result = fetch_with_retry(&client, &req, config.cache_policy(&uri).fetch).await;
+    assert!(result.is_err());
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn fetch_uses_the_route_retries() {
+    let (addr, accepted) = origin(false).await;
+    let config = config();
+    let client = client::build(&config).unwrap();
+    let uri: Uri = format!("http://{}/archive/all.tar", addr).parse().unwrap();
+    let req = Request::get(uri.clone()).body(Body::empty()).unwrap();
+
+    assert!(fetch_with_retry(&client, &req, config.cache_policy(&uri).fetch).await.is_err());
+    assert_eq!(accepted.load(Ordering::SeqCst), 2);
+}
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use hyper::{Body, Request, StatusCode};
+use rust_proxy_server::admin;
+use rust_proxy_server::cache::{CacheEntry, CacheMeta, ProxyCache};
+use rust_proxy_server::client;
+use rust_proxy_server::config::Config;
+use serde_json::{json, Value};
+
+// 通过管理接口的 POST /rpc 调用，返回状态码与解析后的响应体
+async fn call(cache: &Arc<ProxyCache>, body: &str) -> (StatusCode, Value) {
+    let config = Arc::new(Config::default());
+    let client = client::build(&config).unwrap();
+    let req = Request::post("/rpc").body(Body::from(body.to_string())).unwrap();
+    let response = admin::handle_admin_request(req, cache.clone(), client, config).await.unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let value = if body.is_empty() { Value::Null } else { serde_json::from_slice(&body).unwrap() };
+    (status, value)
+}
+
+async fn open() -> (tempfile::TempDir, Arc<ProxyCache>) {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ProxyCache::builder().dir(dir.path()).build().await.unwrap());
+    (dir, cache)
+}
+
+#[tokio::test]
+async fn unknown_methods_and_bad_calls_are_reported() {
+    let (_dir, cache) = open().await;
+
+    let (status, reply) = call(&cache, r#"{"jsonrpc": "2.0", "id": 1, "method": "cache.explode"}"#).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(reply["id"], 1);
+    assert_eq!(reply["error"]["code"], -32601);
+
+    let (_, reply) = call(&cache, r#"{"jsonrpc": "2.0", "id": 2, "method": "cache.purge", "params": {"urls": 5}}"#).await;
+    assert_eq!(reply["id"], 2);
+    assert_eq!(reply["error"]["code"], -32602);
+
+    let (_, reply) = call(&cache, r#"{"jsonrpc": "2.0", "id": 3, "method": "downloads.cancel"}"#).await;
+    assert_eq!(reply["error"]["code"], -32602);
+
+    let (_, reply) = call(&cache, "{not json").await;
+    assert_eq!(reply["id"], Value::Null);
+    assert_eq!(reply["error"]["code"], -32700);
+
+    let (_, reply) = call(&cache, r#"{"id": 4, "method": "stats.get"}"#).await;
+    assert_eq!(reply["error"]["code"], -32600);
+
+    let (_, reply) = call(&cache, "[]").await;
+    assert_eq!(reply["error"]["code"], -32600);
+}
+
+#[tokio::test]
+async fn calls_run_singly_in_batches_and_as_notifications() {
+    let (_dir, cache) = open().await;
+    let url = "http://origin.test/a";
+    let meta: CacheMeta = serde_json::from_value(json!({
+        "content_type": "text/plain",
+        "is_complete": true,
+        "total_size": 5,
+        "url": url,
+        "version": 1,
+    }))
+    .unwrap();
+    let key = Config::default().cache_key(&url.parse().unwrap());
+    cache.set(key.clone(), CacheEntry { content: Bytes::from_static(b"hello"), meta }).await.unwrap();
+
+    let purge = json!({ "jsonrpc": "2.0", "id": "p", "method": "cache.purge", "params": { "urls": [url] } });
+    let (status, reply) = call(&cache, &purge.to_string()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(reply["id"], "p");
+    assert_eq!(reply["result"]["purged"], 1);
+    assert!(reply.get("error").is_none());
+    assert!(cache.get(&key).await.is_none());
+
+    // 批量调用只返回有 id 的调用的结果
+    let batch = r#"[
+        {"jsonrpc": "2.0", "id": 1, "method": "drain.status"},
+        {"jsonrpc": "2.0", "method": "cache.flush"},
+        {"jsonrpc": "2.0", "id": 2, "method": "nope"}
+    ]"#;
+    let (_, reply) = call(&cache, batch).await;
+    let replies = reply.as_array().unwrap();
+    assert_eq!(replies.len(), 2);
+    assert!(replies[0]["result"].is_object());
+    assert_eq!(replies[1]["error"]["code"], -32601);
+
+    // 全部是通知时没有响应体
+    let (status, _) = call(&cache, r#"{"jsonrpc": "2.0", "method": "cache.flush"}"#).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
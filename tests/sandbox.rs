@@ -0,0 +1,58 @@
+#![cfg(target_os = "linux")]
+
+use rust_proxy_server::config::Config;
+use rust_proxy_server::{daemon, sandbox};
+
+// landlock 只限制调用的线程，在单独的线程中开启，不影响其他测试
+#[test]
+fn landlock_limits_files_to_cache_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache_dir = dir.path().join("cache");
+    let mut config = Config::default();
+    config.sandbox.enabled = true;
+
+    let (written, outside) = std::thread::spawn(move || {
+        sandbox::restrict_filesystem(&config, &cache_dir, (None, None)).unwrap();
+        let written = std::fs::write(cache_dir.join("entry"), b"data").is_ok();
+        let outside = std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/Cargo.toml");
+        (written, outside)
+    })
+    .join()
+    .unwrap();
+    assert!(written);
+    // 查询内核支持的 landlock 版本，不支持时返回 -1
+    let version = unsafe {
+        libc::syscall(libc::SYS_landlock_create_ruleset, std::ptr::null::<u8>(), 0, 1u32)
+    };
+    if version > 0 {
+        assert_eq!(outside.unwrap_err().kind(), std::io::ErrorKind::PermissionDenied);
+    }
+}
+
+// 开启沙箱后无法再读取用户数据库：用户事先解析成 uid 与 gid，pid 文件所在目录仍可写
+#[test]
+fn sandbox_starts_with_a_named_user() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache_dir = dir.path().join("cache");
+    let pid_path = dir.path().join("run").join("proxy.pid");
+    std::fs::create_dir(pid_path.parent().unwrap()).unwrap();
+    // 以当前用户的名字配置，切换身份不需要权限
+    let user = unsafe { std::ffi::CStr::from_ptr((*libc::getpwuid(libc::geteuid())).pw_name) };
+    let mut config = Config::default();
+    config.sandbox.enabled = true;
+    config.process.user = Some(user.to_str().unwrap().to_string());
+    config.process.pid_file = Some(pid_path.clone());
+
+    let removed = std::thread::spawn(move || {
+        let pid_file = daemon::PidFile::create(&pid_path).unwrap();
+        let identity = daemon::identity(&config.process).unwrap();
+        assert!(identity.0.is_some());
+        sandbox::restrict_filesystem(&config, &cache_dir, identity).unwrap();
+        daemon::drop_privileges(identity).unwrap();
+        drop(pid_file);
+        !pid_path.exists()
+    })
+    .join()
+    .unwrap();
+    assert!(removed);
+}
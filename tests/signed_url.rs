@@ -0,0 +1,219 @@
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use hyper::Uri;
+use md5::{Digest, Md5};
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs8::{EncodePublicKey, LineEnding};
+use rsa::rand_core::OsRng;
+use rsa::signature::{SignatureEncoding, Signer};
+use rsa::RsaPrivateKey;
+use rust_proxy_server::clock::MockClock;
+use rust_proxy_server::signed_url::SignedUrlConfig;
+use sha1::Sha1;
+use sha2::Sha256;
+
+const NOW: u64 = 1_700_000_000;
+const SECRET: &str = "s3cret";
+
+fn config(value: serde_json::Value) -> SignedUrlConfig {
+    serde_json::from_value(value).unwrap()
+}
+
+fn uri(value: &str) -> Uri {
+    value.parse().unwrap()
+}
+
+fn hmac_signature(message: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+    mac.update(message.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// 默认模板 "$expires$uri $secret"
+fn secure_link_md5(expires: u64, path: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Md5::digest(format!("{}{} {}", expires, path, SECRET)))
+}
+
+// CloudFront 的 URL 安全 base64 变体
+fn cloudfront_encode(value: &[u8]) -> String {
+    STANDARD
+        .encode(value)
+        .chars()
+        .map(|c| match c {
+            '+' => '-',
+            '=' => '_',
+            '/' => '~',
+            c => c,
+        })
+        .collect()
+}
+
+#[test]
+fn hmac_links_expire_by_the_given_clock() {
+    let signed = config(serde_json::json!({ "format": "hmac", "secret": SECRET }));
+    let expires = NOW + 60;
+    let signature = hmac_signature(&format!("/a.mp4?u=1&expires={}", expires));
+    let link = uri(&format!(
+        "http://cdn.test/a.mp4?u=1&expires={}&signature={}",
+        expires, signature
+    ));
+
+    assert!(signed.verify(&link, &MockClock::at_secs(NOW)));
+    assert!(signed.verify(&link, &MockClock::at_secs(expires - 1)));
+    // 到期时间取决于注入的时钟，而不是当前的系统时间
+    assert!(!signed.verify(&link, &MockClock::at_secs(expires)));
+}
+
+#[test]
+fn hmac_rejects_tampered_queries() {
+    let signed = config(serde_json::json!({ "format": "hmac", "secret": SECRET }));
+    let clock = MockClock::at_secs(NOW);
+    let expires = NOW + 60;
+    let signature = hmac_signature(&format!("/a.mp4?u=1&expires={}", expires));
+
+    let tampered = [
+        format!("http://cdn.test/a.mp4?u=2&expires={}&signature={}", expires, signature),
+        format!("http://cdn.test/a.mp4?u=1&x=1&expires={}&signature={}", expires, signature),
+        format!("http://cdn.test/b.mp4?u=1&expires={}&signature={}", expires, signature),
+        format!("http://cdn.test/a.mp4?u=1&expires={}&signature={}", expires + 3600, signature),
+        format!("http://cdn.test/a.mp4?u=1&expires={}", expires),
+    ];
+    for link in tampered {
+        assert!(!signed.verify(&uri(&link), &clock), "{}", link);
+    }
+}
+
+#[test]
+fn secure_link_checks_expiry_and_path() {
+    let signed = config(serde_json::json!({ "format": "secure_link", "secret": SECRET }));
+    let expires = NOW + 60;
+    let md5 = secure_link_md5(expires, "/a.mp4");
+    let link = uri(&format!("http://cdn.test/a.mp4?md5={}&expires={}", md5, expires));
+
+    assert!(signed.verify(&link, &MockClock::at_secs(NOW)));
+    assert!(!signed.verify(&link, &MockClock::at_secs(expires + 1)));
+
+    let clock = MockClock::at_secs(NOW);
+    let moved = uri(&format!("http://cdn.test/b.mp4?md5={}&expires={}", md5, expires));
+    assert!(!signed.verify(&moved, &clock));
+    let extended = uri(&format!("http://cdn.test/a.mp4?md5={}&expires={}", md5, expires + 3600));
+    assert!(!signed.verify(&extended, &clock));
+}
+
+struct KeyPairs {
+    config: SignedUrlConfig,
+    signer: SigningKey<Sha1>,
+    _dir: tempfile::TempDir,
+}
+
+// 生成两对密钥并登记为 K1、K2，签名使用 K1 的私钥
+fn cloudfront() -> KeyPairs {
+    let dir = tempfile::tempdir().unwrap();
+    let mut public_keys = serde_json::Map::new();
+    let mut signer = None;
+    for id in ["K1", "K2"] {
+        let key = RsaPrivateKey::new(&mut OsRng, 1024).unwrap();
+        let path = dir.path().join(format!("{}.pem", id));
+        let pem = key.to_public_key().to_public_key_pem(LineEnding::LF).unwrap();
+        std::fs::write(&path, pem).unwrap();
+        public_keys.insert(id.to_string(), path.to_string_lossy().into_owned().into());
+        signer.get_or_insert(SigningKey::<Sha1>::new(key));
+    }
+    let mut config = config(serde_json::json!({
+        "format": "cloudfront",
+        "public_keys": public_keys,
+    }));
+    config.load_keys().unwrap();
+    KeyPairs {
+        config,
+        signer: signer.unwrap(),
+        _dir: dir,
+    }
+}
+
+impl KeyPairs {
+    fn sign(&self, policy: &str) -> String {
+        cloudfront_encode(&self.signer.sign(policy.as_bytes()).to_vec())
+    }
+
+    fn canned(&self, resource: &str, expires: u64, key_id: &str) -> Uri {
+        let policy = format!(
+            r#"{{"Statement":[{{"Resource":"{}","Condition":{{"DateLessThan":{{"AWS:EpochTime":{}}}}}}}]}}"#,
+            resource, expires
+        );
+        uri(&format!(
+            "{}?Expires={}&Signature={}&Key-Pair-Id={}",
+            resource,
+            expires,
+            self.sign(&policy),
+            key_id
+        ))
+    }
+
+    fn custom(&self, url: &str, policy: &str) -> Uri {
+        uri(&format!(
+            "{}?Policy={}&Signature={}&Key-Pair-Id=K1",
+            url,
+            cloudfront_encode(policy.as_bytes()),
+            self.sign(policy)
+        ))
+    }
+}
+
+#[test]
+fn cloudfront_verifies_canned_policies() {
+    let keys = cloudfront();
+    let expires = NOW + 60;
+    let link = keys.canned("http://cdn.test/a.mp4", expires, "K1");
+
+    assert!(keys.config.verify(&link, &MockClock::at_secs(NOW)));
+    assert!(!keys.config.verify(&link, &MockClock::at_secs(expires)));
+
+    let clock = MockClock::at_secs(NOW);
+    // 签名与另一把已登记或未登记的公钥对不上
+    let other_key = keys.canned("http://cdn.test/a.mp4", expires, "K2");
+    assert!(!keys.config.verify(&other_key, &clock));
+    let unknown_key = keys.canned("http://cdn.test/a.mp4", expires, "K3");
+    assert!(!keys.config.verify(&unknown_key, &clock));
+
+    // canned policy 的 Resource 是完整的 URL，改动路径或查询都会使签名失效
+    let moved = link.to_string().replace("/a.mp4", "/b.mp4");
+    assert!(!keys.config.verify(&uri(&moved), &clock));
+    let extra = link.to_string().replace("a.mp4?", "a.mp4?u=1&");
+    assert!(!keys.config.verify(&uri(&extra), &clock));
+    let extended = link
+        .to_string()
+        .replace(&format!("Expires={}", expires), &format!("Expires={}", expires + 3600));
+    assert!(!keys.config.verify(&uri(&extended), &clock));
+}
+
+#[test]
+fn cloudfront_custom_policies_match_wildcard_resources() {
+    let keys = cloudfront();
+    let clock = MockClock::at_secs(NOW);
+    let policy = format!(
+        r#"{{"Statement":[{{"Resource":"http://cdn.test/video/*","Condition":{{"DateLessThan":{{"AWS:EpochTime":{}}},"DateGreaterThan":{{"AWS:EpochTime":{}}}}}}}]}}"#,
+        NOW + 60,
+        NOW - 60
+    );
+
+    for allowed in ["http://cdn.test/video/a.mp4", "http://cdn.test/video/hd/b.mp4"] {
+        assert!(keys.config.verify(&keys.custom(allowed, &policy), &clock), "{}", allowed);
+    }
+    for denied in ["http://cdn.test/audio/a.mp3", "http://cdn.test/videos/a.mp4"] {
+        assert!(!keys.config.verify(&keys.custom(denied, &policy), &clock), "{}", denied);
+    }
+
+    let link = keys.custom("http://cdn.test/video/a.mp4", &policy);
+    assert!(!keys.config.verify(&link, &MockClock::at_secs(NOW - 61)));
+    assert!(!keys.config.verify(&link, &MockClock::at_secs(NOW + 60)));
+
+    // 改过的 policy 与签名不符
+    let widened = policy.replace("/video/*", "/*");
+    let tampered = link.to_string().replace(
+        &cloudfront_encode(policy.as_bytes()),
+        &cloudfront_encode(widened.as_bytes()),
+    );
+    assert!(!keys.config.verify(&uri(&tampered), &clock));
+}
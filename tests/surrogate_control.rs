@@ -0,0 +1,93 @@
+use hyper::header::{HeaderName, HeaderValue, CACHE_CONTROL};
+use hyper::{HeaderMap, Uri};
+use rust_proxy_server::cache::{lifetime, strip_surrogate_headers};
+use rust_proxy_server::config::Config;
+
+const NOW: u64 = 1_700_000_000;
+
+fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+        headers.append(HeaderName::from_static(name), HeaderValue::from_str(value).unwrap());
+    }
+    headers
+}
+
+fn uri() -> Uri {
+    "http://origin.test/a".parse().unwrap()
+}
+
+fn surrogate_config() -> Config {
+    let mut config = Config::default();
+    config.cache.surrogate_headers = true;
+    config
+}
+
+#[test]
+fn surrogate_lifetime_overrides_cache_control() {
+    let policy = surrogate_config().cache_policy(&uri());
+    let surrogate = headers(&[("cache-control", "no-cache"), ("surrogate-control", "max-age=600")]);
+    assert_eq!(lifetime(&surrogate, &policy, NOW), Some(600));
+
+    // X-Accel-Expires 优先，可以是绝对时间
+    let accel = headers(&[
+        ("cache-control", "max-age=10"),
+        ("surrogate-control", "max-age=600"),
+        ("x-accel-expires", "30"),
+    ]);
+    assert_eq!(lifetime(&accel, &policy, NOW), Some(30));
+    let absolute = format!("@{}", NOW + 90);
+    let accel = headers(&[("x-accel-expires", &absolute)]);
+    assert_eq!(lifetime(&accel, &policy, NOW), Some(90));
+}
+
+#[test]
+fn surrogate_no_store_prevents_caching() {
+    let policy = surrogate_config().cache_policy(&uri());
+    assert!(!policy.may_store(&headers(&[("surrogate-control", "no-store")]), NOW));
+    assert!(!policy.may_store(&headers(&[("cache-control", "public"), ("x-accel-expires", "0")]), NOW));
+
+    // 带凭据的请求：源站给代理的正的新鲜期视为允许共享
+    let mut authenticated = policy;
+    authenticated.authenticated = true;
+    assert!(!authenticated.may_store(&headers(&[("cache-control", "max-age=60")]), NOW));
+    assert!(authenticated.may_store(&headers(&[("surrogate-control", "max-age=60")]), NOW));
+    assert!(!authenticated.may_store(&headers(&[("surrogate-control", "max-age=0")]), NOW));
+    // 绝对时间按传入的时钟计算，已经过去的时间不算允许
+    let absolute = format!("@{}", NOW + 30);
+    let accel = headers(&[("x-accel-expires", &absolute)]);
+    assert!(authenticated.may_store(&accel, NOW));
+    assert!(!authenticated.may_store(&accel, NOW + 30));
+    assert!(!authenticated.may_store(&accel, NOW + 3600));
+}
+
+#[test]
+fn surrogate_headers_are_off_by_default() {
+    let mut policy = Config::default().cache_policy(&uri());
+    policy.authenticated = true;
+    let response = headers(&[("cache-control", "max-age=10"), ("surrogate-control", "max-age=600")]);
+    assert_eq!(lifetime(&response, &policy, NOW), Some(10));
+    assert!(!policy.may_store(&response, NOW));
+}
+
+#[test]
+fn disabled_surrogate_headers_are_ignored() {
+    let mut config = surrogate_config();
+    config.cache.surrogate_headers = false;
+    let policy = config.cache_policy(&uri());
+    let response = headers(&[("cache-control", "max-age=10"), ("surrogate-control", "max-age=600, no-store")]);
+    assert_eq!(lifetime(&response, &policy, NOW), Some(10));
+    assert!(policy.may_store(&response, NOW));
+}
+
+#[test]
+fn surrogate_headers_are_stripped() {
+    let mut response = headers(&[
+        ("cache-control", "no-cache"),
+        ("surrogate-control", "max-age=600"),
+        ("x-accel-expires", "30"),
+    ]);
+    strip_surrogate_headers(&mut response);
+    assert_eq!(response.len(), 1);
+    assert_eq!(response[CACHE_CONTROL], "no-cache");
+}
@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use hyper::Uri;
+use rust_proxy_server::config::{Config, MetricsLabel};
+use rust_proxy_server::metrics::METRICS;
+
+fn parse(toml: &str) -> Config {
+    let config = Config::parse(toml).unwrap();
+    config.validate().unwrap();
+    config
+}
+
+fn record(config: &Config, uri: &str) {
+    let uri: Uri = uri.parse().unwrap();
+    let label = config.metrics_label(&uri);
+    METRICS.record_traffic(&label, "video/mp4", "hit", 100, Duration::from_millis(20));
+}
+
+// 全局指标在同一个测试中按顺序检查
+#[test]
+fn traffic_is_labelled_by_host_or_route() {
+    // 默认按源站 host 区分，/stats 仍然输出 by_host
+    let config = parse("");
+    assert_eq!(config.metrics.label, MetricsLabel::Host);
+    METRICS.configure(&config.metrics);
+    record(&config, "http://CDN.example.com/a.mp4");
+    let stats = serde_json::to_value(METRICS.traffic_stats()).unwrap();
+    assert_eq!(stats["by_host"]["cdn.example.com"]["results"]["hit"]["requests"], 1);
+    assert!(stats.get("by_route").is_none());
+    assert!(METRICS
+        .render()
+        .contains("proxy_cache_requests_total{host=\"cdn.example.com\",mime=\"video\",result=\"hit\"} 1"));
+
+    // 按路由区分：路由名原样出现在 /stats 中，在 Prometheus 文本中转义
+    let config = parse(
+        r#"
+        [metrics]
+        label = "route"
+        max_label_values = 2
+
+        [[routes]]
+        name = "say \"hi\"\\now"
+        path_prefix = "/videos/"
+
+        [[routes]]
+        path_prefix = "/images/"
+        "#,
+    );
+    METRICS.configure(&config.metrics);
+    record(&config, "http://a.example.com/videos/1.mp4");
+    record(&config, "http://a.example.com/images/1.png");
+    // 超过取值个数上限的归入 other
+    record(&config, "http://a.example.com/other");
+    let stats = serde_json::to_value(METRICS.traffic_stats()).unwrap();
+    let by_route = stats["by_route"].as_object().unwrap();
+    let mut routes: Vec<&str> = by_route.keys().map(|key| key.as_str()).collect();
+    routes.sort();
+    assert_eq!(routes, ["#1", "other", "say \"hi\"\\now"]);
+    assert!(stats["by_host"].as_object().unwrap().is_empty());
+    let rendered = METRICS.render();
+    assert!(rendered.contains("proxy_cache_hit_ratio{route=\"say \\\"hi\\\"\\\\now\"} 1"));
+    assert!(rendered.contains("proxy_request_duration_seconds_count{route=\"#1\"} 1"));
+}
@@ -0,0 +1,109 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use rust_proxy_server::cache::{CacheEntry, CacheLock, CacheMeta, ProxyCache};
+use rust_proxy_server::config::{CacheConfig, Config};
+
+fn config() -> CacheConfig {
+    let config = Config::parse(
+        r#"
+        [cache.packing]
+        enabled = true
+        max_object_bytes = 64
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+    config.cache
+}
+
+// 偶数序号的小对象写入打包存储，奇数序号的按文件存放
+fn entry(key: &str, n: usize) -> CacheEntry {
+    let len = if n.is_multiple_of(2) { 32 } else { 256 };
+    let content: Vec<u8> = key.bytes().cycle().take(len).collect();
+    let meta: CacheMeta = serde_json::from_value(serde_json::json!({
+        "content_type": "text/plain",
+        "is_complete": true,
+        "total_size": len,
+        "version": 1,
+    }))
+    .unwrap();
+    CacheEntry {
+        content: Bytes::from(content),
+        meta,
+    }
+}
+
+async fn open(dir: &Path) -> anyhow::Result<ProxyCache> {
+    ProxyCache::builder().config(config()).dir(dir).build().await
+}
+
+// 持续写入 prefix-0、prefix-1 ……直到 stop，written 记录已返回的写入数
+fn keep_writing(
+    cache: Arc<ProxyCache>,
+    prefix: &'static str,
+    written: Arc<AtomicUsize>,
+    stop: Arc<AtomicBool>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut n = 0;
+        while !stop.load(Ordering::SeqCst) {
+            let key = format!("{}-{}", prefix, n);
+            cache.set(key.clone(), entry(&key, n)).await.unwrap();
+            n += 1;
+            written.store(n, Ordering::SeqCst);
+            tokio::task::yield_now().await;
+        }
+    })
+}
+
+async fn assert_intact(cache: &ProxyCache, prefix: &str, count: usize) {
+    for n in 0..count {
+        let key = format!("{}-{}", prefix, n);
+        let restored = cache.get(&key).await.unwrap_or_else(|| panic!("{} is missing", key));
+        assert_eq!(restored.content, entry(&key, n).content, "{}", key);
+    }
+}
+
+#[tokio::test]
+async fn cache_dir_is_handed_over_while_writes_continue() {
+    let dir = tempfile::tempdir().unwrap();
+    let old = Arc::new(open(dir.path()).await.unwrap());
+    let (old_written, new_written) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+    let stop = Arc::new(AtomicBool::new(false));
+    let old_writes = keep_writing(old.clone(), "old", old_written.clone(), stop.clone());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // 目录锁被持有时另一个实例无法打开
+    assert!(open(dir.path()).await.is_err());
+    assert!(CacheLock::try_acquire(dir.path()).unwrap().is_none());
+
+    // 释放之前返回的写入都已落盘，之后旧进程只写内存
+    let persisted = old_written.load(Ordering::SeqCst);
+    old.release_dir().await.unwrap();
+    let lock = CacheLock::try_acquire(dir.path()).unwrap().expect("lock released");
+    let new = Arc::new(ProxyCache::builder().config(config()).dir(dir.path()).lock(lock).build().await.unwrap());
+    let new_writes = keep_writing(new.clone(), "new", new_written.clone(), stop.clone());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    stop.store(true, Ordering::SeqCst);
+    old_writes.await.unwrap();
+    new_writes.await.unwrap();
+    assert!(persisted > 0 && new_written.load(Ordering::SeqCst) > 0);
+
+    // 新进程退出后（相当于升级失败）旧进程重新取得锁，按新进程写入后的段文件继续写
+    new.flush().await.unwrap();
+    drop(new);
+    old.reclaim_dir(Duration::from_secs(5)).await.unwrap();
+    old.set("after-0".to_string(), entry("after-0", 0)).await.unwrap();
+    old.set("after-1".to_string(), entry("after-1", 1)).await.unwrap();
+    old.flush().await.unwrap();
+    drop(old);
+
+    let reopened = open(dir.path()).await.unwrap();
+    assert_intact(&reopened, "old", persisted).await;
+    assert_intact(&reopened, "new", new_written.load(Ordering::SeqCst)).await;
+    assert_intact(&reopened, "after", 2).await;
+}
@@ -0,0 +1,145 @@
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE, HOST};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode, Uri};
+use rust_proxy_server::cache::ProxyCache;
+use rust_proxy_server::config::Config;
+use rust_proxy_server::rewrite::{rewrite_response, rewrite_url, RewriteRule, UrlAction, UrlRewrite, UrlRule};
+use rust_proxy_server::{client, server, target};
+
+fn uri(value: &str) -> Uri {
+    value.parse().unwrap()
+}
+
+#[test]
+fn first_matching_rule_wins() {
+    let rules = vec![
+        UrlRule::new(r"^http://old\.example\.com/(.*)$", "https://new.example.com/$1", UrlAction::PermanentRedirect)
+            .unwrap(),
+        UrlRule::new(r"^http://cdn-a\.example\.com/", "http://cdn-b.example.com/assets/", UrlAction::Rewrite).unwrap(),
+        UrlRule::new(r"cdn", "never", UrlAction::Redirect).unwrap(),
+    ];
+    match rewrite_url(&uri("http://old.example.com/a?b=1"), &rules) {
+        Some(UrlRewrite::Redirect(status, location)) => {
+            assert_eq!(status, StatusCode::MOVED_PERMANENTLY);
+            assert_eq!(location, "https://new.example.com/a?b=1");
+        }
+        _ => panic!("expected a redirect"),
+    }
+    // 只替换匹配到的部分，路径其余部分与查询参数保留
+    match rewrite_url(&uri("http://cdn-a.example.com/v/1.ts?t=2"), &rules) {
+        Some(UrlRewrite::Rewrite(target)) => {
+            assert_eq!(target, "http://cdn-b.example.com/assets/v/1.ts?t=2")
+        }
+        _ => panic!("expected a rewrite"),
+    }
+    assert!(rewrite_url(&uri("http://other.example.com/"), &rules).is_none());
+}
+
+#[test]
+fn retarget_updates_uri_and_host() {
+    let mut req = Request::get("http://cdn-a.example.com/a").body(Body::empty()).unwrap();
+    assert!(target::retarget(&mut req, "HTTP://CDN-B.example.com:80/assets/a"));
+    assert_eq!(req.uri(), &uri("http://cdn-b.example.com/assets/a"));
+    assert_eq!(req.headers()[HOST], "cdn-b.example.com");
+
+    assert!(!target::retarget(&mut req, "/relative/path"));
+    assert!(!target::retarget(&mut req, "ftp://example.com/a"));
+    assert_eq!(req.uri(), &uri("http://cdn-b.example.com/assets/a"));
+}
+
+fn playlist(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/vnd.apple.mpegurl")
+        .header(CONTENT_LENGTH, "21")
+        .body(Body::from("http://origin/seg1.ts"))
+        .unwrap()
+}
+
+fn rules() -> Vec<RewriteRule> {
+    let mut rule: RewriteRule =
+        serde_json::from_value(serde_json::json!({"pattern": "http://origin/", "replacement": "/proxy/"})).unwrap();
+    rule.compile().unwrap();
+    vec![rule]
+}
+
+async fn body(response: Response<Body>) -> Bytes {
+    hyper::body::to_bytes(response.into_body()).await.unwrap()
+}
+
+#[tokio::test]
+async fn only_full_get_responses_are_rewritten() {
+    let rewritten = rewrite_response(&Method::GET, playlist(StatusCode::OK), &rules()).await.unwrap();
+    assert_eq!(rewritten.headers()[CONTENT_LENGTH], "14");
+    assert_eq!(body(rewritten).await, "/proxy/seg1.ts");
+}
+
+#[tokio::test]
+async fn head_responses_keep_their_headers() {
+    let response = rewrite_response(&Method::HEAD, playlist(StatusCode::OK), &rules()).await.unwrap();
+    assert_eq!(response.headers()[CONTENT_LENGTH], "21");
+}
+
+#[tokio::test]
+async fn partial_responses_are_not_rewritten() {
+    let response = rewrite_response(&Method::GET, playlist(StatusCode::PARTIAL_CONTENT), &rules()).await.unwrap();
+    assert_eq!(response.headers()[CONTENT_LENGTH], "21");
+    assert_eq!(body(response).await, "http://origin/seg1.ts");
+}
+
+// 签名按客户端请求的原 URL 校验：改写到不受保护的路由也不能绕过
+#[tokio::test(flavor = "multi_thread")]
+async fn signature_is_checked_before_url_rewrite() {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let counter = hits.clone();
+    let make = make_service_fn(move |_| {
+        let counter = counter.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async { Ok::<_, Infallible>(Response::new(Body::from("public"))) }
+            }))
+        }
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+
+    // 改写规则在加载配置文件时编译
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("proxy.toml");
+    std::fs::write(
+        &path,
+        format!(
+            r#"
+            [[routes]]
+            name = "private"
+            path_prefix = "/private/"
+            signed_url = {{ secret = "s3cret" }}
+            url_rewrites = [{{ pattern = "^http://[^/]+/private/(.*)$", replacement = "http://{}/public/$1" }}]
+            "#,
+            addr
+        ),
+    )
+    .unwrap();
+    let config = Config::load(&path).unwrap();
+    config.validate().unwrap();
+    let cache = Arc::new(ProxyCache::builder().dir(dir.path().join("cache")).build().await.unwrap());
+    let client = client::build(&config).unwrap();
+    let config = Arc::new(config);
+
+    let req = Request::get(format!("http://{}/private/a.ts", addr)).body(Body::empty()).unwrap();
+    let response = server::handle_request(req, cache.clone(), client.clone(), config.clone()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(hits.load(Ordering::SeqCst), 0);
+
+    let req = Request::get(format!("http://{}/public/a.ts", addr)).body(Body::empty()).unwrap();
+    let response = server::handle_request(req, cache, client, config).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(hits.load(Ordering::SeqCst) > 0);
+}